@@ -26,6 +26,7 @@ local function run_tests()
     else
         -- Tests for Linux / MacOS
         os.execute("./test/command_tester.sh")
+        os.execute("target/debug/flua --no-wait test test")
         os.execute("target/debug/luajit test/main.lua")
         os.execute("target/debug/luajit test/data.lua")
         os.execute("target/debug/luajit test/http_async.lua")
@@ -0,0 +1,50 @@
+-- dapi_async, the event loop and the tasks are in async_loop.rs
+--
+-- In a task the waiting functions yield, so other tasks run in the meantime.
+-- In the main chunk they run the event loop until they are done
+
+local core = ...
+local M = {}
+
+local function in_task()
+  local co, main = coroutine.running()
+  return co ~= nil and not main
+end
+
+local function waiting(name)
+  local yielding, blocking = core[name .. "_async"], core[name .. "_block"]
+  return function(...)
+    if in_task() then
+      return yielding(...)
+    end
+    return blocking(...)
+  end
+end
+
+M.spawn = core.spawn
+M.run = core.run
+M.sleep = waiting("sleep")
+M.await = waiting("await")
+M.await_all = waiting("await_all")
+M.fetch = waiting("fetch")
+M.exec = waiting("exec")
+
+-- Calls fn once after ms milliseconds, the task can be cancelled
+function M.set_timeout(fn, ms)
+  return M.spawn(function()
+    M.sleep(ms)
+    return fn()
+  end)
+end
+
+-- Calls fn every ms milliseconds until the task is cancelled
+function M.set_interval(fn, ms)
+  return M.spawn(function()
+    while true do
+      M.sleep(ms)
+      fn()
+    end
+  end)
+end
+
+return M
@@ -0,0 +1,522 @@
+// dapi_async, coroutines on the tokio runtime of flua
+//
+// spawn() runs a function as a task on a LocalSet of the Lua state. sleep(),
+// fetch(), exec() and await() yield inside a task instead of blocking the
+// thread, in the main chunk they drive the event loop until they are done.
+// Tasks which still run at the end of the script are finished before flua
+// exits, errors of tasks nobody awaited are raised then
+
+use mlua::{AnyUserData, FromLuaMulti, Function, IntoLuaMulti, Lua, MultiValue, Result, Table};
+use mlua::{UserData, UserDataMethods, Value};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::process::Stdio;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::Notify;
+use tokio::task::{AbortHandle, LocalSet};
+
+use crate::api::meta::{ClassDoc, FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
+use crate::helper::permissions::{check_run, check_url};
+
+const PRELUDE: &str = include_str!("async_loop.lua");
+
+// The runtime of main.rs, or an own one when flua is used without tokio
+enum Driver {
+    Shared(Handle),
+    Own(Runtime),
+}
+
+// Event loop of one Lua state, stored as app data
+struct EventLoop {
+    driver: Driver,
+    local: LocalSet,
+    tasks: RefCell<Vec<Rc<TaskState>>>,
+    running: Cell<bool>,
+}
+
+impl EventLoop {
+    // `function` is the dapi function which started the loop, for errors
+    fn new(function: &'static str) -> Result<Self> {
+        let driver = match Handle::try_current() {
+            Ok(handle) => Driver::Shared(handle),
+            Err(_) => Driver::Own(
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|e| {
+                        dapi_error(function, format!("Could not start the event loop: {}", e))
+                    })?,
+            ),
+        };
+
+        Ok(EventLoop {
+            driver,
+            local: LocalSet::new(),
+            tasks: RefCell::new(Vec::new()),
+            running: Cell::new(false),
+        })
+    }
+
+    // Runs the tasks until `future` is done, only outside of a task
+    fn block_on<F: Future>(&self, function: &'static str, future: F) -> Result<F::Output> {
+        // A runtime which was entered since, tokio can not block inside it
+        if matches!(self.driver, Driver::Own(_)) && Handle::try_current().is_ok() {
+            return Err(dapi_error(
+                function,
+                "can not wait inside of another tokio runtime",
+            ));
+        }
+
+        if self.running.replace(true) {
+            return Err(dapi_error(
+                function,
+                "can not wait for the event loop inside of it, only in a task",
+            ));
+        }
+
+        let future = self.local.run_until(future);
+        let output = match &self.driver {
+            Driver::Shared(handle) => handle.block_on(future),
+            Driver::Own(runtime) => runtime.block_on(future),
+        };
+        self.running.set(false);
+        Ok(output)
+    }
+
+    fn spawn(&self, func: Function, args: MultiValue) -> Rc<TaskState> {
+        let state = Rc::new(TaskState::default());
+        let future = func.call_async::<MultiValue>(args);
+
+        let handle = self.local.spawn_local({
+            let state = Rc::clone(&state);
+            async move { state.finish(future.await) }
+        });
+        *state.abort.borrow_mut() = Some(handle.abort_handle());
+
+        // Finished tasks are only kept for an error nobody awaited
+        let mut tasks = self.tasks.borrow_mut();
+        tasks.retain(|task| !task.is_done() || task.unhandled_error().is_some());
+        tasks.push(Rc::clone(&state));
+        state
+    }
+
+    // Waits until no task runs anymore
+    async fn drain(&self) -> Result<()> {
+        loop {
+            let next = self
+                .tasks
+                .borrow()
+                .iter()
+                .find(|task| !task.is_done())
+                .cloned();
+            match next {
+                Some(task) => {
+                    let _ = task.wait().await;
+                }
+                None => break,
+            }
+        }
+
+        let tasks = std::mem::take(&mut *self.tasks.borrow_mut());
+        match tasks.iter().find_map(|task| task.unhandled_error()) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct TaskState {
+    result: RefCell<Option<Result<MultiValue>>>,
+    done: Notify,
+    abort: RefCell<Option<AbortHandle>>,
+    // Errors of awaited tasks are handled by the script
+    awaited: Cell<bool>,
+}
+
+impl TaskState {
+    fn finish(&self, result: Result<MultiValue>) {
+        let mut slot = self.result.borrow_mut();
+        if slot.is_none() {
+            *slot = Some(result);
+        }
+        drop(slot);
+        self.done.notify_waiters();
+    }
+
+    fn is_done(&self) -> bool {
+        self.result.borrow().is_some()
+    }
+
+    fn unhandled_error(&self) -> Option<mlua::Error> {
+        if self.awaited.get() {
+            return None;
+        }
+        match &*self.result.borrow() {
+            Some(Err(error)) => Some(error.clone()),
+            _ => None,
+        }
+    }
+
+    async fn wait(&self) -> Result<MultiValue> {
+        loop {
+            if let Some(result) = self.result.borrow().clone() {
+                return result;
+            }
+            self.done.notified().await;
+        }
+    }
+}
+
+// Handle of a task for Lua
+struct Task(Rc<TaskState>);
+
+impl UserData for Task {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("done", |_, task, ()| Ok(task.0.is_done()));
+
+        // Stops the task, await() of it fails afterwards
+        methods.add_method("cancel", |_, task, ()| {
+            if let Some(abort) = task.0.abort.borrow_mut().take() {
+                abort.abort();
+            }
+            task.0.awaited.set(true);
+            task.0.finish(Err(dapi_error(
+                "dapi_async.cancel",
+                "the task was cancelled",
+            )));
+            Ok(())
+        });
+    }
+}
+
+fn event_loop(lua: &Lua, function: &'static str) -> Result<Rc<EventLoop>> {
+    if let Some(event_loop) = lua.app_data_ref::<Rc<EventLoop>>() {
+        return Ok(Rc::clone(&event_loop));
+    }
+    let event_loop = Rc::new(EventLoop::new(function)?);
+    lua.set_app_data(Rc::clone(&event_loop));
+    Ok(event_loop)
+}
+
+// Finishes the tasks of the script, nothing to do when dapi_async was not used
+pub fn run_pending(lua: &Lua) -> Result<()> {
+    let Some(event_loop) = lua.app_data_ref::<Rc<EventLoop>>().map(|e| Rc::clone(&e)) else {
+        return Ok(());
+    };
+    event_loop.block_on("dapi_async.run", event_loop.drain())?
+}
+
+fn task_of(value: &AnyUserData) -> Result<Rc<TaskState>> {
+    let task = value.borrow::<Task>()?;
+    task.0.awaited.set(true);
+    Ok(Rc::clone(&task.0))
+}
+
+async fn await_all(lua: Lua, tasks: Table) -> Result<Table> {
+    let tasks = tasks
+        .sequence_values::<AnyUserData>()
+        .map(|task| task_of(&task?))
+        .collect::<Result<Vec<_>>>()?;
+
+    let results = lua.create_table()?;
+    for (i, task) in tasks.iter().enumerate() {
+        let values = task.wait().await?;
+        results.set(i + 1, values.into_iter().next().unwrap_or(Value::Nil))?;
+    }
+    Ok(results)
+}
+
+async fn exec(lua: Lua, command: String) -> Result<Table> {
+    check_run(&lua, &command)?;
+
+    let mut process = if cfg!(target_os = "windows") {
+        let mut process = Command::new("cmd");
+        process.arg("/C");
+        process
+    } else {
+        let mut process = Command::new("sh");
+        process.arg("-c");
+        process
+    };
+    let output = process
+        .arg(&command)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| {
+            dapi_error(
+                "dapi_async.exec",
+                format!("Could not run '{}': {}", command, e),
+            )
+        })?;
+
+    let table = lua.create_table()?;
+    table.set("status", output.status.code().unwrap_or(-1))?;
+    table.set("stdout", lua.create_string(&output.stdout)?)?;
+    table.set("stderr", lua.create_string(&output.stderr)?)?;
+    Ok(table)
+}
+
+// Adds `<name>_async` for tasks and `<name>_block` for the main chunk,
+// `function` is the full name like `dapi_async.sleep`
+fn add_waiting<A, R, F, Fut>(lua: &Lua, core: &Table, function: &'static str, f: F) -> Result<()>
+where
+    A: FromLuaMulti + 'static,
+    R: IntoLuaMulti + 'static,
+    F: Fn(Lua, A) -> Fut + Clone + 'static,
+    Fut: Future<Output = Result<R>> + 'static,
+{
+    let name = function.trim_start_matches("dapi_async.");
+    core.set(
+        format!("{}_async", name),
+        lua.create_async_function(f.clone())?,
+    )?;
+    core.set(
+        format!("{}_block", name),
+        lua.create_function(move |lua, args: A| {
+            event_loop(lua, function)?.block_on(function, f(lua.clone(), args))?
+        })?,
+    )?;
+    Ok(())
+}
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_async",
+    description: "Tasks, timers and waiting without blocking",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("spawn", "Starts the function with the arguments as a task")
+            .params(&[("fn", "function"), ("...", "any")])
+            .returns(&["dapi_async.Task"]),
+        FunctionDoc::new("run", "Waits until all tasks are done"),
+        FunctionDoc::new(
+            "sleep",
+            "Waits some milliseconds, other tasks run meanwhile",
+        )
+        .params(&[("ms", "integer")]),
+        FunctionDoc::new("await", "Waits for a task and returns its values")
+            .params(&[("task", "dapi_async.Task")])
+            .returns(&["any"]),
+        FunctionDoc::new("await_all", "Waits for all tasks, the first value of each")
+            .params(&[("tasks", "dapi_async.Task[]")])
+            .returns(&["any[]"]),
+        FunctionDoc::new("fetch", "The body of a GET request")
+            .params(&[("url", "string")])
+            .returns(&["string"]),
+        FunctionDoc::new(
+            "exec",
+            "Runs a shell command, returns `status`, `stdout` and `stderr`",
+        )
+        .params(&[("command", "string")])
+        .returns(&["table"]),
+        FunctionDoc::new(
+            "set_timeout",
+            "Calls the function once after some milliseconds",
+        )
+        .params(&[("fn", "function"), ("ms", "integer")])
+        .returns(&["dapi_async.Task"]),
+        FunctionDoc::new(
+            "set_interval",
+            "Calls the function every few milliseconds until the task is cancelled",
+        )
+        .params(&[("fn", "function"), ("ms", "integer")])
+        .returns(&["dapi_async.Task"]),
+    ],
+    classes: &[ClassDoc {
+        name: "dapi_async.Task",
+        description: "A running function",
+        methods: &[
+            FunctionDoc::new("done", "Whether the task finished").returns(&["boolean"]),
+            FunctionDoc::new("cancel", "Stops the task, await() of it fails afterwards"),
+        ],
+    }],
+};
+
+pub fn register(lua: &Lua) -> Result<Table> {
+    let core = lua.create_table()?;
+
+    // spawn(fn, ...), starts fn with the arguments as a task
+    core.set(
+        "spawn",
+        lua.create_function(|lua, (func, args): (Function, MultiValue)| {
+            Ok(Task(event_loop(lua, "dapi_async.spawn")?.spawn(func, args)))
+        })?,
+    )?;
+
+    // run(), waits for all tasks
+    core.set("run", lua.create_function(|lua, ()| run_pending(lua))?)?;
+
+    // sleep(ms)
+    add_waiting(lua, &core, "dapi_async.sleep", |_, ms: u64| async move {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+        Ok(())
+    })?;
+
+    // await(task), the return values of the task
+    add_waiting(
+        lua,
+        &core,
+        "dapi_async.await",
+        |_, task: AnyUserData| async move { task_of(&task)?.wait().await },
+    )?;
+
+    // await_all({ task, ... }), the first return value of every task
+    add_waiting(lua, &core, "dapi_async.await_all", await_all)?;
+
+    // fetch(url), the body like dapi_net.fetch
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| {
+            dapi_error(
+                "dapi_async",
+                format!("Could not create the HTTP client: {}", e),
+            )
+        })?;
+    add_waiting(lua, &core, "dapi_async.fetch", move |lua, url: String| {
+        let client = client.clone();
+        async move {
+            check_url(&lua, &url)?;
+            let resp = client
+                .get(&url)
+                .header("User-Agent", "MyLuaRustApp/1.0")
+                .send()
+                .await
+                .map_err(|e| {
+                    dapi_error("dapi_async.fetch", format!("HTTP request failed: {}", e))
+                })?;
+
+            if !resp.status().is_success() {
+                return Err(dapi_error(
+                    "dapi_async.fetch",
+                    format!("HTTP status {}", resp.status()),
+                ));
+            }
+            resp.text().await.map_err(|e| {
+                dapi_error(
+                    "dapi_async.fetch",
+                    format!("Could not read the response body: {}", e),
+                )
+            })
+        }
+    })?;
+
+    // exec(command), a table with status, stdout and stderr like dapi_os.run
+    add_waiting(lua, &core, "dapi_async.exec", exec)?;
+
+    lua.load(PRELUDE).set_name("=dapi_async").call(core)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn lua() -> Lua {
+        let lua = Lua::new();
+        lua.globals().set("a", register(&lua).unwrap()).unwrap();
+        lua
+    }
+
+    #[test]
+    fn tasks_sleep_concurrently() {
+        let lua = lua();
+        let start = Instant::now();
+        let result: String = lua
+            .load(
+                r#"
+                local log = {}
+                local slow = a.spawn(function(x)
+                    a.sleep(150)
+                    table.insert(log, "slow")
+                    return x * 2
+                end, 21)
+                local quick = a.spawn(function()
+                    a.sleep(100)
+                    table.insert(log, "quick")
+                    return "q"
+                end)
+                local results = a.await_all({ slow, quick })
+                return results[1] .. results[2] .. " " .. table.concat(log, ",")
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(result, "42q quick,slow");
+        assert!(start.elapsed() < Duration::from_millis(240));
+    }
+
+    #[test]
+    fn interval_until_cancelled() {
+        let lua = lua();
+        lua.load(
+            r#"
+            ticks = 0
+            local interval = a.set_interval(function() ticks = ticks + 1 end, 20)
+            a.set_timeout(function() interval:cancel() end, 110)
+            "#,
+        )
+        .exec()
+        .unwrap();
+        run_pending(&lua).unwrap();
+
+        let ticks: i64 = lua.globals().get("ticks").unwrap();
+        assert!((3..=6).contains(&ticks), "{}", ticks);
+    }
+
+    #[test]
+    fn errors_of_tasks() {
+        let lua = lua();
+        let message: String = lua
+            .load(
+                r#"
+                local task = a.spawn(function() a.sleep(10) error("awaited") end)
+                local ok, err = pcall(a.await, task)
+                return tostring(err)
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(message.contains("awaited"), "{}", message);
+        run_pending(&lua).unwrap();
+
+        lua.load(r#"a.spawn(function() error("nobody waits") end)"#)
+            .exec()
+            .unwrap();
+        let err = run_pending(&lua).unwrap_err();
+        assert!(err.to_string().contains("nobody waits"), "{}", err);
+    }
+
+    #[test]
+    fn exec_in_a_task() {
+        let lua = lua();
+        let output: String = lua
+            .load(
+                r#"
+                local task = a.spawn(function() return a.exec("echo async").stdout end)
+                return a.await(task)
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(output.trim(), "async");
+    }
+
+    #[test]
+    fn waiting_inside_another_runtime() {
+        let lua = lua();
+        lua.load("a.sleep(1)").exec().unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let err = runtime
+            .block_on(async { lua.load("a.sleep(1)").exec() })
+            .unwrap_err();
+        assert!(err.to_string().contains("another tokio runtime"), "{}", err);
+    }
+}
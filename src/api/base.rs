@@ -4,10 +4,14 @@ use std::io::copy;
 use std::thread;
 use std::time::Duration;
 
-use crate::deprecated;
+use crate::api::meta::{self, FunctionDoc, ModuleDoc};
+use crate::helper::deprecation;
 
 use crate::VERSION;
 
+use crate::helper::dapi_error::dapi_error;
+use crate::helper::exit_code::create_exit_function;
+use crate::helper::permissions::{check_url, check_write};
 use crate::helper::update::version_checker;
 
 use crate::helper::print::{
@@ -19,19 +23,68 @@ use crate::helper::print::{
     YELLOW, clear_terminal,
 };
 
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi",
+    description: "Basics of flua: version checks, errors, exit and terminal colors",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("greet", "Prints a greeting")
+            .params(&[("name", "string")]),
+        FunctionDoc::new("add", "Adds two integers")
+            .params(&[("a", "integer"), ("b", "integer")])
+            .returns(&["integer"]),
+        FunctionDoc::new("version", "The version of flua").returns(&["string"]),
+        FunctionDoc::new(
+            "check_version",
+            "Whether the script runs on the wanted version, warns by default and stops the script when `break_script` is true",
+        )
+        .params(&[
+            ("version", "string"),
+            ("warning", "boolean?"),
+            ("break_script", "boolean?"),
+        ])
+        .returns(&["boolean"]),
+        FunctionDoc::new("throw_error", "Stops the script with an error")
+            .params(&[("message", "string")]),
+        FunctionDoc::new("download", "Downloads a file, false when it failed")
+            .params(&[("url", "string"), ("destination", "string")])
+            .returns(&["boolean"]),
+        FunctionDoc::new("wait", "Pauses the script for some milliseconds")
+            .params(&[("ms", "integer")]),
+        FunctionDoc::new("clear", "Clears the terminal"),
+        FunctionDoc::new(
+            "exit",
+            "Stops the servers, flushes the output and exits, true or nil is 0 and false is 1",
+        )
+        .params(&[("code", "integer|boolean?")]),
+        FunctionDoc::new(
+            "get_colors",
+            "ANSI codes for colored output like `red`, `bg_blue` or `end`",
+        )
+        .returns(&["table<string, string>"]),
+        FunctionDoc::new(
+            "help",
+            "Description of a module like `dapi_io` or a function like `dapi_io.zip`, without a topic the list of modules",
+        )
+        .params(&[("topic", "string?")])
+        .returns(&["string"]),
+    ],
+    classes: &[],
+};
+
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?;
 
     // a Simple greet function which will be removed soon!
     let greet = lua.create_function(|_, name: String| {
-        deprecated!("dapi.greet", "0.1.8", "Why do you even want to use it?");
+        deprecation::used("dapi.greet")?;
         println!("Hello from Rust, {}!", name);
         Ok(())
     })?;
 
     // a Simple add fucntion which will be removed probably soon
     let add = lua.create_function(|_, (a, b): (i64, i64)| {
-        deprecated!("dapi.add", "0.1.8", "Its completely useless bro");
+        deprecation::used("dapi.add")?;
         Ok(a + b)
     })?;
 
@@ -46,7 +99,7 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
             let warning = warning_opt.unwrap_or(true);
             let break_script = break_wrong_version.unwrap_or(false);
 
-            let result = match version_checker(&version, VERSION) {
+            match version_checker(&version, VERSION) {
                 true => {
                     if warning {
                         println!("{}[INFO] Using the right Version for LUAJIT!{}", GREEN, END);
@@ -68,29 +121,29 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
                     if break_script {
                         // check if the 2nd number is different
                         // check if the 3rd number is not newer
-                        return Err(mlua::Error::external(
-                            "Wrong Version used! Interrupting Script!",
+                        return Err(dapi_error(
+                            "dapi.check_version",
+                            format!(
+                                "Wrong version used, the script needs {} but this is {}",
+                                version, VERSION
+                            ),
                         ));
                     }
                     Ok(false)
                 }
-            };
-            result
+            }
         },
     )?;
 
     // Lua Function to throw an Error and Interrupt the Script
-    let throw_error = lua.create_function(|_, msg: String| {
-        return Err::<(), mlua::Error>(mlua::Error::external(msg));
-    })?;
+    let throw_error =
+        lua.create_function(|_, msg: String| Err::<(), mlua::Error>(mlua::Error::external(msg)))?;
 
     // Function to download a file
-    let download = lua.create_function(|_, (url, destination): (String, String)| {
-        deprecated!(
-            "dapi.greet",
-            "0.1.13",
-            "Use dapi_net.download_file() instead!"
-        );
+    let download = lua.create_function(|lua, (url, destination): (String, String)| {
+        deprecation::used("dapi.download")?;
+        check_url(lua, &url)?;
+        check_write(lua, &destination)?;
         match reqwest::blocking::get(&url) {
             Ok(mut resp) => {
                 match File::create(&destination) {
@@ -109,7 +162,7 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     })?;
 
     let wait = lua.create_function(|_, time: u64| {
-        deprecated!("dapi.greet", "0.1.13", "Use dapi_time.wait() instead!");
+        deprecation::used("dapi.wait")?;
         thread::sleep(Duration::from_millis(time));
         Ok(())
     })?;
@@ -182,6 +235,16 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
         Ok(table)
     })?;
 
+    // Help from the metadata of the modules
+    let help = lua.create_function(|_, topic: Option<String>| {
+        meta::help(topic.as_deref()).ok_or_else(|| {
+            dapi_error(
+                "dapi.help",
+                format!("Nothing is known about '{}'", topic.unwrap_or_default()),
+            )
+        })
+    })?;
+
     table.set("greet", greet)?;
     table.set("add", add)?;
     table.set("version", version)?;
@@ -190,7 +253,10 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     table.set("download", download)?;
     table.set("wait", wait)?;
     table.set("clear", clear)?;
+    // Exits with a code after stopping the servers and flushing the output
+    table.set("exit", create_exit_function(lua)?)?;
     table.set("get_colors", get_colors)?;
+    table.set("help", help)?;
 
     Ok(table)
 }
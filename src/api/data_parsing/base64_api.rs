@@ -1,5 +1,23 @@
 use base64::{Engine as _, engine::general_purpose};
-use mlua::{Error, Lua, Result};
+use mlua::{Lua, Result};
+
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_base64",
+    description: "Base64",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("encode", "Encodes a string as Base64")
+            .params(&[("input", "string")])
+            .returns(&["string"]),
+        FunctionDoc::new("decode", "Decodes Base64")
+            .params(&[("base64", "string")])
+            .returns(&["string"]),
+    ],
+    classes: &[],
+};
 
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?; // base64-Tabelle
@@ -12,9 +30,9 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let decode = lua.create_function(|_, b64: String| {
         let bytes = general_purpose::STANDARD
             .decode(b64)
-            .map_err(Error::external)?;
+            .map_err(|e| dapi_error("dapi_base64.decode", e))?;
 
-        let s = String::from_utf8(bytes).map_err(Error::external)?;
+        let s = String::from_utf8(bytes).map_err(|e| dapi_error("dapi_base64.decode", e))?;
         Ok(s)
     })?;
 
@@ -1,6 +1,26 @@
-use mlua::{Error, Lua, Result};
+use mlua::{Lua, Result};
 use std::env;
 
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
+use crate::helper::permissions::{check_env, check_read};
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_dotenv",
+    description: "Environment variables and `.env` files",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("get", "The value of a variable, nil when it is not set")
+            .params(&[("key", "string")])
+            .returns(&["string?"]),
+        FunctionDoc::new("set", "Sets a variable, nil removes it")
+            .params(&[("key", "string"), ("value", "string?")]),
+        FunctionDoc::new("load", "Loads the variables of a file, `.env` by default")
+            .params(&[("path", "string?")]),
+    ],
+    classes: &[],
+};
+
 /// Registers the `.env` module in Lua, providing access to environment variables.
 ///
 /// This module exposes the following functions to Lua:
@@ -10,52 +30,56 @@ use std::env;
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?; // .env-Tabelle
 
-    /// Gets the value of an environment variable.
-    ///
-    /// # Lua
-    /// ```lua
-    /// local value = env.get("DATABASE_URL")
-    /// if value then
-    ///     print("Found:", value)
-    /// else
-    ///     print("Not set")
-    /// end
-    /// ```
-    ///
-    /// # Returns
-    /// - `string`: the value if found
-    /// - `nil`: if the variable is not set
-    let get = lua.create_function(|_, key: String| match env::var(&key) {
-        Ok(val) => Ok(Some(val)),
-        Err(env::VarError::NotPresent) => Ok(None),
-        Err(e) => Err(Error::external(e)),
+    // Gets the value of an environment variable.
+    //
+    // # Lua
+    // ```lua
+    // local value = env.get("DATABASE_URL")
+    // if value then
+    //     print("Found:", value)
+    // else
+    //     print("Not set")
+    // end
+    // ```
+    //
+    // # Returns
+    // - `string`: the value if found
+    // - `nil`: if the variable is not set
+    let get = lua.create_function(|lua, key: String| {
+        check_env(lua, &key)?;
+        match env::var(&key) {
+            Ok(val) => Ok(Some(val)),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(e) => Err(dapi_error("dapi_dotenv.get", e)),
+        }
     })?;
 
-    /// Sets an environment variable (unsafe in multi-threaded contexts).
-    ///
-    /// This uses `std::env::set_var`, which is `unsafe` as of Rust 1.77.
-    /// Only use this in single-threaded scenarios.
-    ///
-    /// # Lua
-    /// ```lua
-    /// env.set("MY_VAR", "123")
-    /// print(env.get("MY_VAR")) --> "123"
-    /// ```
-    ///
-    /// # Safety
-    /// This function uses an `unsafe` block because modifying environment variables
-    /// is not thread-safe across all platforms.
-    ///
-    /// # Errors
-    /// Returns a Lua error if key or value contain null bytes (`\0`), which are invalid.
-    let set = lua.create_function(|_, (key, value): (String, Option<String>)| {
+    // Sets an environment variable (unsafe in multi-threaded contexts).
+    //
+    // This uses `std::env::set_var`, which is `unsafe` as of Rust 1.77.
+    // Only use this in single-threaded scenarios.
+    //
+    // # Lua
+    // ```lua
+    // env.set("MY_VAR", "123")
+    // print(env.get("MY_VAR")) --> "123"
+    // ```
+    //
+    // # Safety
+    // This function uses an `unsafe` block because modifying environment variables
+    // is not thread-safe across all platforms.
+    //
+    // # Errors
+    // Returns a Lua error if key or value contain null bytes (`\0`), which are invalid.
+    let set = lua.create_function(|lua, (key, value): (String, Option<String>)| {
+        check_env(lua, &key)?;
         if key.contains('\0') {
-            return Err(Error::external("Key contains null byte"));
+            return Err(dapi_error("dapi_dotenv.set", "Key contains null byte"));
         }
 
         if let Some(val) = value {
             if val.contains('\0') {
-                return Err(Error::external("Value contains null byte"));
+                return Err(dapi_error("dapi_dotenv.set", "Value contains null byte"));
             }
             unsafe {
                 env::set_var(&key, &val);
@@ -69,19 +93,21 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
         Ok(())
     })?;
 
-    /// Loads environment variables from a `.env` file into the process environment.
-    ///
-    /// # Lua
-    /// ```lua
-    /// env.load()             -- loads from ".env" by default
-    /// env.load("custom.env") -- loads from a custom file
-    /// ```
-    ///
-    /// # Errors
-    /// Returns a Lua error if the file could not be found or parsed.
-    let load = lua.create_function(|_, path: Option<String>| {
+    // Loads environment variables from a `.env` file into the process environment.
+    //
+    // # Lua
+    // ```lua
+    // env.load()             -- loads from ".env" by default
+    // env.load("custom.env") -- loads from a custom file
+    // ```
+    //
+    // # Errors
+    // Returns a Lua error if the file could not be found or parsed.
+    let load = lua.create_function(|lua, path: Option<String>| {
         let path = path.unwrap_or_else(|| ".env".to_string());
-        dotenv::from_filename(path).map_err(mlua::Error::external)?;
+        check_read(lua, &path)?;
+        check_env(lua, &path)?;
+        dotenv::from_filename(path).map_err(|e| dapi_error("dapi_dotenv.load", e))?;
         Ok(())
     })?;
 
@@ -186,7 +212,7 @@ mod tests {
         let env_mod = register(&lua)?;
         lua.globals().set("env", env_mod)?;
 
-        lua.load(&format!(r#"env.load("{}")"#, path)).exec()?;
+        lua.load(format!(r#"env.load("{}")"#, path)).exec()?;
         assert_eq!(env::var("ANOTHER_VAR").unwrap(), "hello_world");
 
         // Aufräumen
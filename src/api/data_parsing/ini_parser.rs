@@ -1,8 +1,10 @@
 use ini;
 
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
 use crate::utils::json_utils;
 
-use mlua::{Error, Lua, Result, Table, Value};
+use mlua::{Lua, Result, Table, Value};
 use serde_json;
 
 use std::collections::HashMap;
@@ -58,6 +60,21 @@ fn map_to_ini_string(map: &HashMap<String, HashMap<String, Option<String>>>) ->
     result
 }
 
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_ini",
+    description: "INI files",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("parse", "Parses INI, a table per section")
+            .params(&[("ini", "string")])
+            .returns(&["table"]),
+        FunctionDoc::new("convert", "Converts a table of sections to INI")
+            .params(&[("value", "table")])
+            .returns(&["string"]),
+    ],
+    classes: &[],
+};
+
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?;
 
@@ -75,10 +92,10 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
             }
         }
 
-        let json_str = serde_json::to_string_pretty(&ini_map)
-            .map_err(|e| mlua::Error::RuntimeError(e.to_string()))?;
+        let json_str =
+            serde_json::to_string_pretty(&ini_map).map_err(|e| dapi_error("dapi_ini.parse", e))?;
         let json_value: serde_json::Value =
-            serde_json::from_str(&json_str).map_err(Error::external)?;
+            serde_json::from_str(&json_str).map_err(|e| dapi_error("dapi_ini.parse", e))?;
         json_utils::json_to_lua(lua, &json_value)
     })?;
 
@@ -1,9 +1,38 @@
 use json5;
-use mlua::{Error, Lua, Result, Value};
+use mlua::{Lua, Result, Value};
 use serde_json;
 
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
 use crate::utils::json_utils;
 
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_json",
+    description: "JSON",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("decode2", "Parses JSON")
+            .params(&[("json", "string")])
+            .returns(&["any"]),
+        FunctionDoc::new(
+            "decode_with_comments",
+            "Parses JSON5, which allows comments",
+        )
+        .params(&[("json", "string")])
+        .returns(&["any"]),
+        FunctionDoc::new("encode", "Converts a value to JSON")
+            .params(&[("value", "any"), ("pretty", "boolean?")])
+            .returns(&["string"]),
+        FunctionDoc::new("compact_to_pretty", "Formats JSON with indentation")
+            .params(&[("json", "string")])
+            .returns(&["string"]),
+        FunctionDoc::new("pretty_to_compact", "Formats JSON on one line")
+            .params(&[("json", "string")])
+            .returns(&["string"]),
+    ],
+    classes: &[],
+};
+
 // TODO
 // Probably fix this
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
@@ -20,14 +49,15 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     // Decode JSON to a Lua Table
     let json_decode2 = lua.create_function(|lua, json_str: String| {
         let json_value: serde_json::Value =
-            serde_json::from_str(&json_str).map_err(Error::external)?;
+            serde_json::from_str(&json_str).map_err(|e| dapi_error("dapi_json.decode2", e))?;
         Ok(json_utils::json_to_lua(lua, &json_value))
     })?;
 
     // JSON5 decode (unterstützt Kommentare)
     let json_decode_with_comments = lua.create_function(|lua, json_str: String| {
-        let json_value: serde_json::Value = json5::from_str(&json_str).map_err(Error::external)?;
-        Ok(json_utils::json_to_lua(lua, &json_value)?)
+        let json_value: serde_json::Value = json5::from_str(&json_str)
+            .map_err(|e| dapi_error("dapi_json.decode_with_comments", e))?;
+        json_utils::json_to_lua(lua, &json_value)
     })?;
 
     // json encode from lua table
@@ -40,7 +70,7 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
         } else {
             serde_json::to_string(&serde_value)
         }
-        .map_err(Error::external)?;
+        .map_err(|e| dapi_error("dapi_json.encode", e))?;
 
         Ok(json_str)
     })?;
@@ -49,9 +79,10 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     // add function to docs
     // Convert compact JSON -> pretty JSON
     let compact_to_pretty = lua.create_function(|_, json_str: String| {
-        let json_value: serde_json::Value =
-            serde_json::from_str(&json_str).map_err(Error::external)?;
-        let pretty = serde_json::to_string_pretty(&json_value).map_err(Error::external)?;
+        let json_value: serde_json::Value = serde_json::from_str(&json_str)
+            .map_err(|e| dapi_error("dapi_json.compact_to_pretty", e))?;
+        let pretty = serde_json::to_string_pretty(&json_value)
+            .map_err(|e| dapi_error("dapi_json.compact_to_pretty", e))?;
         Ok(pretty)
     })?;
 
@@ -59,9 +90,10 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     // add function to docs
     // Convert pretty JSON -> compact JSON
     let pretty_to_compact = lua.create_function(|_, json_str: String| {
-        let json_value: serde_json::Value =
-            serde_json::from_str(&json_str).map_err(Error::external)?;
-        let compact = serde_json::to_string(&json_value).map_err(Error::external)?;
+        let json_value: serde_json::Value = serde_json::from_str(&json_str)
+            .map_err(|e| dapi_error("dapi_json.pretty_to_compact", e))?;
+        let compact = serde_json::to_string(&json_value)
+            .map_err(|e| dapi_error("dapi_json.pretty_to_compact", e))?;
         Ok(compact)
     })?;
 
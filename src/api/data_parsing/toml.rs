@@ -1,11 +1,28 @@
 use mlua::LuaSerdeExt;
-use mlua::{Error, Lua, Result, String as LuaString, Value};
+use mlua::{Lua, Result, String as LuaString, Value};
 use serde_json::Value as JsonValue;
 use toml;
 
 // Import the Helper Method
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
 use crate::utils::toml_utils::json_to_toml;
 
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_toml",
+    description: "TOML",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("decode", "Parses TOML")
+            .params(&[("toml", "string")])
+            .returns(&["table"]),
+        FunctionDoc::new("encode", "Converts a table to TOML")
+            .params(&[("value", "table")])
+            .returns(&["string"]),
+    ],
+    classes: &[],
+};
+
 // TODO
 // Probably fix this
 /// Registriert das Lua-Modul `toml`
@@ -18,10 +35,13 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
         let toml_str = toml_str.to_str()?; // BorrowedStr
 
         // 2. parse TOML → toml::Value
-        let toml_value: toml::Value = toml::from_str(&toml_str).map_err(Error::external)?;
+        let toml_value: toml::Value =
+            toml::from_str(&toml_str).map_err(|e| dapi_error("dapi_toml.decode", e))?;
 
         // 3. konvertiere zu serde_json::Value
-        let json_value: JsonValue = toml_value.try_into().map_err(Error::external)?;
+        let json_value: JsonValue = toml_value
+            .try_into()
+            .map_err(|e| dapi_error("dapi_toml.decode", e))?;
 
         // 4. konvertiere serde_json::Value → Lua-Value (Tabelle)
         let lua_value = lua.to_value(&json_value)?; // <– das ist korrekt!
@@ -30,13 +50,16 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     })?;
     let encode = lua.create_function(|lua, value: Value| {
         // Lua → serde_json::Value
-        let json_value: JsonValue = lua.from_value(value).map_err(Error::external)?;
+        let json_value: JsonValue = lua
+            .from_value(value)
+            .map_err(|e| dapi_error("dapi_toml.encode", e))?;
 
         // serde_json::Value → toml::Value
-        let toml_value = json_to_toml(json_value).map_err(Error::external)?;
+        let toml_value = json_to_toml(json_value).map_err(|e| dapi_error("dapi_toml.encode", e))?;
 
         // toml::Value → TOML-String
-        let toml_string = toml::to_string(&toml_value).map_err(Error::external)?;
+        let toml_string =
+            toml::to_string(&toml_value).map_err(|e| dapi_error("dapi_toml.encode", e))?;
 
         Ok(toml_string)
     })?;
@@ -169,7 +192,7 @@ mod tests {
         let toml_str: String = encode.call(tbl)?;
         let parsed: toml::Value = toml::from_str(&toml_str).unwrap();
 
-        assert_eq!(parsed["outer"]["nested"]["flag"].as_bool().unwrap(), true);
+        assert!(parsed["outer"]["nested"]["flag"].as_bool().unwrap());
         Ok(())
     }
 
@@ -1,18 +1,36 @@
-use mlua::{Error, Lua, Result, Value};
+use mlua::{Lua, Result, Value};
 use serde_json::Value as JsonValue;
 use xmltree;
 use xmltree::{Element, XMLNode};
 
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
 use crate::utils::json_utils;
 
 type XmlResult<T> = std::result::Result<T, mlua::Error>;
 
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_xml",
+    description: "XML",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("decode", "Parses XML")
+            .params(&[("xml", "string")])
+            .returns(&["table"]),
+        FunctionDoc::new("encode", "Converts a table to XML")
+            .params(&[("value", "table")])
+            .returns(&["string"]),
+    ],
+    classes: &[],
+};
+
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?;
 
     // XML decode: String -> Lua Table
     let xml_decode = lua.create_function(|lua, xml_str: String| {
-        let root = xmltree::Element::parse(xml_str.as_bytes()).map_err(Error::external)?;
+        let root = xmltree::Element::parse(xml_str.as_bytes())
+            .map_err(|e| dapi_error("dapi_xml.decode", e))?;
         let json_value = element_to_value(&root);
         json_utils::json_to_lua(lua, &json_value)
     })?;
@@ -22,8 +40,9 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
         let json_value = json_utils::lua_to_json(&value)?;
         let root = value_to_element("root", &json_value)?;
         let mut writer = Vec::new();
-        root.write(&mut writer).map_err(Error::external)?;
-        let xml_str = String::from_utf8(writer).map_err(Error::external)?;
+        root.write(&mut writer)
+            .map_err(|e| dapi_error("dapi_xml.encode", e))?;
+        let xml_str = String::from_utf8(writer).map_err(|e| dapi_error("dapi_xml.encode", e))?;
         Ok(xml_str)
     })?;
 
@@ -40,9 +59,9 @@ fn value_to_element(name: &str, val: &JsonValue) -> XmlResult<Element> {
     match val {
         JsonValue::Object(map) => {
             for (k, v) in map {
-                if k.starts_with("@") {
+                if let Some(attr) = k.strip_prefix('@') {
                     elem.attributes
-                        .insert(k[1..].to_string(), v.as_str().unwrap_or("").to_string());
+                        .insert(attr.to_string(), v.as_str().unwrap_or("").to_string());
                 } else if k == "#text" {
                     elem.children
                         .push(XMLNode::Text(v.as_str().unwrap_or("").to_string()));
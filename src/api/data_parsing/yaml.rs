@@ -1,8 +1,25 @@
-use mlua::{Error, Lua, Result, Value};
+use mlua::{Lua, Result, Value};
 use serde_yaml;
 
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
 use crate::utils::json_utils; // Du nutzt json_utils für (de)serialization Lua <-> Serde
 
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_yaml",
+    description: "YAML",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("decode", "Parses YAML")
+            .params(&[("yaml", "string")])
+            .returns(&["any"]),
+        FunctionDoc::new("encode", "Converts a value to YAML")
+            .params(&[("value", "any")])
+            .returns(&["string"]),
+    ],
+    classes: &[],
+};
+
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?; // YAML-Tabelle
 
@@ -10,7 +27,7 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let yaml_decode = lua.create_function(|lua, yaml_str: String| {
         // Deserialize YAML directly into serde_json::Value
         let json_value: serde_json::Value =
-            serde_yaml::from_str(&yaml_str).map_err(Error::external)?;
+            serde_yaml::from_str(&yaml_str).map_err(|e| dapi_error("dapi_yaml.decode", e))?;
 
         json_utils::json_to_lua(lua, &json_value)
     })?;
@@ -18,7 +35,8 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     // Encode Lua Table to YAML string
     let yaml_encode = lua.create_function(|_, value: Value| {
         let serde_value = json_utils::lua_to_json(&value)?; // unterstützt auch yaml::Value kompatibel
-        let yaml_str = serde_yaml::to_string(&serde_value).map_err(Error::external)?;
+        let yaml_str =
+            serde_yaml::to_string(&serde_value).map_err(|e| dapi_error("dapi_yaml.encode", e))?;
         Ok(yaml_str)
     })?;
 
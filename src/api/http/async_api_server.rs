@@ -1,105 +1,126 @@
 use mlua::{Function, Lua, Result, Table, Value as LuaValue};
 use serde_json::Value as JsonValue;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tokio::sync::{mpsc, oneshot};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+use tokio::sync::oneshot;
 use warp::filters::BoxedFilter;
 use warp::http::{Response, StatusCode};
 use warp::hyper::Body;
 use warp::{Filter, Rejection, Reply};
 
+use crate::api::http::server_controls::server_controls;
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
+use crate::helper::exit_code;
+use crate::helper::limits::{Limits, with_limits};
+use crate::helper::permissions::check_net;
 use crate::utils::json_utils::lua_to_json;
 
+// port, routes and the default limits of the routes
+type StartArgs = (u16, Option<Table>, Option<Table>);
+
+// A handler which hangs in a native call gets this long after its timeout
+// before the request is answered with 503
+const TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+
 enum LuaRequest {
     Call {
         route: String,
-        resp_tx: oneshot::Sender<std::result::Result<JsonValue, String>>,
+        resp_tx: oneshot::Sender<std::result::Result<JsonValue, (StatusCode, String)>>,
     },
 }
 
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_api_async",
+    description: "A JSON API server with Lua handlers",
+    fields: &[],
+    functions: &[FunctionDoc::new(
+        "start_api_server",
+        "Serves `/api/<name>` with the handler functions of the routes, a route may be `{ handler = fn, timeout = \"2s\" }`. The options are default limits. The handlers run one after another, a slow handler delays all routes",
+    )
+    .params(&[("port", "integer"), ("routes", "table?"), ("options", "table?")])],
+    classes: &[],
+};
+
 pub fn register(lua: &Lua) -> Result<Table> {
-    let server_controls: Arc<Mutex<HashMap<u16, oneshot::Sender<()>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    // Shared with dapi.exit() of the state to stop its servers before exiting
+    let server_controls = server_controls(lua);
     let table = lua.create_table()?;
-    let lua = Arc::new(lua.clone()); // Arc<Lua> für sichere Multi-Thread Nutzung
 
-    let lua = Arc::new(lua.clone());
     // --- Start API Server ---
     let start_api_server = {
         let server_controls = Arc::clone(&server_controls);
-        let lua2 = lua.clone(); // <-- lua wird hier geklont
 
-        lua2.create_function(move |_, (port, handlers_table): (u16, Option<Table>)| {
-            let lua = Arc::clone(&lua);
-            let (shutdown_tx, shutdown_rx) = oneshot::channel();
-            server_controls.lock().unwrap().insert(port, shutdown_tx);
+        lua.create_function(move |lua, (port, handlers_table, options): StartArgs| {
+            check_net(lua, &format!("0.0.0.0:{}", port))?;
 
             // Lua-Routen sammeln
-            let mut handlers: HashMap<String, Function> = HashMap::new();
+            let defaults = match &options {
+                Some(options) => Limits::from_table(options)?,
+                None => Limits::default(),
+            };
+            let mut handlers: HashMap<String, (Function, Limits)> = HashMap::new();
             if let Some(table) = handlers_table {
                 for pair in table.pairs::<String, LuaValue>() {
                     let (route, value) = pair?;
-                    let func: Function = match value {
-                        LuaValue::Function(f) => f,
-                        LuaValue::String(s_val) => {
-                            let src: &str = &s_val.to_str()?;
-                            if src.trim_start().starts_with("function") {
-                                match lua.load(src).eval()? {
-                                    LuaValue::Function(f) => f,
-                                    other => {
-                                        return Err(mlua::Error::external(format!(
-                                            "Route '{}' must return a function; got {:?}",
-                                            route, other
-                                        )));
-                                    }
-                                }
-                            } else {
-                                let wrapped = format!("return ({});", src);
-                                let val: LuaValue = lua.load(&wrapped).eval()?;
-                                lua.create_function(move |_, ()| Ok(val.clone()))?
-                            }
-                        }
-                        LuaValue::Nil => lua.create_function(|_, ()| Ok(LuaValue::Nil))?,
-                        other => {
-                            return Err(mlua::Error::external(format!(
-                                "Route '{}' invalid type: {:?}",
-                                route, other
-                            )));
+                    let handler = match value {
+                        // { handler = function() ... end, timeout = "2s" }
+                        LuaValue::Table(route_table) => {
+                            let func = route_handler(lua, &route, route_table.get("handler")?)?;
+                            let limits = Limits::from_table(&route_table)?.or(defaults);
+                            (func, limits)
                         }
+                        value => (route_handler(lua, &route, value)?, defaults),
                     };
-                    handlers.insert(route, func);
+                    handlers.insert(route, handler);
                 }
             }
 
-            let (lua_tx, mut lua_rx) = mpsc::unbounded_channel::<LuaRequest>();
-            let handlers_clone = handlers.clone();
-
-            // Dispatcher-Task für Lua-Handler
-            let dispatcher = async move {
-                while let Some(req) = lua_rx.recv().await {
-                    if let LuaRequest::Call { route, resp_tx } = req {
-                        let res = (|| {
-                            if let Some(func) = handlers_clone.get(&route) {
-                                let val: LuaValue = func.call(())?;
-                                let json = lua_to_json(&val)?;
-                                Ok(json)
-                            } else {
-                                Ok(serde_json::json!({"error": "route not found"}))
-                            }
-                        })()
-                        .map_err(|e: mlua::Error| format!("{:?}", e));
-                        let _ = resp_tx.send(res);
+            // Registered only once the routes are valid, so dapi.exit() finds
+            // no server which never started
+            let (shutdown_tx, shutdown_rx) = oneshot::channel();
+            server_controls.lock().unwrap().insert(port, shutdown_tx);
+
+            let (lua_tx, lua_rx) = mpsc::channel::<LuaRequest>();
+
+            // The handlers run on this thread outside of any runtime, so they
+            // can wait with dapi_async and the server can start in a task
+            let dispatcher = || {
+                while let Ok(req) = lua_rx.recv() {
+                    let LuaRequest::Call { route, resp_tx } = req;
+                    // Given up while an earlier handler hung
+                    if resp_tx.is_closed() {
+                        continue;
                     }
+                    let res = (|| {
+                        if let Some((func, limits)) = handlers.get(&route) {
+                            let val: LuaValue = with_limits(lua, limits, || func.call(()))?;
+                            let json = lua_to_json(&val)?;
+                            Ok(json)
+                        } else {
+                            Ok(serde_json::json!({"error": "route not found"}))
+                        }
+                    })()
+                    .map_err(|e: mlua::Error| {
+                        // A handler which ran into its limits is unavailable,
+                        // the server keeps running
+                        if exit_code::for_lua_error(&e) == exit_code::LIMIT_EXCEEDED {
+                            (StatusCode::SERVICE_UNAVAILABLE, e.to_string())
+                        } else {
+                            (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e))
+                        }
+                    });
+                    let _ = resp_tx.send(res);
                 }
             };
 
             // Warp Filter pro Route
             let mut filters: Vec<BoxedFilter<(Response<Body>,)>> = Vec::new();
-            let handlers_arc = Arc::new(handlers);
-
-            for route_name in handlers_arc.keys() {
+            for route_name in handlers.keys() {
                 let lua_tx_clone = lua_tx.clone();
                 let route_name_clone = route_name.clone();
+                let cutoff = handlers[route_name].1.timeout.map(|t| t.saturating_add(TIMEOUT_GRACE));
 
                 let route = warp::path("api")
                     .and(warp::path(route_name_clone.clone()))
@@ -113,20 +134,38 @@ pub fn register(lua: &Lua) -> Result<Table> {
                             let (resp_tx, resp_rx) = oneshot::channel();
                             lua_tx_clone
                                 .send(LuaRequest::Call {
-                                    route: route_name_clone,
+                                    route: route_name_clone.clone(),
                                     resp_tx,
                                 })
                                 .map_err(|_| warp::reject())?;
 
-                            match resp_rx.await {
+                            // The hook can not stop a native call, so the
+                            // request is abandoned after the timeout
+                            let answer = match cutoff {
+                                Some(cutoff) => match tokio::time::timeout(cutoff, resp_rx).await
+                                {
+                                    Ok(answer) => answer,
+                                    Err(_) => {
+                                        return Ok(warp::reply::with_status(
+                                            format!(
+                                                "Limit exceeded: the route '{}' did not answer in time",
+                                                route_name_clone
+                                            ),
+                                            StatusCode::SERVICE_UNAVAILABLE,
+                                        )
+                                        .into_response());
+                                    }
+                                },
+                                None => resp_rx.await,
+                            };
+
+                            match answer {
                                 Ok(Ok(json)) => Ok::<Response<Body>, Rejection>(
                                     warp::reply::json(&json).into_response(),
                                 ),
-                                Ok(Err(err_msg)) => Ok(warp::reply::with_status(
-                                    err_msg,
-                                    StatusCode::INTERNAL_SERVER_ERROR,
-                                )
-                                .into_response()),
+                                Ok(Err((status, err_msg))) => {
+                                    Ok(warp::reply::with_status(err_msg, status).into_response())
+                                }
                                 Err(_) => Ok(warp::reply::with_status(
                                     "Lua task dropped",
                                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -157,25 +196,44 @@ pub fn register(lua: &Lua) -> Result<Table> {
                 port
             );
 
-            // Synchron blockieren
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap();
+            // Only the routes keep senders, the dispatcher ends with the server
+            drop(lua_tx);
 
-            rt.block_on(async {
-                let port3 = port.clone();
-                let (_, server_future) = warp::serve(combined).bind_with_graceful_shutdown(
-                    ([0, 0, 0, 0], port3),
-                    async move {
-                        shutdown_rx.await.ok();
-                        println!("API server on port {} stopped", port3);
-                    },
-                );
-
-                server_future.await;
+            // The server runs on its own thread, so it still answers while a
+            // handler hangs in a native call. The Lua state can not be shared
+            // between threads, the handlers run on this one
+            let server = std::thread::spawn(move || {
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                rt.block_on(async {
+                    let (_, server_future) = warp::serve(combined)
+                        .try_bind_with_graceful_shutdown(([0, 0, 0, 0], port), async move {
+                            shutdown_rx.await.ok();
+                            println!("API server on port {} stopped", port);
+                        })
+                        .map_err(|e| e.to_string())?;
+                    server_future.await;
+                    Ok::<(), String>(())
+                })
             });
 
+            dispatcher();
+
+            let joined = server.join();
+            server_controls.lock().unwrap().remove(&port);
+            match joined {
+                Ok(result) => result
+                    .map_err(|e| dapi_error("dapi_api_async.start_api_server", e))?,
+                Err(_) => {
+                    return Err(dapi_error(
+                        "dapi_api_async.start_api_server",
+                        "the server thread panicked",
+                    ));
+                }
+            }
+
             Ok(())
         })
     }?;
@@ -184,3 +242,156 @@ pub fn register(lua: &Lua) -> Result<Table> {
 
     Ok(table)
 }
+
+// Function of a route: a function, the code of a function, an expression
+// whose value is returned or nil
+fn route_handler(lua: &Lua, route: &str, value: LuaValue) -> Result<Function> {
+    let func = match value {
+        LuaValue::Function(f) => f,
+        LuaValue::String(s_val) => {
+            let src: &str = &s_val.to_str()?;
+            if src.trim_start().starts_with("function") {
+                match lua.load(src).eval()? {
+                    LuaValue::Function(f) => f,
+                    other => {
+                        return Err(dapi_error(
+                            "dapi_api_async.start_api_server",
+                            format!(
+                                "Route '{}' must return a function, got {}",
+                                route,
+                                other.type_name()
+                            ),
+                        ));
+                    }
+                }
+            } else {
+                let wrapped = format!("return ({});", src);
+                let val: LuaValue = lua.load(&wrapped).eval()?;
+                lua.create_function(move |_, ()| Ok(val.clone()))?
+            }
+        }
+        LuaValue::Nil => lua.create_function(|_, ()| Ok(LuaValue::Nil))?,
+        other => {
+            return Err(dapi_error(
+                "dapi_api_async.start_api_server",
+                format!(
+                    "Route '{}' has the invalid type {}",
+                    route,
+                    other.type_name()
+                ),
+            ));
+        }
+    };
+    Ok(func)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::http::server_controls::server_controls;
+    use crate::helper::permissions::Permissions;
+    use crate::lua_script::new_state;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Instant;
+
+    fn get(port: u16, route: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(
+            stream,
+            "GET /api/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            route
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    // Runs the script with `{port}` replaced on its own thread, the server
+    // never stops and the thread ends with the tests
+    fn serve(script: &'static str) -> u16 {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        std::thread::spawn(move || {
+            let lua = new_state(&Permissions::allow_all(), &[]).unwrap();
+            lua.load(script.replace("{port}", &port.to_string()))
+                .exec()
+                .unwrap();
+        });
+
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(started.elapsed().as_secs() < 5, "the server did not start");
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        port
+    }
+
+    #[test]
+    fn route_blocked_in_a_native_call_answers_503() {
+        let port = serve(
+            r#"
+            local time = require("dapi_time")
+            require("dapi_api_async").start_api_server({port}, {
+                slow = { handler = function() time.wait(5000) end, timeout = "100ms" },
+            })
+            "#,
+        );
+
+        let started = Instant::now();
+        let response = get(port, "slow");
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert!(response.contains("did not answer in time"), "{}", response);
+        assert!(started.elapsed().as_secs() < 4);
+    }
+
+    #[test]
+    fn handlers_can_sleep() {
+        let port = serve(
+            r#"
+            local async = require("dapi_async")
+            require("dapi_api_async").start_api_server({port}, {
+                nap = function() async.sleep(10) return "rested" end,
+            })
+            "#,
+        );
+
+        let response = get(port, "nap");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("rested"), "{}", response);
+    }
+
+    #[test]
+    fn server_in_a_task() {
+        let port = serve(
+            r#"
+            local async = require("dapi_async")
+            async.spawn(function()
+                require("dapi_api_async").start_api_server({port}, {
+                    hi = function() return "from a task" end,
+                })
+            end)
+            async.run()
+            "#,
+        );
+
+        let response = get(port, "hi");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("from a task"), "{}", response);
+    }
+
+    #[test]
+    fn invalid_routes_register_no_server() {
+        let lua = new_state(&Permissions::allow_all(), &[]).unwrap();
+        let err = lua
+            .load(r#"require("dapi_api_async").start_api_server(65002, { bad = 5 })"#)
+            .exec()
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid type"), "{}", err);
+        assert!(!server_controls(&lua).lock().unwrap().contains_key(&65002));
+    }
+}
@@ -1,20 +1,41 @@
 use mlua::{Lua, Result};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use tokio::sync::oneshot;
 use warp::Filter;
 
+use crate::api::http::server_controls::server_controls;
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::permissions::{check_net, check_read};
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_http_async",
+    description: "Static file servers in the background",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new(
+            "start_static_server",
+            "Serves the files of a directory in the background",
+        )
+        .params(&[("directory", "string"), ("port", "integer")]),
+        FunctionDoc::new("stop_static_server", "Stops the server on the port")
+            .params(&[("port", "integer")]),
+    ],
+    classes: &[],
+};
+
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
-    // Arc-Mutex für Zugriff aus Lua auf aktive Server
-    let server_controls: Arc<Mutex<HashMap<u16, oneshot::Sender<()>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    // Shared with dapi.exit() of the state to stop its servers before exiting
+    let server_controls = server_controls(lua);
 
     let table = lua.create_table()?;
 
     // Directory, Port
     let start_static_server = {
         let server_controls = Arc::clone(&server_controls);
-        lua.create_function(move |_, (directory, port): (String, u16)| {
+        lua.create_function(move |lua, (directory, port): (String, u16)| {
+            check_net(lua, &format!("0.0.0.0:{}", port))?;
+            check_read(lua, &directory)?;
+
             let (shutdown_tx, shutdown_rx) = oneshot::channel();
             server_controls.lock().unwrap().insert(port, shutdown_tx);
 
@@ -4,6 +4,21 @@ use std::path::Path;
 // use std::sync::{Arc, Mutex};
 use warp::Filter;
 
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::permissions::{check_net, check_read};
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_http",
+    description: "A static file server",
+    fields: &[],
+    functions: &[FunctionDoc::new(
+        "start_static_server",
+        "Serves the files of a directory until Enter is pressed",
+    )
+    .params(&[("directory", "string"), ("port", "integer")])],
+    classes: &[],
+};
+
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?;
 
@@ -58,7 +73,10 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     // "/" is the System Root Directory
     let start_static_server = {
         // let api_handlers = Arc::clone(&api_handlers);
-        lua.create_function(move |_, (directory, port): (String, u16)| {
+        lua.create_function(move |lua, (directory, port): (String, u16)| {
+            check_net(lua, &format!("0.0.0.0:{}", port))?;
+            check_read(lua, &directory)?;
+
             let dir = directory.clone();
 
             // // Verzeichnisinhalt beim Start auflisten
@@ -94,7 +112,7 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
             // Debug to Check to Directory Content
             println!("Content from '{}':", dir);
             list_files_direct(&dir);
-            println!("");
+            println!();
 
             std::thread::spawn(move || {
                 let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
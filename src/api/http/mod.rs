@@ -1,3 +1,5 @@
 pub mod async_api_server;
 pub mod async_server;
+#[allow(clippy::module_inception)]
 pub mod http;
+pub mod server_controls;
@@ -0,0 +1,103 @@
+// Shutdown channels of all servers which were started from Lua
+//
+// Every Lua state has its own controls, dapi.exit() only stops the servers of
+// its state. Watch mode and the exit of the process stop all of them
+
+use mlua::Lua;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use tokio::sync::oneshot;
+
+type Servers = Mutex<HashMap<u16, oneshot::Sender<()>>>;
+
+pub type ServerControls = Arc<Servers>;
+
+// The controls of every Lua state which registered a server module
+fn registry() -> &'static Mutex<Vec<Weak<Servers>>> {
+    static REGISTRY: OnceLock<Mutex<Vec<Weak<Servers>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+// The controls of the Lua state, stored as app data
+pub fn server_controls(lua: &Lua) -> ServerControls {
+    if let Some(controls) = lua.app_data_ref::<ServerControls>() {
+        return Arc::clone(&controls);
+    }
+
+    let controls = ServerControls::default();
+    let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    registry.retain(|controls| controls.strong_count() > 0);
+    registry.push(Arc::downgrade(&controls));
+    lua.set_app_data(Arc::clone(&controls));
+    controls
+}
+
+// Signals the servers of the controls to stop, returns their ports
+fn stop(controls: &ServerControls) -> Vec<u16> {
+    let mut controls = controls.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut ports = Vec::new();
+    for (port, shutdown) in controls.drain() {
+        let _ = shutdown.send(());
+        ports.push(port);
+    }
+    ports.sort();
+    ports
+}
+
+// Signals the servers of one Lua state to stop, returns their ports
+pub fn stop_state(lua: &Lua) -> Vec<u16> {
+    let controls = lua.app_data_ref::<ServerControls>().map(|c| Arc::clone(&c));
+    controls.map(|controls| stop(&controls)).unwrap_or_default()
+}
+
+// Signals every running server of the process to stop, returns their ports
+pub fn stop_all() -> Vec<u16> {
+    let states: Vec<ServerControls> = registry()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+
+    let mut ports: Vec<u16> = states.iter().flat_map(stop).collect();
+    ports.sort();
+    ports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::exit_code::create_exit_function;
+
+    #[test]
+    fn stop_all_signals_servers() {
+        let lua = Lua::new();
+        let (tx, mut rx) = oneshot::channel();
+        server_controls(&lua).lock().unwrap().insert(65001, tx);
+
+        assert!(stop_all().contains(&65001));
+        assert!(rx.try_recv().is_ok());
+        assert!(!server_controls(&lua).lock().unwrap().contains_key(&65001));
+    }
+
+    #[test]
+    fn exit_only_stops_the_servers_of_its_state() {
+        let (first, second) = (Lua::new(), Lua::new());
+        let (tx, mut stopped) = oneshot::channel();
+        server_controls(&first).lock().unwrap().insert(65003, tx);
+        let (tx, mut running) = oneshot::channel();
+        server_controls(&second).lock().unwrap().insert(65004, tx);
+
+        let exit = create_exit_function(&first).unwrap();
+        assert!(exit.call::<()>(0).is_err());
+        assert!(stopped.try_recv().is_ok());
+        assert!(running.try_recv().is_err());
+        assert!(
+            server_controls(&second)
+                .lock()
+                .unwrap()
+                .contains_key(&65004)
+        );
+    }
+}
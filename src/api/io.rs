@@ -15,75 +15,124 @@ use dirs_next::{
     download_dir, home_dir, picture_dir, video_dir,
 };
 
-use crate::deprecated;
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::bundle::read_asset;
+use crate::helper::deprecation;
 
+use crate::helper::dapi_error::dapi_error;
 use crate::helper::dir::copy_dir_recursive;
+use crate::helper::permissions::{check_read, check_write};
+use crate::watch::track_read;
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_io",
+    description: "Files, directories and zip archives",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("zip", "Packs the directory `src` into the zip file `dest`")
+            .params(&[("src", "string"), ("dest", "string")]),
+        FunctionDoc::new("unzip", "Extracts the zip file into the directory `dest`")
+            .params(&[("zip_file", "string"), ("dest", "string")]),
+        FunctionDoc::new(
+            "get_default_directories",
+            "Directories of the user like `home`, `downloads`, `config` or `cache`",
+        )
+        .returns(&["table<string, string>"]),
+        FunctionDoc::new("create_dir", "Creates a directory and its parents")
+            .params(&[("dir", "string")]),
+        FunctionDoc::new("delete_dir", "Deletes a directory with all its content")
+            .params(&[("dir", "string")]),
+        FunctionDoc::new("copy_file", "Copies a file")
+            .params(&[("from", "string"), ("to", "string")]),
+        FunctionDoc::new("copy_dir", "Copies a directory with all its content")
+            .params(&[("from", "string"), ("to", "string")]),
+        FunctionDoc::new("create_file", "Creates an empty file").params(&[("file", "string")]),
+        FunctionDoc::new("write_file", "Writes the content into a file")
+            .params(&[("file", "string"), ("content", "string")]),
+        FunctionDoc::new("rf", "Reads a file, also an asset of `flua build`")
+            .params(&[("path", "string")])
+            .returns(&["string"]),
+        FunctionDoc::new("append_file", "Appends the content to a file")
+            .params(&[("file", "string"), ("content", "string")]),
+        FunctionDoc::new("get_folder_content", "Names of the entries of a directory")
+            .params(&[("path", "string")])
+            .returns(&["string[]"]),
+        FunctionDoc::new("get_file_size", "Size of a file in bytes")
+            .params(&[("path", "string")])
+            .returns(&["integer"]),
+        FunctionDoc::new("read_line", "The lines of a file, at most `max_lines`")
+            .params(&[("file", "string"), ("max_lines", "integer?")])
+            .returns(&["string[]"]),
+    ],
+    classes: &[],
+};
 
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?;
 
     // ZIP-Funktion
-    let zip = lua.create_function(|_, (src, dest): (String, String)| {
-        deprecated!(
-            "dapi_io.zip",
-            "0.1.10",
-            "The function could go horribly wrong, use at your own risk!"
-        );
+    let zip = lua.create_function(|lua, (src, dest): (String, String)| {
+        deprecation::used("dapi_io.zip")?;
+        check_read(lua, &src)?;
+        check_write(lua, &dest)?;
+        track_read(lua, &src);
 
         // Validieren der Pfade (validate_path -> PathBuf)
         let src_path = validate_path(&src)
-            .map_err(|e| mlua::Error::external(format!("Ungültiger Quellpfad: {}", e)))?;
+            .map_err(|e| dapi_error("dapi_io.zip", format!("Invalid source path: {}", e)))?;
         let dest_path = validate_path(&dest)
-            .map_err(|e| mlua::Error::external(format!("Ungültiger Zielpfad: {}", e)))?;
+            .map_err(|e| dapi_error("dapi_io.zip", format!("Invalid destination path: {}", e)))?;
 
         // Existenz- / Typprüfung
         if !src_path.exists() || !src_path.is_dir() {
-            return Err(mlua::Error::external(
-                "Quellverzeichnis existiert nicht oder ist kein Verzeichnis.",
+            return Err(dapi_error(
+                "dapi_io.zip",
+                format!("The source '{}' is not a directory", src),
             ));
         }
 
         // Konvertiere PathBuf -> &str (UTF-8 prüfen)
         let src_str = src_path
             .to_str()
-            .ok_or_else(|| mlua::Error::external("Quellpfad enthält ungültige UTF-8-Zeichen."))?;
+            .ok_or_else(|| dapi_error("dapi_io.zip", "The source path is not valid UTF-8"))?;
         let dest_str = dest_path
             .to_str()
-            .ok_or_else(|| mlua::Error::external("Zielpfad enthält ungültige UTF-8-Zeichen."))?;
+            .ok_or_else(|| dapi_error("dapi_io.zip", "The destination path is not valid UTF-8"))?;
 
-        zip_dir(src_str, dest_str).map_err(|e| mlua::Error::external(format!("Zip-Fehler: {}", e)))
+        zip_dir(src_str, dest_str)
+            .map_err(|e| dapi_error("dapi_io.zip", format!("Could not create the zip: {}", e)))
     })?;
 
     // UNZIP-Funktion
-    let unzip = lua.create_function(|_, (zip_file, dest): (String, String)| {
-        deprecated!(
-            "dapi_io.unzip",
-            "0.1.10",
-            "The function could go horribly wrong, use at your own risk!"
-        );
+    let unzip = lua.create_function(|lua, (zip_file, dest): (String, String)| {
+        deprecation::used("dapi_io.unzip")?;
+        check_read(lua, &zip_file)?;
+        check_write(lua, &dest)?;
+        track_read(lua, &zip_file);
 
         let zip_path = validate_path(&zip_file)
-            .map_err(|e| mlua::Error::external(format!("Ungültiger Zip-Pfad: {}", e)))?;
+            .map_err(|e| dapi_error("dapi_io.unzip", format!("Invalid zip path: {}", e)))?;
         let dest_path = validate_path(&dest)
-            .map_err(|e| mlua::Error::external(format!("Ungültiger Zielpfad: {}", e)))?;
+            .map_err(|e| dapi_error("dapi_io.unzip", format!("Invalid destination path: {}", e)))?;
 
         // ZIP-Datei muss existieren und eine Datei sein
         if !zip_path.exists() || !zip_path.is_file() {
-            return Err(mlua::Error::external(
-                "ZIP-Datei existiert nicht oder ist keine Datei.",
+            return Err(dapi_error(
+                "dapi_io.unzip",
+                format!("The zip file '{}' does not exist", zip_file),
             ));
         }
 
         // Konvertiere PathBuf -> &str (UTF-8 prüfen)
         let zip_str = zip_path
             .to_str()
-            .ok_or_else(|| mlua::Error::external("ZIP-Pfad enthält ungültige UTF-8-Zeichen."))?;
-        let dest_str = dest_path
-            .to_str()
-            .ok_or_else(|| mlua::Error::external("Zielpfad enthält ungültige UTF-8-Zeichen."))?;
+            .ok_or_else(|| dapi_error("dapi_io.unzip", "The zip path is not valid UTF-8"))?;
+        let dest_str = dest_path.to_str().ok_or_else(|| {
+            dapi_error("dapi_io.unzip", "The destination path is not valid UTF-8")
+        })?;
 
         unzip_file(zip_str, dest_str)
-            .map_err(|e| mlua::Error::external(format!("Unzip-Fehler: {}", e)))
+            .map_err(|e| dapi_error("dapi_io.unzip", format!("Could not extract the zip: {}", e)))
     })?;
 
     // Functions to get the default directories, returns a Lua Tale
@@ -111,120 +160,142 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     })?;
 
     // Create a directory
-    let create_dir = lua.create_function(|_, dir: String| {
+    let create_dir = lua.create_function(|lua, dir: String| {
+        check_write(lua, &dir)?;
         fs::create_dir_all(Path::new(&dir))
-            .map_err(|e| mlua::Error::external(format!("Create dir error: {}", e)))
+            .map_err(|e| dapi_error("dapi_io.create_dir", format!("'{}': {}", dir, e)))
     })?;
 
     // Delete a directory recursively
-    let delete_dir = lua.create_function(|_, dir: String| {
+    let delete_dir = lua.create_function(|lua, dir: String| {
+        check_write(lua, &dir)?;
         fs::remove_dir_all(Path::new(&dir))
-            .map_err(|e| mlua::Error::external(format!("Delete dir error: {}", e)))
+            .map_err(|e| dapi_error("dapi_io.delete_dir", format!("'{}': {}", dir, e)))
     })?;
 
     // Copy a file
-    let copy_file = lua.create_function(|_, (from, to): (String, String)| {
+    let copy_file = lua.create_function(|lua, (from, to): (String, String)| {
+        check_read(lua, &from)?;
+        check_write(lua, &to)?;
+        track_read(lua, &from);
         fs::copy(Path::new(&from), Path::new(&to))
             .map(|_| ()) // Ignore number of bytes copied
-            .map_err(|e| mlua::Error::external(format!("Copy file error: {}", e)))
+            .map_err(|e| {
+                dapi_error(
+                    "dapi_io.copy_file",
+                    format!("'{}' to '{}': {}", from, to, e),
+                )
+            })
     })?;
 
     // Copy Dir
-    let copy_dir = lua.create_function(|_, (from, to): (String, String)| {
-        copy_dir_recursive(Path::new(&from), Path::new(&to))
-            .map_err(|e| mlua::Error::external(format!("Copy directory error: {}", e)))?;
+    let copy_dir = lua.create_function(|lua, (from, to): (String, String)| {
+        check_read(lua, &from)?;
+        check_write(lua, &to)?;
+        track_read(lua, &from);
+        copy_dir_recursive(Path::new(&from), Path::new(&to)).map_err(|e| {
+            dapi_error("dapi_io.copy_dir", format!("'{}' to '{}': {}", from, to, e))
+        })?;
         Ok(())
     })?;
 
     // Create a file
-    let create_file = lua.create_function(|_, file: String| {
-        deprecated!(
-            "dapi_io.create_file",
-            "0.1.10",
-            "The function is although contained in the Lua STD"
-        );
+    let create_file = lua.create_function(|lua, file: String| {
+        deprecation::used("dapi_io.create_file")?;
+        check_write(lua, &file)?;
         fs::File::create(Path::new(&file))
             .map(|_| ())
-            .map_err(|e| mlua::Error::external(format!("Create file error: {}", e)))
+            .map_err(|e| dapi_error("dapi_io.create_file", format!("'{}': {}", file, e)))
     })?;
 
     // Write Data to a file
-    let write_file = lua.create_function(|_, (file, content): (String, String)| {
-        deprecated!(
-            "dapi_io.write_file",
-            "0.1.10",
-            "The function is although contained in the Lua STD"
-        );
+    let write_file = lua.create_function(|lua, (file, content): (String, String)| {
+        deprecation::used("dapi_io.write_file")?;
+        check_write(lua, &file)?;
         fs::write(Path::new(&file), &content)
             .map(|_| ())
-            .map_err(|e| mlua::Error::external(format!("Write file error: {}", e)))
+            .map_err(|e| dapi_error("dapi_io.write_file", format!("'{}': {}", file, e)))
     })?;
 
     // Funktion to read a file and return the content as a String
-    let rf = lua.create_function(
-        |_, path: String| match fs::read_to_string(Path::new(&path)) {
-            Ok(content) => Ok(content),
-            Err(e) => Err(mlua::Error::external(e)),
-        },
-    )?;
+    let rf = lua.create_function(|lua, path: String| {
+        // Assets of a `flua build` executable
+        if let Some(content) = read_asset(lua, &path) {
+            return Ok(String::from_utf8_lossy(&content).to_string());
+        }
+        check_read(lua, &path)?;
+        track_read(lua, &path);
+        fs::read_to_string(Path::new(&path))
+            .map_err(|e| dapi_error("dapi_io.rf", format!("'{}': {}", path, e)))
+    })?;
 
     // Function to append data to the file
-    let append_file = lua.create_function(|_, (file, content): (String, String)| {
+    let append_file = lua.create_function(|lua, (file, content): (String, String)| {
+        check_write(lua, &file)?;
+
         // Datei im Append-Modus öffnen (oder erstellen, wenn sie nicht existiert)
         let mut f = OpenOptions::new()
             .create(true)
             .append(true)
             .open(Path::new(&file))
-            .map_err(|e| mlua::Error::external(format!("Datei-Fehler: {}", e)))?;
+            .map_err(|e| dapi_error("dapi_io.append_file", format!("'{}': {}", file, e)))?;
 
         // Inhalt anhängen
         f.write_all(content.as_bytes())
-            .map_err(|e| mlua::Error::external(format!("Schreib-Fehler: {}", e)))?;
+            .map_err(|e| dapi_error("dapi_io.append_file", format!("'{}': {}", file, e)))?;
 
         Ok(())
     })?;
 
     // Function get the content of a Folder as an Array
     let get_folder_content = lua.create_function(|lua_ctx, path: String| {
-        let entries = fs::read_dir(Path::new(&path)).map_err(mlua::Error::external)?;
+        check_read(lua_ctx, &path)?;
+        let entries = fs::read_dir(Path::new(&path))
+            .map_err(|e| dapi_error("dapi_io.get_folder_content", format!("'{}': {}", path, e)))?;
 
         let lua_table = lua_ctx.create_table()?; // neue Lua-Tabelle
-        let mut index = 1;
 
-        for entry in entries {
-            let entry = entry.map_err(mlua::Error::external)?;
+        for (index, entry) in entries.enumerate() {
+            let entry = entry.map_err(|e| {
+                dapi_error("dapi_io.get_folder_content", format!("'{}': {}", path, e))
+            })?;
             let file_name = entry.file_name();
             let file_name_str = file_name.to_string_lossy();
 
-            lua_table.set(index, file_name_str)?;
-            index += 1;
+            lua_table.set(index + 1, file_name_str)?;
         }
 
         Ok(lua_table)
     })?;
 
     // Function to get the size of an file
-    let get_file_size = lua.create_function(|_, path: String| {
+    let get_file_size = lua.create_function(|lua, path: String| {
+        check_read(lua, &path)?;
         fs::metadata(Path::new(&path))
             .map(|metadata| metadata.len())
-            .map_err(|e| mlua::Error::external(format!("Failed to get file size: {}", e)))
+            .map_err(|e| dapi_error("dapi_io.get_file_size", format!("'{}': {}", path, e)))
     })?;
 
     // Function to read a file line by line
     let read_line = lua.create_function(|lua, (file, max_lines): (String, Option<usize>)| {
-        let file = fs::File::open(Path::new(&file))
-            .map_err(|e| mlua::Error::external(format!("Open file error: {}", e)))?;
-        let reader = io::BufReader::new(file);
+        let reader: Box<dyn BufRead> = match read_asset(lua, &file) {
+            Some(content) => Box::new(io::Cursor::new(content)),
+            None => {
+                check_read(lua, &file)?;
+                track_read(lua, &file);
+                let handle = fs::File::open(Path::new(&file))
+                    .map_err(|e| dapi_error("dapi_io.read_line", format!("'{}': {}", file, e)))?;
+                Box::new(io::BufReader::new(handle))
+            }
+        };
         let lua_table = lua.create_table()?;
 
         for (i, line_result) in reader.lines().enumerate() {
-            if let Some(max) = max_lines {
-                if i >= max {
-                    break;
-                }
+            if max_lines.is_some_and(|max| i >= max) {
+                break;
             }
             let line = line_result
-                .map_err(|e| mlua::Error::external(format!("Read line error: {}", e)))?;
+                .map_err(|e| dapi_error("dapi_io.read_line", format!("'{}': {}", file, e)))?;
             lua_table.set(i + 1, line)?;
         }
 
@@ -0,0 +1,489 @@
+// Metadata of the dapi modules
+//
+// Every module describes its functions in a DOCS constant next to its register
+// function: parameters, return values and a description, deprecations come
+// from helper/deprecation.rs. The descriptions are the source of `flua stubs`
+// and of dapi.help(). Types are written like in LuaLS annotations, optional
+// ones end with '?'
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::api::http::{async_api_server, async_server, http};
+use crate::api::{async_loop, base, data_parsing, io, net, os, test, thread, time};
+use crate::helper::deprecation::{self, Deprecation};
+
+pub struct FunctionDoc {
+    pub name: &'static str,
+    pub description: &'static str,
+    // Name and type, a `...` parameter takes any number of values
+    pub params: &'static [(&'static str, &'static str)],
+    pub returns: &'static [&'static str],
+}
+
+impl FunctionDoc {
+    pub const fn new(name: &'static str, description: &'static str) -> Self {
+        FunctionDoc {
+            name,
+            description,
+            params: &[],
+            returns: &[],
+        }
+    }
+
+    pub const fn params(mut self, params: &'static [(&'static str, &'static str)]) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub const fn returns(mut self, returns: &'static [&'static str]) -> Self {
+        self.returns = returns;
+        self
+    }
+
+    // `prefix` is like `dapi_io.` for functions or `worker:` for methods
+    pub fn signature(&self, prefix: &str) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|(name, ty)| match *name {
+                "..." => format!("...: {}", ty),
+                name => format!("{}: {}", name, ty),
+            })
+            .collect();
+
+        let mut signature = format!("{}{}({})", prefix, self.name, params.join(", "));
+        if !self.returns.is_empty() {
+            let _ = write!(signature, ": {}", self.returns.join(", "));
+        }
+        signature
+    }
+}
+
+// A userdata type which functions of the module return
+pub struct ClassDoc {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub methods: &'static [FunctionDoc],
+}
+
+pub struct ModuleDoc {
+    pub name: &'static str,
+    pub description: &'static str,
+    // Values which are no functions, name and type
+    pub fields: &'static [(&'static str, &'static str)],
+    pub functions: &'static [FunctionDoc],
+    pub classes: &'static [ClassDoc],
+}
+
+impl ModuleDoc {
+    pub fn function(&self, name: &str) -> Option<&'static FunctionDoc> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    // The entry of a function in the deprecation registry
+    pub fn deprecation(&self, function: &FunctionDoc) -> Option<&'static Deprecation> {
+        deprecation::find(&format!("{}.{}", self.name, function.name))
+    }
+
+    pub fn has_field(&self, name: &str) -> bool {
+        self.fields.iter().any(|(field, _)| *field == name)
+    }
+}
+
+// In the order of DAPI_MODULES
+pub const MODULES: &[&ModuleDoc] = &[
+    &base::DOCS,
+    &io::DOCS,
+    &os::DOCS,
+    &http::DOCS,
+    &data_parsing::json::DOCS,
+    &data_parsing::toml::DOCS,
+    &data_parsing::dotenv::DOCS,
+    &data_parsing::yaml::DOCS,
+    &data_parsing::ini_parser::DOCS,
+    &data_parsing::base64_api::DOCS,
+    &data_parsing::xml::DOCS,
+    &async_server::DOCS,
+    &net::net::DOCS,
+    &time::DOCS,
+    &async_api_server::DOCS,
+    &test::DOCS,
+    &async_loop::DOCS,
+    &thread::DOCS,
+];
+
+pub fn module(name: &str) -> Option<&'static ModuleDoc> {
+    MODULES.iter().copied().find(|module| module.name == name)
+}
+
+// A function by its full name like `dapi_io.zip`
+pub fn function(path: &str) -> Option<(&'static ModuleDoc, &'static FunctionDoc)> {
+    let (module_name, function_name) = path.rsplit_once('.')?;
+    let module = module(module_name)?;
+    Some((module, module.function(function_name)?))
+}
+
+// Text of dapi.help(): all modules, one module or one function
+pub fn help(topic: Option<&str>) -> Option<String> {
+    let mut out = String::new();
+
+    let Some(topic) = topic else {
+        out.push_str("dapi modules, dapi.help(\"dapi_io\") shows one of them:\n");
+        for module in MODULES {
+            let _ = writeln!(out, "  {:<16} {}", module.name, module.description);
+        }
+        return Some(out);
+    };
+
+    if let Some(module) = module(topic) {
+        let _ = writeln!(out, "{}: {}", module.name, module.description);
+        for function in module.functions {
+            let _ = write!(out, "  {}", function.signature(""));
+            if module.deprecation(function).is_some() {
+                out.push_str("  (deprecated)");
+            }
+            out.push('\n');
+        }
+        for (name, ty) in module.fields {
+            let _ = writeln!(out, "  {}: {}", name, ty);
+        }
+        for class in module.classes {
+            let _ = writeln!(out, "{}: {}", class.name, class.description);
+            for method in class.methods {
+                let _ = writeln!(out, "  {}", method.signature(":"));
+            }
+        }
+        return Some(out);
+    }
+
+    let (module, function) = function(topic)?;
+    let _ = writeln!(out, "{}", function.signature(&format!("{}.", module.name)));
+    let _ = writeln!(out, "  {}", function.description);
+    if let Some(deprecation) = module.deprecation(function) {
+        let _ = writeln!(
+            out,
+            "  Deprecated {}. {}",
+            deprecation.versions(),
+            deprecation.replacement
+        );
+    }
+    Some(out)
+}
+
+// A LuaLS definition file for one module
+pub fn stub(module: &ModuleDoc) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "---@meta {}\n", module.name);
+
+    for class in module.classes {
+        let local = class.name.rsplit('.').next().unwrap_or(class.name);
+        let _ = writeln!(out, "---{}", class.description);
+        let _ = writeln!(out, "---@class {}", class.name);
+        let _ = writeln!(out, "local {} = {{}}\n", local);
+        for method in class.methods {
+            write_function(&mut out, method, &format!("{}:", local), None);
+        }
+    }
+
+    let _ = writeln!(out, "---{}", module.description);
+    let _ = writeln!(out, "---@class {}", module.name);
+    let _ = writeln!(out, "local {} = {{}}\n", module.name);
+    for (name, ty) in module.fields {
+        let _ = writeln!(out, "---@type {}\n{}.{} = nil\n", ty, module.name, name);
+    }
+    for function in module.functions {
+        let deprecation = module.deprecation(function);
+        write_function(
+            &mut out,
+            function,
+            &format!("{}.", module.name),
+            deprecation,
+        );
+    }
+    let _ = writeln!(out, "return {}", module.name);
+    out
+}
+
+fn write_function(
+    out: &mut String,
+    function: &FunctionDoc,
+    prefix: &str,
+    deprecation: Option<&Deprecation>,
+) {
+    let _ = writeln!(out, "---{}", function.description);
+    if let Some(deprecation) = deprecation {
+        let _ = writeln!(
+            out,
+            "---@deprecated {}. {}",
+            deprecation.versions(),
+            deprecation.replacement
+        );
+    }
+    for (name, ty) in function.params {
+        match ty.strip_suffix('?') {
+            Some(ty) if *name != "..." => {
+                let _ = writeln!(out, "---@param {}? {}", name, ty);
+            }
+            _ => {
+                let _ = writeln!(out, "---@param {} {}", name, ty);
+            }
+        }
+    }
+    for ty in function.returns {
+        let _ = writeln!(out, "---@return {}", ty);
+    }
+    let names: Vec<&str> = function.params.iter().map(|(name, _)| *name).collect();
+    let _ = writeln!(
+        out,
+        "function {}{}({}) end\n",
+        prefix,
+        function.name,
+        names.join(", ")
+    );
+}
+
+// `flua stubs`, one `<module>.lua` per module in `dir`
+pub fn write_stubs(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Could not create '{}': {}", dir.display(), e))?;
+
+    let mut files = Vec::new();
+    for module in MODULES {
+        let file = dir.join(format!("{}.lua", module.name));
+        fs::write(&file, stub(module))
+            .map_err(|e| format!("Could not write '{}': {}", file.display(), e))?;
+        files.push(file);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_script::DAPI_MODULES;
+    use mlua::{Lua, Value};
+
+    // The metadata lists exactly the functions which the modules register,
+    // with as many parameters as the functions take
+    #[test]
+    fn every_registered_function_is_described() {
+        assert_eq!(MODULES.len(), DAPI_MODULES.len());
+
+        // The debug library tells the parameters of Lua functions
+        let lua = unsafe { Lua::unsafe_new() };
+        let getinfo: mlua::Function = lua.load("return debug.getinfo").eval().unwrap();
+        let sources = rust_sources();
+
+        for ((name, register), module) in DAPI_MODULES.iter().zip(MODULES) {
+            assert_eq!(*name, module.name);
+
+            let table = register(&lua).unwrap();
+            let mut registered: Vec<(String, mlua::Function)> = table
+                .pairs::<String, Value>()
+                .map(|pair| pair.unwrap())
+                .filter_map(|(key, value)| match value {
+                    Value::Function(f) => Some((key, f)),
+                    _ => None,
+                })
+                .collect();
+            registered.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let mut described: Vec<&FunctionDoc> = module.functions.iter().collect();
+            described.sort_by_key(|f| f.name);
+            assert_eq!(
+                registered
+                    .iter()
+                    .map(|(key, _)| key.as_str())
+                    .collect::<Vec<_>>(),
+                described.iter().map(|f| f.name).collect::<Vec<_>>(),
+                "functions of {}",
+                name
+            );
+
+            let file = sources
+                .iter()
+                .find(|(_, source)| source.contains(&format!("name: \"{}\",", module.name)))
+                .map(|(_, source)| source.as_str())
+                .unwrap_or_else(|| panic!("no source of {}", module.name));
+
+            for ((key, function), doc) in registered.iter().zip(described) {
+                let info: mlua::Table = getinfo.call((function, "Su")).unwrap();
+                let is_lua = info.get::<String>("what").unwrap() == "Lua";
+                let arity = if is_lua && !info.get::<bool>("isvararg").unwrap() {
+                    info.get::<usize>("nparams").unwrap()
+                } else {
+                    rust_arity(file, &sources, module.name, key)
+                        .unwrap_or_else(|| panic!("no Rust function for {}.{}", name, key))
+                };
+                assert_eq!(
+                    doc.params.len(),
+                    arity,
+                    "parameters of {}.{}",
+                    module.name,
+                    key
+                );
+            }
+        }
+    }
+
+    // The Rust files of the crate
+    fn rust_sources() -> Vec<(PathBuf, String)> {
+        walkdir::WalkDir::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))
+            .into_iter()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "rs"))
+            .map(|entry| {
+                let source = fs::read_to_string(entry.path()).unwrap();
+                (entry.into_path(), source)
+            })
+            .collect()
+    }
+
+    // Parameters of the Rust function which `file` registers as `name`, like
+    // `table.set("name", lua.create_function(|lua, (a, b): (String, u32)| ...))`,
+    // a function or closure bound to a variable, or `add_waiting(.., "module.name", ..)`
+    fn rust_arity(
+        file: &str,
+        sources: &[(PathBuf, String)],
+        module: &str,
+        name: &str,
+    ) -> Option<usize> {
+        let rest = [
+            format!("\"{}\",", name),
+            format!("\"{}.{}\",", module, name),
+        ]
+        .iter()
+        .flat_map(|key| {
+            file.match_indices(key.as_str())
+                .map(|(i, key)| (i, key.len()))
+        })
+        .find(|(i, _)| {
+            let before = file[..*i].trim_end();
+            before.ends_with("set(") || before.ends_with(',')
+        })
+        .map(|(i, len)| file[i + len..].trim_start())?;
+
+        if rest.starts_with("lua.create") || rest.starts_with('|') || rest.starts_with("move") {
+            return closure_arity(rest);
+        }
+        let ident: String = rest
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+        if let Some(i) = file.find(&format!("let {} =", ident)) {
+            return closure_arity(&file[i..]);
+        }
+        let definition = format!("fn {}(", ident);
+        let source = sources
+            .iter()
+            .find_map(|(_, s)| s.find(&definition).map(|i| &s[i + definition.len()..]))?;
+        if rest[ident.len()..].starts_with('(') {
+            // `create_exit_function(lua)` returns the closure
+            return closure_arity(source);
+        }
+        // `fn exec(lua: Lua, command: String)`, the Lua state is not counted
+        let end = source.find(')')?;
+        Some(split_top_level(&source[..end]).len().saturating_sub(1))
+    }
+
+    // Values of the second closure parameter, `|lua, (a, b): (A, B)|` takes two
+    fn closure_arity(code: &str) -> Option<usize> {
+        let start = code.find('|')? + 1;
+        let end = start + code[start..].find('|')?;
+        let params = split_top_level(&code[start..end]);
+        let Some(args) = params.get(1) else {
+            return Some(0);
+        };
+        let pattern = args.split(':').next()?.trim();
+        match pattern.strip_prefix('(').and_then(|p| p.strip_suffix(')')) {
+            Some(inner) => Some(split_top_level(inner).len()),
+            None => Some(1),
+        }
+    }
+
+    fn split_top_level(text: &str) -> Vec<&str> {
+        let mut parts = Vec::new();
+        let (mut depth, mut start) = (0, 0);
+        for (i, c) in text.char_indices() {
+            match c {
+                '(' | '<' | '[' => depth += 1,
+                ')' | '>' | ']' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(&text[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(&text[start..]);
+        parts.into_iter().filter(|p| !p.trim().is_empty()).collect()
+    }
+
+    #[test]
+    fn help_texts() {
+        let text = help(Some("dapi_io.zip")).unwrap();
+        assert!(
+            text.starts_with("dapi_io.zip(src: string, dest: string)\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("Deprecated since 0.1.10. No replacement yet"),
+            "{}",
+            text
+        );
+
+        let text = help(Some("dapi_json")).unwrap();
+        assert!(
+            text.contains("encode(value: any, pretty: boolean?): string"),
+            "{}",
+            text
+        );
+
+        assert!(help(None).unwrap().contains("dapi_thread"));
+        assert!(help(Some("dapi_io.nothing")).is_none());
+    }
+
+    #[test]
+    fn stub_of_a_module() {
+        let stub = stub(&thread::DOCS);
+        assert!(stub.starts_with("---@meta dapi_thread\n"), "{}", stub);
+        assert!(
+            stub.contains("---@class dapi_thread.Worker\nlocal Worker = {}"),
+            "{}",
+            stub
+        );
+        assert!(stub.contains("function Worker:join() end"), "{}", stub);
+        assert!(
+            stub.contains("---@type dapi_thread.Channel\ndapi_thread.parent = nil\n"),
+            "{}",
+            stub
+        );
+        assert!(
+            stub.contains("---@param workers? integer\n---@return table\nfunction dapi_thread.map(items, function_source, workers) end"),
+            "{}",
+            stub
+        );
+        assert!(stub.ends_with("return dapi_thread\n"), "{}", stub);
+
+        let stub = stub_of("dapi_os");
+        assert!(
+            stub.contains("---@deprecated since 0.1.10. Use dapi_os.run2()"),
+            "{}",
+            stub
+        );
+    }
+
+    // Every deprecation names a described function
+    #[test]
+    fn deprecations_are_described() {
+        for deprecation in deprecation::DEPRECATIONS {
+            assert!(function(deprecation.name).is_some(), "{}", deprecation.name);
+        }
+    }
+
+    fn stub_of(name: &str) -> String {
+        stub(module(name).unwrap())
+    }
+}
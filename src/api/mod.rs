@@ -1,7 +1,11 @@
+pub mod async_loop;
 pub mod base;
 pub mod data_parsing;
 pub mod http;
 pub mod io;
+pub mod meta;
 pub mod net;
 pub mod os;
+pub mod test;
+pub mod thread;
 pub mod time;
@@ -1,8 +1,27 @@
-use mlua::{Error, Function, Lua, Result};
+use mlua::{Lua, Result};
 use reqwest::blocking::Client;
 use std::fs::File;
 use std::io::copy;
 
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
+use crate::helper::permissions::{check_url, check_write};
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_net",
+    description: "HTTP requests",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("fetch", "The body of a GET request")
+            .params(&[("url", "string")])
+            .returns(&["string"]),
+        FunctionDoc::new("download_file", "Downloads a file")
+            .params(&[("url", "string"), ("destination", "string")])
+            .returns(&["boolean"]),
+    ],
+    classes: &[],
+};
+
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?;
 
@@ -10,45 +29,74 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let client = Client::builder()
         .timeout(std::time::Duration::from_secs(10))
         .build()
-        .map_err(Error::external)?;
+        .map_err(|e| {
+            dapi_error(
+                "dapi_net",
+                format!("Could not create the HTTP client: {}", e),
+            )
+        })?;
 
     // Fetch-Funktion
     let fetch_client = client.clone();
-    let fetch = lua.create_function(move |_, url: String| {
+    let fetch = lua.create_function(move |lua, url: String| {
+        check_url(lua, &url)?;
+
         let resp = fetch_client
             .get(&url)
             .header("User-Agent", "MyLuaRustApp/1.0")
             .send()
-            .map_err(|e| Error::external(format!("HTTP-Fehler: {}", e)))?;
+            .map_err(|e| dapi_error("dapi_net.fetch", format!("HTTP request failed: {}", e)))?;
 
         if !resp.status().is_success() {
-            return Err(Error::external(format!("HTTP-Status: {}", resp.status())));
+            return Err(dapi_error(
+                "dapi_net.fetch",
+                format!("HTTP status {}", resp.status()),
+            ));
         }
 
-        let body = resp
-            .text()
-            .map_err(|e| Error::external(format!("Fehler beim Lesen des Bodys: {}", e)))?;
+        let body = resp.text().map_err(|e| {
+            dapi_error(
+                "dapi_net.fetch",
+                format!("Could not read the response body: {}", e),
+            )
+        })?;
 
         Ok(body)
     })?;
 
     // Download-Funktion
     let download_client = client.clone();
-    let download_file = lua.create_function(move |_, (url, destination): (String, String)| {
-        let mut resp = download_client
-            .get(&url)
-            .send()
-            .map_err(|e| Error::external(format!("HTTP-Fehler: {}", e)))?;
+    let download_file = lua.create_function(move |lua, (url, destination): (String, String)| {
+        check_url(lua, &url)?;
+        check_write(lua, &destination)?;
+
+        let mut resp = download_client.get(&url).send().map_err(|e| {
+            dapi_error(
+                "dapi_net.download_file",
+                format!("HTTP request failed: {}", e),
+            )
+        })?;
 
         if !resp.status().is_success() {
-            return Err(Error::external(format!("HTTP-Status: {}", resp.status())));
+            return Err(dapi_error(
+                "dapi_net.download_file",
+                format!("HTTP status {}", resp.status()),
+            ));
         }
 
-        let mut out = File::create(&destination)
-            .map_err(|e| Error::external(format!("Datei erstellen fehlgeschlagen: {}", e)))?;
+        let mut out = File::create(&destination).map_err(|e| {
+            dapi_error(
+                "dapi_net.download_file",
+                format!("Could not create '{}': {}", destination, e),
+            )
+        })?;
 
-        copy(&mut resp, &mut out)
-            .map_err(|e| Error::external(format!("Fehler beim Schreiben: {}", e)))?;
+        copy(&mut resp, &mut out).map_err(|e| {
+            dapi_error(
+                "dapi_net.download_file",
+                format!("Could not write '{}': {}", destination, e),
+            )
+        })?;
 
         Ok(true)
     })?;
@@ -64,7 +112,7 @@ mod tests {
     use super::*;
     use httptest::matchers::request;
     use httptest::{Expectation, Server, responders::*};
-    use mlua::Lua;
+    use mlua::{Function, Lua};
     use std::fs;
 
     #[test]
@@ -113,4 +161,28 @@ mod tests {
         // Aufräumen
         let _ = fs::remove_file(tmp_file);
     }
+
+    #[test]
+    fn test_fetch_error_names_the_function() {
+        let lua = Lua::new();
+        let api = register(&lua).expect("Failed to register Lua functions");
+
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/missing"))
+                .respond_with(status_code(404)),
+        );
+
+        let fetch: Function = api.get("fetch").unwrap();
+        let err = fetch
+            .call::<String>(server.url("/missing").to_string())
+            .unwrap_err();
+
+        assert!(
+            err.to_string()
+                .contains("dapi_net.fetch: HTTP status 404 Not Found"),
+            "{}",
+            err
+        );
+    }
 }
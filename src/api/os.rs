@@ -1,13 +1,67 @@
 use mlua::{Lua, Result, Value};
 use std::io::Read;
-use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::process::{Command, Stdio};
 use std::thread;
 
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
 use crate::helper::dir::{join_path, secure_path, split_path};
-
-use crate::deprecated;
+use crate::helper::permissions::{check_read, check_run};
+
+use crate::helper::deprecation;
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_os",
+    description: "The operating system, processes and paths",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new(
+            "get_os_info",
+            "`os_type`, `os_release`, `hostname`, `cpu_num` and `mem_total` in KB",
+        )
+        .returns(&["table"]),
+        FunctionDoc::new("os", "Which system runs the script, the fields `win`, `lin` and `mac`")
+            .returns(&["table<string, boolean>"]),
+        FunctionDoc::new("chdir", "Changes the current directory").params(&[("path", "string")]),
+        FunctionDoc::new("getcwd", "The current directory").returns(&["string"]),
+        FunctionDoc::new("open_link", "Opens a link in the default program")
+            .params(&[("url", "string")]),
+        FunctionDoc::new("open", "Opens a file or a link in the default program")
+            .params(&[("file", "string")]),
+        FunctionDoc::new(
+            "run",
+            "Runs a shell command, returns `status`, `stdout` and `stderr`",
+        )
+        .params(&[("command", "string")])
+        .returns(&["table"]),
+        FunctionDoc::new(
+            "run2",
+            "Runs a shell command and shows its output while it runs, returns `status`, `stdout` and `stderr`",
+        )
+        .params(&[("command", "string")])
+        .returns(&["table"]),
+        FunctionDoc::new(
+            "run3",
+            "Runs a shell command in the terminal of the script, returns `status`",
+        )
+        .params(&[("command", "string")])
+        .returns(&["table"]),
+        FunctionDoc::new("split_path", "The parts of a path")
+            .params(&[("path", "string")])
+            .returns(&["string[]"]),
+        FunctionDoc::new(
+            "secure_path",
+            "The path when it stays below the current directory, nil for paths like `../etc`",
+        )
+        .params(&[("path", "string")])
+        .returns(&["string?"]),
+        FunctionDoc::new("join_path", "Joins the parts to a path")
+            .params(&[("parts", "string[]")])
+            .returns(&["string"]),
+    ],
+    classes: &[],
+};
 
 pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let table = lua.create_table()?;
@@ -33,7 +87,7 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     let os = lua.create_function(|lua, ()| {
         let table = lua.create_table()?;
 
-        let os_type = sys_info::os_type().map_err(|e| mlua::Error::external(e.to_string()))?;
+        let os_type = sys_info::os_type().map_err(|e| dapi_error("dapi_os.os", e))?;
 
         let windows = os_type == "Windows";
         let linux = os_type == "Linux";
@@ -47,7 +101,8 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     })?;
 
     // function to change the current executing directory
-    let chdir = lua.create_function(|_, path: String| {
+    let chdir = lua.create_function(|lua, path: String| {
+        check_read(lua, &path)?;
         std::env::set_current_dir(Path::new(&path))?;
         Ok(())
     })?;
@@ -59,36 +114,37 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     })?;
 
     // Function to open a URL in the default Opener
-    let open_link = lua.create_function(|_, url: String| {
-        deprecated!(
-            "dapi_os.open_link",
-            "0.2.0",
-            "The function changed its name, use 'dapi_os.open()' instead!"
-        );
-
-        open::that(url).map_err(|e| mlua::Error::external(format!("Cannot open URL: {}", e)))?;
+    let open_link = lua.create_function(|lua, url: String| {
+        deprecation::used("dapi_os.open_link")?;
+        check_run(lua, &url)?;
+
+        open::that(&url).map_err(|e| {
+            dapi_error("dapi_os.open_link", format!("Cannot open '{}': {}", url, e))
+        })?;
         Ok(())
     })?;
 
     // Function to open a File in the default program or a Link
-    let open = lua.create_function(|_, file: String| {
-        open::that(file).map_err(|e| mlua::Error::external(format!("Cannot open: {}", e)))?;
+    let open = lua.create_function(|lua, file: String| {
+        check_run(lua, &file)?;
+        open::that(&file)
+            .map_err(|e| dapi_error("dapi_os.open", format!("Cannot open '{}': {}", file, e)))?;
         Ok(())
     })?;
 
     // Function to run a command in the commandline
     let run = lua.create_function(|lua, command: String| {
-        deprecated!(
-            "dapi_os.run",
-            "0.1.10",
-            "The function is although contained in the Lua STD or use 'dapi_os.run2()'"
-        );
+        deprecation::used("dapi_os.run")?;
+        check_run(lua, &command)?;
 
         #[cfg(target_os = "windows")]
-        let output = Command::new("cmd").arg("/C").arg(&command).output()?;
+        let output = Command::new("cmd").arg("/C").arg(&command).output();
 
         #[cfg(not(target_os = "windows"))]
-        let output = Command::new("sh").arg("-c").arg(&command).output()?;
+        let output = Command::new("sh").arg("-c").arg(&command).output();
+
+        let output = output
+            .map_err(|e| dapi_error("dapi_os.run", format!("Cannot run '{}': {}", command, e)))?;
 
         let stdout = String::from_utf8_lossy(&output.stdout).to_string();
         let stderr = String::from_utf8_lossy(&output.stderr).to_string();
@@ -107,6 +163,8 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
 
     // Function to run a sync command in the Terminal
     let run2 = lua.create_function(|lua, command: String| {
+        check_run(lua, &command)?;
+
         let mut child = if cfg!(target_os = "windows") {
             Command::new("cmd")
                 .arg("/C")
@@ -187,6 +245,8 @@ pub fn register(lua: &Lua) -> Result<mlua::Table> {
     // TODO
     // Run a command with colors with out the Errir printing?
     let run3 = lua.create_function(|lua, command: String| {
+        check_run(lua, &command)?;
+
         let status = if cfg!(target_os = "windows") {
             Command::new("cmd")
                 .arg("/C")
@@ -0,0 +1,173 @@
+-- Assertions of dapi_test, describe() and it() are added in test.rs
+--
+-- All assertions raise their error at the line of the test which called them
+
+local M = {}
+
+-- Readable form of a value for the failure messages
+local function show(value, depth)
+  depth = depth or 0
+  if type(value) == "string" then
+    return string.format("%q", value)
+  end
+  if type(value) ~= "table" then
+    return tostring(value)
+  end
+  if depth >= 3 then
+    return "{...}"
+  end
+
+  local keys = {}
+  for key in pairs(value) do
+    keys[#keys + 1] = key
+  end
+  table.sort(keys, function(a, b)
+    if type(a) == type(b) and (type(a) == "number" or type(a) == "string") then
+      return a < b
+    end
+    return type(a) < type(b)
+  end)
+
+  local parts = {}
+  for i, key in ipairs(keys) do
+    if i > 10 then
+      parts[#parts + 1] = "..."
+      break
+    end
+    local name = type(key) == "string" and key or "[" .. show(key, depth + 1) .. "]"
+    parts[#parts + 1] = name .. " = " .. show(value[key], depth + 1)
+  end
+  return "{" .. table.concat(parts, ", ") .. "}"
+end
+
+-- The depth is only used by the recursion
+function M.show(value)
+  return show(value)
+end
+
+-- Path of the first difference of two values, nil if they are equal
+local function difference(actual, expected, path, seen)
+  if actual == expected then
+    return nil
+  end
+  if type(actual) ~= "table" or type(expected) ~= "table" then
+    return path
+  end
+
+  -- Tables which reference themselves are compared only once
+  seen = seen or {}
+  if seen[actual] == expected then
+    return nil
+  end
+  seen[actual] = expected
+
+  for key, value in pairs(expected) do
+    local diff = difference(actual[key], value, path .. "[" .. show(key) .. "]", seen)
+    if diff then
+      return diff
+    end
+  end
+  for key in pairs(actual) do
+    if expected[key] == nil then
+      return path .. "[" .. show(key) .. "]"
+    end
+  end
+  return nil
+end
+
+-- Value at a path like `[1]["name"]`, only used for the messages
+local function value_at(value, path)
+  for key in path:gmatch("%[(.-)%]") do
+    if type(value) ~= "table" then
+      return nil
+    end
+    local loaded = loadstring("return " .. key)
+    value = value[loaded and loaded() or key]
+  end
+  return value
+end
+
+local function fail(message, level)
+  error(message, (level or 1) + 2)
+end
+
+function M.fail(message)
+  fail(message or "failed")
+end
+
+-- Deep equality, tables are equal when all keys and values are equal
+function M.assert_equal(actual, expected, message)
+  local diff = difference(actual, expected, "")
+  if diff then
+    local text = "expected " .. show(expected) .. ", got " .. show(actual)
+    if diff ~= "" then
+      text = text .. " (at " .. diff .. ": expected " .. show(value_at(expected, diff))
+        .. ", got " .. show(value_at(actual, diff)) .. ")"
+    end
+    fail((message and message .. ": " or "") .. text)
+  end
+end
+
+function M.assert_not_equal(actual, unexpected, message)
+  if difference(actual, unexpected, "") == nil then
+    fail((message and message .. ": " or "") .. "expected a value other than " .. show(unexpected))
+  end
+end
+
+function M.assert_true(value, message)
+  if value ~= true then
+    fail((message and message .. ": " or "") .. "expected true, got " .. show(value))
+  end
+end
+
+function M.assert_false(value, message)
+  if value ~= false then
+    fail((message and message .. ": " or "") .. "expected false, got " .. show(value))
+  end
+end
+
+function M.assert_nil(value, message)
+  if value ~= nil then
+    fail((message and message .. ": " or "") .. "expected nil, got " .. show(value))
+  end
+end
+
+function M.assert_not_nil(value, message)
+  if value == nil then
+    fail((message and message .. ": " or "") .. "expected a value, got nil")
+  end
+end
+
+-- Numbers which are equal up to the tolerance, 1e-9 by default
+function M.assert_near(actual, expected, tolerance, message)
+  tolerance = tolerance or 1e-9
+  if type(actual) ~= "number" or math.abs(actual - expected) > tolerance then
+    fail((message and message .. ": " or "") .. "expected " .. show(expected) .. " +/- "
+      .. tolerance .. ", got " .. show(actual))
+  end
+end
+
+-- String matches a Lua pattern
+function M.assert_match(text, pattern, message)
+  if type(text) ~= "string" or not text:find(pattern) then
+    fail((message and message .. ": " or "") .. "expected " .. show(text)
+      .. " to match " .. show(pattern))
+  end
+end
+
+-- The function has to fail, the message has to contain `expected` if given.
+-- Returns the error message
+function M.assert_error(fn, expected, message)
+  local ok, err = pcall(fn)
+  if ok then
+    fail((message and message .. ": " or "") .. "expected an error, but the function succeeded")
+  end
+  local text = tostring(err)
+  if expected and not text:find(expected, 1, true) then
+    fail((message and message .. ": " or "") .. "expected an error containing "
+      .. show(expected) .. ", got " .. show(text))
+  end
+  return text
+end
+
+return M
@@ -0,0 +1,262 @@
+// dapi_test, describe() and it() for `flua test` with the assertions of test.lua
+//
+// `flua test` runs a test file once, it() only collects the bodies and the
+// runner calls them after the file in the same Lua state. Outside of
+// `flua test` it() runs its body right away
+
+use mlua::{Function, Lua, Result, Table};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::api::meta::{FunctionDoc, ModuleDoc};
+
+const ASSERTIONS: &str = include_str!("test.lua");
+
+// A test found in a test file
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    // Names of the describe() blocks and of the test
+    pub name: String,
+    // None for a skipped test
+    pub body: Option<Function>,
+}
+
+#[derive(Debug, Default)]
+pub struct TestState {
+    scope: Vec<String>,
+    pub tests: Vec<TestCase>,
+}
+
+// Shared with the test runner, stored as app data of the Lua state
+#[derive(Clone, Default)]
+pub struct TestRun(pub Rc<RefCell<TestState>>);
+
+impl TestRun {
+    pub fn install(&self, lua: &Lua) {
+        lua.set_app_data(self.clone());
+    }
+
+    // The collected tests, in the order of the file
+    pub fn take_tests(&self) -> Vec<TestCase> {
+        std::mem::take(&mut self.0.borrow_mut().tests)
+    }
+
+    fn add(&self, name: &str, body: Option<Function>) {
+        let mut state = self.0.borrow_mut();
+        let mut full_name = state.scope.join(" ");
+        if !full_name.is_empty() {
+            full_name.push(' ');
+        }
+        full_name.push_str(name);
+
+        state.tests.push(TestCase {
+            name: full_name,
+            body,
+        });
+    }
+}
+
+fn current_run(lua: &Lua) -> Option<TestRun> {
+    lua.app_data_ref::<TestRun>().map(|run| run.clone())
+}
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_test",
+    description: "Tests and assertions for `flua test`",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("describe", "Groups the tests in the function")
+            .params(&[("name", "string"), ("body", "function")]),
+        FunctionDoc::new("it", "A test").params(&[("name", "string"), ("body", "function")]),
+        FunctionDoc::new("skip", "A test which is listed but does not run")
+            .params(&[("name", "string"), ("body", "function")]),
+        FunctionDoc::new("show", "Readable form of a value")
+            .params(&[("value", "any")])
+            .returns(&["string"]),
+        FunctionDoc::new("fail", "Fails the test").params(&[("message", "string?")]),
+        FunctionDoc::new(
+            "assert_equal",
+            "Deep equality, tables are equal when all keys and values are equal",
+        )
+        .params(&[("actual", "any"), ("expected", "any"), ("message", "string?")]),
+        FunctionDoc::new("assert_not_equal", "The values differ").params(&[
+            ("actual", "any"),
+            ("unexpected", "any"),
+            ("message", "string?"),
+        ]),
+        FunctionDoc::new("assert_true", "The value is true")
+            .params(&[("value", "any"), ("message", "string?")]),
+        FunctionDoc::new("assert_false", "The value is false")
+            .params(&[("value", "any"), ("message", "string?")]),
+        FunctionDoc::new("assert_nil", "The value is nil")
+            .params(&[("value", "any"), ("message", "string?")]),
+        FunctionDoc::new("assert_not_nil", "The value is not nil")
+            .params(&[("value", "any"), ("message", "string?")]),
+        FunctionDoc::new(
+            "assert_near",
+            "Numbers which are equal up to the tolerance, 1e-9 by default",
+        )
+        .params(&[
+            ("actual", "number"),
+            ("expected", "number"),
+            ("tolerance", "number?"),
+            ("message", "string?"),
+        ]),
+        FunctionDoc::new("assert_match", "The string matches a Lua pattern").params(&[
+            ("text", "string"),
+            ("pattern", "string"),
+            ("message", "string?"),
+        ]),
+        FunctionDoc::new(
+            "assert_error",
+            "The function has to fail, with `expected` in the message if given. Returns the message",
+        )
+        .params(&[
+            ("fn", "function"),
+            ("expected", "string?"),
+            ("message", "string?"),
+        ])
+        .returns(&["string"]),
+    ],
+    classes: &[],
+};
+
+pub fn register(lua: &Lua) -> Result<Table> {
+    let table: Table = lua.load(ASSERTIONS).set_name("=dapi_test").eval()?;
+
+    // describe(name, fn)
+    let describe = lua.create_function(|lua, (name, body): (String, Function)| {
+        let Some(run) = current_run(lua) else {
+            return body.call::<()>(());
+        };
+
+        run.0.borrow_mut().scope.push(name);
+        let result = body.call::<()>(());
+        run.0.borrow_mut().scope.pop();
+        result
+    })?;
+
+    // it(name, fn)
+    let it = lua.create_function(|lua, (name, body): (String, Function)| {
+        let Some(run) = current_run(lua) else {
+            return body.call::<()>(());
+        };
+
+        run.add(&name, Some(body));
+        Ok(())
+    })?;
+
+    // skip(name, fn), the test is listed but never runs
+    let skip = lua.create_function(|lua, (name, _body): (String, Function)| {
+        if let Some(run) = current_run(lua) {
+            run.add(&name, None);
+        }
+        Ok(())
+    })?;
+
+    table.set("describe", describe)?;
+    table.set("it", it)?;
+    table.set("skip", skip)?;
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lua_with(run: Option<&TestRun>) -> Lua {
+        let lua = Lua::new();
+        if let Some(run) = run {
+            run.install(&lua);
+        }
+        lua.globals().set("t", register(&lua).unwrap()).unwrap();
+        lua
+    }
+
+    const FILE: &str = r#"
+        t.describe("math", function()
+            t.it("adds", function() t.assert_equal(1 + 1, 2) end)
+            t.it("fails", function() t.assert_equal({a = {1, 2}}, {a = {1, 3}}) end)
+        end)
+        t.skip("later", function() error("never runs") end)
+    "#;
+
+    #[test]
+    fn collects_tests_without_running_them() {
+        let run = TestRun::default();
+        lua_with(Some(&run)).load(FILE).exec().unwrap();
+
+        let tests = run.take_tests();
+        let names: Vec<&str> = tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["math adds", "math fails", "later"]);
+        assert!(tests[2].body.is_none());
+        assert!(run.take_tests().is_empty());
+    }
+
+    #[test]
+    fn collected_bodies_run_later() {
+        let run = TestRun::default();
+        let lua = lua_with(Some(&run));
+        lua.load(FILE).exec().unwrap();
+
+        let tests = run.take_tests();
+        assert!(tests[0].body.as_ref().unwrap().call::<()>(()).is_ok());
+        let err = tests[1].body.as_ref().unwrap().call::<()>(()).unwrap_err();
+        assert!(
+            err.to_string().contains("at [\"a\"][2]: expected 3, got 2"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn assertions() {
+        let lua = lua_with(None);
+        lua.load(
+            r#"
+            t.assert_equal({1, {x = "a"}}, {1, {x = "a"}})
+            t.assert_not_equal({1}, {2})
+            t.assert_near(0.1 + 0.2, 0.3)
+            t.assert_near(10, 10.4, 0.5)
+            t.assert_match("flua 0.2.1", "%d+%.%d+")
+            t.assert_true(true)
+            t.assert_false(false)
+            t.assert_nil(nil)
+            t.assert_not_nil(0)
+            local msg = t.assert_error(function() error("broken pipe") end, "broken")
+            assert(msg:find("broken pipe"))
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let err = lua
+            .load("t.assert_near(1, 2, 0.5)")
+            .set_name("=check.lua")
+            .exec()
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("check.lua:1: expected 2 +/- 0.5, got 1"),
+            "{}",
+            err
+        );
+
+        let err = lua
+            .load("t.assert_error(function() end)")
+            .exec()
+            .unwrap_err();
+        assert!(err.to_string().contains("expected an error"), "{}", err);
+    }
+
+    #[test]
+    fn it_runs_directly_without_runner() {
+        let lua = lua_with(None);
+        let err = lua
+            .load(r#"t.it("x", function() t.fail("nope") end)"#)
+            .exec()
+            .unwrap_err();
+        assert!(err.to_string().contains("nope"), "{}", err);
+    }
+}
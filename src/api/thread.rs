@@ -0,0 +1,509 @@
+// dapi_thread, worker Lua states on their own OS threads
+//
+// A worker gets a fresh Lua state with the dapi modules and the permissions,
+// package.path and remaining limits of the script which started it. Values between the states
+// go through channels as JSON, so only nil, booleans, numbers, strings and
+// tables of them can be sent
+
+use mlua::{Function, Lua, Result, Table, UserData, UserDataMethods, Value};
+use serde_json::Value as JsonValue;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::api::meta::{ClassDoc, FunctionDoc, ModuleDoc};
+use crate::helper::dapi_error::dapi_error;
+use crate::helper::limits::{self, Limits};
+use crate::helper::permissions::{self, Permissions, TrustedPaths, check_read};
+use crate::lua_script::{new_state, set_script_paths, strip_shebang};
+use crate::utils::json_utils::{json_to_lua, lua_to_json};
+
+// One end of a channel between two Lua states
+struct Channel {
+    // None after close()
+    tx: Option<Sender<JsonValue>>,
+    rx: Receiver<JsonValue>,
+}
+
+impl Channel {
+    // Both ends of a new channel
+    fn pair() -> (Channel, Channel) {
+        let (a_tx, a_rx) = mpsc::channel();
+        let (b_tx, b_rx) = mpsc::channel();
+        (
+            Channel {
+                tx: Some(a_tx),
+                rx: b_rx,
+            },
+            Channel {
+                tx: Some(b_tx),
+                rx: a_rx,
+            },
+        )
+    }
+
+    fn send(&self, value: &Value) -> Result<()> {
+        let Some(tx) = &self.tx else {
+            return Err(dapi_error("dapi_thread.send", "the channel is closed"));
+        };
+        let json = lua_to_json(value)?;
+        tx.send(json)
+            .map_err(|_| dapi_error("dapi_thread.send", "the other side is gone"))
+    }
+}
+
+// Adds send, recv, try_recv and close, for channels and workers
+fn add_channel_methods<T, M>(methods: &mut M, channel: fn(&T) -> &Channel)
+where
+    T: 'static,
+    M: UserDataMethods<T>,
+{
+    methods.add_method("send", move |_, this, value: Value| {
+        channel(this).send(&value)
+    });
+
+    // Waits for the next value, nil when the other side closed the channel
+    methods.add_method("recv", move |lua, this, ()| match channel(this).rx.recv() {
+        Ok(json) => json_to_lua(lua, &json),
+        Err(_) => Ok(Value::Nil),
+    });
+
+    // The next value or nil when there is none
+    methods.add_method("try_recv", move |lua, this, ()| {
+        match channel(this).rx.try_recv() {
+            Ok(json) => json_to_lua(lua, &json),
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => Ok(Value::Nil),
+        }
+    });
+}
+
+impl UserData for Channel {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        add_channel_methods(methods, |channel| channel);
+        methods.add_method_mut("close", |_, channel, ()| {
+            channel.tx = None;
+            Ok(())
+        });
+    }
+}
+
+// A running worker, seen from the script which started it
+struct Worker {
+    channel: Channel,
+    handle: Option<JoinHandle<std::result::Result<JsonValue, String>>>,
+}
+
+impl UserData for Worker {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        add_channel_methods(methods, |worker| &worker.channel);
+        methods.add_method_mut("close", |_, worker, ()| {
+            worker.channel.tx = None;
+            Ok(())
+        });
+
+        // Waits for the end of the worker and returns the value of its code
+        methods.add_method_mut("join", |lua, worker, ()| {
+            let Some(handle) = worker.handle.take() else {
+                return Err(dapi_error(
+                    "dapi_thread.join",
+                    "the worker was already joined",
+                ));
+            };
+            // The worker may wait for the end of the channel
+            worker.channel.tx = None;
+
+            match handle.join() {
+                Ok(Ok(json)) => json_to_lua(lua, &json),
+                Ok(Err(message)) => Err(dapi_error("dapi_thread.join", message)),
+                Err(_) => Err(dapi_error("dapi_thread.join", "the worker panicked")),
+            }
+        });
+    }
+}
+
+// What a new worker state inherits from the script
+#[derive(Clone)]
+struct Inherited {
+    permissions: Permissions,
+    package_path: String,
+    trusted_paths: Option<TrustedPaths>,
+    limits: Limits,
+}
+
+impl Inherited {
+    fn of(lua: &Lua) -> Result<Self> {
+        let package: Table = lua.globals().get("package")?;
+        Ok(Inherited {
+            permissions: lua
+                .app_data_ref::<Permissions>()
+                .map(|p| p.clone())
+                .unwrap_or_default(),
+            package_path: package.get("path")?,
+            trusted_paths: permissions::trusted_paths(lua),
+            limits: limits::remaining(lua),
+        })
+    }
+
+    fn new_state(&self) -> Result<Lua> {
+        let lua = new_state(&self.permissions, &[])?;
+        let package: Table = lua.globals().get("package")?;
+        package.set("path", self.package_path.as_str())?;
+        if let Some(trusted) = &self.trusted_paths {
+            permissions::set_trusted_paths(&lua, trusted.clone());
+        }
+        self.limits.install(&lua)?;
+        Ok(lua)
+    }
+}
+
+// Code of a worker, a file or Lua source
+enum WorkerCode {
+    File(String),
+    Source(String),
+}
+
+impl WorkerCode {
+    fn parse(lua: &Lua, code: String) -> Result<Self> {
+        if code.ends_with(".lua") && !code.contains('\n') {
+            check_read(lua, &code)?;
+            if !Path::new(&code).is_file() {
+                return Err(dapi_error(
+                    "dapi_thread.spawn",
+                    format!("Worker file '{}' not found", code),
+                ));
+            }
+            return Ok(WorkerCode::File(code));
+        }
+        Ok(WorkerCode::Source(code))
+    }
+
+    // Runs the code with the arguments as `...`, the parent channel is
+    // dapi_thread.parent
+    fn run(
+        self,
+        inherited: &Inherited,
+        channel: Channel,
+        args: JsonValue,
+    ) -> std::result::Result<JsonValue, String> {
+        let run = || -> Result<JsonValue> {
+            let lua = inherited.new_state()?;
+            let module: Table = lua
+                .globals()
+                .get::<Function>("require")?
+                .call("dapi_thread")?;
+            module.set("parent", channel)?;
+
+            let (source, name) = match self {
+                WorkerCode::File(file) => {
+                    set_script_paths(&lua, &file)?;
+                    let source = fs::read_to_string(&file).map_err(|e| {
+                        dapi_error(
+                            "dapi_thread.spawn",
+                            format!("Could not read '{}': {}", file, e),
+                        )
+                    })?;
+                    (strip_shebang(source), format!("@{}", file))
+                }
+                WorkerCode::Source(source) => (source, "=worker".to_string()),
+            };
+
+            let value: Value = lua
+                .load(source)
+                .set_name(name)
+                .call(json_to_lua(&lua, &args)?)?;
+            lua_to_json(&value)
+        };
+        run().map_err(|e| e.to_string())
+    }
+}
+
+// dapi_thread.map(), runs `function_source` for every item on `workers`
+// threads and returns the results in the order of the items
+fn map_items(
+    inherited: &Inherited,
+    items: Vec<JsonValue>,
+    function_source: &str,
+    workers: usize,
+) -> std::result::Result<Vec<JsonValue>, String> {
+    let count = items.len();
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let (result_tx, result_rx) = mpsc::channel();
+
+    let handles: Vec<JoinHandle<()>> = (0..workers.clamp(1, count.max(1)))
+        .map(|_| {
+            let (inherited, queue, result_tx) =
+                (inherited.clone(), Arc::clone(&queue), result_tx.clone());
+            let source = format!("return {}", function_source.trim());
+
+            thread::spawn(move || {
+                let state = inherited.new_state().and_then(|lua| {
+                    let func = lua.load(source).set_name("=map").eval::<Function>()?;
+                    Ok((lua, func))
+                });
+                let (lua, func) = match state {
+                    Ok(state) => state,
+                    Err(e) => {
+                        let _ = result_tx.send((0, Err(e.to_string())));
+                        return;
+                    }
+                };
+
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let Some((index, item)) = next else { break };
+
+                    let result = json_to_lua(&lua, &item)
+                        .and_then(|value| func.call::<Value>(value))
+                        .and_then(|value| lua_to_json(&value))
+                        .map_err(|e| e.to_string());
+                    let failed = result.is_err();
+                    if result_tx.send((index, result)).is_err() || failed {
+                        break;
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(result_tx);
+
+    let mut results = vec![JsonValue::Null; count];
+    for (index, result) in result_rx {
+        match result {
+            Ok(value) => results[index] = value,
+            Err(message) => {
+                // The other workers stop after their current item
+                queue.lock().unwrap().by_ref().for_each(drop);
+                return Err(message);
+            }
+        }
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+    Ok(results)
+}
+
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_thread",
+    description: "Lua code on other threads, values are sent as JSON",
+    // Only in workers
+    fields: &[("parent", "dapi_thread.Channel")],
+    functions: &[
+        FunctionDoc::new(
+            "spawn",
+            "Runs a file or code in a worker, the code gets `args` as `...` and `dapi_thread.parent` as its channel",
+        )
+        .params(&[("code", "string"), ("args", "any?")])
+        .returns(&["dapi_thread.Worker"]),
+        FunctionDoc::new(
+            "map",
+            "Calls the function source for every item on `workers` threads, the results keep the order",
+        )
+        .params(&[
+            ("items", "any[]"),
+            ("function_source", "string"),
+            ("workers", "integer?"),
+        ])
+        .returns(&["table"]),
+    ],
+    classes: &[
+        ClassDoc {
+            name: "dapi_thread.Worker",
+            description: "A worker and the channel to it",
+            methods: &[
+                FunctionDoc::new("send", "Sends a value to the worker").params(&[("value", "any")]),
+                FunctionDoc::new("recv", "Waits for a value, nil when the channel is closed")
+                    .returns(&["any"]),
+                FunctionDoc::new("try_recv", "A value if one is waiting").returns(&["any"]),
+                FunctionDoc::new("close", "Closes the channel to the worker"),
+                FunctionDoc::new("join", "Waits for the worker and returns the value of its code")
+                    .returns(&["any"]),
+            ],
+        },
+        ClassDoc {
+            name: "dapi_thread.Channel",
+            description: "`dapi_thread.parent` in a worker, the channel to the script",
+            methods: &[
+                FunctionDoc::new("send", "Sends a value to the script").params(&[("value", "any")]),
+                FunctionDoc::new("recv", "Waits for a value, nil when the channel is closed")
+                    .returns(&["any"]),
+                FunctionDoc::new("try_recv", "A value if one is waiting").returns(&["any"]),
+                FunctionDoc::new("close", "Closes the channel to the script"),
+            ],
+        },
+    ],
+};
+
+pub fn register(lua: &Lua) -> Result<Table> {
+    let table = lua.create_table()?;
+
+    // spawn(file_or_source, args), the code gets args as `...`
+    let spawn = lua.create_function(|lua, (code, args): (String, Value)| {
+        let code = WorkerCode::parse(lua, code)?;
+        let inherited = Inherited::of(lua)?;
+        let args = lua_to_json(&args)?;
+        let (channel, worker_channel) = Channel::pair();
+
+        let handle = thread::Builder::new()
+            .name("flua-worker".to_string())
+            .spawn(move || code.run(&inherited, worker_channel, args))
+            .map_err(|e| dapi_error("dapi_thread.spawn", format!("No thread: {}", e)))?;
+
+        Ok(Worker {
+            channel,
+            handle: Some(handle),
+        })
+    })?;
+
+    // map(items, function_source, workers)
+    let map = lua.create_function(
+        |lua, (items, function_source, workers): (Table, String, Option<usize>)| {
+            let items = items
+                .sequence_values::<Value>()
+                .map(|item| lua_to_json(&item?))
+                .collect::<Result<Vec<_>>>()?;
+            let workers = workers.unwrap_or_else(|| {
+                thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(4)
+            });
+
+            let results = map_items(&Inherited::of(lua)?, items, &function_source, workers)
+                .map_err(|e| dapi_error("dapi_thread.map", e))?;
+
+            let table = lua.create_table()?;
+            for (i, result) in results.iter().enumerate() {
+                table.set(i + 1, json_to_lua(lua, result)?)?;
+            }
+            Ok(table)
+        },
+    )?;
+
+    table.set("spawn", spawn)?;
+    table.set("map", map)?;
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn lua() -> Lua {
+        let lua = new_state(&Permissions::default(), &[]).unwrap();
+        lua.load("thread = require('dapi_thread')").exec().unwrap();
+        lua
+    }
+
+    #[test]
+    fn worker_with_channel() {
+        let lua = lua();
+        let result: String = lua
+            .load(
+                r#"
+                local worker = thread.spawn([[
+                    local args = ...
+                    local parent = require("dapi_thread").parent
+                    local sum = 0
+                    while true do
+                        local value = parent:recv()
+                        if value == nil then break end
+                        sum = sum + value.n
+                        parent:send({ seen = value.n })
+                    end
+                    return args.name .. "=" .. sum
+                ]], { name = "sum" })
+
+                local seen = {}
+                for i = 1, 3 do
+                    worker:send({ n = i })
+                    table.insert(seen, worker:recv().seen)
+                end
+                return table.concat(seen, ",") .. " " .. worker:join()
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(result, "1,2,3 sum=6");
+    }
+
+    #[test]
+    fn worker_file_and_errors() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("worker.lua");
+        fs::write(&file, "local n = ... return n * 2").unwrap();
+
+        let lua = lua();
+        lua.globals()
+            .set("file", file.to_string_lossy().to_string())
+            .unwrap();
+        let doubled: i64 = lua
+            .load("return thread.spawn(file, 21):join()")
+            .eval()
+            .unwrap();
+        assert_eq!(doubled, 42);
+
+        let err = lua
+            .load("thread.spawn('error(\"broken worker\")'):join()")
+            .exec()
+            .unwrap_err();
+        assert!(err.to_string().contains("broken worker"), "{}", err);
+
+        let err = lua.load("thread.spawn('missing.lua')").exec().unwrap_err();
+        assert!(err.to_string().contains("not found"), "{}", err);
+    }
+
+    #[test]
+    fn map_keeps_the_order() {
+        let lua = lua();
+        let result: String = lua
+            .load(
+                r#"
+                local items = {}
+                for i = 1, 20 do items[i] = { n = i } end
+                local squares = thread.map(items, "function(item) return item.n * item.n end", 3)
+                return table.concat(squares, ",")
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let expected: Vec<String> = (1..=20).map(|i: i64| (i * i).to_string()).collect();
+        assert_eq!(result, expected.join(","));
+
+        let err = lua
+            .load("thread.map({ 1, 2 }, 'function(x) error(\"bad item \" .. x) end')")
+            .exec()
+            .unwrap_err();
+        assert!(err.to_string().contains("bad item"), "{}", err);
+    }
+
+    #[test]
+    fn workers_inherit_the_limits() {
+        let lua = lua();
+        Limits {
+            max_instructions: Some(100_000),
+            ..Limits::default()
+        }
+        .install(&lua)
+        .unwrap();
+
+        let err = lua
+            .load("thread.spawn('while true do end'):join()")
+            .exec()
+            .unwrap_err();
+        assert!(err.to_string().contains("100000 instructions"), "{}", err);
+
+        let err = lua
+            .load("thread.map({ 1 }, 'function() while true do end end')")
+            .exec()
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("instructions is used up"),
+            "{}",
+            err
+        );
+    }
+}
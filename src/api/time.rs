@@ -1,3 +1,4 @@
+use crate::api::meta::{FunctionDoc, ModuleDoc};
 use mlua::{Lua, Result, Table};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
@@ -30,12 +31,12 @@ impl Stopwatch {
     }
 
     fn pause(&mut self) {
-        if let Some(start_time) = self.start {
-            if !self.paused {
-                self.elapsed += start_time.elapsed();
-                self.start = None;
-                self.paused = true;
-            }
+        if let Some(start_time) = self.start
+            && !self.paused
+        {
+            self.elapsed += start_time.elapsed();
+            self.start = None;
+            self.paused = true;
         }
     }
 
@@ -46,15 +47,35 @@ impl Stopwatch {
     }
 
     fn read(&self) -> f64 {
-        if let Some(start_time) = self.start {
-            if !self.paused {
-                return (self.elapsed + start_time.elapsed()).as_secs_f64();
-            }
+        if let Some(start_time) = self.start
+            && !self.paused
+        {
+            return (self.elapsed + start_time.elapsed()).as_secs_f64();
         }
         self.elapsed.as_secs_f64()
     }
 }
 
+pub const DOCS: ModuleDoc = ModuleDoc {
+    name: "dapi_time",
+    description: "Waiting and stopwatches",
+    fields: &[],
+    functions: &[
+        FunctionDoc::new("new_stopwatch", "Creates a stopwatch with the id")
+            .params(&[("id", "string")]),
+        FunctionDoc::new("start", "Starts or continues a stopwatch").params(&[("id", "string")]),
+        FunctionDoc::new("pause", "Pauses a stopwatch").params(&[("id", "string")]),
+        FunctionDoc::new("stop", "Stops and resets a stopwatch").params(&[("id", "string")]),
+        FunctionDoc::new("read", "The time of a stopwatch in seconds")
+            .params(&[("id", "string")])
+            .returns(&["number"]),
+        FunctionDoc::new("wait", "Pauses the script for some milliseconds")
+            .params(&[("ms", "integer")]),
+        FunctionDoc::new("waitfr", "Waits forever, e.g. to keep servers running"),
+    ],
+    classes: &[],
+};
+
 pub fn register(lua: &Lua) -> Result<Table> {
     // HashMap zur Verwaltung mehrerer Stoppuhren per ID
     let watches: Arc<Mutex<HashMap<String, Stopwatch>>> = Arc::new(Mutex::new(HashMap::new()));
@@ -189,7 +210,7 @@ mod tests {
         sw.start();
         thread::sleep(Duration::from_millis(100));
         let elapsed = sw.read();
-        assert!(elapsed >= 0.09 && elapsed <= 0.2, "elapsed = {}", elapsed);
+        assert!((0.09..=0.2).contains(&elapsed), "elapsed = {}", elapsed);
     }
 
     #[test]
@@ -0,0 +1,521 @@
+// `flua build script.lua -o tool`, standalone executables
+//
+// The script and the Lua modules of its project are compiled to LuaJIT
+// bytecode and packed with the assets into a zip archive. The archive is
+// appended to a copy of the flua binary:
+//
+// [flua binary][zip archive][archive length, u64 LE][MAGIC]
+//
+// On start flua looks for MAGIC at the end of its own file and runs the
+// bundled script instead of parsing the command line
+
+use mlua::{ChunkMode, Lua, Table};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use walkdir::WalkDir;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::api::async_loop;
+use crate::helper::exit_code::{FILE_NOT_FOUND, FluaError, USAGE_ERROR, take_exit_request};
+use crate::helper::permissions::Permissions;
+use crate::lua_script::{ScriptNotFound, new_state, set_script_paths};
+use crate::project::Project;
+
+const MAGIC: &[u8; 8] = b"FLUABNDL";
+// Length and MAGIC at the end of the file
+const TRAILER_LEN: u64 = 16;
+
+const ENTRY: &str = "main.luac";
+const MODULES: &str = "modules/";
+const ASSETS: &str = "assets/";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Bundle {
+    // Bytecode of the script
+    pub entry: Vec<u8>,
+    // Bytecode of the modules by their require() name
+    pub modules: BTreeMap<String, Vec<u8>>,
+    // Files for dapi_io by their normalized path
+    pub assets: BTreeMap<String, Vec<u8>>,
+}
+
+impl Bundle {
+    pub fn to_zip(&self) -> zip::result::ZipResult<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        zip.start_file(ENTRY, options)?;
+        zip.write_all(&self.entry)?;
+        for (name, code) in &self.modules {
+            zip.start_file(format!("{}{}", MODULES, name), options)?;
+            zip.write_all(code)?;
+        }
+        for (path, content) in &self.assets {
+            zip.start_file(format!("{}{}", ASSETS, path), options)?;
+            zip.write_all(content)?;
+        }
+
+        Ok(zip.finish()?.into_inner())
+    }
+
+    pub fn from_zip(bytes: &[u8]) -> zip::result::ZipResult<Self> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+        let mut bundle = Bundle::default();
+
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let name = file.name().to_string();
+            let mut content = Vec::new();
+            file.read_to_end(&mut content)?;
+
+            if name == ENTRY {
+                bundle.entry = content;
+            } else if let Some(module) = name.strip_prefix(MODULES) {
+                bundle.modules.insert(module.to_string(), content);
+            } else if let Some(asset) = name.strip_prefix(ASSETS) {
+                bundle.assets.insert(asset.to_string(), content);
+            }
+        }
+
+        Ok(bundle)
+    }
+
+    // The bundle at the end of the running executable, if there is one
+    pub fn embedded() -> Option<Bundle> {
+        let exe = std::env::current_exe().ok()?;
+        let mut file = fs::File::open(exe).ok()?;
+        let (start, len) = find_payload(&mut file)?;
+
+        let mut payload = vec![0; len as usize];
+        file.seek(SeekFrom::Start(start)).ok()?;
+        file.read_exact(&mut payload).ok()?;
+        Bundle::from_zip(&payload).ok()
+    }
+
+    // Adds the modules and assets to a Lua state
+    pub fn install(self: &Arc<Self>, lua: &Lua) -> mlua::Result<()> {
+        lua.set_app_data(Arc::clone(self));
+
+        let package: Table = lua.globals().get("package")?;
+        let preload: Table = package.get("preload")?;
+        for (name, code) in &self.modules {
+            let module = lua
+                .load(&code[..])
+                .set_mode(ChunkMode::Binary)
+                .into_function()?;
+            preload.set(name.as_str(), module)?;
+        }
+        Ok(())
+    }
+
+    // Runs the bundled script, all command line arguments go to `arg`
+    pub fn run(self, lua_args: Vec<String>) -> Result<(), FluaError> {
+        let bundle = Arc::new(self);
+        let run = || -> mlua::Result<()> {
+            let lua = new_state(&Permissions::default(), &lua_args)?;
+            if let Ok(exe) = std::env::current_exe() {
+                set_script_paths(&lua, &exe.to_string_lossy())?;
+            }
+            bundle.install(&lua)?;
+            let result = lua
+                .load(&bundle.entry[..])
+                .set_mode(ChunkMode::Binary)
+                .exec()
+                .and_then(|()| async_loop::run_pending(&lua));
+            take_exit_request(&lua, result)
+        };
+        run().map_err(|e| FluaError::from_lua(&e, None))
+    }
+}
+
+// Start and length of the zip archive, None for a normal flua binary
+fn find_payload(file: &mut fs::File) -> Option<(u64, u64)> {
+    let size = file.seek(SeekFrom::End(0)).ok()?;
+    if size < TRAILER_LEN {
+        return None;
+    }
+
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64))).ok()?;
+    file.read_exact(&mut trailer).ok()?;
+    if &trailer[8..] != MAGIC {
+        return None;
+    }
+
+    let len = u64::from_le_bytes(trailer[..8].try_into().ok()?);
+    let start = size.checked_sub(TRAILER_LEN + len)?;
+    Some((start, len))
+}
+
+// Content of a bundled asset for dapi_io, None outside of bundles
+pub fn read_asset(lua: &Lua, path: &str) -> Option<Vec<u8>> {
+    let bundle = lua.app_data_ref::<Arc<Bundle>>()?;
+    bundle.assets.get(&normalize(path)).cloned()
+}
+
+// `./templates\page.html` => `templates/page.html`
+fn normalize(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    path.split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Compiles Lua code to bytecode, the chunk name is used in error messages
+fn compile(lua: &Lua, code: &str, chunk_name: &str) -> Result<Vec<u8>, FluaError> {
+    let code = if code.starts_with("#!") {
+        format!("--{}", code)
+    } else {
+        code.to_string()
+    };
+
+    lua.load(code)
+        .set_name(format!("@{}", chunk_name))
+        .into_function()
+        .map(|function| function.dump(false))
+        .map_err(|e| FluaError::from_lua(&e, None))
+}
+
+// Module names of `require("name")` calls with a literal name
+fn required_modules(code: &str) -> Vec<String> {
+    let bytes = code.as_bytes();
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let mut names = Vec::new();
+    let mut start = 0;
+
+    while let Some(found) = code[start..].find("require") {
+        let at = start + found;
+        start = at + "require".len();
+        if (at > 0 && is_word(bytes[at - 1])) || bytes.get(start).is_some_and(|&b| is_word(b)) {
+            continue;
+        }
+
+        // `require("name")`, `require "name"` and `require 'name'`
+        let rest = code[start..].trim_start();
+        let rest = rest.strip_prefix('(').unwrap_or(rest).trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        if let Some(end) = rest[1..].find([quote, '\n']) {
+            names.push(rest[1..end + 1].to_string());
+        }
+    }
+    names
+}
+
+// File of a module in the require directories, `lib/init.lua` is `require("lib")`
+fn find_module<'a>(dirs: &'a [PathBuf], name: &str) -> Option<(&'a PathBuf, PathBuf)> {
+    let parts: Vec<&str> = name.split('.').collect();
+    if parts
+        .iter()
+        .any(|p| p.is_empty() || p.contains(['/', '\\']))
+    {
+        return None;
+    }
+    let relative: PathBuf = parts.iter().collect();
+
+    dirs.iter().find_map(|dir| {
+        [
+            dir.join(&relative).with_extension("lua"),
+            dir.join(&relative).join("init.lua"),
+        ]
+        .into_iter()
+        .find(|path| path.is_file())
+        .map(|path| (dir, path))
+    })
+}
+
+// Files of an asset path, directories are added with all their files
+fn collect_assets(path: &str) -> Result<Vec<(String, PathBuf)>, FluaError> {
+    let root = Path::new(path);
+    if !root.exists() {
+        return Err(FluaError::new(
+            FILE_NOT_FOUND,
+            format!("Asset '{}' not found!", path),
+        ));
+    }
+
+    let mut assets = Vec::new();
+    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            let name = normalize(&entry.path().to_string_lossy());
+            assets.push((name, entry.into_path()));
+        }
+    }
+    Ok(assets)
+}
+
+// Builds the bundle for a script
+pub fn create_bundle(script: &str, assets: &[String]) -> Result<Bundle, FluaError> {
+    let script_path = fs::canonicalize(script).map_err(|_| {
+        FluaError::from_lua(
+            &mlua::Error::external(ScriptNotFound(script.to_string())),
+            None,
+        )
+    })?;
+    let script_dir = script_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    // Like at runtime the script directory comes first, then the project
+    let mut require_dirs = vec![script_dir.clone()];
+    if let Some(project) = Project::find(&script_dir)? {
+        project.check_version()?;
+        for dir in project.require_dirs() {
+            if !require_dirs.contains(&dir) {
+                require_dirs.push(dir);
+            }
+        }
+    }
+
+    let lua = Lua::new();
+    let mut bundle = Bundle::default();
+
+    let read = |path: &Path| {
+        fs::read_to_string(path)
+            .map_err(|e| FluaError::from(format!("Could not read '{}': {}", path.display(), e)))
+    };
+
+    let file_name = script_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let entry = read(&script_path)?;
+    bundle.entry = compile(&lua, &entry, &file_name)?;
+
+    // Only modules which the script requires, directly or through other modules
+    let mut pending = required_modules(&entry);
+    while let Some(name) = pending.pop() {
+        if bundle.modules.contains_key(&name) {
+            continue;
+        }
+        // `dapi_*` and dlm13 modules are not files in the require directories
+        let Some((dir, path)) = find_module(&require_dirs, &name) else {
+            continue;
+        };
+        if path == script_path {
+            continue;
+        }
+        let code = read(&path)?;
+        let chunk_name = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy();
+        bundle
+            .modules
+            .insert(name, compile(&lua, &code, &chunk_name)?);
+        pending.extend(required_modules(&code));
+    }
+
+    for asset in assets {
+        for (name, path) in collect_assets(asset)? {
+            let content = fs::read(&path).map_err(|e| {
+                FluaError::from(format!("Could not read '{}': {}", path.display(), e))
+            })?;
+            bundle.assets.insert(name, content);
+        }
+    }
+
+    Ok(bundle)
+}
+
+// Writes a copy of the running flua binary with the bundle at the end
+pub fn write_executable(bundle: &Bundle, output: &Path) -> Result<(), FluaError> {
+    let exe = std::env::current_exe()
+        .map_err(|e| FluaError::from(format!("Could not find the flua binary: {}", e)))?;
+    let mut binary = fs::read(&exe)
+        .map_err(|e| FluaError::from(format!("Could not read the flua binary: {}", e)))?;
+
+    // A bundled flua builds with the plain binary
+    let mut file = fs::File::open(&exe)
+        .map_err(|e| FluaError::from(format!("Could not read the flua binary: {}", e)))?;
+    if let Some((start, _)) = find_payload(&mut file) {
+        binary.truncate(start as usize);
+    }
+
+    let payload = bundle
+        .to_zip()
+        .map_err(|e| FluaError::from(format!("Could not pack the bundle: {}", e)))?;
+    binary.extend_from_slice(&payload);
+    binary.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    binary.extend_from_slice(MAGIC);
+
+    if output.is_dir() {
+        return Err(FluaError::new(
+            USAGE_ERROR,
+            format!("The output '{}' is a directory", output.display()),
+        ));
+    }
+    fs::write(output, binary)
+        .map_err(|e| FluaError::from(format!("Could not write '{}': {}", output.display(), e)))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = fs::set_permissions(output, fs::Permissions::from_mode(0o755));
+    }
+
+    Ok(())
+}
+
+// `flua build`
+pub fn build(script: &str, output: Option<&Path>, assets: &[String]) -> Result<(), FluaError> {
+    let bundle = create_bundle(script, assets)?;
+
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => {
+            let name = Path::new(script)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| "app".to_string());
+            PathBuf::from(format!("{}{}", name, std::env::consts::EXE_SUFFIX))
+        }
+    };
+
+    write_executable(&bundle, &output)?;
+    println!(
+        "Built '{}' with {} modules and {} assets",
+        output.display(),
+        bundle.modules.len(),
+        bundle.assets.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn zip_round_trip() {
+        let mut bundle = Bundle {
+            entry: vec![1, 2, 3],
+            ..Bundle::default()
+        };
+        bundle.modules.insert("lib.util".to_string(), vec![4]);
+        bundle
+            .assets
+            .insert("templates/page.html".to_string(), b"<p>".to_vec());
+
+        let zip = bundle.to_zip().unwrap();
+        assert_eq!(Bundle::from_zip(&zip).unwrap(), bundle);
+    }
+
+    #[test]
+    fn finds_the_payload() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tool");
+
+        let payload = Bundle::default().to_zip().unwrap();
+        let mut content = b"binary".to_vec();
+        content.extend_from_slice(&payload);
+        content.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        content.extend_from_slice(MAGIC);
+        fs::write(&path, content).unwrap();
+
+        let mut file = fs::File::open(&path).unwrap();
+        assert_eq!(find_payload(&mut file), Some((6, payload.len() as u64)));
+
+        fs::write(&path, b"just a binary").unwrap();
+        let mut file = fs::File::open(&path).unwrap();
+        assert_eq!(find_payload(&mut file), None);
+    }
+
+    #[test]
+    fn bundled_modules_and_assets() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("lib")).unwrap();
+        fs::create_dir_all(dir.path().join("data")).unwrap();
+        fs::write(
+            dir.path().join("main.lua"),
+            r#"
+            local util = require("lib.util")
+            local text = require("dapi_io").rf("data/hello.txt")
+            result = util.shout(text) .. " " .. arg[1]
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("lib/util.lua"),
+            "return { shout = function(s) return s:upper() end }",
+        )
+        .unwrap();
+        fs::write(dir.path().join("lib/util_test.lua"), "error('not bundled')").unwrap();
+        fs::write(dir.path().join("data/hello.txt"), "hello").unwrap();
+
+        let script = dir.path().join("main.lua");
+        let asset = dir.path().join("data");
+        let mut bundle = create_bundle(
+            &script.to_string_lossy(),
+            &[asset.to_string_lossy().to_string()],
+        )
+        .unwrap();
+        assert_eq!(bundle.modules.keys().collect::<Vec<_>>(), ["lib.util"]);
+
+        // Assets are looked up by the path like the script reads them
+        let asset = bundle.assets.pop_first().unwrap().1;
+        bundle.assets.insert("data/hello.txt".to_string(), asset);
+
+        let bundle = Arc::new(bundle);
+        let lua = new_state(&Permissions::default(), &["world".to_string()]).unwrap();
+        bundle.install(&lua).unwrap();
+        lua.load(&bundle.entry[..])
+            .set_mode(ChunkMode::Binary)
+            .exec()
+            .unwrap();
+
+        let result: String = lua.globals().get("result").unwrap();
+        assert_eq!(result, "HELLO world");
+    }
+
+    #[test]
+    fn only_required_modules_are_bundled() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("lib/text")).unwrap();
+        fs::create_dir_all(dir.path().join("other")).unwrap();
+        fs::write(
+            dir.path().join("main.lua"),
+            "local util = require('lib.util')\nlocal json = require(\"dapi_json\")",
+        )
+        .unwrap();
+        fs::write(dir.path().join("lib/util.lua"), "return require 'lib.text'").unwrap();
+        fs::write(dir.path().join("lib/text/init.lua"), "return {}").unwrap();
+        // Files which are not required may be broken
+        fs::write(dir.path().join("other/broken.lua"), "this is not lua").unwrap();
+        fs::write(dir.path().join("other/latin1.lua"), b"-- \xe9t\xe9").unwrap();
+
+        let bundle = create_bundle(&dir.path().join("main.lua").to_string_lossy(), &[]).unwrap();
+        assert_eq!(
+            bundle.modules.keys().collect::<Vec<_>>(),
+            ["lib.text", "lib.util"]
+        );
+
+        // A broken module which is required fails the build
+        fs::write(dir.path().join("lib/text/init.lua"), "this is not lua").unwrap();
+        assert!(create_bundle(&dir.path().join("main.lua").to_string_lossy(), &[]).is_err());
+    }
+
+    #[test]
+    fn required_module_names() {
+        let code = r#"
+            local a = require("a.b")
+            local c = require 'c'
+            local d = require "d" .. x
+            local e = my_require("e")
+            local f = require(name)
+        "#;
+        assert_eq!(required_modules(code), ["a.b", "c", "d"]);
+        assert!(find_module(&[PathBuf::from(".")], "..evil").is_none());
+    }
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize("./templates\\page.html"), "templates/page.html");
+        assert_eq!(normalize("a//b/./c"), "a/b/c");
+    }
+}
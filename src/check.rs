@@ -0,0 +1,773 @@
+// `flua check`, static checks of the dapi calls of a script
+//
+// The script is split into tokens and never run. Names which get a module from
+// `require("dapi_...")` are followed through the file and every access like
+// `dapi_os.run(...)` is compared with the metadata of api/meta.rs and the
+// deprecation registry: deprecated functions are warnings, unknown functions
+// and wrong argument counts are errors. Scopes are not followed, so only names
+// which are bound once in the whole file, by the require(), are checked. A
+// local, parameter or assignment with the same name anywhere turns it off
+
+use mlua::Lua;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::api::meta::{self, FunctionDoc, ModuleDoc};
+use crate::helper::error_report::MainChunk;
+use crate::helper::exit_code::{CHECK_FAILED, FILE_NOT_FOUND, FluaError};
+use crate::helper::print::{END, GREEN, RED, YELLOW};
+use crate::lua_script::{ScriptNotFound, strip_shebang};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Name,
+    Str,
+    Number,
+    Symbol,
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: Kind,
+    // The content of strings, without quotes
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+const SYMBOLS: &[&str] = &["...", "..", "==", "~=", "<=", ">=", "::", "<<", ">>", "//"];
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl Lexer {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.line_start = self.pos;
+        }
+        Some(c)
+    }
+
+    // The level of a long bracket like `[[` or `[==[` at the position
+    fn long_bracket(&self) -> Option<usize> {
+        if self.peek(0) != Some('[') {
+            return None;
+        }
+        let mut level = 0;
+        while self.peek(1 + level) == Some('=') {
+            level += 1;
+        }
+        (self.peek(1 + level) == Some('[')).then_some(level)
+    }
+
+    // Content of a long string or comment, an unclosed one ends with the file
+    fn long_string(&mut self, level: usize) -> String {
+        for _ in 0..level + 2 {
+            self.bump();
+        }
+        let mut content = String::new();
+        while let Some(c) = self.bump() {
+            if c == ']'
+                && (0..level).all(|k| self.peek(k) == Some('='))
+                && self.peek(level) == Some(']')
+            {
+                for _ in 0..level + 1 {
+                    self.bump();
+                }
+                break;
+            }
+            content.push(c);
+        }
+        content
+    }
+
+    fn skip_space_and_comments(&mut self) {
+        while let Some(c) = self.peek(0) {
+            if c == '-' && self.peek(1) == Some('-') {
+                self.bump();
+                self.bump();
+                match self.long_bracket() {
+                    Some(level) => {
+                        self.long_string(level);
+                    }
+                    None => {
+                        while self.peek(0).is_some_and(|c| c != '\n') {
+                            self.bump();
+                        }
+                    }
+                }
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        self.skip_space_and_comments();
+        let (line, column) = (self.line, self.pos - self.line_start + 1);
+        let c = self.peek(0)?;
+
+        let (kind, text) = if let Some(level) = self.long_bracket() {
+            (Kind::Str, self.long_string(level))
+        } else if c == '"' || c == '\'' {
+            self.bump();
+            let mut text = String::new();
+            while let Some(next) = self.bump() {
+                match next {
+                    '\\' => {
+                        text.push(next);
+                        if let Some(escaped) = self.bump() {
+                            text.push(escaped);
+                        }
+                    }
+                    // An unfinished string ends with the line
+                    '\n' => break,
+                    next if next == c => break,
+                    next => text.push(next),
+                }
+            }
+            (Kind::Str, text)
+        } else if c.is_ascii_digit()
+            || (c == '.' && self.peek(1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            let mut text = String::new();
+            while let Some(next) = self.peek(0) {
+                let exponent = !text.starts_with("0x")
+                    && matches!(text.chars().last(), Some('e' | 'E'))
+                    && matches!(next, '+' | '-');
+                if !(next.is_ascii_alphanumeric()
+                    || (next == '.' && self.peek(1) != Some('.'))
+                    || exponent)
+                {
+                    break;
+                }
+                text.push(next);
+                self.bump();
+            }
+            (Kind::Number, text)
+        } else if c.is_alphabetic() || c == '_' {
+            let mut text = String::new();
+            while let Some(next) = self.peek(0).filter(|c| c.is_alphanumeric() || *c == '_') {
+                text.push(next);
+                self.bump();
+            }
+            (Kind::Name, text)
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| {
+                    symbol
+                        .chars()
+                        .enumerate()
+                        .all(|(k, s)| self.peek(k) == Some(s))
+                })
+                .map(|symbol| symbol.to_string())
+                .unwrap_or_else(|| c.to_string());
+            for _ in 0..symbol.len() {
+                self.bump();
+            }
+            (Kind::Symbol, symbol)
+        };
+
+        Some(Token {
+            kind,
+            text,
+            line,
+            column,
+        })
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut lexer = Lexer {
+        chars: source.chars().collect(),
+        pos: 0,
+        line: 1,
+        line_start: 0,
+    };
+    std::iter::from_fn(|| lexer.next_token()).collect()
+}
+
+struct Checker<'a> {
+    tokens: &'a [Token],
+    // Names which hold a dapi module
+    modules: HashMap<String, &'static ModuleDoc>,
+    // How often each name is declared or assigned in the file
+    bindings: HashMap<String, usize>,
+    // Functions which the script adds to a module, like `function dapi_io.x()`
+    added: HashSet<(&'static str, String)>,
+    deny_deprecated: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    // Whether the token at `i` is the symbol or keyword, strings never match
+    fn is(&self, i: usize, text: &str) -> bool {
+        self.tokens
+            .get(i)
+            .is_some_and(|token| token.kind != Kind::Str && token.text == text)
+    }
+
+    fn is_name(&self, i: usize) -> bool {
+        self.tokens.get(i).is_some_and(|token| {
+            token.kind == Kind::Name && !KEYWORDS.contains(&token.text.as_str())
+        })
+    }
+
+    fn is_str(&self, i: usize) -> bool {
+        self.tokens
+            .get(i)
+            .is_some_and(|token| token.kind == Kind::Str)
+    }
+
+    // `require("name")` or `require "name"` at `i`, the name and the index after it
+    fn require_at(&self, i: usize) -> Option<(&'a str, usize)> {
+        if !self.is(i, "require") {
+            return None;
+        }
+        if self.is(i + 1, "(") && self.is_str(i + 2) && self.is(i + 3, ")") {
+            return Some((&self.tokens[i + 2].text, i + 4));
+        }
+        if self.is_str(i + 1) {
+            return Some((&self.tokens[i + 1].text, i + 2));
+        }
+        None
+    }
+
+    // Counts the locals, parameters, loop variables, assignments and global
+    // functions of every name. A `name = value` right inside `{}` is a field
+    fn count_bindings(&self) -> HashMap<String, usize> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut bind = |token: &Token| *counts.entry(token.text.clone()).or_default() += 1;
+        // Open brackets and blocks
+        let mut open: Vec<&str> = Vec::new();
+
+        for i in 0..self.tokens.len() {
+            let token = &self.tokens[i];
+            if token.kind == Kind::Str {
+                continue;
+            }
+            match token.text.as_str() {
+                "{" | "(" | "[" | "function" | "do" | "then" | "repeat" => open.push(&token.text),
+                "}" | ")" | "]" | "end" | "until" | "elseif" => {
+                    open.pop();
+                }
+                _ => {}
+            }
+
+            if self.is(i, "local") || self.is(i, "for") {
+                // `local a <const>, b` and `for k, v in`
+                let mut j = i + 1;
+                while self.is_name(j) {
+                    bind(&self.tokens[j]);
+                    j += 1;
+                    if self.is(j, "<") {
+                        j += 3;
+                    }
+                    if !self.is(j, ",") {
+                        break;
+                    }
+                    j += 1;
+                }
+            } else if self.is(i, "function") {
+                // `function f(a, b)` binds f unless it is `t.f` or local,
+                // the parameters are bound in every form
+                let mut j = i + 1;
+                if self.is_name(j) && self.is(j + 1, "(") && !(i > 0 && self.is(i - 1, "local")) {
+                    bind(&self.tokens[j]);
+                }
+                while !self.is(j, "(") && j < self.tokens.len() {
+                    j += 1;
+                }
+                while !self.is(j, ")") && j < self.tokens.len() {
+                    if self.is_name(j) {
+                        bind(&self.tokens[j]);
+                    }
+                    j += 1;
+                }
+            } else if self.is_name(i)
+                && open.last() != Some(&"{")
+                && !(i > 0
+                    && [".", ":", ",", "local", "for", "function"]
+                        .iter()
+                        .any(|s| self.is(i - 1, s)))
+            {
+                // `a, b = ...`
+                let mut targets = vec![i];
+                while self.is(targets[targets.len() - 1] + 1, ",")
+                    && self.is_name(targets[targets.len() - 1] + 2)
+                {
+                    targets.push(targets[targets.len() - 1] + 2);
+                }
+                if self.is(targets[targets.len() - 1] + 1, "=") {
+                    for j in targets {
+                        bind(&self.tokens[j]);
+                    }
+                }
+            }
+        }
+        counts
+    }
+
+    fn report(&mut self, i: usize, severity: Severity, message: String) {
+        let token = &self.tokens[i];
+        self.diagnostics.push(Diagnostic {
+            line: token.line,
+            column: token.column,
+            severity,
+            message,
+        });
+    }
+
+    // `local a, b = require("dapi_io"), ...` or the same without `local`
+    fn assignment(&mut self, i: usize) -> Option<()> {
+        let local = self.is(i, "local");
+        if !local
+            && i > 0
+            && [".", ":", ",", "{", "local"]
+                .iter()
+                .any(|s| self.is(i - 1, s))
+        {
+            return None;
+        }
+
+        let mut j = if local { i + 1 } else { i };
+        let mut names = Vec::new();
+        loop {
+            if !self.is_name(j) {
+                return None;
+            }
+            names.push(self.tokens[j].text.clone());
+            j += 1;
+            if !self.is(j, ",") {
+                break;
+            }
+            j += 1;
+        }
+        if !self.is(j, "=") {
+            return None;
+        }
+        j += 1;
+
+        for name in &names {
+            // Only a plain require() counts, not `require("dapi_io").zip`
+            let module = self.require_at(j).filter(|(_, end)| {
+                ![".", ":", "[", "(", "{"].iter().any(|s| self.is(*end, s)) && !self.is_str(*end)
+            });
+            let Some((module, end)) =
+                module.and_then(|(module, end)| Some((meta::module(module)?, end)))
+            else {
+                break;
+            };
+            // Names which are bound again are not followed
+            if self.bindings.get(name) == Some(&1) {
+                self.modules.insert(name.clone(), module);
+            }
+            j = end;
+            if self.is(j, ",") {
+                j += 1;
+            }
+        }
+        Some(())
+    }
+
+    // An access like `dapi_io.zip(...)` or `require("dapi_io").zip` at `i`
+    fn access(&mut self, i: usize) -> Option<()> {
+        let (module, j) = match self.require_at(i) {
+            Some((name, end)) => (meta::module(name)?, end),
+            None => {
+                if !self.is_name(i) || (i > 0 && (self.is(i - 1, ".") || self.is(i - 1, ":"))) {
+                    return None;
+                }
+                (*self.modules.get(&self.tokens[i].text)?, i + 1)
+            }
+        };
+
+        let method = self.is(j, ":");
+        if !(method || self.is(j, ".")) || !self.is_name(j + 1) {
+            return None;
+        }
+        let name = &self.tokens[j + 1].text;
+        // Scripts may add their own functions to a module
+        if self.is(j + 2, "=") || (i > 0 && self.is(i - 1, "function")) {
+            self.added.insert((module.name, name.clone()));
+            return None;
+        }
+
+        let Some(function) = module.function(name) else {
+            if !module.has_field(name) && !self.added.contains(&(module.name, name.clone())) {
+                let message = format!("{}.{} does not exist", module.name, name);
+                self.report(i, Severity::Error, message);
+            }
+            return None;
+        };
+
+        if let Some(deprecation) = module.deprecation(function) {
+            let severity = if self.deny_deprecated {
+                Severity::Error
+            } else {
+                Severity::Warning
+            };
+            let message = format!(
+                "{}.{} is deprecated {}. {}",
+                module.name,
+                function.name,
+                deprecation.versions(),
+                deprecation.replacement
+            );
+            self.report(i, severity, message);
+        }
+
+        // A method call passes the module as the first argument
+        if !method && let Some((count, expands)) = self.arguments(j + 2) {
+            self.check_count(i, module, function, count, expands);
+        }
+        Some(())
+    }
+
+    // The number of arguments of a call at `i`, None when there is no call.
+    // `expands` is true when the last argument is a call or `...`, which can
+    // be any number of values
+    fn arguments(&self, i: usize) -> Option<(usize, bool)> {
+        if self.is_str(i) || self.is(i, "{") {
+            return Some((1, false));
+        }
+        if !self.is(i, "(") {
+            return None;
+        }
+
+        let (mut depth, mut count, mut start) = (0, 0, i + 1);
+        for j in i + 1..self.tokens.len() {
+            if ["(", "{", "["].iter().any(|s| self.is(j, s)) {
+                depth += 1;
+            } else if [")", "}", "]"].iter().any(|s| self.is(j, s)) {
+                if depth > 0 {
+                    depth -= 1;
+                    continue;
+                }
+                if j == i + 1 {
+                    return Some((0, false));
+                }
+                return Some((count + 1, self.expands(start, j)));
+            } else if depth == 0 && self.is(j, ",") {
+                count += 1;
+                start = j + 1;
+            }
+        }
+        None
+    }
+
+    // Whether the expression of the tokens `start..end` can be several values
+    fn expands(&self, start: usize, end: usize) -> bool {
+        if end - start == 1 {
+            return self.is(start, "...");
+        }
+        let last = end - 1;
+        let call = self.is(last, ")") || self.is(last, "}");
+        (call && !self.is(start, "(") && !self.is(start, "{"))
+            || (self.is_str(last) && (self.is_name(last - 1) || self.is(last - 1, ")")))
+    }
+
+    fn check_count(
+        &mut self,
+        i: usize,
+        module: &ModuleDoc,
+        function: &FunctionDoc,
+        count: usize,
+        expands: bool,
+    ) {
+        let varargs = function.params.iter().any(|(name, _)| *name == "...");
+        let max = function.params.len() - usize::from(varargs);
+        let min = function
+            .params
+            .iter()
+            .filter(|(name, ty)| *name != "..." && !ty.ends_with('?'))
+            .count();
+
+        let known = count - usize::from(expands);
+        let too_few = count < min && !expands;
+        let too_many = !varargs && known > max;
+        if !(too_few || too_many) {
+            return;
+        }
+
+        let expected = if varargs {
+            format!("at least {}", min)
+        } else if min == max {
+            min.to_string()
+        } else {
+            format!("{} to {}", min, max)
+        };
+        let message = format!(
+            "{}.{} takes {} argument{}, got {}",
+            module.name,
+            function.name,
+            expected,
+            if expected == "1" { "" } else { "s" },
+            count
+        );
+        self.report(i, Severity::Error, message);
+    }
+}
+
+// Problems of the dapi calls in a script, in the order of the source
+pub fn check_source(source: &str, deny_deprecated: bool) -> Vec<Diagnostic> {
+    let tokens = tokenize(&strip_shebang(source.to_string()));
+    let mut checker = Checker {
+        tokens: &tokens,
+        modules: HashMap::new(),
+        bindings: HashMap::new(),
+        added: HashSet::new(),
+        deny_deprecated,
+        diagnostics: Vec::new(),
+    };
+    checker.bindings = checker.count_bindings();
+    for i in 0..tokens.len() {
+        checker.assignment(i);
+        checker.access(i);
+    }
+    checker.diagnostics
+}
+
+// `flua check`, prints the problems of the files and fails with CHECK_FAILED
+// when there are errors. Syntax errors stop the check
+pub fn check_files(files: &[String], deny_deprecated: bool) -> Result<(), FluaError> {
+    let (mut errors, mut warnings) = (0, 0);
+
+    for file in files {
+        if !Path::new(file).exists() {
+            return Err(FluaError::new(
+                FILE_NOT_FOUND,
+                ScriptNotFound(file.clone()).to_string(),
+            ));
+        }
+        let source =
+            fs::read_to_string(file).map_err(|e| format!("Could not read '{}': {}", file, e))?;
+
+        if let Err(e) = Lua::new()
+            .load(strip_shebang(source.clone()))
+            .set_name(format!("@{}", file))
+            .into_function()
+        {
+            let main = MainChunk {
+                name: file,
+                source: &source,
+            };
+            return Err(FluaError::from_lua(&e, Some(&main)));
+        }
+
+        for diagnostic in check_source(&source, deny_deprecated) {
+            let (color, label) = match diagnostic.severity {
+                Severity::Warning => {
+                    warnings += 1;
+                    (YELLOW, "warning")
+                }
+                Severity::Error => {
+                    errors += 1;
+                    (RED, "error")
+                }
+            };
+            println!(
+                "{}{}:{}:{}: {}: {}{}",
+                color, file, diagnostic.line, diagnostic.column, label, diagnostic.message, END
+            );
+        }
+    }
+
+    if errors == 0 && warnings == 0 {
+        println!("{}No problems found in {} files{}", GREEN, files.len(), END);
+        return Ok(());
+    }
+    println!("{} errors, {} warnings", errors, warnings);
+
+    if errors > 0 {
+        return Err(FluaError::new(
+            CHECK_FAILED,
+            format!("flua check found {} errors", errors),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str, deny_deprecated: bool) -> Vec<String> {
+        check_source(source, deny_deprecated)
+            .into_iter()
+            .map(|d| format!("{}:{} {:?} {}", d.line, d.column, d.severity, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn tokens_skip_comments_and_strings() {
+        let tokens =
+            tokenize("--[==[ dapi_io.zip() ]==] x = 'a\\'b' .. [[\nlong]] -- rest\n0x1F 1e-3 ...");
+        let texts: Vec<(&str, usize)> = tokens.iter().map(|t| (t.text.as_str(), t.line)).collect();
+        assert_eq!(
+            texts,
+            vec![
+                ("x", 1),
+                ("=", 1),
+                ("a\\'b", 1),
+                ("..", 1),
+                ("\nlong", 1),
+                ("0x1F", 3),
+                ("1e-3", 3),
+                ("...", 3)
+            ]
+        );
+        assert_eq!(tokens[0].column, 27);
+    }
+
+    #[test]
+    fn deprecated_calls() {
+        let source = r#"#!/usr/bin/env flua
+local os2 = require("dapi_os")
+local dapi = require "dapi"
+os2.run("ls")
+dapi.wait(10)
+local json = require("dapi_json").encode({})
+"#;
+        assert_eq!(
+            messages(source, false),
+            vec![
+                "4:1 Warning dapi_os.run is deprecated since 0.1.10. Use dapi_os.run2() or io.popen() of the Lua standard library instead",
+                "5:1 Warning dapi.wait is deprecated since 0.1.13. Use dapi_time.wait() instead",
+            ]
+        );
+        assert!(
+            messages(source, true)
+                .iter()
+                .all(|message| message.contains(" Error "))
+        );
+    }
+
+    #[test]
+    fn unknown_functions_and_argument_counts() {
+        let source = r#"
+local io, json = require("dapi_io"), require("dapi_json")
+io.nothing()
+json.encode()
+json.encode({}, true, 3)
+json.encode(...)
+json.encode{ a = 1 }
+json.encode(unpack(args))
+require("dapi_thread").parent:send(1)
+require("dapi_time").sleep(1)
+io.helper = function() end
+io.helper()
+function json.extra() end
+json.extra(1)
+"#;
+        assert_eq!(
+            messages(source, false),
+            vec![
+                "3:1 Error dapi_io.nothing does not exist",
+                "4:1 Error dapi_json.encode takes 1 to 2 arguments, got 0",
+                "5:1 Error dapi_json.encode takes 1 to 2 arguments, got 3",
+                "10:1 Error dapi_time.sleep does not exist",
+            ]
+        );
+    }
+
+    #[test]
+    fn reassigned_names_are_not_checked() {
+        let source = r#"
+local t = require("dapi_time")
+t.wait(1, 2)
+t = {}
+t.wait(1, 2)
+local other = require("other")
+other.anything()
+"#;
+        assert!(messages(source, false).is_empty());
+    }
+
+    #[test]
+    fn shadowed_names_are_not_checked() {
+        let source = r#"
+local json = require("dapi_json")
+local io, t = require("dapi_io"), require("dapi_time")
+local os2, time = require("dapi_os"), require("dapi_time")
+local function show(io) io.write("x", 1, 2) end
+for _, t in ipairs({}) do t.wait(1, 2) end
+local config = { json = json, t = 1 }
+local os2 = {}
+json.encode()
+time.wait()
+"#;
+        assert_eq!(
+            messages(source, false),
+            vec![
+                "9:1 Error dapi_json.encode takes 1 to 2 arguments, got 0",
+                "10:1 Error dapi_time.wait takes 1 argument, got 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_files_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, source: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, source).unwrap();
+            path.to_string_lossy().to_string()
+        };
+
+        let clean = write("clean.lua", "local t = require('dapi_time')\nt.wait(1)\n");
+        let deprecated = write("old.lua", "require('dapi').wait(1)\n");
+        let broken = write("broken.lua", "local = 1\n");
+
+        assert!(check_files(std::slice::from_ref(&clean), false).is_ok());
+        assert!(check_files(std::slice::from_ref(&deprecated), false).is_ok());
+        assert_eq!(
+            check_files(&[clean, deprecated], true).unwrap_err().code,
+            CHECK_FAILED
+        );
+        assert_eq!(
+            check_files(&[broken], false).unwrap_err().code,
+            crate::helper::exit_code::SYNTAX_ERROR
+        );
+        assert_eq!(
+            check_files(&["missing.lua".to_string()], false)
+                .unwrap_err()
+                .code,
+            FILE_NOT_FOUND
+        );
+    }
+}
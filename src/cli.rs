@@ -0,0 +1,1056 @@
+// Command Line Interface of flua
+//
+// `flua <script.lua>` is a shortcut for `flua run <script.lua>`, everything
+// else is a subcommand with its own generated help message
+
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+
+use crate::VERSION;
+use crate::helper::deprecation::Policy;
+use crate::helper::limits::{self, Limits};
+use crate::helper::permissions::Permissions;
+use crate::init::Template;
+use crate::lua_script::ScriptSource;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "flua",
+    version = VERSION,
+    about = "A simple Lua scripting environment with a custom Lua API written in Rust",
+    after_help = "More info about Flua and the Lua API:\n  https://github.com/ShadowDara/LuaAPI-Rust\n  https://shadowdara.github.io/flua/",
+    args_conflicts_with_subcommands = true,
+    disable_version_flag = true
+)]
+pub struct Cli {
+    /// Print the version
+    #[arg(short = 'v', long, action = ArgAction::Version)]
+    version: Option<bool>,
+
+    #[command(flatten)]
+    pub general: GeneralOptions,
+
+    #[command(flatten)]
+    pub script: ScriptOptions,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// Options which can be used with every subcommand
+#[derive(Debug, Args)]
+pub struct GeneralOptions {
+    /// Exit instantly after an error instead of waiting some time (old: -nw)
+    #[arg(long, global = true)]
+    pub no_wait: bool,
+
+    /// Do not search or load the config file (old: -no-config)
+    #[arg(long, global = true)]
+    pub no_config: bool,
+
+    /// Suppress the start and end info messages
+    #[arg(long, global = true)]
+    pub no_info: bool,
+
+    /// What calls of deprecated dapi functions do, `warn` by default or the
+    /// value of the config file
+    #[arg(long, global = true, value_enum, value_name = "POLICY")]
+    pub deprecations: Option<Policy>,
+}
+
+// Options for running a Lua script
+#[derive(Debug, Args)]
+pub struct ScriptOptions {
+    /// Lua script to run, `-` reads the script from stdin
+    #[arg(value_name = "SCRIPT")]
+    pub script: Option<String>,
+
+    /// Arguments for the `arg` table of the script
+    #[arg(value_name = "ARGS")]
+    pub args: Vec<String>,
+
+    /// Arguments after `--` are passed verbatim, even when they start with '-'
+    #[arg(last = true, value_name = "RAW_ARGS")]
+    pub raw_args: Vec<String>,
+
+    /// Run Lua code instead of a script file
+    #[arg(short = 'e', value_name = "CODE")]
+    pub eval: Option<String>,
+
+    #[command(flatten)]
+    pub permissions: PermissionOptions,
+
+    #[command(flatten)]
+    pub limits: LimitOptions,
+
+    #[command(flatten)]
+    pub profile: ProfileOptions,
+
+    #[command(flatten)]
+    pub coverage: CoverageOptions,
+}
+
+impl ScriptOptions {
+    // Where the code comes from, None starts the REPL
+    pub fn source(&self) -> Option<ScriptSource> {
+        if let Some(code) = &self.eval {
+            return Some(ScriptSource::Inline(code.clone()));
+        }
+
+        match self.script.as_deref() {
+            Some("-") => Some(ScriptSource::Stdin),
+            Some(path) => Some(ScriptSource::File(path.to_string())),
+            None => None,
+        }
+    }
+
+    // All arguments for the Lua `arg` table
+    pub fn lua_args(&self) -> Vec<String> {
+        let mut lua_args = Vec::new();
+
+        // With -e the script position is already a Lua argument
+        if self.eval.is_some() {
+            lua_args.extend(self.script.iter().cloned());
+        }
+        lua_args.extend(self.args.iter().cloned());
+        lua_args.extend(self.raw_args.iter().cloned());
+        lua_args
+    }
+}
+
+// Options for the safe mode
+#[derive(Debug, Args)]
+pub struct PermissionOptions {
+    /// Run in safe mode (no file, network or process access until it is granted)
+    #[arg(long)]
+    pub safe: bool,
+
+    /// Allow reading files in <DIR> in safe mode, without a value everything
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = "", value_delimiter = ',')]
+    pub allow_read: Vec<String>,
+
+    /// Allow writing files in <DIR> in safe mode, without a value everything
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = "", value_delimiter = ',')]
+    pub allow_write: Vec<String>,
+
+    /// Allow network access to <HOST> or <HOST:PORT> in safe mode, without a value everything
+    #[arg(long, value_name = "HOST", num_args = 0..=1, default_missing_value = "", value_delimiter = ',')]
+    pub allow_net: Vec<String>,
+
+    /// Allow starting other programms in safe mode
+    #[arg(long)]
+    pub allow_run: bool,
+
+    /// Allow reading and setting environment variables in safe mode
+    #[arg(long)]
+    pub allow_env: bool,
+}
+
+impl PermissionOptions {
+    // Without --safe every script has full access
+    pub fn permissions(&self) -> Permissions {
+        if !self.safe {
+            return Permissions::allow_all();
+        }
+
+        let mut permissions = Permissions::safe();
+        for (flag, values) in [
+            ("--allow-read", &self.allow_read),
+            ("--allow-write", &self.allow_write),
+            ("--allow-net", &self.allow_net),
+        ] {
+            for value in values {
+                permissions.parse_flag(&format!("{}={}", flag, value));
+            }
+        }
+        if self.allow_run {
+            permissions.parse_flag("--allow-run");
+        }
+        if self.allow_env {
+            permissions.parse_flag("--allow-env");
+        }
+        permissions
+    }
+}
+
+// Options for the execution limits of a script
+#[derive(Debug, Args)]
+pub struct LimitOptions {
+    /// Stop the script after this time, e.g. 30s, 500ms or 2m
+    #[arg(long, value_name = "DURATION", value_parser = limits::parse_duration)]
+    pub timeout: Option<std::time::Duration>,
+
+    /// Memory cap of the Lua state, e.g. 256M or 1G
+    #[arg(long, value_name = "SIZE", value_parser = limits::parse_size)]
+    pub max_memory: Option<usize>,
+
+    /// Stop the script after this many Lua instructions
+    #[arg(long, value_name = "COUNT")]
+    pub max_instructions: Option<u64>,
+}
+
+impl LimitOptions {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            timeout: self.timeout,
+            max_memory: self.max_memory,
+            max_instructions: self.max_instructions,
+        }
+    }
+}
+
+// Options of the sampling profiler
+#[derive(Debug, Clone, Args)]
+pub struct ProfileOptions {
+    /// Profile the script and write collapsed stacks for flamegraph tools
+    #[arg(long, value_name = "FILE")]
+    pub profile: Option<PathBuf>,
+
+    /// Number of functions in the profile table
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "profile")]
+    pub profile_top: usize,
+}
+
+// Options of the line coverage
+#[derive(Debug, Clone, Args)]
+pub struct CoverageOptions {
+    /// Record the executed lines and write lcov.info and index.html
+    #[arg(long)]
+    pub coverage: bool,
+
+    /// Directory of the coverage report
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "coverage",
+        requires = "coverage"
+    )]
+    pub coverage_dir: PathBuf,
+}
+
+impl CoverageOptions {
+    // The report directory, None without --coverage
+    pub fn dir(&self) -> Option<&Path> {
+        self.coverage.then_some(self.coverage_dir.as_path())
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a Lua script or a dlm13 module
+    Run(RunArgs),
+
+    /// Run a script again whenever it or a file it uses changes
+    Watch(WatchArgs),
+
+    /// Run the tests in `*_test.lua` and `test_*.lua` files
+    Test(TestArgs),
+
+    /// Debug a script in an editor over the Debug Adapter Protocol
+    Debug(DebugArgs),
+
+    /// Compile a script into a standalone executable
+    Build(BuildArgs),
+
+    /// Check the dapi calls of scripts without running them
+    Check {
+        /// Lua scripts to check
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// Treat calls of deprecated functions as errors
+        #[arg(long)]
+        deny_deprecated: bool,
+    },
+
+    /// List the deprecated dapi functions and their replacements
+    Deprecations,
+
+    /// Write LuaLS type definitions of the dapi modules for editor completion
+    Stubs {
+        /// Directory of the `---@meta` files
+        #[arg(long, default_value = "types")]
+        out: PathBuf,
+    },
+
+    /// Create a new dlm13 module or project
+    Init(InitArgs),
+
+    /// Start an interactive Lua prompt with all modules
+    Repl {
+        #[command(flatten)]
+        permissions: PermissionOptions,
+    },
+
+    /// Manage the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Work with dlm13 modules
+    Module {
+        #[command(subcommand)]
+        action: ModuleAction,
+    },
+
+    /// Check for updates of flua
+    Update,
+
+    /// Install flua
+    Install,
+}
+
+#[derive(Debug, Args)]
+pub struct InitArgs {
+    /// Folder and module name, without it the current directory is used
+    #[arg(value_name = "NAME")]
+    pub name: Option<String>,
+
+    /// What the entrypoint main.lua does
+    #[arg(long, value_enum, default_value_t = Template::Script)]
+    pub template: Template,
+
+    /// Overwrite existing files
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Path to a Lua script (`-` for stdin) or the name of a dlm13 module
+    #[arg(value_name = "TARGET")]
+    pub target: Option<String>,
+
+    /// Arguments for the `arg` table of the script or module
+    #[arg(value_name = "ARGS")]
+    pub args: Vec<String>,
+
+    /// Arguments after `--` are passed verbatim, even when they start with '-'
+    #[arg(last = true, value_name = "RAW_ARGS")]
+    pub raw_args: Vec<String>,
+
+    /// Path of the module directory (old: -path=<dir>)
+    #[arg(long, value_name = "DIR")]
+    pub path: Option<String>,
+
+    #[command(flatten)]
+    pub permissions: PermissionOptions,
+
+    #[command(flatten)]
+    pub limits: LimitOptions,
+
+    #[command(flatten)]
+    pub profile: ProfileOptions,
+
+    #[command(flatten)]
+    pub coverage: CoverageOptions,
+}
+
+impl RunArgs {
+    // `flua run <script.lua>` runs a script, everything else is a module
+    pub fn is_script(&self) -> bool {
+        if self.path.is_some() {
+            return false;
+        }
+        match self.target.as_deref() {
+            Some(target) => {
+                target == "-" || target.ends_with(".lua") || std::path::Path::new(target).is_file()
+            }
+            None => false,
+        }
+    }
+
+    // All arguments for the Lua `arg` table
+    pub fn lua_args(&self) -> Vec<String> {
+        let mut lua_args = self.args.clone();
+        lua_args.extend(self.raw_args.iter().cloned());
+        lua_args
+    }
+
+    // The name and the `arg` table of a module. With --path all arguments go
+    // to the module, only the placeholder of the old `flua run module
+    // -path=<dir>` is dropped
+    pub fn module_args(&self) -> (Option<&str>, Vec<String>) {
+        if self.path.is_none() {
+            return (self.target.as_deref(), self.lua_args());
+        }
+        let mut lua_args = self.lua_args();
+        match self.target.as_deref() {
+            None | Some("module") => {}
+            Some(target) => lua_args.insert(0, target.to_string()),
+        }
+        (None, lua_args)
+    }
+
+    // The same options as `flua <script.lua>`
+    pub fn into_script(self) -> ScriptOptions {
+        ScriptOptions {
+            script: self.target,
+            args: self.args,
+            raw_args: self.raw_args,
+            eval: None,
+            permissions: self.permissions,
+            limits: self.limits,
+            profile: self.profile,
+            coverage: self.coverage,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct WatchArgs {
+    /// Lua script to watch
+    #[arg(value_name = "SCRIPT")]
+    pub script: String,
+
+    /// Arguments for the `arg` table of the script
+    #[arg(value_name = "ARGS")]
+    pub args: Vec<String>,
+
+    /// Arguments after `--` are passed verbatim, even when they start with '-'
+    #[arg(last = true, value_name = "RAW_ARGS")]
+    pub raw_args: Vec<String>,
+
+    #[command(flatten)]
+    pub permissions: PermissionOptions,
+
+    #[command(flatten)]
+    pub limits: LimitOptions,
+}
+
+impl WatchArgs {
+    // All arguments for the Lua `arg` table
+    pub fn lua_args(&self) -> Vec<String> {
+        let mut lua_args = self.args.clone();
+        lua_args.extend(self.raw_args.iter().cloned());
+        lua_args
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct DebugArgs {
+    /// Lua script to debug
+    #[arg(value_name = "SCRIPT")]
+    pub script: String,
+
+    /// Arguments for the `arg` table of the script
+    #[arg(value_name = "ARGS")]
+    pub args: Vec<String>,
+
+    /// Arguments after `--` are passed verbatim, even when they start with '-'
+    #[arg(last = true, value_name = "RAW_ARGS")]
+    pub raw_args: Vec<String>,
+
+    /// TCP port on 127.0.0.1 where the editor connects
+    #[arg(long, value_name = "PORT", default_value_t = 4711)]
+    pub port: u16,
+
+    #[command(flatten)]
+    pub permissions: PermissionOptions,
+}
+
+impl DebugArgs {
+    // All arguments for the Lua `arg` table
+    pub fn lua_args(&self) -> Vec<String> {
+        let mut lua_args = self.args.clone();
+        lua_args.extend(self.raw_args.iter().cloned());
+        lua_args
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct TestArgs {
+    /// Directory with the test files
+    #[arg(value_name = "DIR", default_value = ".")]
+    pub dir: String,
+
+    /// Write the results as JUnit XML, e.g. for CI
+    #[arg(long, value_name = "FILE")]
+    pub junit: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub permissions: PermissionOptions,
+
+    /// Limits for every single test
+    #[command(flatten)]
+    pub limits: LimitOptions,
+
+    /// Coverage of all tests together
+    #[command(flatten)]
+    pub coverage: CoverageOptions,
+}
+
+#[derive(Debug, Args)]
+pub struct BuildArgs {
+    /// Entry script of the executable
+    #[arg(value_name = "SCRIPT")]
+    pub script: String,
+
+    /// Path of the executable, the name of the script by default
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// File or directory which the script can read with dapi_io
+    #[arg(long = "asset", value_name = "PATH")]
+    pub assets: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Create a new config file, an existing one is not overwritten
+    Generate,
+    /// Open the config file in the default programm
+    Open,
+    /// Open the config folder in the default programm
+    Opendir,
+    /// Print the config folder
+    Dir,
+    /// Check if the config file is correct
+    Check,
+    /// Delete the current config file
+    Clean,
+}
+
+impl ConfigAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigAction::Generate => "generate",
+            ConfigAction::Open => "open",
+            ConfigAction::Opendir => "opendir",
+            ConfigAction::Dir => "dir",
+            ConfigAction::Check => "check",
+            ConfigAction::Clean => "clean",
+        }
+    }
+}
+
+// Parsed once per run, boxing RunArgs would only make the matches longer
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Subcommand)]
+pub enum ModuleAction {
+    /// Run a dlm13 module
+    Run(RunArgs),
+
+    /// Install a module folder or zip file into the module store
+    Install {
+        /// Folder with a dlm13.yml or a zip file of it
+        #[arg(value_name = "PATH")]
+        source: PathBuf,
+
+        /// Replace the version when it is installed already
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// List the installed modules
+    List,
+
+    /// Remove a module, or one version with <name>@<version>
+    Remove {
+        #[arg(value_name = "NAME[@VERSION]")]
+        module: String,
+    },
+
+    /// Pick the dependencies of a module from the store and write dlm13.lock
+    Lock {
+        /// Folder of the module
+        #[arg(value_name = "DIR", default_value = ".")]
+        dir: PathBuf,
+    },
+
+    /// Set the version which `flua run <name>` uses
+    Default {
+        #[arg(value_name = "NAME@VERSION")]
+        module: String,
+    },
+}
+
+// Flags of GeneralOptions
+const GLOBAL_FLAGS: [&str; 3] = ["--no-wait", "--no-config", "--no-info"];
+
+// Rewrites the arguments of older flua versions to the new syntax
+//
+// -nw          => --no-wait
+// -no-config   => --no-config
+// h            => --help (only as first argument)
+// --help-config => config --help
+// l, lua-args  => removed, the following arguments are script arguments
+// run module -path=<dir> => run module --path=<dir>
+// run update, run install => update, install
+//
+// Only the arguments in front of the script or subcommand are rewritten, the
+// arguments after the script belong to it. Behind the target of `flua run` a
+// `--` is put in front of the first unknown option
+pub fn normalize_args(args: Vec<String>) -> Vec<String> {
+    let mut normalized = Vec::with_capacity(args.len());
+    // Index of the script or subcommand
+    let mut first = None;
+
+    for (i, arg) in args.into_iter().enumerate() {
+        if i == 0 || first.is_some() {
+            normalized.push(arg);
+            continue;
+        }
+
+        match arg.as_str() {
+            "--" => {
+                first = Some(normalized.len());
+                normalized.push(arg);
+            }
+            "-nw" => normalized.push("--no-wait".to_string()),
+            "-no-config" => normalized.push("--no-config".to_string()),
+            "h" if i == 1 => normalized.push("--help".to_string()),
+            "--help-config" => {
+                normalized.push("config".to_string());
+                normalized.push("--help".to_string());
+            }
+            "l" | "lua-args" => {}
+            a if a.starts_with('-') => normalized.push(arg),
+            _ => {
+                first = Some(normalized.len());
+                normalized.push(arg);
+            }
+        }
+    }
+
+    // The old module syntax `flua run module -path=<dir>`
+    if let Some(first) = first
+        && normalized[first] == "run"
+        && normalized.get(first + 1).is_some_and(|a| a == "module")
+        && let Some(path) = normalized.get_mut(first + 2)
+        && path.starts_with("-path=")
+    {
+        path.insert(0, '-');
+    }
+
+    // The old `flua run update` and `flua run install` without arguments
+    if let Some(first) = first
+        && normalized.len() == first + 2
+        && normalized[first] == "run"
+        && matches!(normalized[first + 1].as_str(), "update" | "install")
+    {
+        normalized.remove(first);
+    }
+
+    // Unknown options after the target of `flua run` are Lua arguments like
+    // in `flua run greeter Alice --loud`, clap takes the arguments after `--`
+    // verbatim
+    if let Some(first) = first
+        && normalized[first] == "run"
+    {
+        let mut command = Cli::command();
+        command.build();
+        if let Some(run) = command.find_subcommand("run")
+            && let Some(i) = first_lua_option(run, &normalized[first + 1..])
+        {
+            let i = first + 1 + i;
+            if let Some(raw) = normalized[i..].iter().position(|a| a == "--") {
+                normalized.remove(i + raw);
+            }
+            normalized.insert(i, "--".to_string());
+        }
+    }
+
+    // Global flags in front of a subcommand are moved behind it, otherwise
+    // clap takes `flua --no-wait test` for the script `test`
+    let flags = normalized
+        .iter()
+        .skip(1)
+        .take_while(|a| GLOBAL_FLAGS.contains(&a.as_str()))
+        .count();
+    if flags > 0
+        && let Some(next) = normalized.get(1 + flags)
+        && Cli::command().find_subcommand(next).is_some()
+    {
+        let command = normalized.remove(1 + flags);
+        normalized.insert(1, command);
+    }
+
+    normalized
+}
+
+// Index of the first option after the target which `command` does not know,
+// the arguments start behind the subcommand
+fn first_lua_option(command: &clap::Command, args: &[String]) -> Option<usize> {
+    let mut target = false;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "--" {
+            return None;
+        }
+        if arg.len() > 1 && arg.starts_with('-') {
+            let (option, attached) = match arg.strip_prefix("--") {
+                Some(long) => {
+                    let name = long.split('=').next().unwrap_or(long);
+                    let option = command.get_arguments().find(|a| a.get_long() == Some(name));
+                    (option, long.contains('='))
+                }
+                None => {
+                    let short = arg.chars().nth(1);
+                    let option = command.get_arguments().find(|a| a.get_short() == short);
+                    (option, arg.len() > 2)
+                }
+            };
+            match option {
+                // The value of `--timeout 30s` is no target
+                Some(option) if option.get_action().takes_values() && !attached => i += 1,
+                Some(_) => {}
+                None if target => return Some(i),
+                None => {}
+            }
+        } else {
+            target = true;
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        Cli::try_parse_from(normalize_args(args)).expect("arguments should parse")
+    }
+
+    #[test]
+    fn global_flags_before_subcommand() {
+        let cli = parse(&["flua", "-nw", "--no-info", "run", "script.lua"]);
+        assert!(cli.general.no_wait);
+        assert!(cli.general.no_info);
+        assert!(matches!(cli.command, Some(Command::Run(_))));
+
+        let cli = parse(&["flua", "--no-wait", "script.lua"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.script.script.as_deref(), Some("script.lua"));
+    }
+
+    #[test]
+    fn script_with_args_and_raw_args() {
+        let cli = parse(&["flua", "script.lua", "a", "b", "--", "-x", "--y"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.script.script.as_deref(), Some("script.lua"));
+        assert_eq!(cli.script.lua_args(), vec!["a", "b", "-x", "--y"]);
+    }
+
+    #[test]
+    fn legacy_flags_are_rewritten() {
+        let cli = parse(&["flua", "-nw", "-no-config", "l", "script.lua", "one", "two"]);
+        assert!(cli.general.no_wait);
+        assert!(cli.general.no_config);
+        assert_eq!(cli.script.lua_args(), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn script_args_are_not_rewritten() {
+        let cli = parse(&[
+            "flua",
+            "tool.lua",
+            "l",
+            "lua-args",
+            "h",
+            "--",
+            "-nw",
+            "-no-config",
+            "-path=x",
+        ]);
+        assert!(!cli.general.no_wait);
+        assert!(!cli.general.no_config);
+        assert_eq!(
+            cli.script.lua_args(),
+            vec!["l", "lua-args", "h", "-nw", "-no-config", "-path=x"]
+        );
+
+        let cli = parse(&["flua", "run", "tool.lua", "l", "module"]);
+        let Some(Command::Run(run)) = cli.command else {
+            panic!("expected the run command");
+        };
+        assert_eq!(run.lua_args(), vec!["l", "module"]);
+
+        // -nw after the script is no flua flag any more
+        let args = ["flua", "tool.lua", "-nw"].map(String::from);
+        assert!(Cli::try_parse_from(normalize_args(args.to_vec())).is_err());
+    }
+
+    #[test]
+    fn old_update_and_install_commands() {
+        assert!(matches!(
+            parse(&["flua", "-nw", "run", "update"]).command,
+            Some(Command::Update)
+        ));
+        assert!(matches!(
+            parse(&["flua", "run", "install"]).command,
+            Some(Command::Install)
+        ));
+
+        // With arguments it is a module
+        let cli = parse(&["flua", "run", "update", "now"]);
+        let Some(Command::Run(run)) = cli.command else {
+            panic!("expected the run command");
+        };
+        assert_eq!(run.module_args(), (Some("update"), vec!["now".into()]));
+    }
+
+    #[test]
+    fn unknown_options_after_the_run_target_are_args() {
+        let cli = parse(&[
+            "flua",
+            "run",
+            "--safe",
+            "tool.lua",
+            "--timeout",
+            "3s",
+            "x",
+            "--loud",
+            "--safe",
+            "--",
+            "-y",
+        ]);
+        let Some(Command::Run(run)) = cli.command else {
+            panic!("expected the run command");
+        };
+        assert!(run.permissions.safe);
+        assert!(run.limits.limits().timeout.is_some());
+        assert_eq!(run.lua_args(), vec!["x", "--loud", "--safe", "-y"]);
+
+        let cli = parse(&["flua", "run", "--path=dir", "module", "a", "-b"]);
+        let Some(Command::Run(run)) = cli.command else {
+            panic!("expected the run command");
+        };
+        assert!(!run.is_script());
+        assert_eq!(run.module_args(), (None, vec!["a".into(), "-b".into()]));
+    }
+
+    #[test]
+    fn inline_code_takes_all_positionals_as_args() {
+        let cli = parse(&["flua", "-e", "print(arg[1])", "first", "second"]);
+        assert!(matches!(cli.script.source(), Some(ScriptSource::Inline(_))));
+        assert_eq!(cli.script.lua_args(), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn stdin_and_repl_sources() {
+        let cli = parse(&["flua", "-"]);
+        assert!(matches!(cli.script.source(), Some(ScriptSource::Stdin)));
+
+        let cli = parse(&["flua"]);
+        assert!(cli.script.source().is_none());
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn permissions_from_flags() {
+        let cli = parse(&[
+            "flua",
+            "script.lua",
+            "--safe",
+            "--allow-net=example.com,localhost",
+            "--allow-read",
+            "--allow-run",
+            "--allow-env",
+        ]);
+        let permissions = cli.script.permissions.permissions();
+        assert!(permissions.safe);
+        assert!(permissions.check_net("example.com").is_ok());
+        assert!(permissions.check_net("other.com").is_err());
+        assert!(permissions.check_read(std::path::Path::new("/etc")).is_ok());
+        assert!(permissions.check_run("ls").is_ok());
+        assert!(permissions.check_env("HOME").is_ok());
+        assert!(
+            permissions
+                .check_write(std::path::Path::new("out.txt"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn limits_from_flags() {
+        let cli = parse(&[
+            "flua",
+            "run",
+            "script.lua",
+            "--timeout=30s",
+            "--max-memory=256M",
+            "--max-instructions",
+            "1000000",
+        ]);
+        let Some(Command::Run(run)) = cli.command else {
+            panic!("expected the run command");
+        };
+        let limits = run.limits.limits();
+        assert_eq!(limits.timeout, Some(std::time::Duration::from_secs(30)));
+        assert_eq!(limits.max_memory, Some(256 * 1024 * 1024));
+        assert_eq!(limits.max_instructions, Some(1_000_000));
+
+        let args = ["flua", "script.lua", "--timeout=soon"].map(String::from);
+        assert!(Cli::try_parse_from(normalize_args(args.to_vec())).is_err());
+    }
+
+    #[test]
+    fn profile_options() {
+        let cli = parse(&["flua", "--profile=out.folded", "script.lua"]);
+        assert_eq!(
+            cli.script.profile.profile,
+            Some(PathBuf::from("out.folded"))
+        );
+        assert_eq!(cli.script.profile.profile_top, 10);
+
+        let args = ["flua", "--profile-top=3", "script.lua"].map(String::from);
+        assert!(Cli::try_parse_from(normalize_args(args.to_vec())).is_err());
+    }
+
+    #[test]
+    fn coverage_options() {
+        let cli = parse(&["flua", "--coverage", "script.lua"]);
+        assert!(cli.script.coverage.coverage);
+        assert_eq!(cli.script.coverage.coverage_dir, PathBuf::from("coverage"));
+
+        let cli = parse(&["flua", "test", "tests", "--coverage", "--coverage-dir=out"]);
+        let Some(Command::Test(test)) = cli.command else {
+            panic!("expected the test command");
+        };
+        assert_eq!(test.coverage.coverage_dir, PathBuf::from("out"));
+    }
+
+    #[test]
+    fn debug_command() {
+        let cli = parse(&["flua", "debug", "--port", "9000", "main.lua", "a"]);
+        let Some(Command::Debug(debug)) = cli.command else {
+            panic!("expected the debug command");
+        };
+        assert_eq!(debug.port, 9000);
+        assert_eq!(debug.script, "main.lua");
+        assert_eq!(debug.lua_args(), vec!["a"]);
+    }
+
+    #[test]
+    fn check_command() {
+        let cli = parse(&["flua", "check", "a.lua", "b.lua", "--deny-deprecated"]);
+        let Some(Command::Check {
+            files,
+            deny_deprecated,
+        }) = cli.command
+        else {
+            panic!("expected the check command");
+        };
+        assert_eq!(files, vec!["a.lua", "b.lua"]);
+        assert!(deny_deprecated);
+
+        assert!(Cli::try_parse_from(["flua", "check"]).is_err());
+    }
+
+    #[test]
+    fn deprecation_policy() {
+        let cli = parse(&["flua", "deprecations"]);
+        assert!(matches!(cli.command, Some(Command::Deprecations)));
+        assert_eq!(cli.general.deprecations, None);
+
+        let cli = parse(&["flua", "run", "main.lua", "--deprecations", "error"]);
+        assert_eq!(cli.general.deprecations, Some(Policy::Error));
+
+        let args = ["flua", "--deprecations=loud", "main.lua"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn init_command() {
+        let cli = parse(&["flua", "init"]);
+        let Some(Command::Init(init)) = cli.command else {
+            panic!("expected the init command");
+        };
+        assert_eq!(init.name, None);
+        assert_eq!(init.template, Template::Script);
+        assert!(!init.force);
+
+        let cli = parse(&["flua", "init", "api", "--template=server", "--force"]);
+        let Some(Command::Init(init)) = cli.command else {
+            panic!("expected the init command");
+        };
+        assert_eq!(init.name.as_deref(), Some("api"));
+        assert_eq!(init.template, Template::Server);
+        assert!(init.force);
+    }
+
+    #[test]
+    fn module_store_commands() {
+        let cli = parse(&["flua", "module", "install", "greeter.zip", "--force"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Module {
+                action: ModuleAction::Install { source, force: true }
+            }) if source == Path::new("greeter.zip")
+        ));
+
+        let cli = parse(&["flua", "module", "remove", "greeter@1.0.0"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Module {
+                action: ModuleAction::Remove { module }
+            }) if module == "greeter@1.0.0"
+        ));
+
+        let cli = parse(&["flua", "module", "lock"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Module {
+                action: ModuleAction::Lock { dir }
+            }) if dir == Path::new(".")
+        ));
+
+        let cli = parse(&["flua", "module", "list"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Module {
+                action: ModuleAction::List
+            })
+        ));
+    }
+
+    #[test]
+    fn stubs_command() {
+        let cli = parse(&["flua", "stubs"]);
+        assert!(matches!(cli.command, Some(Command::Stubs { out }) if out == Path::new("types")));
+
+        let cli = parse(&["flua", "stubs", "--out", "lua/types"]);
+        assert!(
+            matches!(cli.command, Some(Command::Stubs { out }) if out == Path::new("lua/types"))
+        );
+    }
+
+    #[test]
+    fn subcommands() {
+        let cli = parse(&["flua", "config", "generate", "--no-wait"]);
+        assert!(cli.general.no_wait);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Config {
+                action: ConfigAction::Generate
+            })
+        ));
+
+        let cli = parse(&["flua", "run", "module", "-path=test/datestmodule"]);
+        match cli.command {
+            Some(Command::Run(run)) => {
+                assert!(!run.is_script());
+                assert_eq!(run.path.as_deref(), Some("test/datestmodule"));
+            }
+            other => panic!("expected run, got {:?}", other),
+        }
+
+        let cli = parse(&["flua", "run", "script.lua", "x", "--safe", "--", "-y"]);
+        match cli.command {
+            Some(Command::Run(run)) => {
+                assert!(run.is_script());
+                assert!(run.permissions.safe);
+                let script = run.into_script();
+                assert_eq!(script.script.as_deref(), Some("script.lua"));
+                assert_eq!(script.lua_args(), vec!["x", "-y"]);
+            }
+            other => panic!("expected run, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,394 @@
+// The subcommands of the flua binary
+
+use std::env;
+use std::net::TcpListener;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::cli::{
+    Command, CoverageOptions, DebugArgs, InitArgs, ModuleAction, ProfileOptions, RunArgs,
+    ScriptOptions, TestArgs,
+};
+use crate::coverage::Coverage;
+use crate::debugger::{self, Debugger};
+use crate::dlm13::lock;
+use crate::dlm13::store::{self, Store};
+use crate::helper::deprecation;
+use crate::helper::exit_code::{self, ExitRequest, FluaError};
+use crate::helper::limits::{self, LimitExceeded, Limits};
+use crate::helper::permissions::Permissions;
+use crate::helper::print::{BOLD, END, GREEN};
+use crate::lua_script::{self, ScriptSource};
+use crate::profiler::Profiler;
+use crate::project::Project;
+use crate::{api, bundle, check, dlm13, helper, init, repl, test_runner, watch};
+
+// Time after --timeout until a script in a native call is abandoned
+const TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+
+// Runs a parsed command line, without a command the script, the project
+// entrypoint or the REPL
+pub async fn run(
+    command: Option<Command>,
+    script: ScriptOptions,
+    info: bool,
+) -> Result<(), FluaError> {
+    match command {
+        None => run_script_or_repl(script, info).await,
+        Some(Command::Run(run)) => handle_run_command(run, info).await,
+        Some(Command::Watch(watch)) => {
+            let permissions = watch.permissions.permissions();
+            let lua_args = watch.lua_args();
+            let limits = watch.limits.limits();
+            watch::watch_script(watch.script, permissions, lua_args, limits).await
+        }
+        Some(Command::Test(test)) => handle_test(test).await,
+        Some(Command::Debug(debug)) => handle_debug(debug).await,
+        Some(Command::Build(build)) => {
+            bundle::build(&build.script, build.output.as_deref(), &build.assets)
+        }
+        Some(Command::Check {
+            files,
+            deny_deprecated,
+        }) => check::check_files(&files, deny_deprecated),
+        Some(Command::Deprecations) => {
+            deprecation::print_list();
+            Ok(())
+        }
+        Some(Command::Stubs { out }) => {
+            let files = api::meta::write_stubs(&out)?;
+            println!(
+                "Wrote {} type definitions to {}",
+                files.len(),
+                out.display()
+            );
+            Ok(())
+        }
+        Some(Command::Init(init)) => handle_init(init),
+        Some(Command::Repl { permissions }) => handle_repl(permissions.permissions()).await,
+        Some(Command::Config { action }) => {
+            helper::config::configstuff(action.name()).map_err(FluaError::from)
+        }
+        Some(Command::Module {
+            action: ModuleAction::Run(run),
+        }) => handle_module(run, info).await,
+        Some(Command::Module { action }) => handle_module_store(action),
+        Some(Command::Update) => {
+            helper::update::update().map_err(|e| FluaError::from(format!("Update failed: {}", e)))
+        }
+        Some(Command::Install) => helper::update::install()
+            .map_err(|e| FluaError::from(format!("Installation failed: {}", e))),
+    }
+}
+
+// Runs the script of an executable of `flua build`, all command line
+// arguments go to `arg`
+pub async fn run_bundle(bundle: bundle::Bundle, lua_args: Vec<String>) -> Result<(), FluaError> {
+    tokio::task::spawn_blocking(move || bundle.run(lua_args))
+        .await
+        .map_err(|e| FluaError::from(format!("Join error: {}", e)))?
+}
+
+// Runs a script, inline code or stdin, without any of them the entrypoint
+// of the project in the current directory or the REPL starts
+async fn run_script_or_repl(script: ScriptOptions, info: bool) -> Result<(), FluaError> {
+    let permissions = script.permissions.permissions();
+    let limits = script.limits.limits();
+    let source = match script.source() {
+        Some(source) => Some(source),
+        None => project_entrypoint()?,
+    };
+
+    match source {
+        Some(source) => {
+            let lua_args = script.lua_args();
+            handle_script_execution(
+                source,
+                permissions,
+                limits,
+                script.profile,
+                script.coverage,
+                info,
+                lua_args,
+            )
+            .await
+        }
+        None => handle_repl(permissions).await,
+    }
+}
+
+// Entrypoint of the project in the current directory or a parent directory
+fn project_entrypoint() -> Result<Option<ScriptSource>, String> {
+    let cwd = env::current_dir().map_err(|e| format!("Path does not work: {}", e))?;
+
+    Ok(Project::find(&cwd)?
+        .and_then(|project| project.entrypoint())
+        .map(|entry| ScriptSource::File(entry.to_string_lossy().to_string())))
+}
+
+// `flua run <script.lua | module>`
+async fn handle_run_command(run: RunArgs, info: bool) -> Result<(), FluaError> {
+    if run.is_script() {
+        return run_script_or_repl(run.into_script(), info).await;
+    }
+    if run.target.is_none() && run.path.is_none() {
+        // `flua run` in a project runs its entrypoint
+        return match project_entrypoint()? {
+            Some(source) => {
+                let permissions = run.permissions.permissions();
+                let limits = run.limits.limits();
+                let lua_args = run.lua_args();
+                handle_script_execution(
+                    source,
+                    permissions,
+                    limits,
+                    run.profile,
+                    run.coverage,
+                    info,
+                    lua_args,
+                )
+                .await
+            }
+            None => Err(FluaError::new(
+                exit_code::USAGE_ERROR,
+                "Missing a script or module to run, see flua run --help",
+            )),
+        };
+    }
+    handle_module(run, info).await
+}
+
+// Runs the entrypoint of a dlm13 module, the arguments after the name go to
+// its `arg` table
+async fn handle_module(run: RunArgs, info: bool) -> Result<(), FluaError> {
+    let (name, lua_args) = run.module_args();
+    let module = dlm13::resolve(name, run.path.as_deref().map(Path::new))?;
+    if info {
+        println!(
+            "{}[LUAJIT-INFO] Starting module: {}@{}{}",
+            GREEN, module.config.name, module.config.version, END
+        );
+    }
+
+    handle_script_execution(
+        module.source(),
+        run.permissions.permissions(),
+        run.limits.limits(),
+        run.profile,
+        run.coverage,
+        info,
+        lua_args,
+    )
+    .await
+}
+
+// `flua module install|list|remove|lock|default`
+fn handle_module_store(action: ModuleAction) -> Result<(), FluaError> {
+    let store = Store::open();
+    match action {
+        ModuleAction::Run(_) => unreachable!("modules run in handle_module"),
+        ModuleAction::Install { source, force } => {
+            let module = store.install(&source, force)?;
+            println!(
+                "{}Installed {}@{}{}",
+                GREEN, module.config.name, module.config.version, END
+            );
+        }
+        ModuleAction::List => {
+            let installed = store.list();
+            if installed.is_empty() {
+                println!("No modules installed, install one with `flua module install <path>`");
+            }
+            for module in installed {
+                let default = if module.default { "*" } else { " " };
+                println!(
+                    "{} {}{:<20}{} {:<10} {}",
+                    default, BOLD, module.name, END, module.version, module.description
+                );
+            }
+        }
+        ModuleAction::Remove { module } => {
+            let (name, version) = store::split_spec(&module);
+            let removed = store.remove(name, version)?;
+            println!("Removed {} {}", name, removed.join(", "));
+        }
+        ModuleAction::Lock { dir } => {
+            let module = dlm13::Module::load(&dir)?;
+            let lockfile = lock::lock(&module, &store)?;
+            for locked in &lockfile.packages {
+                println!("Locked {}@{}", locked.name, locked.version);
+            }
+            println!(
+                "{}Wrote {} with {} modules{}",
+                GREEN,
+                dir.join(lock::LOCKFILE).display(),
+                lockfile.packages.len(),
+                END
+            );
+        }
+        ModuleAction::Default { module } => {
+            let (name, Some(version)) = store::split_spec(&module) else {
+                return Err(FluaError::new(
+                    exit_code::USAGE_ERROR,
+                    format!(
+                        "Missing the version, use `flua module default {}@<version>`",
+                        module
+                    ),
+                ));
+            };
+            store.set_default(name, version)?;
+            println!("{}@{} is the default now", name, version);
+        }
+    }
+    Ok(())
+}
+
+// `flua init [name]`
+fn handle_init(args: InitArgs) -> Result<(), FluaError> {
+    let files = init::init(
+        Path::new("."),
+        args.name.as_deref(),
+        args.template,
+        args.force,
+    )?;
+    let dir = args.name.as_deref().unwrap_or(".");
+    println!("{}Created {} files in {}{}", GREEN, files.len(), dir, END);
+    println!(
+        "Run it with `flua {}/main.lua`, test it with `flua test {}`",
+        dir, dir
+    );
+    Ok(())
+}
+
+// Function to start the interactive Lua prompt
+async fn handle_repl(permissions: Permissions) -> Result<(), FluaError> {
+    tokio::task::spawn_blocking(move || {
+        repl::start_repl(&permissions).map_err(|e| match e.downcast_ref::<ExitRequest>() {
+            Some(exit) => FluaError::exit(exit.0),
+            None => FluaError::from(format!("REPL error: {}", e)),
+        })
+    })
+    .await
+    .map_err(|e| FluaError::from(format!("Join error: {}", e)))?
+}
+
+// `flua test [dir]`
+async fn handle_test(test: TestArgs) -> Result<(), FluaError> {
+    let permissions = test.permissions.permissions();
+    let limits = test.limits.limits();
+
+    tokio::task::spawn_blocking(move || {
+        test_runner::run_tests(
+            Path::new(&test.dir),
+            &permissions,
+            &limits,
+            test.junit.as_deref(),
+            test.coverage.dir(),
+        )
+    })
+    .await
+    .map_err(|e| FluaError::from(format!("Join error: {}", e)))?
+}
+
+// `flua debug script.lua`, waits for an editor and runs the script under the
+// debugger
+async fn handle_debug(debug: DebugArgs) -> Result<(), FluaError> {
+    let permissions = debug.permissions.permissions();
+    let lua_args = debug.lua_args();
+
+    tokio::task::spawn_blocking(move || {
+        let listener = TcpListener::bind(("127.0.0.1", debug.port))
+            .map_err(|e| format!("Could not listen on port {}: {}", debug.port, e))?;
+        eprintln!("Waiting for a debugger on 127.0.0.1:{}", debug.port);
+        let (client, requests) = debugger::dap::accept(&listener)
+            .map_err(|e| format!("The debugger could not connect: {}", e))?;
+
+        let debugger = Debugger::new(client, requests);
+        let setup = {
+            let debugger = debugger.clone();
+            move |lua: &mlua::Lua| debugger.install(lua)
+        };
+        let source = ScriptSource::File(debug.script);
+        let result = lua_script::run_script_with(&source, &permissions, lua_args, &setup);
+        debugger.finish(&result);
+        result
+    })
+    .await
+    .map_err(|e| FluaError::from(format!("Join error: {}", e)))?
+}
+
+// Function to run a Lua script -> returns a Error
+async fn handle_script_execution(
+    source: ScriptSource,
+    permissions: Permissions,
+    limits: Limits,
+    profile: ProfileOptions,
+    coverage: CoverageOptions,
+    info: bool,
+    lua_args: Vec<String>,
+) -> Result<(), FluaError> {
+    let path = source.display_name().to_string();
+
+    if info {
+        println!("{}[LUAJIT-INFO] Running script: {}{}", GREEN, path, END);
+        if permissions.safe {
+            println!("{}[LUAJIT-INFO] Running in safe mode{}", GREEN, END);
+        }
+    }
+
+    let task = tokio::task::spawn_blocking(move || {
+        let profiler = profile.profile.as_ref().map(|_| Profiler::default());
+        let lines = coverage.dir().map(|_| Coverage::for_current_dir());
+        let setup = {
+            let (profiler, lines) = (profiler.clone(), lines.clone());
+            move |lua: &mlua::Lua| {
+                limits.install(lua)?;
+                if let Some(profiler) = &profiler {
+                    profiler.install(lua)?;
+                }
+                match &lines {
+                    Some(lines) => lines.install(lua),
+                    None => Ok(()),
+                }
+            }
+        };
+        let result = lua_script::run_script_with(&source, &permissions, lua_args, &setup);
+
+        // The profile and the coverage are also written when the script failed
+        if let (Some(profiler), Some(file)) = (profiler, &profile.profile) {
+            profiler.finish(file, profile.profile_top)?;
+        }
+        if let (Some(lines), Some(dir)) = (lines, coverage.dir()) {
+            lines.finish(dir)?;
+        }
+        result
+    });
+
+    // The hook can not stop a native call like dapi_time.waitfr, after a short
+    // grace period flua gives up on the script
+    let join_result = match limits.timeout {
+        Some(timeout) => tokio::time::timeout(timeout.saturating_add(TIMEOUT_GRACE), task)
+            .await
+            .map_err(|_| {
+                FluaError::new(
+                    exit_code::LIMIT_EXCEEDED,
+                    LimitExceeded(format!(
+                        "the timeout of {} was reached",
+                        limits::format_duration(timeout)
+                    ))
+                    .to_string(),
+                )
+            })?,
+        None => task.await,
+    }
+    .map_err(|e| FluaError::from(format!("Join error: {}", e)))?; // 1. await + JoinError behandeln
+
+    // 2. Jetzt das innere Result behandeln, der Exit Code hängt vom Fehler ab
+    join_result?;
+
+    if info {
+        println!("{}[LUAJIT-INFO] Finished executing: {}{}", GREEN, path, END);
+    }
+
+    Ok(())
+}
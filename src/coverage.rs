@@ -0,0 +1,380 @@
+// `--coverage`, line coverage of scripts and modules
+//
+// A line hook counts how often every line of the Lua files below the project
+// directory ran, files outside of it are skipped. At the end `lcov.info` and
+// an `index.html` are written to the coverage directory. Lines without
+// bytecode, like comments or a lone `end`, are left out of the report
+
+use mlua::{Debug, Lua, Table};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::helper::hooks;
+use crate::lua_script::strip_shebang;
+use crate::project::Project;
+use crate::test_runner::escape;
+
+struct CoverageData {
+    root: PathBuf,
+    // Hits per line of every file
+    files: BTreeMap<PathBuf, BTreeMap<usize, u64>>,
+    // Chunk names like `@lib/util.lua` and their file, None when skipped
+    sources: HashMap<String, Option<PathBuf>>,
+}
+
+// Shared by all Lua states of one run, e.g. all tests of `flua test`
+#[derive(Clone)]
+pub struct Coverage(Rc<RefCell<CoverageData>>);
+
+// Lines of one file in the report
+struct FileReport {
+    path: PathBuf,
+    source: String,
+    hits: BTreeMap<usize, u64>,
+}
+
+impl FileReport {
+    fn found(&self) -> usize {
+        self.hits.len()
+    }
+
+    fn covered(&self) -> usize {
+        self.hits.values().filter(|hits| **hits > 0).count()
+    }
+}
+
+impl Coverage {
+    // Only files below `root` are recorded
+    pub fn new(root: &Path) -> Self {
+        let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        Coverage(Rc::new(RefCell::new(CoverageData {
+            root,
+            files: BTreeMap::new(),
+            sources: HashMap::new(),
+        })))
+    }
+
+    // Coverage of the project in the current directory, or of the directory
+    pub fn for_current_dir() -> Self {
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        match Project::find(&cwd) {
+            Ok(Some(project)) => Coverage::new(&project.root),
+            _ => Coverage::new(&cwd),
+        }
+    }
+
+    pub fn install(&self, lua: &Lua) -> mlua::Result<()> {
+        lua.set_app_data(self.clone());
+        hooks::set_lines(lua, "coverage", true)
+    }
+
+    fn record(&self, chunk: &str, line: usize) {
+        let mut data = self.0.borrow_mut();
+        let file = match data.sources.get(chunk) {
+            Some(file) => file.clone(),
+            None => {
+                let file = chunk
+                    .strip_prefix('@')
+                    .and_then(|path| fs::canonicalize(path).ok())
+                    .filter(|path| path.starts_with(&data.root));
+                data.sources.insert(chunk.to_string(), file.clone());
+                file
+            }
+        };
+
+        if let Some(file) = file {
+            *data.files.entry(file).or_default().entry(line).or_default() += 1;
+        }
+    }
+
+    // All recorded files with the lines which can run, missed ones with 0 hits
+    fn reports(&self) -> Vec<FileReport> {
+        let data = self.0.borrow();
+        data.files
+            .iter()
+            .filter_map(|(path, hits)| {
+                let source = fs::read_to_string(path).ok()?;
+                let mut hits = hits.clone();
+                for line in executable_lines(&source) {
+                    hits.entry(line).or_insert(0);
+                }
+                Some(FileReport {
+                    path: path.clone(),
+                    source,
+                    hits,
+                })
+            })
+            .collect()
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> std::borrow::Cow<'a, str> {
+        let root = self.0.borrow().root.clone();
+        match path.strip_prefix(&root) {
+            Ok(relative) => relative.to_string_lossy(),
+            Err(_) => path.to_string_lossy(),
+        }
+    }
+
+    pub fn lcov(&self) -> String {
+        let mut out = String::new();
+        for file in self.reports() {
+            let _ = writeln!(out, "TN:");
+            let _ = writeln!(out, "SF:{}", file.path.display());
+            for (line, hits) in &file.hits {
+                let _ = writeln!(out, "DA:{},{}", line, hits);
+            }
+            let _ = writeln!(out, "LF:{}", file.found());
+            let _ = writeln!(out, "LH:{}", file.covered());
+            let _ = writeln!(out, "end_of_record");
+        }
+        out
+    }
+
+    // One page with a summary table and the source of every file
+    pub fn html(&self) -> String {
+        let reports = self.reports();
+        let mut out = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>flua coverage</title>\n<style>\n\
+             body { font-family: sans-serif; margin: 2em; }\n\
+             table { border-collapse: collapse; }\n\
+             td, th { padding: 2px 10px; text-align: left; }\n\
+             pre { margin: 0; }\n\
+             .hit { background: #dfd; }\n\
+             .miss { background: #fdd; }\n\
+             .line { color: #888; text-align: right; }\n\
+             </style>\n</head>\n<body>\n<h1>Coverage</h1>\n",
+        );
+
+        let (found, covered) = reports.iter().fold((0, 0), |(found, covered), file| {
+            (found + file.found(), covered + file.covered())
+        });
+        let _ = writeln!(
+            out,
+            "<p>{} of {} lines ({})</p>",
+            covered,
+            found,
+            percent(covered, found)
+        );
+
+        out.push_str("<table>\n<tr><th>File</th><th>Lines</th><th>Covered</th></tr>\n");
+        for (i, file) in reports.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "<tr><td><a href=\"#file{}\">{}</a></td><td>{} / {}</td><td>{}</td></tr>",
+                i,
+                escape(&self.relative(&file.path)),
+                file.covered(),
+                file.found(),
+                percent(file.covered(), file.found())
+            );
+        }
+        out.push_str("</table>\n");
+
+        for (i, file) in reports.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "<h2 id=\"file{}\">{}</h2>\n<table>",
+                i,
+                escape(&self.relative(&file.path))
+            );
+            for (number, text) in file.source.lines().enumerate() {
+                let number = number + 1;
+                let (class, hits) = match file.hits.get(&number) {
+                    Some(0) => ("miss", "0".to_string()),
+                    Some(hits) => ("hit", hits.to_string()),
+                    None => ("", String::new()),
+                };
+                let _ = writeln!(
+                    out,
+                    "<tr class=\"{}\"><td class=\"line\">{}</td><td class=\"line\">{}</td><td><pre>{}</pre></td></tr>",
+                    class,
+                    number,
+                    hits,
+                    escape(text)
+                );
+            }
+            out.push_str("</table>\n");
+        }
+
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    // Writes lcov.info and index.html and prints the summary on stderr
+    pub fn finish(&self, dir: &Path) -> Result<(), String> {
+        let write = |name: &str, content: String| {
+            let path = dir.join(name);
+            fs::write(&path, content)
+                .map_err(|e| format!("Could not write '{}': {}", path.display(), e))
+        };
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create '{}': {}", dir.display(), e))?;
+        write("lcov.info", self.lcov())?;
+        write("index.html", self.html())?;
+
+        let (found, covered) = self
+            .reports()
+            .iter()
+            .fold((0, 0), |(found, covered), file| {
+                (found + file.found(), covered + file.covered())
+            });
+        eprintln!(
+            "Coverage: {} of {} lines ({}), report in {}",
+            covered,
+            found,
+            percent(covered, found),
+            dir.join("index.html").display()
+        );
+        Ok(())
+    }
+}
+
+fn percent(covered: usize, found: usize) -> String {
+    if found == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}%", covered as f64 * 100.0 / found as f64)
+}
+
+// Called by the hook of hooks.rs
+pub(crate) fn record_line(lua: &Lua, debug: &Debug) {
+    let Some(coverage) = lua.app_data_ref::<Coverage>().map(|c| c.clone()) else {
+        return;
+    };
+    let Some(line) = debug.current_line() else {
+        return;
+    };
+    if let Some(chunk) = debug.source().source.as_deref() {
+        coverage.record(chunk, line);
+    }
+}
+
+// Walks the bytecode of a function and its nested functions with jit.util,
+// every instruction knows the line it was compiled from
+const LINES_OF_BYTECODE: &str = r#"
+local util = require("jit.util")
+local lines = {}
+local function walk(func)
+    local info = util.funcinfo(func)
+    for pc = 1, info.bytecodes - 1 do
+        local line = util.funcinfo(func, pc).currentline
+        if line and line > 0 then lines[line] = true end
+    end
+    local i = -1
+    while info.children do
+        local constant = util.funck(func, i)
+        if constant == nil then break end
+        if type(constant) == "proto" then walk(constant) end
+        i = i - 1
+    end
+end
+walk(...)
+return lines
+"#;
+
+// Lines which have bytecode, comments or a lone `end` of a table have none.
+// The source is compiled in a fresh state and never run
+pub(crate) fn executable_lines(source: &str) -> BTreeSet<usize> {
+    let lines = || -> mlua::Result<BTreeSet<usize>> {
+        let lua = Lua::new();
+        let chunk = lua
+            .load(strip_shebang(source.to_string()))
+            .into_function()?;
+        let lines: Table = lua.load(LINES_OF_BYTECODE).call(chunk)?;
+        lines
+            .pairs::<usize, bool>()
+            .map(|pair| pair.map(|(line, _)| line))
+            .collect()
+    };
+    lines().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::permissions::Permissions;
+    use crate::lua_script::new_state;
+
+    const SOURCE: &str = "-- comment
+local t = {
+    a = 1,
+}
+
+local function pick(x)
+    if x then
+        return 1
+    else
+        return 2
+    end
+end
+
+pick(true)
+return t
+";
+
+    fn run(coverage: &Coverage, file: &Path, source: &str) {
+        let lua = new_state(&Permissions::default(), &[]).unwrap();
+        coverage.install(&lua).unwrap();
+        lua.load(source)
+            .set_name(format!("@{}", file.display()))
+            .exec()
+            .unwrap();
+    }
+
+    #[test]
+    fn only_lines_with_bytecode_count() {
+        let lines: Vec<usize> = executable_lines(SOURCE).into_iter().collect();
+        assert_eq!(lines, vec![2, 7, 8, 10, 12, 14, 15]);
+        assert!(executable_lines("local = broken").is_empty());
+    }
+
+    #[test]
+    fn records_lines_and_writes_lcov() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("pick.lua");
+        fs::write(&file, SOURCE).unwrap();
+
+        let coverage = Coverage::new(dir.path());
+        run(&coverage, &file, SOURCE);
+
+        let lcov = coverage.lcov();
+        assert!(lcov.contains("SF:"), "{}", lcov);
+        assert!(lcov.contains("DA:8,1\n"), "{}", lcov);
+        assert!(lcov.contains("DA:10,0\n"), "{}", lcov);
+        assert!(lcov.contains("LF:7\nLH:6\n"), "{}", lcov);
+
+        let html = coverage.html();
+        assert!(html.contains("pick.lua"), "{}", html);
+        assert!(html.contains("class=\"miss\""), "{}", html);
+    }
+
+    #[test]
+    fn skips_files_outside_the_root() {
+        let root = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let file = other.path().join("pick.lua");
+        fs::write(&file, SOURCE).unwrap();
+
+        let coverage = Coverage::new(root.path());
+        run(&coverage, &file, SOURCE);
+        assert!(coverage.lcov().is_empty());
+    }
+
+    #[test]
+    fn finish_writes_the_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("pick.lua");
+        fs::write(&file, SOURCE).unwrap();
+
+        let coverage = Coverage::new(dir.path());
+        run(&coverage, &file, SOURCE);
+        let out = dir.path().join("coverage");
+        coverage.finish(&out).unwrap();
+        assert!(out.join("lcov.info").is_file());
+        assert!(out.join("index.html").is_file());
+    }
+}
@@ -0,0 +1,170 @@
+// The wire format of the Debug Adapter Protocol
+//
+// Every message is JSON with a `Content-Length` header. A reader thread parses
+// the requests of the editor and passes them on through a channel, responses
+// and events are written by whoever holds the Client
+
+use serde_json::{Value as JsonValue, json};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub seq: i64,
+    pub command: String,
+    pub arguments: JsonValue,
+}
+
+impl Request {
+    // An argument like `arguments.source.path`, Null when it is missing
+    pub fn arg(&self, path: &str) -> &JsonValue {
+        path.split('.').fold(&self.arguments, |value, key| {
+            value.get(key).unwrap_or(&JsonValue::Null)
+        })
+    }
+}
+
+// The writing half of the connection
+pub struct Client {
+    stream: Mutex<(TcpStream, i64)>,
+}
+
+impl Client {
+    fn send(&self, mut message: JsonValue) {
+        let mut guard = self.stream.lock().unwrap_or_else(|e| e.into_inner());
+        let (stream, seq) = &mut *guard;
+        *seq += 1;
+        message["seq"] = json!(*seq);
+
+        let body = message.to_string();
+        // A closed connection shows up as a disconnect in the reader
+        let _ = write!(stream, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = stream.flush();
+    }
+
+    pub fn respond(&self, request: &Request, body: JsonValue) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "success": true,
+            "command": request.command,
+            "body": body,
+        }));
+    }
+
+    pub fn fail(&self, request: &Request, message: impl Into<String>) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "success": false,
+            "command": request.command,
+            "message": message.into(),
+        }));
+    }
+
+    pub fn event(&self, event: &str, body: JsonValue) {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }));
+    }
+}
+
+// Waits for one editor on `listener`, the requests arrive on the receiver.
+// It yields a `disconnect` request when the editor goes away
+pub fn accept(listener: &TcpListener) -> std::io::Result<(Arc<Client>, Receiver<Request>)> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let client = Arc::new(Client {
+        stream: Mutex::new((stream, 0)),
+    });
+
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut reader = reader;
+        while let Some(request) = read_request(&mut reader) {
+            if tx.send(request).is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(Request {
+            seq: 0,
+            command: "disconnect".to_string(),
+            arguments: JsonValue::Null,
+        });
+    });
+    Ok((client, rx))
+}
+
+// The next request, None at the end of the stream or on a broken message
+fn read_request(reader: &mut impl BufRead) -> Option<Request> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let mut body = vec![0; length?];
+        reader.read_exact(&mut body).ok()?;
+        let message: JsonValue = serde_json::from_slice(&body).ok()?;
+
+        // Only requests come from the editor, anything else is skipped
+        if message["type"] == "request" {
+            return Some(Request {
+                seq: message["seq"].as_i64().unwrap_or(0),
+                command: message["command"].as_str().unwrap_or("").to_string(),
+                arguments: message.get("arguments").cloned().unwrap_or(JsonValue::Null),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_framed_requests() {
+        let first =
+            r#"{"seq":1,"type":"request","command":"initialize","arguments":{"clientID":"test"}}"#;
+        let event = r#"{"seq":2,"type":"event","event":"ignored"}"#;
+        let second = r#"{"seq":3,"type":"request","command":"setBreakpoints","arguments":{"source":{"path":"main.lua"}}}"#;
+        let input = format!(
+            "Content-Length: {}\r\n\r\n{}Content-Length: {}\r\n\r\n{}content-length: {}\r\n\r\n{}",
+            first.len(),
+            first,
+            event.len(),
+            event,
+            second.len(),
+            second
+        );
+        let mut reader = BufReader::new(input.as_bytes());
+
+        let request = read_request(&mut reader).unwrap();
+        assert_eq!(request.command, "initialize");
+        assert_eq!(request.arg("clientID"), "test");
+
+        let request = read_request(&mut reader).unwrap();
+        assert_eq!(request.seq, 3);
+        assert_eq!(request.arg("source.path"), "main.lua");
+        assert!(request.arg("source.name").is_null());
+
+        assert!(read_request(&mut reader).is_none());
+    }
+}
@@ -0,0 +1,866 @@
+// `flua debug --port N script.lua`, a Debug Adapter Protocol server
+//
+// The editor connects over TCP, sets its breakpoints and sends
+// `configurationDone`, then the script starts. The line hook of hooks.rs
+// checks every line for a breakpoint or the end of a step. While the script
+// is paused the hook answers the requests for stack frames, variables and
+// expressions itself, it returns when the editor continues or steps.
+// Locals and upvalues come from the debug library, which is loaded for the
+// debugger only and never visible to the script
+
+pub mod dap;
+
+use mlua::{Debug, Function, Lua, MultiValue, Table, Value};
+use serde_json::{Value as JsonValue, json};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+
+use crate::coverage;
+use crate::helper::exit_code::FluaError;
+use crate::helper::hooks;
+use dap::{Client, Request};
+
+// Only one thread is shown, coroutines are part of its stack
+const THREAD_ID: i64 = 1;
+
+// debug.getlocal and debug.getinfo count their own frame, the paused function
+// is one level above it
+const LEVEL_OFFSET: usize = 1;
+
+// The editor closed the connection or sent `disconnect`
+#[derive(Debug)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the debugger disconnected, the script was stopped")
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+// What the script does until the next stop
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Run,
+    // Stop at the first line, `stopOnEntry` of launch
+    Entry,
+    Pause,
+    In,
+    // Stop at a line with at most / less than this stack depth
+    Over(usize),
+    Out(usize),
+}
+
+// What the hook does after a request
+enum Flow {
+    Stay,
+    Resume,
+    Disconnect,
+}
+
+// Things the editor can expand, the ids are only valid while paused
+#[derive(Clone)]
+enum Reference {
+    Locals(usize),
+    Upvalues(usize),
+    Table(Table),
+}
+
+struct Session {
+    client: Arc<Client>,
+    requests: Receiver<Request>,
+    // The private debug library
+    debug: Option<Table>,
+    breakpoints: HashMap<PathBuf, BTreeSet<usize>>,
+    // Chunk names like `@lib/util.lua` and their canonical path
+    sources: HashMap<String, Option<PathBuf>>,
+    step: Step,
+    references: Vec<Reference>,
+}
+
+#[derive(Clone)]
+pub struct Debugger(Rc<RefCell<Session>>);
+
+impl Debugger {
+    pub fn new(client: Arc<Client>, requests: Receiver<Request>) -> Self {
+        Debugger(Rc::new(RefCell::new(Session {
+            client,
+            requests,
+            debug: None,
+            breakpoints: HashMap::new(),
+            sources: HashMap::new(),
+            step: Step::Run,
+            references: Vec::new(),
+        })))
+    }
+
+    // Answers the requests of the editor until `configurationDone`, after
+    // that the script may run
+    pub fn install(&self, lua: &Lua) -> mlua::Result<()> {
+        self.0.borrow_mut().debug = Some(load_debug_library(lua)?);
+        lua.set_app_data(self.clone());
+        hooks::set_lines(lua, "debugger", true)?;
+
+        loop {
+            let request = self
+                .next_request()
+                .ok_or_else(|| mlua::Error::external(Disconnected))?;
+            if request.command == "configurationDone" {
+                self.client().respond(&request, json!({}));
+                return Ok(());
+            }
+            if let Flow::Disconnect = self.handle(lua, &request, false)? {
+                return Err(mlua::Error::external(Disconnected));
+            }
+        }
+    }
+
+    // Tells the editor that the script ended
+    pub fn finish(&self, result: &Result<(), FluaError>) {
+        let client = self.client();
+        let code = match result {
+            Ok(()) => 0,
+            Err(e) if e.exited => e.code,
+            Err(e) => {
+                client.event(
+                    "output",
+                    json!({ "category": "stderr", "output": format!("{}\n", e.message) }),
+                );
+                e.code
+            }
+        };
+        client.event("exited", json!({ "exitCode": code }));
+        client.event("terminated", json!({}));
+    }
+
+    fn client(&self) -> Arc<Client> {
+        self.0.borrow().client.clone()
+    }
+
+    fn next_request(&self) -> Option<Request> {
+        self.0.borrow().requests.recv().ok()
+    }
+
+    fn debug_function(&self, name: &str) -> mlua::Result<Function> {
+        match &self.0.borrow().debug {
+            Some(debug) => debug.get(name),
+            None => Err(mlua::Error::runtime("the debugger is not installed")),
+        }
+    }
+
+    fn reference(&self, reference: Reference) -> usize {
+        let mut session = self.0.borrow_mut();
+        session.references.push(reference);
+        session.references.len()
+    }
+
+    // Called for every line
+    fn line(&self, lua: &Lua, debug: &Debug) -> mlua::Result<()> {
+        // Requests which arrive while the script runs, like new breakpoints
+        loop {
+            let request = self.0.borrow().requests.try_recv();
+            let Ok(request) = request else {
+                break;
+            };
+            if let Flow::Disconnect = self.handle(lua, &request, false)? {
+                return Err(mlua::Error::external(Disconnected));
+            }
+        }
+
+        let step = self.0.borrow().step;
+        let reason = if self.at_breakpoint(debug) {
+            Some("breakpoint")
+        } else {
+            match step {
+                Step::Run => None,
+                Step::Entry => Some("entry"),
+                Step::Pause => Some("pause"),
+                Step::In => Some("step"),
+                Step::Over(depth) => (stack_depth(lua) <= depth).then_some("step"),
+                Step::Out(depth) => (stack_depth(lua) < depth).then_some("step"),
+            }
+        };
+
+        match reason {
+            Some(reason) => self.pause(lua, reason),
+            None => Ok(()),
+        }
+    }
+
+    fn at_breakpoint(&self, debug: &Debug) -> bool {
+        let mut session = self.0.borrow_mut();
+        if session.breakpoints.is_empty() {
+            return false;
+        }
+        let (Some(line), Some(chunk)) = (debug.current_line(), debug.source().source) else {
+            return false;
+        };
+
+        let file = match session.sources.get(chunk.as_ref()) {
+            Some(file) => file.clone(),
+            None => {
+                let file = chunk
+                    .strip_prefix('@')
+                    .and_then(|path| fs::canonicalize(path).ok());
+                session.sources.insert(chunk.to_string(), file.clone());
+                file
+            }
+        };
+        file.and_then(|file| session.breakpoints.get(&file))
+            .is_some_and(|lines| lines.contains(&line))
+    }
+
+    // Waits in the hook until the editor continues or steps
+    fn pause(&self, lua: &Lua, reason: &str) -> mlua::Result<()> {
+        self.0.borrow_mut().step = Step::Run;
+        self.client().event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+
+        let flow = loop {
+            let Some(request) = self.next_request() else {
+                break Flow::Disconnect;
+            };
+            match self.handle(lua, &request, true)? {
+                Flow::Stay => {}
+                flow => break flow,
+            }
+        };
+
+        self.0.borrow_mut().references.clear();
+        match flow {
+            Flow::Disconnect => Err(mlua::Error::external(Disconnected)),
+            _ => Ok(()),
+        }
+    }
+
+    fn handle(&self, lua: &Lua, request: &Request, paused: bool) -> mlua::Result<Flow> {
+        let client = self.client();
+        let resume = |step: Step| {
+            self.0.borrow_mut().step = step;
+            Flow::Resume
+        };
+
+        let flow = match request.command.as_str() {
+            "initialize" => {
+                client.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                    }),
+                );
+                client.event("initialized", json!({}));
+                Flow::Stay
+            }
+            "launch" | "attach" => {
+                if request.arg("stopOnEntry").as_bool() == Some(true) {
+                    self.0.borrow_mut().step = Step::Entry;
+                }
+                client.respond(request, json!({}));
+                Flow::Stay
+            }
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(request);
+                client.respond(request, body);
+                Flow::Stay
+            }
+            "setExceptionBreakpoints" | "configurationDone" => {
+                client.respond(request, json!({}));
+                Flow::Stay
+            }
+            "threads" => {
+                client.respond(
+                    request,
+                    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+                );
+                Flow::Stay
+            }
+            "pause" => {
+                client.respond(request, json!({}));
+                if !paused {
+                    self.0.borrow_mut().step = Step::Pause;
+                }
+                Flow::Stay
+            }
+            "disconnect" => {
+                client.respond(request, json!({}));
+                Flow::Disconnect
+            }
+            "continue" | "next" | "stepIn" | "stepOut" if !paused => {
+                client.fail(request, "The script is not paused");
+                Flow::Stay
+            }
+            "continue" => {
+                client.respond(request, json!({ "allThreadsContinued": true }));
+                resume(Step::Run)
+            }
+            "next" => {
+                client.respond(request, json!({}));
+                resume(Step::Over(stack_depth(lua)))
+            }
+            "stepIn" => {
+                client.respond(request, json!({}));
+                resume(Step::In)
+            }
+            "stepOut" => {
+                client.respond(request, json!({}));
+                resume(Step::Out(stack_depth(lua)))
+            }
+            "stackTrace" | "scopes" | "variables" | "evaluate" if !paused => {
+                client.fail(request, "The script is not paused");
+                Flow::Stay
+            }
+            "stackTrace" => {
+                let frames = stack_frames(lua);
+                client.respond(
+                    request,
+                    json!({ "stackFrames": frames, "totalFrames": frames.len() }),
+                );
+                Flow::Stay
+            }
+            "scopes" => {
+                let level = frame_level(request.arg("frameId"));
+                let scopes = json!([
+                    {
+                        "name": "Locals",
+                        "variablesReference": self.reference(Reference::Locals(level)),
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Upvalues",
+                        "variablesReference": self.reference(Reference::Upvalues(level)),
+                        "expensive": false,
+                    },
+                ]);
+                client.respond(request, json!({ "scopes": scopes }));
+                Flow::Stay
+            }
+            "variables" => {
+                match self.variables(request.arg("variablesReference")) {
+                    Ok(variables) => client.respond(request, json!({ "variables": variables })),
+                    Err(e) => client.fail(request, e.to_string()),
+                }
+                Flow::Stay
+            }
+            "evaluate" => {
+                let expression = request.arg("expression").as_str().unwrap_or("");
+                let level = frame_level(request.arg("frameId"));
+                match self.evaluate(lua, expression, level) {
+                    Ok(body) => client.respond(request, body),
+                    Err(e) => client.fail(request, e.to_string()),
+                }
+                Flow::Stay
+            }
+            command => {
+                client.fail(request, format!("Unsupported request '{}'", command));
+                Flow::Stay
+            }
+        };
+        Ok(flow)
+    }
+
+    // Replaces the breakpoints of one file. A breakpoint on a line without
+    // code moves to the next line with code
+    fn set_breakpoints(&self, request: &Request) -> JsonValue {
+        let path = request.arg("source.path").as_str().unwrap_or("");
+        let wanted: Vec<usize> = match request.arg("breakpoints").as_array() {
+            Some(breakpoints) => breakpoints
+                .iter()
+                .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                .map(|line| line as usize)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let file = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        let code = fs::read_to_string(&file)
+            .map(|source| coverage::executable_lines(&source))
+            .unwrap_or_default();
+
+        let mut lines = BTreeSet::new();
+        let breakpoints: Vec<JsonValue> = wanted
+            .iter()
+            .map(|wanted| match code.range(wanted..).next() {
+                Some(line) => {
+                    lines.insert(*line);
+                    json!({ "verified": true, "line": line })
+                }
+                None => json!({
+                    "verified": false,
+                    "line": wanted,
+                    "message": "No code on or after this line",
+                }),
+            })
+            .collect();
+
+        self.0.borrow_mut().breakpoints.insert(file, lines);
+        json!({ "breakpoints": breakpoints })
+    }
+
+    // Locals of the function at `level`, without temporaries like `(for index)`
+    fn locals(&self, level: usize) -> mlua::Result<Vec<(String, Value)>> {
+        let getlocal = self.debug_function("getlocal")?;
+        let mut locals = Vec::new();
+        for index in 1.. {
+            let (name, value): (Option<String>, Value) =
+                getlocal.call((level + LEVEL_OFFSET, index))?;
+            let Some(name) = name else {
+                break;
+            };
+            if !name.starts_with('(') {
+                locals.push((name, value));
+            }
+        }
+        Ok(locals)
+    }
+
+    fn upvalues(&self, level: usize) -> mlua::Result<Vec<(String, Value)>> {
+        let info: Option<Table> = self
+            .debug_function("getinfo")?
+            .call((level + LEVEL_OFFSET, "f"))?;
+        let Some(Value::Function(func)) = info.map(|info| info.get("func")).transpose()? else {
+            return Ok(Vec::new());
+        };
+
+        let getupvalue = self.debug_function("getupvalue")?;
+        let mut upvalues = Vec::new();
+        for index in 1.. {
+            let (name, value): (Option<String>, Value) = getupvalue.call((&func, index))?;
+            let Some(name) = name else {
+                break;
+            };
+            // Upvalues of native functions have no names
+            if !name.is_empty() {
+                upvalues.push((name, value));
+            }
+        }
+        Ok(upvalues)
+    }
+
+    fn variables(&self, reference: &JsonValue) -> mlua::Result<Vec<JsonValue>> {
+        let index = reference.as_u64().unwrap_or(0) as usize;
+        let reference = self
+            .0
+            .borrow()
+            .references
+            .get(index.wrapping_sub(1))
+            .cloned();
+        let entries = match reference {
+            Some(Reference::Locals(level)) => self.locals(level)?,
+            Some(Reference::Upvalues(level)) => self.upvalues(level)?,
+            Some(Reference::Table(table)) => table_entries(&table)?,
+            None => return Err(mlua::Error::runtime("Unknown variables reference")),
+        };
+
+        Ok(entries
+            .into_iter()
+            .map(|(name, value)| {
+                let mut variable = self.describe(&value);
+                variable["name"] = json!(name);
+                variable
+            })
+            .collect())
+    }
+
+    // The value, the type and a reference when the value can be expanded
+    fn describe(&self, value: &Value) -> JsonValue {
+        let reference = match value {
+            Value::Table(table) => self.reference(Reference::Table(table.clone())),
+            _ => 0,
+        };
+        json!({
+            "value": display(value),
+            "type": value.type_name(),
+            "variablesReference": reference,
+        })
+    }
+
+    // Runs the expression with the locals and upvalues of the frame in scope,
+    // assignments to them do not change the frame
+    fn evaluate(&self, lua: &Lua, expression: &str, level: usize) -> mlua::Result<JsonValue> {
+        let env = lua.create_table()?;
+        for (name, value) in self.upvalues(level)?.into_iter().chain(self.locals(level)?) {
+            env.raw_set(name, value)?;
+        }
+        let meta = lua.create_table()?;
+        meta.raw_set("__index", lua.globals())?;
+        env.set_metatable(Some(meta))?;
+
+        let chunk = match lua
+            .load(format!("return {}", expression))
+            .set_name("=(eval)")
+            .set_environment(env.clone())
+            .into_function()
+        {
+            Ok(chunk) => chunk,
+            Err(_) => lua
+                .load(expression)
+                .set_name("=(eval)")
+                .set_environment(env)
+                .into_function()?,
+        };
+
+        let values: MultiValue = chunk.call(())?;
+        let body = match values.len() {
+            0 => json!({ "result": "nil", "variablesReference": 0 }),
+            1 => {
+                let described = self.describe(&values[0]);
+                json!({
+                    "result": described["value"],
+                    "type": described["type"],
+                    "variablesReference": described["variablesReference"],
+                })
+            }
+            _ => {
+                let shown: Vec<String> = values.iter().map(display).collect();
+                json!({ "result": shown.join(", "), "variablesReference": 0 })
+            }
+        };
+        Ok(body)
+    }
+}
+
+// Called by the hook of hooks.rs
+pub(crate) fn on_line(lua: &Lua, debug: &Debug) -> mlua::Result<()> {
+    let Some(debugger) = lua.app_data_ref::<Debugger>().map(|d| d.clone()) else {
+        return Ok(());
+    };
+    debugger.line(lua, debug)
+}
+
+// The debug library without the `debug` global, only the debugger calls it
+fn load_debug_library(lua: &Lua) -> mlua::Result<Table> {
+    // mlua refuses to load the debug library into a safe state, luaopen_debug
+    // registers it as the `debug` global and in package.loaded
+    let debug: Table = unsafe {
+        lua.exec_raw((), |state| {
+            let top = mlua::ffi::lua_gettop(state);
+            mlua::ffi::luaopen_debug(state);
+            mlua::ffi::lua_insert(state, top + 1);
+            mlua::ffi::lua_settop(state, top + 1);
+        })?
+    };
+    lua.globals().raw_set("debug", Value::Nil)?;
+    if let Some(loaded) = lua
+        .globals()
+        .get::<Option<Table>>("package")?
+        .map(|package| package.get::<Option<Table>>("loaded"))
+        .transpose()?
+        .flatten()
+    {
+        loaded.raw_set("debug", Value::Nil)?;
+    }
+    Ok(debug)
+}
+
+// Number of frames on the stack of the running coroutine
+fn stack_depth(lua: &Lua) -> usize {
+    let mut depth = 0;
+    while lua.inspect_stack(depth, |_| ()).is_some() {
+        depth += 1;
+    }
+    depth
+}
+
+// Frame ids are the stack level plus one
+fn frame_level(frame_id: &JsonValue) -> usize {
+    (frame_id.as_u64().unwrap_or(1) as usize).saturating_sub(1)
+}
+
+fn stack_frames(lua: &Lua) -> Vec<JsonValue> {
+    let mut frames = Vec::new();
+    let mut level = 0;
+    while let Some(frame) = lua.inspect_stack(level, |debug| {
+        let source = debug.source();
+        let id = level + 1;
+        let line = debug.current_line().unwrap_or(0);
+
+        if source.what == "C" {
+            let name = debug.names().name.as_deref().unwrap_or("?").to_string();
+            return json!({
+                "id": id,
+                "name": format!("{} [C]", name),
+                "line": 0,
+                "column": 0,
+                "presentationHint": "subtle",
+            });
+        }
+
+        let name = match source.what {
+            "main" => "main chunk".to_string(),
+            _ => debug
+                .names()
+                .name
+                .as_deref()
+                .unwrap_or("function")
+                .to_string(),
+        };
+        let chunk = source.source.as_deref().unwrap_or("?");
+        let source = match chunk.strip_prefix('@') {
+            Some(path) => {
+                let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                json!({ "name": name, "path": path })
+            }
+            None => json!({ "name": chunk.trim_start_matches('=') }),
+        };
+
+        json!({
+            "id": id,
+            "name": name,
+            "source": source,
+            "line": line,
+            "column": 1,
+        })
+    }) {
+        frames.push(frame);
+        level += 1;
+    }
+    frames
+}
+
+// Entries of a table, array part first
+fn table_entries(table: &Table) -> mlua::Result<Vec<(String, Value)>> {
+    let mut numbers = Vec::new();
+    let mut others = Vec::new();
+    for pair in table.pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        match key {
+            Value::Integer(index) => numbers.push((index, value)),
+            Value::String(name) => others.push((name.to_string_lossy(), value)),
+            key => others.push((format!("[{}]", display(&key)), value)),
+        }
+    }
+    numbers.sort_by_key(|(index, _)| *index);
+    others.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(numbers
+        .into_iter()
+        .map(|(index, value)| (format!("[{}]", index), value))
+        .chain(others)
+        .collect())
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(text) => format!("{:?}", text.to_string_lossy()),
+        value => value
+            .to_string()
+            .unwrap_or_else(|_| value.type_name().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::permissions::Permissions;
+    use crate::lua_script::{ScriptSource, run_script_with};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    const SCRIPT: &str = "local total = 0
+local function add(n)
+    local doubled = n * 2
+    total = total + doubled
+    return doubled
+end
+
+local list = { 'a', 'b' }
+add(1)
+add(2)
+assert(total == 6 and #list == 2)
+-- The debug library stays hidden
+assert(debug == nil)
+";
+
+    // The editor side of the connection
+    struct Editor {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+        seq: i64,
+    }
+
+    impl Editor {
+        fn request(&mut self, command: &str, arguments: JsonValue) -> JsonValue {
+            self.seq += 1;
+            let body = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            })
+            .to_string();
+            write!(
+                self.stream,
+                "Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+
+            let seq = self.seq;
+            self.wait(|message| message["request_seq"] == seq)
+        }
+
+        fn event(&mut self, event: &str) -> JsonValue {
+            self.wait(|message| message["event"] == event)
+        }
+
+        // Skips all messages until the wanted one
+        fn wait(&mut self, wanted: impl Fn(&JsonValue) -> bool) -> JsonValue {
+            loop {
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    self.reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    length = line["Content-Length: ".len()..].parse().unwrap();
+                }
+                let mut body = vec![0; length];
+                self.reader.read_exact(&mut body).unwrap();
+                let message: JsonValue = serde_json::from_slice(&body).unwrap();
+                if wanted(&message) {
+                    return message;
+                }
+            }
+        }
+
+        fn variables(&mut self, reference: &JsonValue) -> HashMap<String, String> {
+            let response = self.request("variables", json!({ "variablesReference": reference }));
+            response["body"]["variables"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|variable| {
+                    let name = variable["name"].as_str().unwrap().to_string();
+                    (name, variable["value"].as_str().unwrap().to_string())
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn breakpoints_steps_and_variables() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("main.lua");
+        fs::write(&script, SCRIPT).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let file = script.to_string_lossy().to_string();
+        let runner = std::thread::spawn(move || {
+            let (client, requests) = dap::accept(&listener).unwrap();
+            let debugger = Debugger::new(client, requests);
+            let setup = {
+                let debugger = debugger.clone();
+                move |lua: &Lua| debugger.install(lua)
+            };
+            let result = run_script_with(
+                &ScriptSource::File(file),
+                &Permissions::default(),
+                Vec::new(),
+                &setup,
+            );
+            debugger.finish(&result);
+            result.map_err(|e| e.message)
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(10)))
+            .unwrap();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        let mut editor = Editor {
+            stream,
+            reader,
+            seq: 0,
+        };
+
+        let response = editor.request("initialize", json!({ "adapterID": "flua" }));
+        assert_eq!(response["success"], true);
+        editor.event("initialized");
+
+        // Line 7 is empty, the breakpoint moves to line 8
+        let path = script.to_string_lossy();
+        let response = editor.request(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }, { "line": 7 }] }),
+        );
+        let breakpoints = &response["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["line"], 3);
+        assert_eq!(breakpoints[1]["line"], 8);
+        editor.request("launch", json!({}));
+        editor.request("configurationDone", json!({}));
+
+        let stopped = editor.event("stopped");
+        assert_eq!(stopped["body"]["reason"], "breakpoint");
+        let response = editor.request("stackTrace", json!({ "threadId": THREAD_ID }));
+        assert_eq!(response["body"]["stackFrames"][0]["line"], 8);
+
+        editor.request("continue", json!({ "threadId": THREAD_ID }));
+        editor.event("stopped");
+        let response = editor.request("stackTrace", json!({ "threadId": THREAD_ID }));
+        let frames = &response["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "add");
+        assert_eq!(frames[0]["line"], 3);
+        assert_eq!(frames[1]["name"], "main chunk");
+        assert_eq!(frames[1]["line"], 9);
+
+        let response = editor.request("scopes", json!({ "frameId": 1 }));
+        let scopes = &response["body"]["scopes"];
+        let locals = editor.variables(&scopes[0]["variablesReference"]);
+        assert_eq!(locals["n"], "1");
+        let upvalues = editor.variables(&scopes[1]["variablesReference"]);
+        assert_eq!(upvalues["total"], "0");
+
+        let response = editor.request(
+            "evaluate",
+            json!({ "expression": "n + total + 10", "frameId": 1 }),
+        );
+        assert_eq!(response["body"]["result"], "11");
+        let response = editor.request("evaluate", json!({ "expression": "list", "frameId": 2 }));
+        let items = editor.variables(&response["body"]["variablesReference"]);
+        assert_eq!(items["[2]"], "\"b\"");
+        let response = editor.request("evaluate", json!({ "expression": "nil +", "frameId": 1 }));
+        assert_eq!(response["success"], false);
+
+        editor.request("next", json!({ "threadId": THREAD_ID }));
+        editor.event("stopped");
+        let response = editor.request("stackTrace", json!({ "threadId": THREAD_ID }));
+        assert_eq!(response["body"]["stackFrames"][0]["line"], 4);
+
+        editor.request("stepOut", json!({ "threadId": THREAD_ID }));
+        editor.event("stopped");
+        let response = editor.request("stackTrace", json!({ "threadId": THREAD_ID }));
+        let frames = &response["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "main chunk");
+        assert_eq!(frames[0]["line"], 10);
+
+        // The second call stops at the breakpoint again
+        editor.request("continue", json!({ "threadId": THREAD_ID }));
+        editor.event("stopped");
+        let response = editor.request("scopes", json!({ "frameId": 1 }));
+        let locals = editor.variables(&response["body"]["scopes"][0]["variablesReference"]);
+        assert_eq!(locals["n"], "2");
+
+        editor.request("continue", json!({ "threadId": THREAD_ID }));
+        let exited = editor.event("exited");
+        assert_eq!(exited["body"]["exitCode"], 0);
+        runner.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,425 @@
+// require() of dlm13 modules
+//
+// `require("helpers")` runs the entrypoint of the module helpers,
+// `require("helpers.text.format")` the file text/format.lua or
+// text/format/init.lua in its folder. Two searchers in package.loaders find
+// the modules:
+//
+// - the dependencies of the running module, right after package.preload, so
+//   a dependency wins over a file with the same name in package.path
+// - the default versions of the store, after all other loaders, so local
+//   files and C modules keep their names
+
+use mlua::{Function, Lua, MultiValue, Table, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dlm13::Module;
+use crate::dlm13::store::Store;
+use crate::helper::permissions::check_read;
+use crate::watch;
+
+// Adds the searcher for the dependencies, nothing happens without modules
+pub fn install(lua: &Lua, modules: Vec<Module>) -> mlua::Result<()> {
+    if modules.is_empty() {
+        return Ok(());
+    }
+
+    let searcher = lua.create_function(move |lua, name: String| {
+        let (module_name, _) = split_name(&name);
+        match modules.iter().find(|m| m.config.name == module_name) {
+            Some(module) => search(lua, module, &name),
+            None => not_found(lua, format!("no dlm13 dependency '{}'", module_name)),
+        }
+    })?;
+    // Position 1 is package.preload with the dapi modules
+    loaders(lua)?.raw_insert(2, searcher)
+}
+
+// Adds the searcher for the modules of `store` as the last loader
+pub fn install_store(lua: &Lua, store: Store) -> mlua::Result<()> {
+    let searcher = lua.create_function(move |lua, name: String| {
+        let (module_name, _) = split_name(&name);
+        if store.versions(module_name).is_empty() {
+            return not_found(
+                lua,
+                format!("no module '{}' in the dlm13 module store", module_name),
+            );
+        }
+        let module = store.get(module_name, None).map_err(|e| {
+            mlua::Error::runtime(format!(
+                "dlm13 module '{}' could not be loaded: {}",
+                module_name, e.message
+            ))
+        })?;
+        search(lua, &module, &name)
+    })?;
+
+    let loaders = loaders(lua)?;
+    loaders.raw_insert(loaders.raw_len() as i64 + 1, searcher)
+}
+
+fn loaders(lua: &Lua) -> mlua::Result<Table> {
+    let package: Table = lua.globals().get("package")?;
+    // LuaJIT calls them loaders, Lua 5.2+ searchers
+    match package.get::<Option<Table>>("loaders")? {
+        Some(loaders) => Ok(loaders),
+        None => package.get("searchers"),
+    }
+}
+
+// `helpers.text.format` => ("helpers", Some("text.format")), module names
+// can not contain dots
+fn split_name(name: &str) -> (&str, Option<&str>) {
+    match name.split_once('.') {
+        Some((module, rest)) => (module, Some(rest)),
+        None => (name, None),
+    }
+}
+
+// Files of the module which `name` can mean, none for names like
+// `helpers../tmp/evil` which would leave the module folder
+fn candidates(module: &Module, name: &str) -> Vec<PathBuf> {
+    match split_name(name).1 {
+        None => vec![module.entrypoint()],
+        Some(rest) => {
+            let parts: Vec<&str> = rest.split('.').collect();
+            if parts
+                .iter()
+                .any(|p| p.is_empty() || *p == ".." || p.contains(['/', '\\']))
+            {
+                return Vec::new();
+            }
+            let path: PathBuf = module.dir.join(parts.iter().collect::<PathBuf>());
+            vec![path.with_extension("lua"), path.join("init.lua")]
+        }
+    }
+}
+
+// The loader of the first file that exists, like the searchers of Lua only
+// a message when nothing fits
+fn search(lua: &Lua, module: &Module, name: &str) -> mlua::Result<MultiValue> {
+    let files = candidates(module, name);
+    if files.is_empty() {
+        return not_found(
+            lua,
+            format!(
+                "dlm13 module '{}@{}': invalid name '{}'",
+                module.config.name, module.config.version, name
+            ),
+        );
+    }
+    match files.iter().find(|file| file.is_file()) {
+        Some(file) => {
+            let loader = load_file(lua, module, file)?;
+            Ok(MultiValue::from_iter([Value::Function(loader)]))
+        }
+        None => {
+            let tried: Vec<String> = files
+                .iter()
+                .map(|file| format!("no file '{}'", file.display()))
+                .collect();
+            not_found(
+                lua,
+                format!(
+                    "dlm13 module '{}@{}': {}",
+                    module.config.name,
+                    module.config.version,
+                    tried.join(", ")
+                ),
+            )
+        }
+    }
+}
+
+fn not_found(lua: &Lua, message: String) -> mlua::Result<MultiValue> {
+    let message = lua.create_string(format!("\n\t{}", message))?;
+    Ok(MultiValue::from_iter([Value::String(message)]))
+}
+
+// A file of the module as a function, errors name the module, version and file
+fn load_file(lua: &Lua, module: &Module, file: &Path) -> mlua::Result<Function> {
+    let failed = |reason: String| {
+        mlua::Error::runtime(format!(
+            "dlm13 module '{}@{}' could not load '{}': {}",
+            module.config.name,
+            module.config.version,
+            file.display(),
+            reason
+        ))
+    };
+
+    // Symlinks and the entrypoint of the manifest must stay in the module folder
+    let inside = match (fs::canonicalize(&module.dir), fs::canonicalize(file)) {
+        (Ok(dir), Ok(path)) => path.starts_with(dir),
+        _ => false,
+    };
+    if !inside {
+        return Err(failed(
+            "the file is outside of the module folder".to_string(),
+        ));
+    }
+    check_read(lua, &file.to_string_lossy())?;
+
+    let source = fs::read_to_string(file).map_err(|e| failed(e.to_string()))?;
+    watch::track_read(lua, &file.to_string_lossy());
+    lua.load(source)
+        .set_name(format!("@{}", file.display()))
+        .into_function()
+        .map_err(|e| failed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlm13::MANIFEST;
+    use crate::helper::permissions::Permissions;
+
+    // Installs `name@version` with the files next to main.lua
+    fn install_module(
+        store: &Store,
+        root: &Path,
+        name: &str,
+        version: &str,
+        files: &[(&str, &str)],
+    ) {
+        let dir = root
+            .join("src")
+            .join(format!("{}-{}", name, version))
+            .join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(MANIFEST),
+            format!(
+                "name: \"{}\"\nversion: \"{}\"\ndescription: \"\"\nauthor: \"\"\nlicense: \"\"\nlink: \"\"\nentrypoint: \"main.lua\"\nluajitversion: {{ min: \"0.0.1\", max: \"9.9.9\" }}\nedition: 2025\n",
+                name, version
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.lua"),
+            format!("return {{ version = '{}', name = ... }}", version),
+        )
+        .unwrap();
+        for (file, source) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        store.install(&dir, false).unwrap();
+    }
+
+    fn state(store: Store) -> Lua {
+        let lua = Lua::new();
+        install_store(&lua, store).unwrap();
+        lua
+    }
+
+    #[test]
+    fn modules_of_the_store() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        let files = [
+            ("text.lua", "return 'text'"),
+            ("text/format/init.lua", "return 'format'"),
+        ];
+        install_module(&store, root.path(), "helpers", "1.0.0", &files);
+        install_module(&store, root.path(), "helpers", "2.0.0", &files);
+        store.set_default("helpers", "1.0.0").unwrap();
+
+        let lua = state(store);
+        let (version, name, text, format): (String, String, String, String) = lua
+            .load(
+                r#"
+                local helpers = require("helpers")
+                return helpers.version, helpers.name, require("helpers.text"), require("helpers.text.format")
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            (
+                version.as_str(),
+                name.as_str(),
+                text.as_str(),
+                format.as_str()
+            ),
+            ("1.0.0", "helpers", "text", "format")
+        );
+    }
+
+    #[test]
+    fn dependencies_come_before_the_store() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        install_module(&store, root.path(), "helpers", "1.0.0", &[]);
+        install_module(&store, root.path(), "helpers", "2.0.0", &[]);
+        store.set_default("helpers", "1.0.0").unwrap();
+        let locked = store.get("helpers", Some("2.0.0")).unwrap();
+
+        let lua = state(Store::at(root.path().join("modules")));
+        install(&lua, vec![locked]).unwrap();
+        let version: String = lua
+            .load("return require('helpers').version")
+            .eval()
+            .unwrap();
+        assert_eq!(version, "2.0.0");
+    }
+
+    #[test]
+    fn errors_name_module_version_and_file() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        install_module(
+            &store,
+            root.path(),
+            "helpers",
+            "1.0.0",
+            &[("broken.lua", "return +")],
+        );
+
+        let lua = state(store);
+        let err = lua
+            .load("require('helpers.broken')")
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("dlm13 module 'helpers@1.0.0' could not load"),
+            "{}",
+            err
+        );
+        assert!(err.contains("broken.lua"), "{}", err);
+
+        let err = lua
+            .load("require('helpers.missing')")
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("dlm13 module 'helpers@1.0.0': no file"),
+            "{}",
+            err
+        );
+        assert!(err.contains("missing.lua"), "{}", err);
+
+        let err = lua
+            .load("require('unknown')")
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("no module 'unknown' in the dlm13 module store"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn names_can_not_leave_the_module_folder() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        install_module(&store, root.path(), "helpers", "1.0.0", &[]);
+        fs::write(root.path().join("evil.lua"), "return 'evil'").unwrap();
+
+        let lua = state(store);
+        let evil = root.path().join("evil").to_string_lossy().to_string();
+        for name in [
+            format!("helpers..{}", evil),
+            format!("helpers.{}", evil),
+            "helpers...evil".to_string(),
+            "helpers.".to_string(),
+        ] {
+            let err = lua
+                .load(format!("require([[{}]])", name))
+                .exec()
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("invalid name"), "{}", err);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_can_not_leave_the_module_folder() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        install_module(&store, root.path(), "helpers", "1.0.0", &[]);
+        let module = store.get("helpers", None).unwrap();
+        fs::write(root.path().join("evil.lua"), "return 'evil'").unwrap();
+        std::os::unix::fs::symlink(root.path().join("evil.lua"), module.dir.join("link.lua"))
+            .unwrap();
+
+        let lua = state(store);
+        let err = lua
+            .load("require('helpers.link')")
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("outside of the module folder"), "{}", err);
+    }
+
+    #[test]
+    fn module_files_need_read_permission() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        install_module(
+            &store,
+            root.path(),
+            "helpers",
+            "1.0.0",
+            &[("text.lua", "return 'text'")],
+        );
+
+        let lua = state(store);
+        crate::helper::permissions::install(&lua, Permissions::safe()).unwrap();
+        let err = lua
+            .load("require('helpers.text')")
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("--allow-read"), "{}", err);
+    }
+
+    #[test]
+    fn module_files_are_tracked_in_watch_mode() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        install_module(
+            &store,
+            root.path(),
+            "helpers",
+            "1.0.0",
+            &[("text.lua", "return 'text'")],
+        );
+        let module = store.get("helpers", None).unwrap();
+
+        let lua = state(store);
+        let list = watch::WatchList::default();
+        list.install(&lua).unwrap();
+        lua.load("require('helpers.text')").exec().unwrap();
+
+        let dir = fs::canonicalize(&module.dir).unwrap();
+        assert_eq!(list.files(), vec![dir.join("text.lua")]);
+    }
+
+    #[test]
+    fn local_files_come_before_the_store() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        install_module(&store, root.path(), "helpers", "1.0.0", &[]);
+        fs::write(
+            root.path().join("helpers.lua"),
+            "return { version = 'local' }",
+        )
+        .unwrap();
+
+        let lua = state(store);
+        crate::lua_script::set_require_paths(&lua, &[root.path().to_path_buf()]).unwrap();
+        let version: String = lua
+            .load("return require('helpers').version")
+            .eval()
+            .unwrap();
+        assert_eq!(version, "local");
+    }
+}
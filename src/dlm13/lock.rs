@@ -0,0 +1,439 @@
+// Dependencies of dlm13 modules and the dlm13.lock file
+//
+// `dependencies:` in dlm13.yml maps module names to semver ranges. The
+// resolver picks one version of every module in the store, the newest which
+// fits all ranges of the module and of its dependencies. `flua module lock`
+// writes the picked versions with a hash of their files to dlm13.lock, later
+// runs use exactly these versions and refuse changed files
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::dlm13::store::Store;
+use crate::dlm13::{Module, ModuleConfig};
+use crate::helper::exit_code::{FILE_NOT_FOUND, FluaError, USAGE_ERROR};
+
+pub const LOCKFILE: &str = "dlm13.lock";
+
+// Resolving stops after this many rounds, each round can only add modules
+// or move them to other versions
+const MAX_ROUNDS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Locked {
+    pub name: String,
+    pub version: String,
+    // `sha256:<hex>` of the files of the module
+    pub hash: String,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub packages: Vec<Locked>,
+}
+
+// Ranges for every module, with the module which needs it
+type Requirements = BTreeMap<String, Vec<(VersionReq, String)>>;
+
+fn add_requirements(
+    requirements: &mut Requirements,
+    config: &ModuleConfig,
+) -> Result<(), FluaError> {
+    for (name, range) in &config.dependencies {
+        let req = VersionReq::parse(range).map_err(|e| {
+            FluaError::new(
+                USAGE_ERROR,
+                format!(
+                    "The module '{}' has the invalid range '{}' for '{}': {}",
+                    config.name, range, name, e
+                ),
+            )
+        })?;
+        requirements
+            .entry(name.clone())
+            .or_default()
+            .push((req, config.name.clone()));
+    }
+    Ok(())
+}
+
+fn requirements_of<'a>(
+    root: &ModuleConfig,
+    modules: impl Iterator<Item = &'a Module>,
+) -> Result<Requirements, FluaError> {
+    let mut requirements = Requirements::new();
+    add_requirements(&mut requirements, root)?;
+    for module in modules {
+        add_requirements(&mut requirements, &module.config)?;
+    }
+    if let Some((_, needed_by)) = requirements.get(&root.name).and_then(|reqs| reqs.first()) {
+        return Err(FluaError::from(format!(
+            "The module '{}' depends on itself through '{}'",
+            root.name, needed_by
+        )));
+    }
+    Ok(requirements)
+}
+
+// Like `^1.2 (helpers), ~1.2.3 (app)`
+fn describe(reqs: &[(VersionReq, String)]) -> String {
+    reqs.iter()
+        .map(|(req, needed_by)| format!("{} ({})", req, needed_by))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// The newest installed version of `name` which fits all ranges
+fn pick(store: &Store, name: &str, reqs: &[(VersionReq, String)]) -> Result<Module, FluaError> {
+    let installed = store.versions(name);
+    if installed.is_empty() {
+        return Err(FluaError::new(
+            FILE_NOT_FOUND,
+            format!(
+                "The dependency '{}' is not installed, it is needed as {}",
+                name,
+                describe(reqs)
+            ),
+        ));
+    }
+
+    let version = installed.iter().rev().find(|version| {
+        Version::parse(version).is_ok_and(|v| reqs.iter().all(|(req, _)| req.matches(&v)))
+    });
+    match version {
+        Some(version) => store.get(name, Some(version)),
+        None => Err(FluaError::new(
+            FILE_NOT_FOUND,
+            format!(
+                "No installed version of '{}' fits {}, installed versions: {}",
+                name,
+                describe(reqs),
+                installed.join(", ")
+            ),
+        )),
+    }
+}
+
+// All modules which `root` needs directly or through other modules, sorted
+// by name
+pub fn resolve(root: &ModuleConfig, store: &Store) -> Result<Vec<Module>, FluaError> {
+    let mut selected: BTreeMap<String, Module> = BTreeMap::new();
+
+    for _ in 0..MAX_ROUNDS {
+        let requirements = requirements_of(root, selected.values())?;
+
+        let mut next = BTreeMap::new();
+        for (name, reqs) in &requirements {
+            next.insert(name.clone(), pick(store, name, reqs)?);
+        }
+
+        let same = next.len() == selected.len()
+            && next.iter().all(|(name, module)| {
+                selected
+                    .get(name)
+                    .is_some_and(|m| m.config.version == module.config.version)
+            });
+        selected = next;
+        if same {
+            return Ok(selected.into_values().collect());
+        }
+    }
+
+    Err(FluaError::from(format!(
+        "Could not resolve the dependencies of '{}', the ranges keep changing the picked versions",
+        root.name
+    )))
+}
+
+// `sha256:<hex>` of all files of the module except a dlm13.lock, the paths
+// are part of the hash
+pub fn hash_dir(dir: &Path) -> Result<String, FluaError> {
+    // A file which can not be read would change the hash without notice
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dir) {
+        let entry = entry.map_err(|e| format!("Could not read '{}': {}", dir.display(), e))?;
+        if entry.file_type().is_file() && entry.file_name() != LOCKFILE {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for file in files {
+        let relative = file.strip_prefix(dir).unwrap_or(&file);
+        let content =
+            fs::read(&file).map_err(|e| format!("Could not read '{}': {}", file.display(), e))?;
+        hasher.update(relative.to_string_lossy().replace('\\', "/").as_bytes());
+        hasher.update([0]);
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+    }
+
+    let hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok(format!("sha256:{}", hex))
+}
+
+// Resolves the dependencies of the module and writes its dlm13.lock
+pub fn lock(module: &Module, store: &Store) -> Result<Lockfile, FluaError> {
+    let mut lockfile = Lockfile::default();
+    for dependency in resolve(&module.config, store)? {
+        lockfile.packages.push(Locked {
+            hash: hash_dir(&dependency.dir)?,
+            name: dependency.config.name,
+            version: dependency.config.version,
+        });
+    }
+
+    let path = module.dir.join(LOCKFILE);
+    let yaml = serde_yaml::to_string(&lockfile)
+        .map_err(|e| format!("Could not write '{}': {}", path.display(), e))?;
+    fs::write(
+        &path,
+        format!(
+            "# Written by `flua module lock`, do not edit it by hand\n{}",
+            yaml
+        ),
+    )
+    .map_err(|e| format!("Could not write '{}': {}", path.display(), e))?;
+    Ok(lockfile)
+}
+
+pub fn read_lockfile(dir: &Path) -> Result<Option<Lockfile>, FluaError> {
+    let path = dir.join(LOCKFILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let yaml = fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read '{}': {}", path.display(), e))?;
+    let lockfile = serde_yaml::from_str(&yaml)
+        .map_err(|e| format!("Invalid lockfile '{}': {}", path.display(), e))?;
+    Ok(Some(lockfile))
+}
+
+// The dependencies of the module in `dir` for a run: the versions of
+// dlm13.lock when there is one, else the versions the resolver picks now
+pub fn dependencies(dir: &Path, store: &Store) -> Result<Vec<Module>, FluaError> {
+    let config = ModuleConfig::read(dir)?;
+    if config.dependencies.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(lockfile) = read_lockfile(dir)? else {
+        return resolve(&config, store);
+    };
+
+    let outdated = |reason: String| {
+        FluaError::from(format!(
+            "The {} of '{}' is outdated, {}. Run `flua module lock` again",
+            LOCKFILE, config.name, reason
+        ))
+    };
+
+    let mut modules = Vec::new();
+    for locked in &lockfile.packages {
+        let module = store.get(&locked.name, Some(&locked.version))?;
+        if hash_dir(&module.dir)? != locked.hash {
+            return Err(FluaError::from(format!(
+                "The files of '{}@{}' in '{}' changed since they were locked in {}, install the module again or run `flua module lock`",
+                locked.name,
+                locked.version,
+                module.dir.display(),
+                LOCKFILE
+            )));
+        }
+        modules.push(module);
+    }
+
+    // Every range of the module and its dependencies has to fit the lock
+    for (name, reqs) in requirements_of(&config, modules.iter())? {
+        let Some(module) = modules.iter().find(|m| m.config.name == name) else {
+            return Err(outdated(format!("'{}' is missing", name)));
+        };
+        let version = Version::parse(&module.config.version)
+            .map_err(|e| outdated(format!("'{}' has no semver version: {}", name, e)))?;
+        if let Some((req, needed_by)) = reqs.iter().find(|(req, _)| !req.matches(&version)) {
+            return Err(outdated(format!(
+                "'{}' needs {} {}, but {} is locked",
+                needed_by, name, req, module.config.version
+            )));
+        }
+    }
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Writes the module `name@version` with the dependencies and installs it
+    fn install(store: &Store, root: &Path, name: &str, version: &str, deps: &str) -> PathBuf {
+        let dir = root
+            .join("src")
+            .join(format!("{}-{}", name, version))
+            .join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(crate::dlm13::MANIFEST),
+            format!(
+                "name: \"{}\"\nversion: \"{}\"\ndescription: \"\"\nauthor: \"\"\nlicense: \"\"\nlink: \"\"\nentrypoint: \"main.lua\"\nluajitversion: {{ min: \"0.0.1\", max: \"9.9.9\" }}\nedition: 2025\ndependencies: {{ {} }}\n",
+                name, version, deps
+            ),
+        )
+        .unwrap();
+        fs::write(dir.join("main.lua"), format!("return '{}'", version)).unwrap();
+        store.install(&dir, false).unwrap();
+        dir
+    }
+
+    fn versions(modules: &[Module]) -> Vec<String> {
+        modules
+            .iter()
+            .map(|m| format!("{}@{}", m.config.name, m.config.version))
+            .collect()
+    }
+
+    #[test]
+    fn ranges() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        for version in [
+            "1.0.0",
+            "1.2.0",
+            "1.2.7",
+            "1.300.0",
+            "2.0.0-beta.1",
+            "2.0.0",
+        ] {
+            install(&store, root.path(), "lib", version, "");
+        }
+
+        let picked = |range: &str| {
+            let app = install(
+                &store,
+                root.path(),
+                "app",
+                "0.1.0",
+                &format!("lib: \"{}\"", range),
+            );
+            let config = ModuleConfig::read(&app).unwrap();
+            store.remove("app", None).unwrap();
+            resolve(&config, &store).map(|modules| versions(&modules))
+        };
+
+        assert_eq!(picked("^1.2").unwrap(), ["lib@1.300.0"]);
+        assert_eq!(picked("~1.2").unwrap(), ["lib@1.2.7"]);
+        assert_eq!(picked(">=1.0.0, <1.2.0").unwrap(), ["lib@1.0.0"]);
+        assert_eq!(picked("1.2.*").unwrap(), ["lib@1.2.7"]);
+        assert_eq!(picked("*").unwrap(), ["lib@2.0.0"]);
+        assert_eq!(picked("=2.0.0-beta.1").unwrap(), ["lib@2.0.0-beta.1"]);
+        assert_eq!(picked(">=2.0.0-beta.0").unwrap(), ["lib@2.0.0"]);
+
+        let err = picked("^3").unwrap_err();
+        assert!(
+            err.message
+                .contains("No installed version of 'lib' fits ^3 (app)"),
+            "{}",
+            err.message
+        );
+        let err = picked("not a range").unwrap_err();
+        assert_eq!(err.code, USAGE_ERROR);
+    }
+
+    #[test]
+    fn transitive_dependencies_share_one_version() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        install(&store, root.path(), "strings", "1.1.0", "");
+        install(&store, root.path(), "strings", "1.5.0", "");
+        install(&store, root.path(), "helpers", "2.0.0", "strings: \"~1.1\"");
+        let app = install(
+            &store,
+            root.path(),
+            "app",
+            "0.1.0",
+            "helpers: \"2\", strings: \"^1\"",
+        );
+
+        let config = ModuleConfig::read(&app).unwrap();
+        let modules = resolve(&config, &store).unwrap();
+        assert_eq!(versions(&modules), ["helpers@2.0.0", "strings@1.1.0"]);
+
+        install(&store, root.path(), "loop", "1.0.0", "app: \"*\"");
+        let looped = install(&store, root.path(), "app", "0.2.0", "loop: \"1\"");
+        let err = resolve(&ModuleConfig::read(&looped).unwrap(), &store).unwrap_err();
+        assert!(err.message.contains("depends on itself"), "{}", err.message);
+    }
+
+    #[test]
+    fn lockfile() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        install(&store, root.path(), "lib", "1.0.0", "");
+
+        let app = root.path().join("app");
+        fs::create_dir_all(&app).unwrap();
+        fs::write(
+            app.join(crate::dlm13::MANIFEST),
+            "name: \"app\"\nversion: \"0.1.0\"\ndescription: \"\"\nauthor: \"\"\nlicense: \"\"\nlink: \"\"\nentrypoint: \"main.lua\"\nluajitversion: { min: \"0.0.1\", max: \"9.9.9\" }\nedition: 2025\ndependencies: { lib: \"^1\" }\n",
+        )
+        .unwrap();
+        fs::write(app.join("main.lua"), "").unwrap();
+        let module = Module::load(&app).unwrap();
+
+        let lockfile = lock(&module, &store).unwrap();
+        assert_eq!(read_lockfile(&app).unwrap(), Some(lockfile));
+        let locked = &read_lockfile(&app).unwrap().unwrap().packages[0];
+        assert_eq!(
+            (locked.name.as_str(), locked.version.as_str()),
+            ("lib", "1.0.0")
+        );
+        assert!(locked.hash.starts_with("sha256:") && locked.hash.len() == 71);
+
+        // A newer version is only used after locking again
+        install(&store, root.path(), "lib", "1.1.0", "");
+        assert_eq!(
+            versions(&dependencies(&app, &store).unwrap()),
+            ["lib@1.0.0"]
+        );
+        lock(&module, &store).unwrap();
+        assert_eq!(
+            versions(&dependencies(&app, &store).unwrap()),
+            ["lib@1.1.0"]
+        );
+
+        // Changed files of a locked module
+        let installed = store.get("lib", Some("1.1.0")).unwrap();
+        fs::write(installed.entrypoint(), "return 'changed'").unwrap();
+        let err = dependencies(&app, &store).unwrap_err();
+        assert!(err.message.contains("'lib@1.1.0'"), "{}", err.message);
+        fs::write(installed.entrypoint(), "return '1.1.0'").unwrap();
+
+        // A range which the lock does not fit anymore
+        let manifest = fs::read_to_string(app.join(crate::dlm13::MANIFEST)).unwrap();
+        fs::write(
+            app.join(crate::dlm13::MANIFEST),
+            manifest.replace("lib: \"^1\"", "lib: \"^1\", other: \"1\""),
+        )
+        .unwrap();
+        let err = lock(&Module::load(&app).unwrap(), &store).unwrap_err();
+        assert!(
+            err.message.contains("'other' is not installed"),
+            "{}",
+            err.message
+        );
+        let err = dependencies(&app, &store).unwrap_err();
+        assert!(err.message.contains("outdated"), "{}", err.message);
+
+        // Files which can not be walked are reported, not skipped
+        assert!(hash_dir(&root.path().join("missing")).is_err());
+    }
+}
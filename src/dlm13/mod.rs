@@ -0,0 +1,456 @@
+// Custom Modules for Luajit
+// IDK what the name means ...
+//
+// A module is a folder with a dlm13.yml manifest and an entrypoint script.
+// `flua run <name>` finds the folder `<name>` in the current directory or
+// else the module in the store, `--path=<dir>` points to it directly. Before
+// the entrypoint runs the manifest is checked: the name has to match the
+// folder, the entrypoint has to exist and the edition and the flua version
+// range have to fit
+
+pub mod loader;
+pub mod lock;
+pub mod store;
+
+use semver::Version;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::VERSION;
+use crate::helper::exit_code::{FILE_NOT_FOUND, FluaError, USAGE_ERROR};
+use crate::lua_script::ScriptSource;
+use store::Store;
+
+// Module Index File
+pub const MANIFEST: &str = "dlm13.yml";
+
+// Editions of the manifest which this flua understands
+pub const EDITIONS: &[i16] = &[2025];
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct ModuleConfig {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    author: String,
+    license: String,
+    link: String,
+    pub entrypoint: String,
+    luajitversion: Luajitversion,
+    edition: i16,
+    // Modules of the store with a semver range like `^1.2` or `>=1.0, <2.0`
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, String>,
+}
+
+// Range of flua versions which can run the module, both inclusive
+#[derive(Debug, Deserialize)]
+struct Luajitversion {
+    min: String,
+    max: String,
+}
+
+// A module with a valid manifest
+#[derive(Debug)]
+pub struct Module {
+    pub dir: PathBuf,
+    pub config: ModuleConfig,
+}
+
+impl ModuleConfig {
+    // Reads the manifest in `dir` without checking it
+    pub fn read(dir: &Path) -> Result<ModuleConfig, FluaError> {
+        let manifest = dir.join(MANIFEST);
+        if !manifest.is_file() {
+            return Err(FluaError::new(
+                FILE_NOT_FOUND,
+                format!(
+                    "No dlm13 module in '{}', {} is missing",
+                    dir.display(),
+                    MANIFEST
+                ),
+            ));
+        }
+
+        let yaml_str = fs::read_to_string(&manifest)
+            .map_err(|e| format!("Could not read '{}': {}", manifest.display(), e))?;
+        let config = serde_yaml::from_str(&yaml_str)
+            .map_err(|e| format!("Invalid manifest '{}': {}", manifest.display(), e))?;
+        Ok(config)
+    }
+}
+
+impl Module {
+    // Reads and checks the manifest of the module in `dir`
+    pub fn load(dir: &Path) -> Result<Module, FluaError> {
+        let config = ModuleConfig::read(dir)?;
+        let module = Module {
+            dir: dir.to_path_buf(),
+            config,
+        };
+        module.check()?;
+        Ok(module)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let name = &self.config.name;
+
+        let folder = fs::canonicalize(&self.dir)
+            .ok()
+            .and_then(|dir| dir.file_name().map(|f| f.to_string_lossy().to_string()));
+        if folder.as_deref() != Some(name.as_str()) {
+            return Err(format!(
+                "The module '{}' has to be in a folder with the same name, not in '{}'",
+                name,
+                self.dir.display()
+            ));
+        }
+
+        if !EDITIONS.contains(&self.config.edition) {
+            return Err(format!(
+                "The module '{}' has the edition {}, this flua supports {:?}",
+                name, self.config.edition, EDITIONS
+            ));
+        }
+
+        check_version_range(name, &self.config.luajitversion)?;
+
+        if !self.entrypoint().is_file() {
+            return Err(format!(
+                "The entrypoint '{}' of the module '{}' does not exist",
+                self.config.entrypoint, name
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn entrypoint(&self) -> PathBuf {
+        self.dir.join(&self.config.entrypoint)
+    }
+
+    // The entrypoint as a script for lua_script
+    pub fn source(&self) -> ScriptSource {
+        ScriptSource::File(self.entrypoint().to_string_lossy().to_string())
+    }
+}
+
+// The module folder of a script, the nearest directory with a dlm13.yml
+pub fn find_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join(MANIFEST).is_file())
+        .map(Path::to_path_buf)
+}
+
+// Names of modules are used as folder names and in require()
+pub fn check_name(name: &str) -> Result<(), FluaError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        return Ok(());
+    }
+    Err(FluaError::new(
+        USAGE_ERROR,
+        format!(
+            "'{}' is no valid name, use letters, digits, '_' and '-'",
+            name
+        ),
+    ))
+}
+
+// dlm13.yml of a new module for `flua init`, it runs with this flua version
+// up to the next minor version
+pub fn new_manifest(name: &str, entrypoint: &str) -> String {
+    let max = match Version::parse(VERSION) {
+        Ok(version) => format!("{}.{}.0", version.major, version.minor + 1),
+        Err(_) => VERSION.to_string(),
+    };
+    format!(
+        r#"name: "{name}"
+version: "0.1.0"
+description: ""
+author: ""
+license: ""
+link: ""
+
+# Start Point for the Module
+entrypoint: "{entrypoint}"
+
+# Versions of flua which can run the module
+luajitversion:
+  min: "{VERSION}"
+  max: "{max}"
+
+# Modules of the store which main.lua can require(), like
+#   helpers: "^1.2"
+# `flua module lock` writes the versions to dlm13.lock
+dependencies: {{}}
+
+# DO NOT EDIT THIS, THIS IS VERY IMPORTANT, IF THERE ARE CHANGES
+# IN THE MODULE FILE !!!
+edition: {edition}
+"#,
+        edition = EDITIONS[EDITIONS.len() - 1]
+    )
+}
+
+// Finds the module for `flua run <name>[@version]` or `flua run --path=<dir>`.
+// With a path the name is optional, but has to match the manifest when it is
+// given.
+// Without a path the folder `<name>` comes first, then the store
+pub fn resolve(name: Option<&str>, path: Option<&Path>) -> Result<Module, FluaError> {
+    resolve_in(&Store::open(), name, path)
+}
+
+fn resolve_in(store: &Store, name: Option<&str>, path: Option<&Path>) -> Result<Module, FluaError> {
+    let dir = match (path, name) {
+        (Some(path), _) => path.to_path_buf(),
+        (None, Some(spec)) => {
+            let (name, version) = store::split_spec(spec);
+            if version.is_none() && Path::new(name).join(MANIFEST).is_file() {
+                PathBuf::from(name)
+            } else {
+                return store.get(name, version);
+            }
+        }
+        (None, None) => {
+            return Err(FluaError::new(
+                USAGE_ERROR,
+                "Missing the name or the --path of the module",
+            ));
+        }
+    };
+
+    let module = Module::load(&dir)?;
+    if let Some(name) = name
+        && name != module.config.name
+    {
+        return Err(FluaError::from(format!(
+            "The module in '{}' is '{}', not '{}'",
+            dir.display(),
+            module.config.name,
+            name
+        )));
+    }
+    Ok(module)
+}
+
+fn check_version_range(name: &str, range: &Luajitversion) -> Result<(), String> {
+    let parse = |version: &str| {
+        Version::parse(version).map_err(|e| {
+            format!(
+                "The module '{}' has the invalid version '{}' in luajitversion, it needs a version like 0.2.1: {}",
+                name, version, e
+            )
+        })
+    };
+    let (min, max) = (parse(&range.min)?, parse(&range.max)?);
+    let current = Version::parse(VERSION).map_err(|e| e.to_string())?;
+
+    if current < min || current > max {
+        return Err(format!(
+            "The module '{}' needs flua {} to {}, but this is flua {}",
+            name, range.min, range.max, VERSION
+        ));
+    }
+    Ok(())
+}
+
+// Order of two module versions by semver, versions which can not be parsed
+// come after the others
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    match (Version::parse(a), Version::parse(b)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+// TODO
+// Ideas
+// add Option to create a shortcut for executing a module
+// Because flua run module ... is very long
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("1.2.3", "1.2.3"), Ordering::Equal);
+        assert_eq!(compare_versions("2.0.0", "1.9.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.2.4", "1.2.5"), Ordering::Less);
+        // No limit of 255 and semver order for pre-releases
+        assert_eq!(compare_versions("1.256.0", "1.255.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0.0-beta.2", "1.0.0"), Ordering::Less);
+        assert_eq!(
+            compare_versions("1.0.0-beta.10", "1.0.0-beta.2"),
+            Ordering::Greater
+        );
+        // Invalid versions like `1.2` come last
+        assert_eq!(compare_versions("1.2", "0.0.1"), Ordering::Greater);
+        assert_eq!(compare_versions("a.b.c", "1.2"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_deserialize_valid_config() {
+        let yaml = r#"
+name: "testmodule"
+version: "0.1.0"
+description: "A test module"
+author: "You"
+license: "MIT"
+link: "https://example.com"
+entrypoint: "main.lua"
+luajitversion:
+  min: "2.1.0"
+  max: "2.1.999"
+edition: 2025
+"#;
+
+        let config: ModuleConfig = serde_yaml::from_str(yaml).expect("Valid YAML should parse");
+        assert_eq!(config.name, "testmodule");
+        assert_eq!(config.luajitversion.min, "2.1.0");
+        assert_eq!(config.edition, 2025);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_deserialize_invalid_yaml() {
+        let invalid_yaml = r#"
+name: "broken"
+entrypoint: 123  # falscher Typ
+"#;
+        let _: ModuleConfig = serde_yaml::from_str(invalid_yaml).unwrap(); // sollte fehlschlagen
+    }
+
+    use crate::cli::{Cli, Command, normalize_args};
+    use crate::helper::permissions::Permissions;
+    use crate::lua_script::run_script;
+    use clap::Parser;
+    use std::fs::write;
+
+    // A module folder `testmod` with the manifest values after `name`
+    fn module_dir(root: &Path, folder: &str, manifest: &str) -> PathBuf {
+        let dir = root.join(folder);
+        std::fs::create_dir_all(&dir).unwrap();
+        write(
+            dir.join(MANIFEST),
+            format!(
+                "name: \"testmod\"\nversion: \"0.1.0\"\ndescription: \"desc\"\nauthor: \"you\"\nlicense: \"MIT\"\nlink: \"none\"\n{}",
+                manifest
+            ),
+        )
+        .unwrap();
+        dir
+    }
+
+    const VALID: &str = "entrypoint: \"main.lua\"\nluajitversion: { min: \"0.0.1\", max: \"9.9.9\" }\nedition: 2025\n";
+
+    #[test]
+    fn test_start_module_with_valid_config() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = module_dir(root.path(), "testmod", VALID);
+        let out = dir.join("out.txt");
+        write(
+            dir.join("main.lua"),
+            format!(
+                "local f = io.open({:?}, 'w')\nf:write(table.concat(arg, ','))\nf:close()",
+                out.to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        let module = resolve(Some("testmod"), Some(&dir)).expect("the module is valid");
+        assert_eq!(module.config.name, "testmod");
+
+        let args = vec!["a".to_string(), "-b".to_string()];
+        run_script(&module.source(), &Permissions::allow_all(), args).unwrap();
+        assert_eq!(fs::read_to_string(out).unwrap(), "a,-b");
+    }
+
+    #[test]
+    fn test_run_module_invocations() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = module_dir(root.path(), "testmod", VALID);
+        let out = root.path().join("out.txt");
+        write(
+            dir.join("main.lua"),
+            format!(
+                "local f = io.open({:?}, 'w')\nf:write(table.concat(arg, ','))\nf:close()",
+                out.to_string_lossy()
+            ),
+        )
+        .unwrap();
+        let store = Store::at(root.path().join("store"));
+        store.install(&dir, false).unwrap();
+
+        let path = dir.to_string_lossy();
+        let forms = [
+            vec!["run".to_string(), "testmod".into()],
+            vec!["run".to_string(), format!("--path={}", path)],
+            vec![
+                "-nw".to_string(),
+                "run".into(),
+                "module".into(),
+                format!("-path={}", path),
+            ],
+        ];
+        for form in forms {
+            let mut args = vec!["flua".to_string()];
+            args.extend(form.iter().cloned());
+            args.extend(["Alice".to_string(), "--loud".into()]);
+
+            let cli = Cli::try_parse_from(normalize_args(args)).unwrap();
+            let Some(Command::Run(run)) = cli.command else {
+                panic!("expected the run command for {:?}", form);
+            };
+            let (name, lua_args) = run.module_args();
+            let module = resolve_in(&store, name, run.path.as_deref().map(Path::new)).unwrap();
+            run_script(&module.source(), &Permissions::allow_all(), lua_args).unwrap();
+            assert_eq!(
+                fs::read_to_string(&out).unwrap(),
+                "Alice,--loud",
+                "{:?}",
+                form
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_modules() {
+        let root = tempfile::tempdir().unwrap();
+        let error = |dir: &Path| Module::load(dir).unwrap_err().message;
+
+        let dir = module_dir(root.path(), "other", VALID);
+        write(dir.join("main.lua"), "").unwrap();
+        assert!(error(&dir).contains("folder with the same name"));
+
+        let dir = module_dir(root.path(), "testmod", VALID);
+        assert!(error(&dir).contains("entrypoint 'main.lua'"));
+        write(dir.join("main.lua"), "").unwrap();
+        assert!(resolve(Some("wrong"), Some(&dir)).is_err());
+
+        let edition = VALID.replace("2025", "2030");
+        module_dir(root.path(), "testmod", &edition);
+        assert!(error(&dir).contains("edition 2030"));
+
+        let range = VALID.replace("9.9.9", "0.0.2");
+        module_dir(root.path(), "testmod", &range);
+        assert!(error(&dir).contains("needs flua 0.0.1 to 0.0.2"));
+
+        let range = VALID.replace("9.9.9", "1.0");
+        module_dir(root.path(), "testmod", &range);
+        assert!(error(&dir).contains("invalid version '1.0'"));
+
+        let missing = Module::load(&root.path().join("missing")).unwrap_err();
+        assert_eq!(missing.code, FILE_NOT_FOUND);
+    }
+}
@@ -0,0 +1,465 @@
+// The module store, installed dlm13 modules in the flua data dir
+//
+// Every version of a module has its own folder and the file `default` holds
+// the version which `flua run <name>` uses:
+//
+//   modules/greeter/default          "1.1.0"
+//   modules/greeter/1.0.0/greeter/   dlm13.yml, main.lua, ...
+//   modules/greeter/1.1.0/greeter/
+//
+// The inner folder has the name of the module, so the installed module passes
+// the same checks as a local one. Installing first builds the module in
+// `.staging` and moves it into place only when it is valid
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dlm13::{self, MANIFEST, Module, ModuleConfig};
+use crate::helper::config::flua_data_dir;
+use crate::helper::dir::copy_dir_recursive;
+use crate::helper::exit_code::{FILE_NOT_FOUND, FluaError, USAGE_ERROR};
+use crate::utils::zip_utils::unzip_file;
+
+const DEFAULT: &str = "default";
+const STAGING: &str = ".staging";
+
+pub struct Store {
+    root: PathBuf,
+}
+
+// One installed version for `flua module list`
+#[derive(Debug)]
+pub struct Installed {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub default: bool,
+}
+
+// `greeter@1.0.0` => ("greeter", Some("1.0.0"))
+pub fn split_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (spec, None),
+    }
+}
+
+// Versions are folder names, so they can not contain separators or `..`
+fn check_version(name: &str, version: &str) -> Result<(), FluaError> {
+    let valid = !version.is_empty()
+        && !version.starts_with('.')
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+' | '_'));
+    if valid {
+        return Ok(());
+    }
+    Err(FluaError::new(
+        USAGE_ERROR,
+        format!(
+            "The module '{}' has the invalid version '{}'",
+            name, version
+        ),
+    ))
+}
+
+impl Store {
+    // The store in the flua data dir
+    pub fn open() -> Store {
+        Store::at(flua_data_dir().join("modules"))
+    }
+
+    pub fn at(root: impl Into<PathBuf>) -> Store {
+        Store { root: root.into() }
+    }
+
+    fn version_dir(&self, name: &str, version: &str) -> PathBuf {
+        self.root.join(name).join(version).join(name)
+    }
+
+    // All installed versions of a module, the oldest first
+    pub fn versions(&self, name: &str) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(self.root.join(name)) else {
+            return Vec::new();
+        };
+        let mut versions: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|version| self.version_dir(name, version).join(MANIFEST).is_file())
+            .collect();
+        versions.sort_by(|a, b| dlm13::compare_versions(a, b));
+        versions
+    }
+
+    // The version of the `default` file, or the newest when it is missing or
+    // points to a removed version
+    pub fn default_version(&self, name: &str) -> Option<String> {
+        let versions = self.versions(name);
+        let default = fs::read_to_string(self.root.join(name).join(DEFAULT)).ok();
+        match default.map(|v| v.trim().to_string()) {
+            Some(version) if versions.contains(&version) => Some(version),
+            _ => versions.last().cloned(),
+        }
+    }
+
+    pub fn set_default(&self, name: &str, version: &str) -> Result<(), FluaError> {
+        if !self.versions(name).iter().any(|v| v == version) {
+            return Err(self.not_installed(name, Some(version)));
+        }
+        let path = self.root.join(name).join(DEFAULT);
+        fs::write(&path, version)
+            .map_err(|e| FluaError::from(format!("Could not write '{}': {}", path.display(), e)))
+    }
+
+    fn not_installed(&self, name: &str, version: Option<&str>) -> FluaError {
+        let versions = self.versions(name);
+        let message = match version {
+            Some(version) if !versions.is_empty() => format!(
+                "The module '{}@{}' is not installed, installed versions: {}",
+                name,
+                version,
+                versions.join(", ")
+            ),
+            _ => format!(
+                "The module '{}' is not installed, install it with `flua module install <path>`",
+                name
+            ),
+        };
+        FluaError::new(FILE_NOT_FOUND, message)
+    }
+
+    // The module `name` in `version` or the default version
+    pub fn get(&self, name: &str, version: Option<&str>) -> Result<Module, FluaError> {
+        let version = match version {
+            Some(version) => version.to_string(),
+            None => self
+                .default_version(name)
+                .ok_or_else(|| self.not_installed(name, None))?,
+        };
+        let dir = self.version_dir(name, &version);
+        if !dir.join(MANIFEST).is_file() {
+            return Err(self.not_installed(name, Some(&version)));
+        }
+        Module::load(&dir)
+    }
+
+    // Installs the module folder or zip file at `source`. An installed version
+    // is only replaced with `force`. The first version becomes the default
+    pub fn install(&self, source: &Path, force: bool) -> Result<Module, FluaError> {
+        if !source.exists() {
+            return Err(FluaError::new(
+                FILE_NOT_FOUND,
+                format!("'{}' does not exist", source.display()),
+            ));
+        }
+
+        let staging = self.root.join(STAGING).join(unique_name());
+        let result = self.install_staged(source, &staging, force);
+        let _ = fs::remove_dir_all(&staging);
+        result
+    }
+
+    fn install_staged(
+        &self,
+        source: &Path,
+        staging: &Path,
+        force: bool,
+    ) -> Result<Module, FluaError> {
+        let io_error = |what: &str, path: &Path, e: std::io::Error| {
+            FluaError::from(format!("Could not {} '{}': {}", what, path.display(), e))
+        };
+
+        let found = if source.is_dir() {
+            source.to_path_buf()
+        } else {
+            let unzipped = staging.join("unzipped");
+            unzip_file(&source.to_string_lossy(), &unzipped.to_string_lossy())
+                .map_err(|e| io_error("unzip", source, e))?;
+            find_manifest(&unzipped).ok_or_else(|| {
+                FluaError::new(
+                    FILE_NOT_FOUND,
+                    format!("No {} in '{}'", MANIFEST, source.display()),
+                )
+            })?
+        };
+
+        let config = ModuleConfig::read(&found)?;
+        dlm13::check_name(&config.name)?;
+        check_version(&config.name, &config.version)?;
+
+        let target = self.version_dir(&config.name, &config.version);
+        if target.exists() && !force {
+            return Err(FluaError::new(
+                USAGE_ERROR,
+                format!(
+                    "The module '{}@{}' is already installed, use --force to replace it",
+                    config.name, config.version
+                ),
+            ));
+        }
+
+        // The staged folder gets the name of the module before the checks
+        let staged = staging.join(&config.name);
+        if source.is_dir() {
+            copy_dir_recursive(&found, &staged).map_err(|e| io_error("copy", source, e))?;
+        } else {
+            fs::rename(&found, &staged).map_err(|e| io_error("move", &found, e))?;
+        }
+        Module::load(&staged)?;
+
+        if target.exists() {
+            fs::remove_dir_all(&target).map_err(|e| io_error("remove", &target, e))?;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| io_error("create", parent, e))?;
+        }
+        fs::rename(&staged, &target).map_err(|e| io_error("move", &staged, e))?;
+
+        if !self.root.join(&config.name).join(DEFAULT).is_file() {
+            self.set_default(&config.name, &config.version)?;
+        }
+        Module::load(&target)
+    }
+
+    // Removes one version or, without a version, the whole module. Returns
+    // the removed versions
+    pub fn remove(&self, name: &str, version: Option<&str>) -> Result<Vec<String>, FluaError> {
+        let versions = self.versions(name);
+        let dir = self.root.join(name);
+        let remove = |path: &Path| {
+            fs::remove_dir_all(path).map_err(|e| {
+                FluaError::from(format!("Could not remove '{}': {}", path.display(), e))
+            })
+        };
+
+        let Some(version) = version else {
+            if versions.is_empty() {
+                return Err(self.not_installed(name, None));
+            }
+            remove(&dir)?;
+            return Ok(versions);
+        };
+
+        if !versions.iter().any(|v| v == version) {
+            return Err(self.not_installed(name, Some(version)));
+        }
+        if versions.len() == 1 {
+            remove(&dir)?;
+            return Ok(versions);
+        }
+
+        let was_default = self.default_version(name).as_deref() == Some(version);
+        remove(&dir.join(version))?;
+        if was_default && let Some(newest) = self.versions(name).last() {
+            self.set_default(name, newest)?;
+        }
+        Ok(vec![version.to_string()])
+    }
+
+    // Every installed version, sorted by name and version
+    pub fn list(&self) -> Vec<Installed> {
+        let Ok(entries) = fs::read_dir(&self.root) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| !name.starts_with('.'))
+            .collect();
+        names.sort();
+
+        let mut installed = Vec::new();
+        for name in names {
+            let default = self.default_version(&name);
+            for version in self.versions(&name) {
+                let Ok(config) = ModuleConfig::read(&self.version_dir(&name, &version)) else {
+                    continue;
+                };
+                installed.push(Installed {
+                    default: default.as_deref() == Some(version.as_str()),
+                    name: name.clone(),
+                    version,
+                    description: config.description,
+                });
+            }
+        }
+        installed
+    }
+}
+
+// The folder with the manifest, the top of the zip or its only folder
+fn find_manifest(dir: &Path) -> Option<PathBuf> {
+    if dir.join(MANIFEST).is_file() {
+        return Some(dir.to_path_buf());
+    }
+    let folders: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    match folders.as_slice() {
+        [folder] if folder.join(MANIFEST).is_file() => Some(folder.clone()),
+        _ => None,
+    }
+}
+
+// Staging folder of one installation, so parallel installs do not collide
+fn unique_name() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    format!("{}-{}", std::process::id(), nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::zip_utils::zip_dir;
+
+    // The source folder of `greeter` in `version`
+    fn source(root: &Path, version: &str) -> PathBuf {
+        let dir = root.join("src").join(version).join("greeter");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(MANIFEST),
+            format!(
+                "name: \"greeter\"\nversion: \"{}\"\ndescription: \"Says hello\"\nauthor: \"\"\nlicense: \"\"\nlink: \"\"\nentrypoint: \"main.lua\"\nluajitversion: {{ min: \"0.0.1\", max: \"9.9.9\" }}\nedition: 2025\n",
+                version
+            ),
+        )
+        .unwrap();
+        fs::write(dir.join("main.lua"), format!("return '{}'", version)).unwrap();
+        dir
+    }
+
+    #[test]
+    fn versions_and_default() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+
+        store.install(&source(root.path(), "1.0.0"), false).unwrap();
+        store
+            .install(&source(root.path(), "1.10.0"), false)
+            .unwrap();
+        store.install(&source(root.path(), "1.2.0"), false).unwrap();
+        assert_eq!(store.versions("greeter"), ["1.0.0", "1.2.0", "1.10.0"]);
+
+        // The first installation stays the default
+        assert_eq!(store.get("greeter", None).unwrap().config.version, "1.0.0");
+        let module = store.get("greeter", Some("1.2.0")).unwrap();
+        assert!(module.dir.ends_with("greeter/1.2.0/greeter"));
+
+        store.set_default("greeter", "1.10.0").unwrap();
+        let list = store.list();
+        assert_eq!(list.len(), 3);
+        assert!(list.iter().all(|m| m.description == "Says hello"));
+        assert_eq!(
+            list.iter()
+                .filter(|m| m.default)
+                .map(|m| &m.version)
+                .collect::<Vec<_>>(),
+            ["1.10.0"]
+        );
+
+        let err = store.get("greeter", Some("2.0.0")).unwrap_err();
+        assert_eq!(err.code, FILE_NOT_FOUND);
+        assert!(
+            err.message.contains("1.0.0, 1.2.0, 1.10.0"),
+            "{}",
+            err.message
+        );
+        assert!(store.set_default("greeter", "2.0.0").is_err());
+    }
+
+    #[test]
+    fn install_only_replaces_with_force() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        let dir = source(root.path(), "1.0.0");
+
+        store.install(&dir, false).unwrap();
+        let err = store.install(&dir, false).unwrap_err();
+        assert!(err.message.contains("already installed"), "{}", err.message);
+
+        fs::write(dir.join("main.lua"), "return 'new'").unwrap();
+        let module = store.install(&dir, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(module.entrypoint()).unwrap(),
+            "return 'new'"
+        );
+        assert!(
+            !root
+                .path()
+                .join("modules")
+                .join(STAGING)
+                .read_dir()
+                .unwrap()
+                .any(|_| true)
+        );
+    }
+
+    #[test]
+    fn install_from_zip() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        let dir = source(root.path(), "0.3.0");
+        let zip = root.path().join("greeter.zip");
+        zip_dir(&dir.to_string_lossy(), &zip.to_string_lossy()).unwrap();
+
+        let module = store.install(&zip, false).unwrap();
+        assert_eq!(module.config.name, "greeter");
+        assert_eq!(store.versions("greeter"), ["0.3.0"]);
+    }
+
+    #[test]
+    fn invalid_modules_are_not_installed() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+
+        let dir = source(root.path(), "1.0.0");
+        fs::remove_file(dir.join("main.lua")).unwrap();
+        let err = store.install(&dir, false).unwrap_err();
+        assert!(err.message.contains("entrypoint"), "{}", err.message);
+
+        let dir = source(root.path(), "../1.0.0");
+        let err = store.install(&dir, false).unwrap_err();
+        assert!(err.message.contains("invalid version"), "{}", err.message);
+
+        let err = store
+            .install(&root.path().join("missing"), false)
+            .unwrap_err();
+        assert_eq!(err.code, FILE_NOT_FOUND);
+        assert!(store.list().is_empty());
+    }
+
+    #[test]
+    fn remove_versions() {
+        let root = tempfile::tempdir().unwrap();
+        let store = Store::at(root.path().join("modules"));
+        for version in ["1.0.0", "1.1.0", "2.0.0"] {
+            store.install(&source(root.path(), version), false).unwrap();
+        }
+
+        // Removing the default moves it to the newest version
+        assert_eq!(store.remove("greeter", Some("1.0.0")).unwrap(), ["1.0.0"]);
+        assert_eq!(store.default_version("greeter").as_deref(), Some("2.0.0"));
+        assert!(store.remove("greeter", Some("1.0.0")).is_err());
+
+        assert_eq!(store.remove("greeter", None).unwrap(), ["1.1.0", "2.0.0"]);
+        assert!(!root.path().join("modules/greeter").exists());
+        assert_eq!(
+            store.remove("greeter", None).unwrap_err().code,
+            FILE_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn specs() {
+        assert_eq!(split_spec("greeter"), ("greeter", None));
+        assert_eq!(split_spec("greeter@1.0.0"), ("greeter", Some("1.0.0")));
+    }
+}
@@ -3,15 +3,36 @@
 use dirs_next;
 use mlua::Lua;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::helper::exit;
+use crate::helper::deprecation::Policy;
 
 // flua Config struct
 pub struct FluaConfig {
     // CONFIG VALUES
+    #[allow(dead_code)]
     pub wait_time: u64,
     pub show_info: bool,
+    // `ignore`, `warn` or `error`, --deprecations overrides it
+    pub deprecations: Policy,
+}
+
+// Directory for the Config File and other flua files like the REPL history
+pub fn flua_config_dir() -> PathBuf {
+    let mut path: PathBuf = dirs_next::config_dir().expect("could not find config_dir()");
+
+    path.push("@shadowdara");
+    path.push("flua");
+    path
+}
+
+// Directory for data of flua like the module store
+pub fn flua_data_dir() -> PathBuf {
+    let mut path: PathBuf = dirs_next::data_dir().expect("could not find data_dir()");
+
+    path.push("@shadowdara");
+    path.push("flua");
+    path
 }
 
 // Function to load the Config File
@@ -20,14 +41,11 @@ pub fn loadconfig(doload: bool) -> FluaConfig {
         return FluaConfig {
             wait_time: 3,
             show_info: true,
+            deprecations: Policy::default(),
         };
     }
 
-    let mut path: PathBuf = dirs_next::config_dir().expect("could not find config_dir()");
-
-    path.push("@shadowdara");
-    path.push("flua");
-    path.push("config.lua");
+    let path: PathBuf = flua_config_dir().join("config.lua");
 
     let contents: String = match fs::read_to_string(&path) {
         Ok(c) => c,
@@ -36,6 +54,7 @@ pub fn loadconfig(doload: bool) -> FluaConfig {
             return FluaConfig {
                 wait_time: 3,
                 show_info: true,
+                deprecations: Policy::default(),
             };
         }
     };
@@ -52,23 +71,36 @@ pub fn loadconfig(doload: bool) -> FluaConfig {
     let wait_time: u64 = config_table.get("wait_time").unwrap_or(3);
     let show_info: bool = config_table.get("wait_time").unwrap_or(true);
 
-    return FluaConfig {
+    let deprecations = match config_table.get::<Option<String>>("deprecations") {
+        Ok(Some(name)) => Policy::parse(&name).unwrap_or_else(|| {
+            eprintln!(
+                "Unknown value '{}' of c.deprecations, use 'ignore', 'warn' or 'error'",
+                name
+            );
+            Policy::default()
+        }),
+        _ => Policy::default(),
+    };
+
+    FluaConfig {
         wait_time,
         show_info,
-    };
+        deprecations,
+    }
 }
 
-pub fn configstuff(args: Vec<String>, wait_on_exit: bool) {
-    let mut args_iter = args.iter().peekable();
-    let mut path: PathBuf = dirs_next::config_dir().expect("could not find config_dir()");
+// Runs a `flua config <action>` command on the default config file
+pub fn configstuff(action: &str) -> Result<(), String> {
+    config_action(action, &flua_config_dir().join("config.lua"))
+}
 
-    path.push("@shadowdara");
-    path.push("flua");
-    path.push("config.lua");
+// Runs a config action on the config file at `path`
+pub fn config_action(action: &str, path: &Path) -> Result<(), String> {
+    let mut path: PathBuf = path.to_path_buf();
 
-    match args_iter.peek().map(|s| s.as_str()) {
+    match action {
         // To generate a new Config File
-        Some("generate") => {
+        "generate" => {
             println!("Generating new config file...");
 
             if path.exists() {
@@ -93,47 +125,55 @@ c.wait_time = 0
 
 -- Setting for Info printing in the Terminal
 c.show_info = false
+
+-- Calls of deprecated functions: "ignore", "warn" or "error"
+c.deprecations = "warn"
 "#;
 
-                match fs::write(path.clone(), contents) {
-                    Ok(_) => println!("File '{}' created!", path.display()),
-                    Err(e) => eprintln!("Error while creating file: {}", e),
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .map_err(|e| format!("Error while creating config folder: {}", e))?;
                 }
+                fs::write(&path, contents)
+                    .map_err(|e| format!("Error while creating file: {}", e))?;
+                println!("File '{}' created!", path.display());
             }
         }
         // to delete the config file
-        Some("clean") => match fs::remove_file(path.clone()) {
-            Ok(_) => println!("==> Configfile '{}' deleted!", path.display()),
-            Err(e) => println!("==> Error while deleting: {}", e),
-        },
+        "clean" => {
+            fs::remove_file(&path).map_err(|e| format!("Error while deleting: {}", e))?;
+            println!("==> Configfile '{}' deleted!", path.display());
+        }
         // To open the Config File in a default Editor
-        Some("open") => {
-            // args_iter.next(); // consume the "open" argument
+        "open" => {
             println!("Opening config file...");
-            open::that(&path).expect("Failed to open file");
-            // Open code here
+            open::that(&path).map_err(|e| format!("Failed to open file: {}", e))?;
         }
-        Some("opendir") => {
+        "opendir" => {
             // Open the directory of the config file
             path.pop();
-            open::that(path).expect("Could not open config Folder");
+            open::that(&path).map_err(|e| format!("Could not open config Folder: {}", e))?;
         }
-        Some("dir") => {
+        "dir" => {
             // Show the directory of the config file
             path.pop();
             println!("Config Folder: {}", path.display());
         }
-        Some("check") => {
+        "check" => {
             // Trying to load the config file if it works
-            println!("Config Check Implemented soon!\nPlease open an Issue on GitHub if you see this!");
+            println!(
+                "Config Check Implemented soon!\nPlease open an Issue on GitHub if you see this!"
+            );
         }
-        _ => {
-            println!("No subcommand specified for config.");
-            println!("or run flua --help-config")
+        other => {
+            return Err(format!(
+                "Unknown config action '{}', run flua config --help",
+                other
+            ));
         }
     }
-    // Interrupt after opening the Config File
-    exit(wait_on_exit, false);
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -191,17 +231,12 @@ mod tests {
     // }
 
     #[test]
-    fn configstuff_generate_creates_file() {
+    fn config_action_generate_creates_file() {
         let dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("XDG_CONFIG_HOME", dir.path());
-        }
-
-        let args = vec![String::from("generate")];
+        let config_path = dir.path().join("@shadowdara/flua/config.lua");
 
-        configstuff(args, false);
+        config_action("generate", &config_path).unwrap();
 
-        let config_path = dir.path().join("@shadowdara/flua/config.lua");
         assert!(
             config_path.exists(),
             "Config file should exist after generate"
@@ -209,22 +244,25 @@ mod tests {
     }
 
     #[test]
-    fn configstuff_clean_deletes_file() {
+    fn config_action_generate_keeps_existing_file() {
         let dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("XDG_CONFIG_HOME", dir.path());
-        }
+        let config_path = dir.path().join("config.lua");
+        fs::write(&config_path, "c = {}").unwrap();
 
-        let config_dir = dir.path().join("@shadowdara/flua");
-        fs::create_dir_all(&config_dir).unwrap();
-        let config_path = config_dir.join("config.lua");
+        config_action("generate", &config_path).unwrap();
+
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), "c = {}");
+    }
+
+    #[test]
+    fn config_action_clean_deletes_file() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.lua");
 
         fs::write(&config_path, "test").unwrap();
         assert!(config_path.exists());
 
-        let args = vec![String::from("clean")];
-
-        configstuff(args, false);
+        config_action("clean", &config_path).unwrap();
         assert!(
             !config_path.exists(),
             "Config file should be deleted by 'clean'"
@@ -232,27 +270,15 @@ mod tests {
     }
 
     #[test]
-    fn configstuff_open_does_not_panic() {
+    fn config_action_clean_missing_file_is_an_error() {
         let dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("XDG_CONFIG_HOME", dir.path());
-        }
-
-        let args = vec![String::from("open")];
-
-        let _ = std::panic::catch_unwind(|| configstuff(args, false));
+        assert!(config_action("clean", &dir.path().join("config.lua")).is_err());
     }
 
     #[test]
-    fn configstuff_no_argument_prints_message() {
+    fn config_action_unknown_action_is_an_error() {
         let dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("XDG_CONFIG_HOME", dir.path());
-        }
-
-        let args: Vec<String> = vec![];
-
-        configstuff(args, false);
+        assert!(config_action("unknown", &dir.path().join("config.lua")).is_err());
     }
 
     // // TODO
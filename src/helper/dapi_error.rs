@@ -0,0 +1,40 @@
+// Errors of dapi functions
+//
+// Every error starts with the name of the function which raised it, like
+// `dapi_net.fetch: HTTP request failed: ...`
+
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct DapiError {
+    pub function: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for DapiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.function, self.message)
+    }
+}
+
+impl std::error::Error for DapiError {}
+
+// Creates the Lua error for a failed dapi function
+pub fn dapi_error(function: &'static str, message: impl fmt::Display) -> mlua::Error {
+    mlua::Error::external(DapiError {
+        function,
+        message: message.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_starts_with_function() {
+        let err = dapi_error("dapi_net.fetch", "HTTP status 404 Not Found");
+        assert_eq!(err.to_string(), "dapi_net.fetch: HTTP status 404 Not Found");
+        assert!(err.downcast_ref::<DapiError>().is_some());
+    }
+}
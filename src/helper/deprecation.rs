@@ -0,0 +1,277 @@
+// Deprecated dapi functions
+//
+// Every deprecation is listed once in DEPRECATIONS, the functions call
+// `deprecation::used()` and the policy decides what happens: nothing, one
+// warning on stderr per function and run, or an error. `flua check`,
+// `flua stubs`, dapi.help() and `flua deprecations` read the same list
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::helper::dapi_error::dapi_error;
+use crate::helper::print::{END, YELLOW};
+
+pub struct Deprecation {
+    // Full name like `dapi_os.run`
+    pub name: &'static str,
+    pub since: &'static str,
+    // Version which removes the function, None when it is not planned yet
+    pub removal: Option<&'static str>,
+    // What to do instead
+    pub replacement: &'static str,
+}
+
+pub const DEPRECATIONS: &[Deprecation] = &[
+    Deprecation {
+        name: "dapi.greet",
+        since: "0.1.8",
+        removal: None,
+        replacement: "Use print() instead",
+    },
+    Deprecation {
+        name: "dapi.add",
+        since: "0.1.8",
+        removal: None,
+        replacement: "Use the + operator instead",
+    },
+    Deprecation {
+        name: "dapi.download",
+        since: "0.1.13",
+        removal: None,
+        replacement: "Use dapi_net.download_file() instead",
+    },
+    Deprecation {
+        name: "dapi.wait",
+        since: "0.1.13",
+        removal: None,
+        replacement: "Use dapi_time.wait() instead",
+    },
+    Deprecation {
+        name: "dapi_io.zip",
+        since: "0.1.10",
+        removal: None,
+        replacement: "No replacement yet, the function could go horribly wrong",
+    },
+    Deprecation {
+        name: "dapi_io.unzip",
+        since: "0.1.10",
+        removal: None,
+        replacement: "No replacement yet, the function could go horribly wrong",
+    },
+    Deprecation {
+        name: "dapi_io.create_file",
+        since: "0.1.10",
+        removal: None,
+        replacement: "Use io.open() of the Lua standard library instead",
+    },
+    Deprecation {
+        name: "dapi_io.write_file",
+        since: "0.1.10",
+        removal: None,
+        replacement: "Use io.open() of the Lua standard library instead",
+    },
+    Deprecation {
+        name: "dapi_os.open_link",
+        since: "0.2.0",
+        removal: None,
+        replacement: "Use dapi_os.open() instead, the function changed its name",
+    },
+    Deprecation {
+        name: "dapi_os.run",
+        since: "0.1.10",
+        removal: None,
+        replacement: "Use dapi_os.run2() or io.popen() of the Lua standard library instead",
+    },
+];
+
+impl Deprecation {
+    // Like `since 0.1.13`, or `since 0.1.13 and will be removed in 0.3.0`
+    pub fn versions(&self) -> String {
+        match self.removal {
+            Some(removal) => format!("since {} and will be removed in {}", self.since, removal),
+            None => format!("since {}", self.since),
+        }
+    }
+}
+
+pub fn find(name: &str) -> Option<&'static Deprecation> {
+    DEPRECATIONS
+        .iter()
+        .find(|deprecation| deprecation.name == name)
+}
+
+// What happens when a script calls a deprecated function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Policy {
+    Ignore,
+    #[default]
+    Warn,
+    Error,
+}
+
+impl Policy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ignore" => Some(Policy::Ignore),
+            "warn" => Some(Policy::Warn),
+            "error" => Some(Policy::Error),
+            _ => None,
+        }
+    }
+}
+
+// One policy for the whole process, so dapi_thread workers use it as well
+static POLICY: AtomicU8 = AtomicU8::new(Policy::Warn as u8);
+static WARNED: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+
+// Set by main from --deprecations or the config file
+pub fn set_policy(policy: Policy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn policy() -> Policy {
+    match POLICY.load(Ordering::Relaxed) {
+        0 => Policy::Ignore,
+        2 => Policy::Error,
+        _ => Policy::Warn,
+    }
+}
+
+// Called by a deprecated function before it does anything
+pub fn used(name: &'static str) -> mlua::Result<()> {
+    debug_assert!(find(name).is_some(), "{} is not in DEPRECATIONS", name);
+    apply(name, policy())
+}
+
+fn apply(name: &'static str, policy: Policy) -> mlua::Result<()> {
+    let Some(deprecation) = find(name) else {
+        return Ok(());
+    };
+
+    match policy {
+        Policy::Ignore => Ok(()),
+        Policy::Warn => {
+            if first_use(name) {
+                eprintln!(
+                    "{}[DEPRECATED-WARNING] '{}' is deprecated {}. {}{}",
+                    YELLOW,
+                    name,
+                    deprecation.versions(),
+                    deprecation.replacement,
+                    END
+                );
+            }
+            Ok(())
+        }
+        Policy::Error => Err(dapi_error(
+            name,
+            format!(
+                "deprecated {}, the deprecation policy makes this an error. {}",
+                deprecation.versions(),
+                deprecation.replacement
+            ),
+        )),
+    }
+}
+
+// True only for the first call of every function
+fn first_use(name: &'static str) -> bool {
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    warned.get_or_insert_with(HashSet::new).insert(name)
+}
+
+// `flua deprecations`
+pub fn print_list() {
+    for deprecation in DEPRECATIONS {
+        println!(
+            "{}{:<20}{} deprecated {}\n{:<20} {}",
+            YELLOW,
+            deprecation.name,
+            END,
+            deprecation.versions(),
+            "",
+            deprecation.replacement
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_lookup() {
+        let download = find("dapi.download").unwrap();
+        assert_eq!(download.since, "0.1.13");
+        assert_eq!(download.versions(), "since 0.1.13");
+        assert!(find("dapi.version").is_none());
+
+        let planned = Deprecation {
+            name: "dapi.example",
+            since: "0.2.1",
+            removal: Some("1.0.0"),
+            replacement: "",
+        };
+        assert_eq!(
+            planned.versions(),
+            "since 0.2.1 and will be removed in 1.0.0"
+        );
+    }
+
+    #[test]
+    fn every_used_name_is_registered() {
+        let pattern = "deprecation::used(\"";
+        let mut names = Vec::new();
+        for entry in walkdir::WalkDir::new("src") {
+            let path = entry.unwrap().into_path();
+            if path.extension().is_none_or(|ext| ext != "rs") {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            for (start, _) in source.match_indices(pattern) {
+                let rest = &source[start + pattern.len()..];
+                let name = &rest[..rest.find('"').unwrap()];
+                assert!(
+                    find(name).is_some(),
+                    "{} in {:?} is not registered",
+                    name,
+                    path
+                );
+                names.push(name.to_string());
+            }
+        }
+
+        // Every registered function calls used()
+        for deprecation in DEPRECATIONS {
+            assert!(
+                names.iter().any(|name| name == deprecation.name),
+                "{}",
+                deprecation.name
+            );
+        }
+    }
+
+    #[test]
+    fn policies() {
+        assert!(apply("dapi.greet", Policy::Ignore).is_ok());
+        assert!(apply("dapi.greet", Policy::Warn).is_ok());
+
+        let err = apply("dapi.wait", Policy::Error).unwrap_err().to_string();
+        assert!(
+            err.contains("dapi.wait: deprecated since 0.1.13"),
+            "{}",
+            err
+        );
+        assert!(err.contains("Use dapi_time.wait() instead"), "{}", err);
+
+        assert_eq!(Policy::parse("error"), Some(Policy::Error));
+        assert_eq!(Policy::parse("loud"), None);
+    }
+
+    #[test]
+    fn warns_once() {
+        first_use("dapi.add");
+        assert!(!first_use("dapi.add"));
+    }
+}
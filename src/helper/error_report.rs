@@ -0,0 +1,309 @@
+// Error reports for Lua scripts with the failing source line and the traceback
+//
+// main.lua:3: attempt to call a nil value (global 'foo')
+//   --> main.lua:3
+//    |
+//  2 | local x = 1
+//  3 | foo(x)
+//    | ^^^^^^
+//  4 | print(x)
+//    |
+// stack traceback:
+//     ...
+
+use std::fmt::Write;
+use std::fs;
+
+// Lines before and after the failing line
+const CONTEXT_LINES: usize = 2;
+
+// Source of the main chunk, the code of `flua -e` and `flua -` is not a file
+pub struct MainChunk<'a> {
+    // Name like Lua prints it in messages, e.g. `main.lua` or `(command line)`
+    pub name: &'a str,
+    pub source: &'a str,
+}
+
+// Formats a Lua error with the source context and the full traceback
+pub fn report(error: &mlua::Error, main: Option<&MainChunk>) -> String {
+    format_error(error, main, true)
+}
+
+// Like report() but without the traceback, for the failures of `flua test`
+pub fn report_short(error: &mlua::Error, main: Option<&MainChunk>) -> String {
+    format_error(error, main, false)
+}
+
+fn format_error(error: &mlua::Error, main: Option<&MainChunk>, with_traceback: bool) -> String {
+    let (message, traceback) = split_error(error);
+
+    let mut out = message.trim_end().to_string();
+
+    let location = message
+        .lines()
+        .find_map(parse_location)
+        .or_else(|| traceback.as_deref().and_then(traceback_location));
+
+    if let Some((chunk, line)) = location
+        && let Some(context) = source_context(&chunk, line, main)
+    {
+        out.push('\n');
+        out.push_str(&context);
+    }
+
+    if with_traceback && let Some(traceback) = traceback {
+        out.push('\n');
+        out.push_str(traceback.trim_end());
+    }
+
+    out
+}
+
+// Splits an error into the message and the Lua traceback
+fn split_error(error: &mlua::Error) -> (String, Option<String>) {
+    match error {
+        // Errors of Rust functions, the deepest traceback is the complete one
+        mlua::Error::CallbackError { traceback, cause } => {
+            let (mut cause, mut traceback) = (cause, traceback);
+            while let mlua::Error::CallbackError {
+                cause: inner_cause,
+                traceback: inner_traceback,
+            } = &**cause
+            {
+                cause = inner_cause;
+                traceback = inner_traceback;
+            }
+            let (message, _) = split_error(cause);
+            (message, Some(traceback.clone()))
+        }
+        mlua::Error::RuntimeError(message) => split_traceback(message, "runtime error: "),
+        mlua::Error::SyntaxError { message, .. } => split_traceback(message, "syntax error: "),
+        mlua::Error::WithContext { context, cause } => {
+            let (message, traceback) = split_error(cause);
+            (format!("{}\n{}", context, message), traceback)
+        }
+        other => (other.to_string(), None),
+    }
+}
+
+fn split_traceback(message: &str, prefix: &str) -> (String, Option<String>) {
+    match message.find("\nstack traceback:") {
+        Some(pos) => (
+            format!("{}{}", prefix, &message[..pos]),
+            Some(message[pos + 1..].to_string()),
+        ),
+        None => (format!("{}{}", prefix, message), None),
+    }
+}
+
+// Finds `chunk:line:` in a line like `main.lua:3: attempt to call a nil value`
+fn parse_location(text: &str) -> Option<(String, usize)> {
+    let text = text.trim();
+
+    for (i, _) in text.match_indices(':') {
+        let rest = &text[i + 1..];
+        let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+
+        if digits > 0 && rest[digits..].starts_with(':') {
+            let chunk = text[..i]
+                .trim_start_matches("runtime error: ")
+                .trim_start_matches("syntax error: ");
+            let line = rest[..digits].parse().ok()?;
+            return Some((chunk.to_string(), line));
+        }
+    }
+
+    None
+}
+
+// The first frame of the traceback which is not a Rust or C function
+fn traceback_location(traceback: &str) -> Option<(String, usize)> {
+    traceback
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim_start().starts_with("[C]"))
+        .find_map(parse_location)
+}
+
+// The failing line with the lines around it and a caret line below it
+fn source_context(chunk: &str, line: usize, main: Option<&MainChunk>) -> Option<String> {
+    let source = match main {
+        Some(main) if is_same_chunk(main.name, chunk) => main.source.to_string(),
+        // Modules loaded with require() are read from the disk
+        _ => fs::read_to_string(chunk).ok()?,
+    };
+
+    let lines: Vec<&str> = source.lines().collect();
+    if line == 0 || line > lines.len() {
+        return None;
+    }
+
+    let first = line.saturating_sub(CONTEXT_LINES).max(1);
+    let last = (line + CONTEXT_LINES).min(lines.len());
+    let width = last.to_string().len();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{:width$}--> {}:{}", "", chunk, line, width = width);
+    let _ = writeln!(out, "{:width$} |", "", width = width);
+
+    for number in first..=last {
+        let text = lines[number - 1];
+        let _ = writeln!(out, "{:>width$} | {}", number, text, width = width);
+
+        if number == line {
+            let indent = text.len() - text.trim_start().len();
+            let marker = "^".repeat(text.trim().chars().count().max(1));
+            let _ = writeln!(
+                out,
+                "{:width$} | {}{}",
+                "",
+                &text[..indent],
+                marker,
+                width = width
+            );
+        }
+    }
+    let _ = write!(out, "{:width$} |", "", width = width);
+
+    Some(out)
+}
+
+// LuaJIT shortens long chunk names to `...end/of/the/path.lua`
+fn is_same_chunk(name: &str, chunk: &str) -> bool {
+    match chunk.strip_prefix("...") {
+        Some(end) => name.ends_with(end),
+        None => name == chunk,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::Lua;
+    use tempfile::tempdir;
+
+    #[test]
+    fn runtime_error_with_context_and_traceback() {
+        let lua = Lua::new();
+        let source = "local x = 1\nlocal y = 2\n  foo(x)\nprint(y)\n";
+        let err = lua.load(source).set_name("=main.lua").exec().unwrap_err();
+
+        let report = report(
+            &err,
+            Some(&MainChunk {
+                name: "main.lua",
+                source,
+            }),
+        );
+
+        assert!(
+            report.starts_with("runtime error: main.lua:3:"),
+            "{}",
+            report
+        );
+        assert!(report.contains("--> main.lua:3"), "{}", report);
+        assert!(report.contains("1 | local x = 1"), "{}", report);
+        assert!(report.contains("3 |   foo(x)"), "{}", report);
+        assert!(report.contains("  |   ^^^^^^"), "{}", report);
+        assert!(report.contains("4 | print(y)"), "{}", report);
+        assert!(report.contains("stack traceback:"), "{}", report);
+    }
+
+    #[test]
+    fn syntax_error_points_to_line() {
+        let lua = Lua::new();
+        let source = "print(1)\nlocal = 5\n";
+        let err = lua
+            .load(source)
+            .set_name("=(command line)")
+            .exec()
+            .unwrap_err();
+
+        let report = report(
+            &err,
+            Some(&MainChunk {
+                name: "(command line)",
+                source,
+            }),
+        );
+        assert!(report.starts_with("syntax error: "), "{}", report);
+        assert!(report.contains("2 | local = 5"), "{}", report);
+    }
+
+    #[test]
+    fn callback_error_uses_the_lua_caller() {
+        let lua = Lua::new();
+        let fail = lua
+            .create_function(|_, ()| Err::<(), _>(mlua::Error::external("dapi_test.fail: broken")))
+            .unwrap();
+        lua.globals().set("fail", fail).unwrap();
+
+        let source = "local a = 1\nfail()\n";
+        let err = lua.load(source).set_name("=main.lua").exec().unwrap_err();
+        let report = report(
+            &err,
+            Some(&MainChunk {
+                name: "main.lua",
+                source,
+            }),
+        );
+
+        assert!(report.starts_with("dapi_test.fail: broken"), "{}", report);
+        assert!(report.contains("2 | fail()"), "{}", report);
+        assert!(report.contains("in function 'fail'"), "{}", report);
+    }
+
+    #[test]
+    fn error_in_required_module_reads_the_file() {
+        let dir = tempdir().unwrap();
+        let module = dir.path().join("broken.lua");
+        fs::write(
+            &module,
+            "local M = {}\nfunction M.run()\n  error('inside module')\nend\nreturn M\n",
+        )
+        .unwrap();
+
+        let lua = Lua::new();
+        let source = format!(
+            "package.path = {:?} .. '/?.lua;' .. package.path\nrequire('broken').run()\n",
+            dir.path().to_string_lossy()
+        );
+        let err = lua.load(&source).set_name("=main.lua").exec().unwrap_err();
+        let report = report(
+            &err,
+            Some(&MainChunk {
+                name: "main.lua",
+                source: &source,
+            }),
+        );
+
+        assert!(report.contains("inside module"), "{}", report);
+        assert!(
+            report.contains("3 |   error('inside module')"),
+            "{}",
+            report
+        );
+        assert!(report.contains("main.lua:2: in main chunk"), "{}", report);
+    }
+
+    #[test]
+    fn parse_locations() {
+        assert_eq!(
+            parse_location("main.lua:12: oops"),
+            Some(("main.lua".to_string(), 12))
+        );
+        assert_eq!(
+            parse_location("\t(command line):1: in main chunk"),
+            Some(("(command line)".to_string(), 1))
+        );
+        assert_eq!(
+            parse_location(r"C:\scripts\main.lua:4: x"),
+            Some((r"C:\scripts\main.lua".to_string(), 4))
+        );
+        assert_eq!(parse_location("[C]: in function 'error'"), None);
+        assert!(is_same_chunk(
+            "/a/very/long/path/main.lua",
+            ".../path/main.lua"
+        ));
+    }
+}
@@ -0,0 +1,254 @@
+// Exit codes of flua
+//
+// 0 success, 1 runtime error, 2 wrong command line usage, 3 syntax error,
+// 4 script file not found, 5 permission denied in safe mode, 6 failed tests,
+// 7 a timeout, memory or instruction limit was exceeded, 8 `flua check`
+// found errors.
+// `dapi.exit(code)` and `os.exit(code)` exit with their own code, they raise
+// an ExitRequest which the CLI turns into the exit of the process
+
+use mlua::{Function, Lua, Value};
+use std::fmt;
+use std::io::Write;
+
+use crate::helper::error_report::{MainChunk, report};
+use crate::helper::hooks;
+use crate::helper::limits::LimitExceeded;
+use crate::helper::permissions::PermissionError;
+use crate::lua_script::ScriptNotFound;
+
+pub const SUCCESS: i32 = 0;
+pub const RUNTIME_ERROR: i32 = 1;
+// Unknown or missing command line arguments
+pub const USAGE_ERROR: i32 = 2;
+pub const SYNTAX_ERROR: i32 = 3;
+pub const FILE_NOT_FOUND: i32 = 4;
+pub const PERMISSION_DENIED: i32 = 5;
+// At least one test of `flua test` failed
+pub const TESTS_FAILED: i32 = 6;
+// --timeout, --max-memory or --max-instructions
+pub const LIMIT_EXCEEDED: i32 = 7;
+// Unknown dapi functions, wrong argument counts or with --deny-deprecated
+// deprecated functions
+pub const CHECK_FAILED: i32 = 8;
+
+// Error of a flua command with the exit code of the process
+#[derive(Debug)]
+pub struct FluaError {
+    pub code: i32,
+    pub message: String,
+    // The script called exit(), nothing failed and `code` is its exit code
+    pub exited: bool,
+}
+
+impl FluaError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        FluaError {
+            code,
+            message: message.into(),
+            exited: false,
+        }
+    }
+
+    // A script which called dapi.exit(code) or os.exit(code)
+    pub fn exit(code: i32) -> Self {
+        FluaError {
+            exited: true,
+            ..FluaError::new(code, ExitRequest(code).to_string())
+        }
+    }
+
+    // Error of a Lua script, the exit code depends on the kind of the error
+    pub fn from_lua(error: &mlua::Error, main: Option<&MainChunk>) -> Self {
+        match exit_request(error) {
+            Some(code) => FluaError::exit(code),
+            None => FluaError::new(for_lua_error(error), report(error, main)),
+        }
+    }
+}
+
+// Error of dapi.exit() and os.exit(). The hook raises it again on every
+// instruction, so a pcall() can not catch it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExitRequest(pub i32);
+
+impl fmt::Display for ExitRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The script exited with code {}", self.0)
+    }
+}
+
+impl std::error::Error for ExitRequest {}
+
+impl From<String> for FluaError {
+    fn from(message: String) -> Self {
+        FluaError::new(RUNTIME_ERROR, message)
+    }
+}
+
+impl fmt::Display for FluaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// Errors of Rust functions are wrapped in CallbackErrors
+fn root_cause(error: &mlua::Error) -> &mlua::Error {
+    let mut root = error;
+    while let Some(parent) = root.parent() {
+        root = parent;
+    }
+    root
+}
+
+// Exit code of dapi.exit() or os.exit() when the error is an ExitRequest
+pub fn exit_request(error: &mlua::Error) -> Option<i32> {
+    match root_cause(error) {
+        mlua::Error::ExternalError(e) => e.downcast_ref::<ExitRequest>().map(|exit| exit.0),
+        _ => None,
+    }
+}
+
+// Exit code for an error of a Lua script
+pub fn for_lua_error(error: &mlua::Error) -> i32 {
+    if let Some(code) = exit_request(error) {
+        return code;
+    }
+
+    match root_cause(error) {
+        mlua::Error::SyntaxError { .. } => SYNTAX_ERROR,
+        mlua::Error::MemoryError(_) => LIMIT_EXCEEDED,
+        mlua::Error::ExternalError(e) if e.downcast_ref::<LimitExceeded>().is_some() => {
+            LIMIT_EXCEEDED
+        }
+        mlua::Error::ExternalError(e) if e.downcast_ref::<PermissionError>().is_some() => {
+            PERMISSION_DENIED
+        }
+        mlua::Error::ExternalError(e) if e.downcast_ref::<ScriptNotFound>().is_some() => {
+            FILE_NOT_FOUND
+        }
+        _ => RUNTIME_ERROR,
+    }
+}
+
+// Lua function for dapi.exit() and os.exit(), takes a number or a boolean
+// like os.exit() in Lua: nil and true are 0, false is 1
+pub fn create_exit_function(lua: &Lua) -> mlua::Result<Function> {
+    lua.create_function(|lua, code: Value| -> mlua::Result<()> {
+        let code = match code {
+            Value::Nil | Value::Boolean(true) => SUCCESS,
+            Value::Boolean(false) => RUNTIME_ERROR,
+            Value::Integer(code) => code as i32,
+            Value::Number(code) => code as i32,
+            other => {
+                return Err(mlua::Error::external(format!(
+                    "dapi.exit: the exit code has to be a number or a boolean, got {}",
+                    other.type_name()
+                )));
+            }
+        };
+        crate::api::http::server_controls::stop_state(lua);
+
+        lua.set_app_data(ExitRequest(code));
+        hooks::set_interval(lua, "exit", Some(1))?;
+        Err(mlua::Error::external(ExitRequest(code)))
+    })
+}
+
+// Called by the hook, raises the ExitRequest again after exit() was called
+pub(crate) fn check_exit(lua: &Lua) -> mlua::Result<()> {
+    match lua.app_data_ref::<ExitRequest>() {
+        Some(exit) => Err(mlua::Error::external(*exit)),
+        None => Ok(()),
+    }
+}
+
+// The result of a script, or the ExitRequest when it called exit(), even if
+// the error was caught or lost on the way. The state can run code again
+pub fn take_exit_request(lua: &Lua, result: mlua::Result<()>) -> mlua::Result<()> {
+    match lua.remove_app_data::<ExitRequest>() {
+        Some(exit) => {
+            hooks::set_interval(lua, "exit", None)?;
+            Err(mlua::Error::external(exit))
+        }
+        None => result,
+    }
+}
+
+// Stops all running servers, flushes the output and exits the process
+pub fn exit_with_cleanup(code: i32) -> ! {
+    crate::api::http::server_controls::stop_all();
+
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax_error_code() {
+        let lua = Lua::new();
+        let err = lua.load("local = 1").exec().unwrap_err();
+        assert_eq!(for_lua_error(&err), SYNTAX_ERROR);
+    }
+
+    #[test]
+    fn runtime_error_code() {
+        let lua = Lua::new();
+        let err = lua.load("error('boom')").exec().unwrap_err();
+        assert_eq!(for_lua_error(&err), RUNTIME_ERROR);
+    }
+
+    #[test]
+    fn permission_error_inside_callback() {
+        let lua = Lua::new();
+        let denied = lua
+            .create_function(|_, ()| {
+                Err::<(), _>(mlua::Error::external(PermissionError {
+                    kind: "read",
+                    target: "/etc/passwd".to_string(),
+                    flag: "--allow-read",
+                }))
+            })
+            .unwrap();
+        lua.globals().set("denied", denied).unwrap();
+
+        let err = lua.load("denied()").exec().unwrap_err();
+        assert_eq!(for_lua_error(&err), PERMISSION_DENIED);
+    }
+
+    #[test]
+    fn exit_can_not_be_caught() {
+        let lua = Lua::new();
+        lua.globals()
+            .set("exit", create_exit_function(&lua).unwrap())
+            .unwrap();
+
+        let result = lua.load("pcall(exit, 3) reached = true").exec();
+        let result = take_exit_request(&lua, result);
+        let err = result.unwrap_err();
+        assert_eq!(exit_request(&err), Some(3));
+        assert_eq!(for_lua_error(&err), 3);
+        assert!(lua.globals().get::<Value>("reached").unwrap().is_nil());
+
+        // The state runs again after the request was taken
+        assert!(lua.load("reached = true").exec().is_ok());
+    }
+
+    #[test]
+    fn exit_error_of_a_script() {
+        let err = FluaError::from_lua(&mlua::Error::external(ExitRequest(0)), None);
+        assert!(err.exited);
+        assert_eq!(err.code, SUCCESS);
+    }
+
+    #[test]
+    fn missing_script_code() {
+        let err = mlua::Error::external(ScriptNotFound("missing.lua".to_string()));
+        assert_eq!(for_lua_error(&err), FILE_NOT_FOUND);
+    }
+}
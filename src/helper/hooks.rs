@@ -0,0 +1,98 @@
+// The debug hook of a Lua state
+//
+// mlua supports only one hook per state, so the execution limits, exit(),
+// watch mode and the profiler register their instruction interval here, the
+// coverage and the debugger ask for line events, and one hook calls them all.
+// The JIT compiler is switched off with the first user, compiled traces would
+// never reach the hook
+
+use mlua::{Debug, DebugEvent, HookTriggers, Lua, Table, VmState};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::helper::{exit_code, limits};
+use crate::{coverage, debugger, profiler, watch};
+
+// Instruction intervals of the users, the hook runs at the smallest one
+#[derive(Default)]
+struct Hooks {
+    intervals: BTreeMap<&'static str, u32>,
+    lines: BTreeSet<&'static str>,
+}
+
+impl Hooks {
+    fn interval(&self) -> Option<u32> {
+        self.intervals.values().min().copied()
+    }
+}
+
+// Sets or removes (None) the interval of one user like "limits"
+pub fn set_interval(lua: &Lua, user: &'static str, interval: Option<u32>) -> mlua::Result<()> {
+    update(lua, |hooks| {
+        match interval {
+            Some(interval) => hooks.intervals.insert(user, interval),
+            None => hooks.intervals.remove(user),
+        };
+    })
+}
+
+// Turns the line events for one user on or off
+pub fn set_lines(lua: &Lua, user: &'static str, enabled: bool) -> mlua::Result<()> {
+    update(lua, |hooks| {
+        if enabled {
+            hooks.lines.insert(user);
+        } else {
+            hooks.lines.remove(user);
+        }
+    })
+}
+
+fn update(lua: &Lua, change: impl FnOnce(&mut Hooks)) -> mlua::Result<()> {
+    if lua.app_data_ref::<Hooks>().is_none() {
+        lua.set_app_data(Hooks::default());
+        if let Some(jit) = lua.globals().get::<Option<Table>>("jit")? {
+            jit.get::<mlua::Function>("off")?.call::<()>(())?;
+            jit.get::<mlua::Function>("flush")?.call::<()>(())?;
+        }
+    }
+
+    let (interval, lines) = {
+        let mut hooks = lua.app_data_mut::<Hooks>().expect("hooks are installed");
+        change(&mut hooks);
+        (hooks.interval(), !hooks.lines.is_empty())
+    };
+
+    let mut triggers = HookTriggers::new();
+    if let Some(interval) = interval {
+        triggers = triggers.every_nth_instruction(interval);
+    }
+    if lines {
+        triggers = triggers.every_line();
+    }
+    if interval.is_none() && !lines {
+        lua.remove_hook();
+        return Ok(());
+    }
+    lua.set_hook(triggers, on_event)
+}
+
+fn on_event(lua: &Lua, debug: &Debug) -> mlua::Result<VmState> {
+    match debug.event() {
+        DebugEvent::Line => {
+            coverage::record_line(lua, debug);
+            debugger::on_line(lua, debug)?;
+        }
+        DebugEvent::Count => {
+            let step = lua
+                .app_data_ref::<Hooks>()
+                .and_then(|hooks| hooks.interval())
+                .unwrap_or(1);
+
+            profiler::sample(lua);
+            limits::check_budgets(lua, step)?;
+            exit_code::check_exit(lua)?;
+            watch::check_stopped(lua)?;
+        }
+        _ => {}
+    }
+    Ok(VmState::Continue)
+}
@@ -0,0 +1,421 @@
+// Execution limits for Lua scripts: timeout, memory cap and instruction budget
+//
+// The timeout and the instruction budget are checked by the hook of hooks.rs
+// every CHECK_INTERVAL instructions, the memory cap is the allocator limit of
+// the Lua state. Native functions like dapi_time.waitfr can not be
+// interrupted, `flua run` gives up on them shortly after the timeout
+
+use mlua::{Lua, Table, Value};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::helper::hooks;
+
+// Instructions between two checks of the hook
+const CHECK_INTERVAL: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub timeout: Option<Duration>,
+    // Bytes
+    pub max_memory: Option<usize>,
+    pub max_instructions: Option<u64>,
+}
+
+// Error of a script which ran into a limit
+#[derive(Debug)]
+pub struct LimitExceeded(pub String);
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Limit exceeded: {}", self.0)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+// Memory cap of the state, for the states started from it
+struct MemoryCap(usize);
+
+// Budget of the script or of one call with_limits(), they can be nested
+struct Budget {
+    timeout: Option<(Duration, Instant)>,
+    instructions: Option<(u64, u64)>,
+}
+
+#[derive(Default)]
+struct Budgets {
+    stack: Vec<Budget>,
+    // The hook runs on every instruction after a breach, so a pcall() around
+    // the loop can not swallow the error
+    exceeded: bool,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    // Limits of a Lua table like `{ timeout = "2s", max_instructions = 1e6 }`,
+    // used for the handlers of the API server
+    pub fn from_table(table: &Table) -> mlua::Result<Self> {
+        let timeout = match table.get::<Value>("timeout")? {
+            Value::Nil => None,
+            Value::Integer(secs) => Some(Duration::from_secs(secs.max(0) as u64)),
+            Value::Number(secs) => Some(
+                Duration::try_from_secs_f64(secs.max(0.0))
+                    .map_err(|_| mlua::Error::external(format!("timeout {} is too long", secs)))?,
+            ),
+            Value::String(text) => {
+                Some(parse_duration(&text.to_str()?).map_err(mlua::Error::external)?)
+            }
+            other => {
+                return Err(mlua::Error::external(format!(
+                    "timeout has to be a number or a string, got {}",
+                    other.type_name()
+                )));
+            }
+        };
+        let max_instructions = table
+            .get::<Option<f64>>("max_instructions")?
+            .map(|n| n.max(0.0) as u64);
+
+        Ok(Limits {
+            timeout,
+            max_memory: None,
+            max_instructions,
+        })
+    }
+
+    // Unset limits are taken from `fallback`
+    pub fn or(self, fallback: Limits) -> Limits {
+        Limits {
+            timeout: self.timeout.or(fallback.timeout),
+            max_memory: self.max_memory.or(fallback.max_memory),
+            max_instructions: self.max_instructions.or(fallback.max_instructions),
+        }
+    }
+
+    // Installs the limits for the whole Lua state
+    pub fn install(&self, lua: &Lua) -> mlua::Result<()> {
+        if let Some(bytes) = self.max_memory {
+            lua.set_memory_limit(bytes)?;
+            lua.set_app_data(MemoryCap(bytes));
+        }
+        if self.timeout.is_some() || self.max_instructions.is_some() {
+            push_budget(lua, self)?;
+        }
+        Ok(())
+    }
+
+    fn budget(&self) -> Budget {
+        Budget {
+            // A deadline too far in the future is no deadline
+            timeout: self.timeout.and_then(|timeout| {
+                Instant::now()
+                    .checked_add(timeout)
+                    .map(|deadline| (timeout, deadline))
+            }),
+            instructions: self.max_instructions.map(|max| (max, max)),
+        }
+    }
+}
+
+// Runs `f` with an own timeout and instruction budget, the limits of the
+// script stay active
+pub fn with_limits<R>(
+    lua: &Lua,
+    limits: &Limits,
+    f: impl FnOnce() -> mlua::Result<R>,
+) -> mlua::Result<R> {
+    if limits.timeout.is_none() && limits.max_instructions.is_none() {
+        return f();
+    }
+
+    push_budget(lua, limits)?;
+    let result = f();
+    let reset = match lua.app_data_mut::<Budgets>() {
+        Some(mut budgets) => {
+            budgets.stack.pop();
+            std::mem::take(&mut budgets.exceeded)
+        }
+        None => false,
+    };
+    if reset {
+        hooks::set_interval(lua, "limits", Some(CHECK_INTERVAL))?;
+    }
+    result
+}
+
+// Drops the timeout and instruction budget of install(), the memory cap
+// stays. `flua test` gives every test its own budget after the file ran
+pub fn clear_budgets(lua: &Lua) -> mlua::Result<()> {
+    if lua.remove_app_data::<Budgets>().is_some() {
+        hooks::set_interval(lua, "limits", None)?;
+    }
+    Ok(())
+}
+
+// The limits which are left for a Lua state started from this one, like a
+// dapi_thread worker: the memory cap, the time until the nearest deadline and
+// the smallest instruction budget
+pub fn remaining(lua: &Lua) -> Limits {
+    let mut limits = Limits {
+        max_memory: lua.app_data_ref::<MemoryCap>().map(|cap| cap.0),
+        ..Limits::default()
+    };
+    let Some(budgets) = lua.app_data_ref::<Budgets>() else {
+        return limits;
+    };
+
+    let now = Instant::now();
+    for budget in &budgets.stack {
+        if let Some((_, deadline)) = budget.timeout {
+            let left = deadline.saturating_duration_since(now);
+            limits.timeout = Some(limits.timeout.map_or(left, |t| t.min(left)));
+        }
+        if let Some((_, left)) = budget.instructions {
+            limits.max_instructions = Some(limits.max_instructions.map_or(left, |m| m.min(left)));
+        }
+    }
+    limits
+}
+
+fn push_budget(lua: &Lua, limits: &Limits) -> mlua::Result<()> {
+    if lua.app_data_ref::<Budgets>().is_none() {
+        lua.set_app_data(Budgets::default());
+        hooks::set_interval(lua, "limits", Some(CHECK_INTERVAL))?;
+    }
+    if let Some(mut budgets) = lua.app_data_mut::<Budgets>() {
+        budgets.stack.push(limits.budget());
+    }
+    Ok(())
+}
+
+// Called by the hook every `step` instructions
+pub(crate) fn check_budgets(lua: &Lua, step: u32) -> mlua::Result<()> {
+    let Some(mut budgets) = lua.app_data_mut::<Budgets>() else {
+        return Ok(());
+    };
+
+    let mut breach = None;
+    for budget in budgets.stack.iter_mut() {
+        if let Some((timeout, deadline)) = budget.timeout
+            && Instant::now() >= deadline
+        {
+            breach = Some(format!(
+                "the timeout of {} was reached",
+                format_duration(timeout)
+            ));
+            break;
+        }
+        if let Some((max, left)) = &mut budget.instructions {
+            *left = left.saturating_sub(step as u64);
+            if *left == 0 {
+                breach = Some(format!("the budget of {} instructions is used up", max));
+                break;
+            }
+        }
+    }
+
+    let Some(message) = breach else {
+        return Ok(());
+    };
+    if !budgets.exceeded {
+        budgets.exceeded = true;
+        drop(budgets);
+        hooks::set_interval(lua, "limits", Some(1))?;
+    }
+    Err(mlua::Error::external(LimitExceeded(message)))
+}
+
+// `30s`, `500ms`, `2m`, `1h`, a plain number are seconds
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{}', use e.g. 30s or 500ms", text))?;
+    let secs = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => {
+            return Err(format!(
+                "Invalid duration '{}', use e.g. 30s or 500ms",
+                text
+            ));
+        }
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("The duration '{}' is too long", text))
+}
+
+// `256M`, `1G`, `512K`, `1024` bytes
+pub fn parse_size(text: &str) -> Result<usize, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number: usize = number
+        .parse()
+        .map_err(|_| format!("Invalid size '{}', use e.g. 256M or 1G", text))?;
+    let factor = match unit.trim().to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Invalid size '{}', use e.g. 256M or 1G", text)),
+    };
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| format!("The size '{}' is too large", text))
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    if duration.subsec_millis() == 0 {
+        format!("{}s", duration.as_secs())
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::exit_code::{LIMIT_EXCEEDED, for_lua_error};
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1.5"), Ok(Duration::from_millis(1500)));
+        assert!(parse_duration("soon").is_err());
+
+        assert_eq!(parse_size("256M"), Ok(256 * 1024 * 1024));
+        assert_eq!(parse_size("1GB"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert!(parse_size("lots").is_err());
+    }
+
+    #[test]
+    fn remaining_limits() {
+        let lua = Lua::new();
+        assert!(remaining(&lua).is_empty());
+
+        Limits {
+            timeout: Some(Duration::from_secs(60)),
+            max_memory: Some(64 * 1024 * 1024),
+            max_instructions: Some(1_000_000),
+        }
+        .install(&lua)
+        .unwrap();
+        let limits = Limits {
+            timeout: Some(Duration::from_secs(2)),
+            ..Limits::default()
+        };
+        let inner = with_limits(&lua, &limits, || Ok(remaining(&lua))).unwrap();
+        assert!(inner.timeout.unwrap() <= Duration::from_secs(2));
+        assert_eq!(inner.max_memory, Some(64 * 1024 * 1024));
+        assert!(inner.max_instructions.unwrap() <= 1_000_000);
+
+        let outer = remaining(&lua);
+        assert!(outer.timeout.unwrap() > Duration::from_secs(2));
+    }
+
+    #[test]
+    fn huge_values_are_errors() {
+        let err = parse_duration("99999999999999999999999").unwrap_err();
+        assert!(err.contains("too long"), "{}", err);
+        let err = parse_size("99999999999999G").unwrap_err();
+        assert!(err.contains("too large"), "{}", err);
+
+        let lua = Lua::new();
+        let table: Table = lua.load("return { timeout = math.huge }").eval().unwrap();
+        let err = Limits::from_table(&table).unwrap_err();
+        assert!(err.to_string().contains("too long"), "{}", err);
+
+        // No deadline fits, the budget runs without one
+        let table: Table = lua.load("return { timeout = 1e18 }").eval().unwrap();
+        let limits = Limits::from_table(&table).unwrap();
+        let value = with_limits(&lua, &limits, || lua.load("return 1 + 1").eval::<i64>());
+        assert_eq!(value.unwrap(), 2);
+    }
+
+    #[test]
+    fn endless_loop_times_out() {
+        let lua = Lua::new();
+        Limits {
+            timeout: Some(Duration::from_millis(100)),
+            ..Limits::default()
+        }
+        .install(&lua)
+        .unwrap();
+
+        let start = Instant::now();
+        let err = lua.load("while true do end").exec().unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(err.to_string().contains("timeout of 100ms"), "{}", err);
+        assert_eq!(for_lua_error(&err), LIMIT_EXCEEDED);
+    }
+
+    #[test]
+    fn pcall_can_not_catch_the_limit() {
+        let lua = Lua::new();
+        Limits {
+            max_instructions: Some(50_000),
+            ..Limits::default()
+        }
+        .install(&lua)
+        .unwrap();
+
+        let err = lua
+            .load("while true do pcall(function() for i = 1, 100 do end end) end")
+            .exec()
+            .unwrap_err();
+        assert!(err.to_string().contains("50000 instructions"), "{}", err);
+    }
+
+    #[test]
+    fn memory_cap() {
+        let lua = Lua::new();
+        Limits {
+            max_memory: Some(8 * 1024 * 1024),
+            ..Limits::default()
+        }
+        .install(&lua)
+        .unwrap();
+
+        let err = lua
+            .load("local t = {} for i = 1, 1e8 do t[i] = string.rep('x', 100) .. i end")
+            .exec()
+            .unwrap_err();
+        assert_eq!(for_lua_error(&err), LIMIT_EXCEEDED, "{}", err);
+    }
+
+    #[test]
+    fn nested_budget_for_one_call() {
+        let lua = Lua::new();
+        let handler: mlua::Function = lua
+            .load("return function() while true do end end")
+            .eval()
+            .unwrap();
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        };
+
+        let err = with_limits(&lua, &limits, || handler.call::<()>(())).unwrap_err();
+        assert!(err.to_string().contains("Limit exceeded"), "{}", err);
+
+        // Without the budget the state runs normally again
+        assert_eq!(lua.load("return 1 + 1").eval::<i64>().unwrap(), 2);
+    }
+}
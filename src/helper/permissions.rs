@@ -0,0 +1,834 @@
+// Permissions for the safe mode
+//
+// Without `--safe` every script has full access. With `--safe` everything
+// is denied until it is granted with one of the `--allow-*` flags.
+
+use mlua::{Function, Lua, Table, Value};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+// A single permission, either denied, granted for everything or only for
+// a list of entries (directories or hosts)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Grant<T> {
+    Denied,
+    All,
+    Only(Vec<T>),
+}
+
+impl<T> Grant<T> {
+    // Add an entry to the grant, `None` grants everything
+    fn add(&mut self, entry: Option<T>) {
+        match entry {
+            None => *self = Grant::All,
+            Some(entry) => match self {
+                Grant::All => {}
+                Grant::Denied => *self = Grant::Only(vec![entry]),
+                Grant::Only(list) => list.push(entry),
+            },
+        }
+    }
+}
+
+// All permissions of a running script
+#[derive(Debug, Clone)]
+pub struct Permissions {
+    pub safe: bool,
+    pub read: Grant<PathBuf>,
+    pub write: Grant<PathBuf>,
+    pub net: Grant<String>,
+    pub run: bool,
+    pub env: bool,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Permissions::allow_all()
+    }
+}
+
+impl Permissions {
+    // Permissions without --safe, everything is allowed
+    pub fn allow_all() -> Self {
+        Permissions {
+            safe: false,
+            read: Grant::All,
+            write: Grant::All,
+            net: Grant::All,
+            run: true,
+            env: true,
+        }
+    }
+
+    // Permissions for --safe, everything is denied until it is granted
+    pub fn safe() -> Self {
+        Permissions {
+            safe: true,
+            read: Grant::Denied,
+            write: Grant::Denied,
+            net: Grant::Denied,
+            run: false,
+            env: false,
+        }
+    }
+
+    // Parses a single `--allow-*` flag, returns false if the flag is unknown
+    //
+    // `--allow-read`, `--allow-write` and `--allow-net` grant everything
+    // without a value, multiple values can be separated with a comma
+    pub fn parse_flag(&mut self, flag: &str) -> bool {
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (flag, None),
+        };
+
+        let entries: Vec<&str> = match value {
+            Some(value) => value.split(',').filter(|v| !v.is_empty()).collect(),
+            None => Vec::new(),
+        };
+
+        match name {
+            "--allow-read" => {
+                if entries.is_empty() {
+                    self.read.add(None);
+                }
+                for entry in entries {
+                    self.read.add(Some(resolve_path(Path::new(entry))));
+                }
+            }
+            "--allow-write" => {
+                if entries.is_empty() {
+                    self.write.add(None);
+                }
+                for entry in entries {
+                    self.write.add(Some(resolve_path(Path::new(entry))));
+                }
+            }
+            "--allow-net" => {
+                if entries.is_empty() {
+                    self.net.add(None);
+                }
+                for entry in entries {
+                    self.net.add(Some(entry.to_lowercase()));
+                }
+            }
+            "--allow-run" => self.run = true,
+            "--allow-env" => self.env = true,
+            _ => return false,
+        }
+
+        true
+    }
+
+    // Check if a file or directory can be read
+    pub fn check_read(&self, path: &Path) -> Result<(), PermissionError> {
+        check_path(&self.read, path, "read", "--allow-read")
+    }
+
+    // Check if a file or directory can be written, created or deleted
+    pub fn check_write(&self, path: &Path) -> Result<(), PermissionError> {
+        check_path(&self.write, path, "write", "--allow-write")
+    }
+
+    // Check if a host can be reached, the host can contain a port
+    // like `example.com:8080`, a grant without a port allows every port
+    pub fn check_net(&self, host: &str) -> Result<(), PermissionError> {
+        let host = host.to_lowercase();
+        let allowed = match &self.net {
+            Grant::All => true,
+            Grant::Denied => false,
+            Grant::Only(hosts) => hosts.iter().any(|granted| {
+                granted == &host
+                    || host
+                        .rsplit_once(':')
+                        .is_some_and(|(name, _port)| granted == name)
+            }),
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(PermissionError::new("net", host, "--allow-net"))
+        }
+    }
+
+    // Check if a url can be fetched
+    pub fn check_url(&self, url: &str) -> Result<(), PermissionError> {
+        match reqwest::Url::parse(url) {
+            Ok(parsed) => {
+                let host = parsed.host_str().unwrap_or_default();
+                match parsed.port_or_known_default() {
+                    Some(port) => self.check_net(&format!("{}:{}", host, port)),
+                    None => self.check_net(host),
+                }
+            }
+            Err(_) => self.check_net(url),
+        }
+    }
+
+    // Check if a subprocess can be started
+    pub fn check_run(&self, command: &str) -> Result<(), PermissionError> {
+        if self.run {
+            Ok(())
+        } else {
+            Err(PermissionError::new(
+                "run",
+                command.to_string(),
+                "--allow-run",
+            ))
+        }
+    }
+
+    // Check if an environment variable can be read or changed
+    pub fn check_env(&self, name: &str) -> Result<(), PermissionError> {
+        if self.env {
+            Ok(())
+        } else {
+            Err(PermissionError::new("env", name.to_string(), "--allow-env"))
+        }
+    }
+}
+
+// Error which is raised in Lua when a permission is missing
+#[derive(Debug, Clone)]
+pub struct PermissionError {
+    pub kind: &'static str,
+    pub target: String,
+    pub flag: &'static str,
+}
+
+impl PermissionError {
+    fn new(kind: &'static str, target: String, flag: &'static str) -> Self {
+        PermissionError { kind, target, flag }
+    }
+}
+
+impl fmt::Display for PermissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "permission denied: {} access to '{}' (grant it with {})",
+            self.kind, self.target, self.flag
+        )
+    }
+}
+
+impl std::error::Error for PermissionError {}
+
+fn check_path(
+    grant: &Grant<PathBuf>,
+    path: &Path,
+    kind: &'static str,
+    flag: &'static str,
+) -> Result<(), PermissionError> {
+    match grant {
+        Grant::All => Ok(()),
+        Grant::Denied => Err(PermissionError::new(kind, path.display().to_string(), flag)),
+        Grant::Only(dirs) => {
+            let resolved = resolve_path(path);
+            if dirs.iter().any(|dir| resolved.starts_with(dir)) {
+                Ok(())
+            } else {
+                Err(PermissionError::new(
+                    kind,
+                    resolved.display().to_string(),
+                    flag,
+                ))
+            }
+        }
+    }
+}
+
+// Makes a path absolute and resolves `.`, `..` and symlinks of the part
+// of the path which already exists, so `granted/../secret` can not escape
+fn resolve_path(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+
+    let mut normalized = PathBuf::new();
+    for comp in absolute.components() {
+        match comp {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+
+    // Canonicalize the longest existing parent
+    let mut existing = normalized.clone();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match existing.file_name() {
+            Some(name) => rest.push(name.to_os_string()),
+            None => return normalized,
+        }
+        if !existing.pop() {
+            return normalized;
+        }
+    }
+
+    let mut resolved = std::fs::canonicalize(&existing).unwrap_or(existing);
+    for name in rest.iter().rev() {
+        resolved.push(name);
+    }
+    resolved
+}
+
+// Get the Permissions of a Lua state, states without Permissions
+// (like in the tests) have full access
+fn with_permissions<F>(lua: &Lua, check: F) -> mlua::Result<()>
+where
+    F: FnOnce(&Permissions) -> Result<(), PermissionError>,
+{
+    match lua.app_data_ref::<Permissions>() {
+        Some(permissions) => check(&permissions).map_err(mlua::Error::external),
+        None => Ok(()),
+    }
+}
+
+// Helper functions for the dapi register functions
+pub fn check_read(lua: &Lua, path: &str) -> mlua::Result<()> {
+    with_permissions(lua, |p| p.check_read(Path::new(path)))
+}
+
+pub fn check_write(lua: &Lua, path: &str) -> mlua::Result<()> {
+    with_permissions(lua, |p| p.check_write(Path::new(path)))
+}
+
+pub fn check_net(lua: &Lua, host: &str) -> mlua::Result<()> {
+    with_permissions(lua, |p| p.check_net(host))
+}
+
+pub fn check_url(lua: &Lua, url: &str) -> mlua::Result<()> {
+    with_permissions(lua, |p| p.check_url(url))
+}
+
+pub fn check_run(lua: &Lua, command: &str) -> mlua::Result<()> {
+    with_permissions(lua, |p| p.check_run(command))
+}
+
+pub fn check_env(lua: &Lua, name: &str) -> mlua::Result<()> {
+    with_permissions(lua, |p| p.check_env(name))
+}
+
+// Templates of package.path which flua set up, like the directory of the
+// script. require() reads their files without --allow-read in safe mode
+#[derive(Clone, Default)]
+pub struct TrustedPaths(Vec<String>);
+
+// Trusts the templates of a package.path like `dir/?.lua;dir/?/init.lua`
+pub fn trust_package_path(lua: &Lua, path: &str) {
+    if let Some(mut trusted) = lua.app_data_mut::<TrustedPaths>() {
+        let templates = path.split(';').filter(|t| !t.is_empty());
+        trusted.0.extend(templates.map(String::from));
+    }
+}
+
+// The trusted templates, for a dapi_thread worker
+pub fn trusted_paths(lua: &Lua) -> Option<TrustedPaths> {
+    lua.app_data_ref::<TrustedPaths>().map(|t| t.clone())
+}
+
+pub fn set_trusted_paths(lua: &Lua, trusted: TrustedPaths) {
+    lua.set_app_data(trusted);
+}
+
+// Stores the Permissions in the Lua state and restricts the Lua STD
+// when running in safe mode
+pub fn install(lua: &Lua, permissions: Permissions) -> mlua::Result<()> {
+    let safe = permissions.safe;
+    lua.set_app_data(permissions);
+
+    if !safe {
+        return Ok(());
+    }
+
+    let globals = lua.globals();
+    let os: Table = globals.get("os")?;
+    let io: Table = globals.get("io")?;
+
+    // Subprocesses
+    os.set(
+        "execute",
+        guard(lua, &os, "execute", |lua, args| {
+            let command = first_string(args).unwrap_or_default();
+            check_run(lua, &command)
+        })?,
+    )?;
+    io.set(
+        "popen",
+        guard(lua, &io, "popen", |lua, args| {
+            let command = first_string(args).unwrap_or_default();
+            check_run(lua, &command)
+        })?,
+    )?;
+
+    // Files
+    io.set(
+        "open",
+        guard(lua, &io, "open", |lua, args| {
+            let path = first_string(args).unwrap_or_default();
+            let mode = match args.get(1) {
+                Some(Value::String(s)) => s.to_string_lossy(),
+                _ => "r".to_string(),
+            };
+            // r and r+ read the file, a+ too, w+ only reads what it wrote
+            if mode.starts_with('r') || (mode.starts_with('a') && mode.contains('+')) {
+                check_read(lua, &path)?;
+            }
+            if mode.starts_with(['w', 'a']) || mode.contains('+') {
+                check_write(lua, &path)?;
+            }
+            Ok(())
+        })?,
+    )?;
+    for name in ["lines", "input"] {
+        io.set(
+            name,
+            guard(lua, &io, name, |lua, args| match first_string(args) {
+                Some(path) => check_read(lua, &path),
+                None => Ok(()),
+            })?,
+        )?;
+    }
+    io.set(
+        "output",
+        guard(lua, &io, "output", |lua, args| match first_string(args) {
+            Some(path) => check_write(lua, &path),
+            None => Ok(()),
+        })?,
+    )?;
+    os.set(
+        "remove",
+        guard(lua, &os, "remove", |lua, args| {
+            check_write(lua, &first_string(args).unwrap_or_default())
+        })?,
+    )?;
+    os.set(
+        "rename",
+        guard(lua, &os, "rename", |lua, args| {
+            for value in args.iter().take(2) {
+                if let Value::String(s) = value {
+                    check_write(lua, &s.to_string_lossy())?;
+                }
+            }
+            Ok(())
+        })?,
+    )?;
+
+    // Precompiled bytecode can break out of the sandbox, every way to load
+    // code only accepts text
+    let load: Function = globals.get("load")?;
+    let loadfile = text_only(lua, globals.get("loadfile")?, 1)?;
+    globals.set("load", text_only(lua, load.clone(), 2)?)?;
+    globals.set("loadstring", text_only(lua, load, 2)?)?;
+    globals.set("loadfile", {
+        let loadfile = loadfile.clone();
+        lua.create_function(move |lua, args: mlua::MultiValue| {
+            if let Some(Value::String(path)) = args.front() {
+                check_read(lua, &path.to_string_lossy())?;
+            }
+            loadfile.call::<mlua::MultiValue>(args)
+        })?
+    })?;
+    // dofile(path) is loadfile(path)()
+    let dofile_loadfile = loadfile.clone();
+    globals.set(
+        "dofile",
+        lua.create_function(move |lua, path: Option<String>| {
+            if let Some(path) = &path {
+                check_read(lua, path)?;
+            }
+            let (chunk, error): (Option<Function>, Option<String>) = dofile_loadfile.call(path)?;
+            match chunk {
+                Some(chunk) => chunk.call::<mlua::MultiValue>(()),
+                None => Err(mlua::Error::runtime(error.unwrap_or_default())),
+            }
+        })?,
+    )?;
+    let string: Table = globals.get("string")?;
+    string.set("dump", Value::Nil)?;
+
+    // Environment variables
+    os.set(
+        "getenv",
+        guard(lua, &os, "getenv", |lua, args| {
+            check_env(lua, &first_string(args).unwrap_or_default())
+        })?,
+    )?;
+
+    // The debug library can read and change every value, the guards too.
+    // mlua leaves it out of a safe state, it must stay out
+    globals.set("debug", Value::Nil)?;
+    let package: Table = globals.get("package")?;
+    let loaded: Table = package.get("loaded")?;
+    loaded.set("debug", Value::Nil)?;
+
+    // Native libraries, the searchers of package.cpath are already replaced
+    // by mlua in a safe state
+    package.set("loadlib", Value::Nil)?;
+    package.set("cpath", "")?;
+
+    // A script can put any directory into package.path, only the files of
+    // the paths which flua set up are read without a check
+    let default_path: String = package.get("path")?;
+    lua.set_app_data(TrustedPaths::default());
+    trust_package_path(lua, &default_path);
+    let loaders: Table = match package.get::<Option<Table>>("loaders")? {
+        Some(loaders) => loaders,
+        None => package.get("searchers")?,
+    };
+    loaders.raw_set(2, lua_searcher(lua, loadfile)?)?;
+
+    Ok(())
+}
+
+// The searcher of require() for Lua files in package.path, like the one of
+// Lua with a read check for the templates which flua did not set up
+fn lua_searcher(lua: &Lua, loadfile: Function) -> mlua::Result<Function> {
+    lua.create_function(move |lua, name: String| {
+        let package: Table = lua.globals().get("package")?;
+        let path: String = package.get("path")?;
+        let file_name = name.replace('.', "/");
+
+        let mut tried = String::new();
+        for template in path.split(';').filter(|t| !t.is_empty()) {
+            let file = template.replace('?', &file_name);
+            if !Path::new(&file).is_file() {
+                tried.push_str(&format!("\n\tno file '{}'", file));
+                continue;
+            }
+
+            let trusted = lua
+                .app_data_ref::<TrustedPaths>()
+                .is_some_and(|trusted| trusted.0.iter().any(|t| t == template));
+            if !trusted {
+                check_read(lua, &file)?;
+            }
+
+            let (chunk, error): (Option<Function>, Option<String>) =
+                loadfile.call(file.as_str())?;
+            return match chunk {
+                Some(chunk) => Ok(Value::Function(chunk)),
+                None => Err(mlua::Error::runtime(format!(
+                    "error loading module '{}' from file '{}':\n\t{}",
+                    name,
+                    file,
+                    error.unwrap_or_default()
+                ))),
+            };
+        }
+        Ok(Value::String(lua.create_string(&tried)?))
+    })
+}
+
+// Wraps a function of a Lua table with a permission check
+fn guard<F>(lua: &Lua, table: &Table, name: &str, check: F) -> mlua::Result<Function>
+where
+    F: Fn(&Lua, &[Value]) -> mlua::Result<()> + 'static,
+{
+    let original: Function = table.get(name)?;
+    lua.create_function(move |lua, args: mlua::MultiValue| {
+        let values: Vec<Value> = args.iter().cloned().collect();
+        check(lua, &values)?;
+        original.call::<mlua::MultiValue>(args)
+    })
+}
+
+// Calls `load` with the mode at `mode_index` set to "t", so only text is
+// accepted. The other arguments stay the same
+fn text_only(lua: &Lua, load: Function, mode_index: usize) -> mlua::Result<Function> {
+    lua.create_function(move |lua, args: mlua::MultiValue| {
+        let mut args: Vec<Value> = args.into_iter().collect();
+        if args.len() <= mode_index {
+            args.resize(mode_index + 1, Value::Nil);
+        }
+        args[mode_index] = Value::String(lua.create_string("t")?);
+        load.call::<mlua::MultiValue>(mlua::MultiValue::from_vec(args))
+    })
+}
+
+fn first_string(args: &[Value]) -> Option<String> {
+    match args.first() {
+        Some(Value::String(s)) => Some(s.to_string_lossy()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn allow_all_grants_everything() {
+        let p = Permissions::allow_all();
+        assert!(p.check_read(Path::new("/etc/passwd")).is_ok());
+        assert!(p.check_write(Path::new("/tmp/x")).is_ok());
+        assert!(p.check_net("example.com").is_ok());
+        assert!(p.check_run("ls").is_ok());
+    }
+
+    #[test]
+    fn safe_denies_everything() {
+        let p = Permissions::safe();
+        assert!(p.check_read(Path::new("file.txt")).is_err());
+        assert!(p.check_write(Path::new("file.txt")).is_err());
+        assert!(p.check_net("example.com").is_err());
+        assert!(p.check_run("ls").is_err());
+    }
+
+    #[test]
+    fn parse_flags() {
+        let mut p = Permissions::safe();
+        assert!(p.parse_flag("--allow-run"));
+        assert!(p.parse_flag("--allow-net=example.com,localhost:8080"));
+        assert!(p.parse_flag("--allow-read"));
+        assert!(!p.parse_flag("--allow-everything"));
+
+        assert!(p.run);
+        assert_eq!(p.read, Grant::All);
+        assert_eq!(
+            p.net,
+            Grant::Only(vec![
+                "example.com".to_string(),
+                "localhost:8080".to_string()
+            ])
+        );
+        assert_eq!(p.write, Grant::Denied);
+    }
+
+    #[test]
+    fn net_grant_with_and_without_port() {
+        let mut p = Permissions::safe();
+        p.parse_flag("--allow-net=example.com,localhost:8080");
+
+        assert!(p.check_net("example.com").is_ok());
+        assert!(p.check_net("example.com:443").is_ok());
+        assert!(p.check_net("localhost:8080").is_ok());
+        assert!(p.check_net("localhost:9090").is_err());
+        assert!(p.check_url("https://example.com/index.html").is_ok());
+        assert!(p.check_url("http://other.org/").is_err());
+    }
+
+    #[test]
+    fn path_grant_blocks_traversal() {
+        let dir = tempdir().unwrap();
+        let granted = dir.path().join("granted");
+        std::fs::create_dir_all(&granted).unwrap();
+
+        let mut p = Permissions::safe();
+        p.parse_flag(&format!("--allow-write={}", granted.display()));
+
+        assert!(p.check_write(&granted.join("new_file.txt")).is_ok());
+        assert!(p.check_write(&granted.join("sub/dir/file.txt")).is_ok());
+        assert!(p.check_write(&granted.join("../secret.txt")).is_err());
+        assert!(p.check_write(&dir.path().join("secret.txt")).is_err());
+    }
+
+    #[test]
+    fn safe_mode_restricts_lua_std() {
+        let lua = Lua::new();
+        install(&lua, Permissions::safe()).unwrap();
+
+        let err = lua
+            .load(r#"os.execute("echo hi")"#)
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("permission denied"), "{}", err);
+
+        let err = lua
+            .load(r#"io.open("some_file.txt", "w")"#)
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("permission denied"), "{}", err);
+
+        let loadlib: Value = lua.load("return package.loadlib").eval().unwrap();
+        assert!(loadlib.is_nil());
+    }
+
+    #[test]
+    fn granted_read_allows_io_open() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.txt");
+        std::fs::write(&file, "hello").unwrap();
+
+        let mut permissions = Permissions::safe();
+        permissions.parse_flag(&format!("--allow-read={}", dir.path().display()));
+
+        let lua = Lua::new();
+        install(&lua, permissions).unwrap();
+        lua.globals()
+            .set("FILE", file.to_string_lossy().to_string())
+            .unwrap();
+
+        let content: String = lua
+            .load(r#"local f = io.open(FILE, "r"); local c = f:read("*a"); f:close(); return c"#)
+            .eval()
+            .unwrap();
+        assert_eq!(content, "hello");
+
+        // Writing is still denied
+        assert!(lua.load(r#"io.open(FILE, "a")"#).exec().is_err());
+    }
+
+    #[test]
+    fn granted_write_allows_io_open_without_read() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("out.txt");
+
+        let mut permissions = Permissions::safe();
+        permissions.parse_flag(&format!("--allow-write={}", dir.path().display()));
+
+        let lua = Lua::new();
+        install(&lua, permissions).unwrap();
+        lua.globals()
+            .set("FILE", file.to_string_lossy().to_string())
+            .unwrap();
+
+        lua.load(
+            r#"
+            local f = assert(io.open(FILE, "w")); f:write("a"); f:close()
+            f = assert(io.open(FILE, "ab")); f:write("b"); f:close()
+            "#,
+        )
+        .exec()
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "ab");
+
+        // Reading needs --allow-read
+        for mode in ["r", "r+", "a+"] {
+            let code = format!(r#"io.open(FILE, "{}")"#, mode);
+            assert!(lua.load(&code).exec().is_err(), "{}", mode);
+        }
+    }
+
+    #[test]
+    fn safe_mode_only_loads_text() {
+        let lua = Lua::new();
+        // Bytecode from a state without the restrictions
+        let bytecode = Lua::new()
+            .load("return 42")
+            .into_function()
+            .unwrap()
+            .dump(false);
+        install(&lua, Permissions::safe()).unwrap();
+        lua.globals()
+            .set("BYTECODE", lua.create_string(&bytecode).unwrap())
+            .unwrap();
+
+        for code in [
+            "return load(BYTECODE)",
+            "return loadstring(BYTECODE)",
+            "return load(BYTECODE, 'chunk', 'b')",
+            "local done; return load(function() if done then return nil end done = true return BYTECODE end)",
+        ] {
+            let (chunk, _): (Value, Value) = lua.load(code).eval().unwrap();
+            assert!(chunk.is_nil(), "{}", code);
+        }
+
+        let answer: i32 = lua.load("return load('return 42')()").eval().unwrap();
+        assert_eq!(answer, 42);
+
+        let (dump, debug, required): (Value, Value, bool) = lua
+            .load("return string.dump, debug, pcall(require, 'debug')")
+            .eval()
+            .unwrap();
+        assert!(dump.is_nil());
+        assert!(debug.is_nil());
+        assert!(!required);
+    }
+
+    #[test]
+    fn granted_read_allows_dofile() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("lib.lua");
+        std::fs::write(&file, "return 41 + 1").unwrap();
+
+        let lua = Lua::new();
+        install(&lua, Permissions::safe()).unwrap();
+        lua.globals()
+            .set("FILE", file.to_string_lossy().to_string())
+            .unwrap();
+        assert!(lua.load("dofile(FILE)").exec().is_err());
+
+        let mut permissions = Permissions::safe();
+        permissions.parse_flag(&format!("--allow-read={}", dir.path().display()));
+        let lua = Lua::new();
+        install(&lua, permissions).unwrap();
+        lua.globals()
+            .set("FILE", file.to_string_lossy().to_string())
+            .unwrap();
+        let answer: i32 = lua.load("return dofile(FILE)").eval().unwrap();
+        assert_eq!(answer, 42);
+        let answer: i32 = lua.load("return loadfile(FILE)()").eval().unwrap();
+        assert_eq!(answer, 42);
+    }
+
+    #[test]
+    fn require_checks_added_package_paths() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("secret.lua"), "return 42").unwrap();
+        let path = format!("{}/?.lua", dir.path().display());
+
+        let lua = Lua::new();
+        install(&lua, Permissions::safe()).unwrap();
+        lua.globals().set("DIR", path.as_str()).unwrap();
+        let err = lua
+            .load("package.path = DIR; require('secret')")
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("--allow-read"), "{}", err);
+
+        // Granted or set up by flua, the module loads
+        let mut permissions = Permissions::safe();
+        permissions.parse_flag(&format!("--allow-read={}", dir.path().display()));
+        let lua = Lua::new();
+        install(&lua, permissions).unwrap();
+        lua.globals().set("DIR", path.as_str()).unwrap();
+        let answer: i32 = lua
+            .load("package.path = DIR; return require('secret')")
+            .eval()
+            .unwrap();
+        assert_eq!(answer, 42);
+
+        let lua = Lua::new();
+        install(&lua, Permissions::safe()).unwrap();
+        crate::lua_script::set_require_paths(&lua, &[dir.path().to_path_buf()]).unwrap();
+        let answer: i32 = lua.load("return require('secret')").eval().unwrap();
+        assert_eq!(answer, 42);
+    }
+
+    #[test]
+    fn safe_mode_restricts_environment_variables() {
+        let lua = Lua::new();
+        install(&lua, Permissions::safe()).unwrap();
+        let err = lua
+            .load("os.getenv('HOME')")
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("--allow-env"), "{}", err);
+
+        let mut permissions = Permissions::safe();
+        permissions.parse_flag("--allow-env");
+        let lua = Lua::new();
+        install(&lua, permissions).unwrap();
+        assert!(lua.load("os.getenv('HOME')").exec().is_ok());
+    }
+
+    #[test]
+    fn states_without_permissions_are_unrestricted() {
+        let lua = Lua::new();
+        assert!(check_read(&lua, "/etc/passwd").is_ok());
+        assert!(check_run(&lua, "ls").is_ok());
+    }
+}
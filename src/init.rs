@@ -0,0 +1,221 @@
+// `flua init`, a new dlm13 module or project
+//
+// Every template writes a dlm13.yml, the entrypoint main.lua, an example test,
+// a .gitignore and the type stubs of `flua stubs` with a .luarc.json for the
+// Lua language server. Existing files are only overwritten with --force,
+// without it nothing is written when one of them exists
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::api::meta;
+use crate::dlm13;
+use crate::helper::exit_code::FluaError;
+
+// What main.lua does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Template {
+    // A script which prints its arguments
+    #[default]
+    Script,
+    // A module with functions which the entrypoint uses
+    Module,
+    // A JSON API with dapi_api_async
+    Server,
+}
+
+const SCRIPT_MAIN: &str = r#"-- Entrypoint, run it with `flua main.lua` or `flua run <name>`
+
+local name = arg[1] or "world"
+print("Hello, " .. name .. "!")
+"#;
+
+const MODULE_MAIN: &str = r#"-- Entrypoint of the module, scripts get its functions with
+-- `local {name} = require("{name}")`
+
+local M = {}
+
+function M.greet(name)
+    return "Hello, " .. (name or "world") .. "!"
+end
+
+return M
+"#;
+
+const SERVER_MAIN: &str = r#"-- Entrypoint, run it with `flua main.lua` or `flua run <name>`
+-- and open http://127.0.0.1:8080/api/hello
+
+local api = require("dapi_api_async")
+
+local port = tonumber(arg[1]) or 8080
+print("Listening on http://127.0.0.1:" .. port)
+
+api.start_api_server(port, {
+    hello = function()
+        return { message = "Hello from flua!" }
+    end,
+})
+"#;
+
+const EXAMPLE_TEST: &str = r#"-- Run the tests with `flua test`
+
+local t = require("dapi_test")
+
+t.describe("example", function()
+    t.it("adds numbers", function()
+        t.assert_equal(1 + 1, 2)
+    end)
+
+    t.it("compares tables", function()
+        t.assert_equal({ a = 1, list = { 1, 2 } }, { a = 1, list = { 1, 2 } })
+    end)
+end)
+"#;
+
+const GITIGNORE: &str = "# Reports of flua --coverage and --profile
+coverage/
+*.folded
+
+# Type stubs, `flua stubs --out types` writes them again
+types/
+";
+
+const LUARC: &str = r#"{
+    "runtime.version": "LuaJIT",
+    "workspace.library": ["types"]
+}
+"#;
+
+impl Template {
+    fn main(self, name: &str) -> String {
+        let main = match self {
+            Template::Script => SCRIPT_MAIN,
+            Template::Module => MODULE_MAIN,
+            Template::Server => SERVER_MAIN,
+        };
+        main.replace("{name}", name)
+    }
+}
+
+// `flua init [name]`, creates the folder `name` in `parent` or, without a
+// name, fills `parent` itself. Returns the written files
+pub fn init(
+    parent: &Path,
+    name: Option<&str>,
+    template: Template,
+    force: bool,
+) -> Result<Vec<PathBuf>, FluaError> {
+    let (dir, name) = match name {
+        Some(name) => (parent.join(name), name.to_string()),
+        None => {
+            let dir = fs::canonicalize(parent)
+                .map_err(|e| format!("Could not open '{}': {}", parent.display(), e))?;
+            let name = dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            (dir, name)
+        }
+    };
+    dlm13::check_name(&name)?;
+
+    let files: Vec<(PathBuf, String)> = vec![
+        (
+            dir.join(dlm13::MANIFEST),
+            dlm13::new_manifest(&name, "main.lua"),
+        ),
+        (dir.join("main.lua"), template.main(&name)),
+        (
+            dir.join("test").join("main_test.lua"),
+            EXAMPLE_TEST.to_string(),
+        ),
+        (dir.join(".gitignore"), GITIGNORE.to_string()),
+        (dir.join(".luarc.json"), LUARC.to_string()),
+    ];
+    let types = dir.join("types");
+
+    if !force {
+        let stubs = meta::MODULES
+            .iter()
+            .map(|module| types.join(format!("{}.lua", module.name)));
+        let existing: Vec<String> = files
+            .iter()
+            .map(|(path, _)| path.clone())
+            .chain(stubs)
+            .filter(|path| path.exists())
+            .map(|path| path.display().to_string())
+            .collect();
+        if !existing.is_empty() {
+            return Err(FluaError::from(format!(
+                "Not overwriting existing files, use --force to replace them:\n  {}",
+                existing.join("\n  ")
+            )));
+        }
+    }
+
+    let mut written = Vec::new();
+    for (path, content) in files {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Could not create '{}': {}", parent.display(), e))?;
+        }
+        fs::write(&path, content)
+            .map_err(|e| format!("Could not write '{}': {}", path.display(), e))?;
+        written.push(path);
+    }
+    written.extend(meta::write_stubs(&types)?);
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlm13::Module;
+    use crate::helper::exit_code::USAGE_ERROR;
+
+    #[test]
+    fn creates_a_valid_module() {
+        let root = tempfile::tempdir().unwrap();
+        let files = init(root.path(), Some("greeter"), Template::Module, false).unwrap();
+
+        let dir = root.path().join("greeter");
+        assert!(files.contains(&dir.join("test/main_test.lua")));
+        assert!(dir.join("types/dapi_io.lua").is_file());
+        assert!(dir.join(".gitignore").is_file());
+
+        let module = Module::load(&dir).unwrap();
+        assert_eq!(module.config.name, "greeter");
+        let main = fs::read_to_string(module.entrypoint()).unwrap();
+        assert!(
+            main.contains("local greeter = require(\"greeter\")"),
+            "{}",
+            main
+        );
+    }
+
+    #[test]
+    fn refuses_to_overwrite() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("app");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.lua"), "-- mine").unwrap();
+
+        let err = init(&dir, None, Template::Server, false).unwrap_err();
+        assert!(err.message.contains("main.lua"), "{}", err.message);
+        assert!(!dir.join(dlm13::MANIFEST).exists());
+        assert_eq!(fs::read_to_string(dir.join("main.lua")).unwrap(), "-- mine");
+
+        init(&dir, None, Template::Server, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("main.lua")).unwrap(),
+            SERVER_MAIN
+        );
+    }
+
+    #[test]
+    fn names_are_checked() {
+        let root = tempfile::tempdir().unwrap();
+        let err = init(root.path(), Some("../x"), Template::Script, false).unwrap_err();
+        assert_eq!(err.code, USAGE_ERROR);
+    }
+}
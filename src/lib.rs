@@ -0,0 +1,33 @@
+// flua as a library, embeds the Lua environment of flua with the dapi modules
+//
+// The `flua` binary in main.rs is a client of this crate, other programs use
+// FluaRuntime to run scripts with their own modules
+
+pub mod api;
+pub mod bundle;
+pub mod check;
+pub mod cli;
+pub mod commands;
+pub mod coverage;
+pub mod debugger;
+pub mod dlm13;
+pub mod helper;
+pub mod init;
+pub mod lua_script;
+pub mod profiler;
+pub mod project;
+pub mod repl;
+pub mod runtime;
+pub mod test_runner;
+pub mod utils;
+pub mod watch;
+
+#[cfg(windows)]
+pub mod windows_utf8;
+
+pub use helper::exit_code::FluaError;
+pub use helper::limits::Limits;
+pub use helper::permissions::Permissions;
+pub use runtime::{FluaRuntime, FluaRuntimeBuilder};
+
+pub const VERSION: &str = "0.2.1";
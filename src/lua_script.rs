@@ -1,127 +1,293 @@
 use mlua::{Lua, Result, Table};
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 use crate::api::{
-    base, data_parsing, http as api_http, io as api_io, net as api_net, os as api_os,
-    time as api_time,
+    async_loop, base, data_parsing, http as api_http, io as api_io, net as api_net, os as api_os,
+    test as api_test, thread as api_thread, time as api_time,
 };
+use crate::dlm13::{self, loader, lock, store::Store};
+use crate::helper::error_report::MainChunk;
+use crate::helper::exit_code::{FluaError, take_exit_request};
+use crate::helper::permissions::{self, Permissions};
+use crate::helper::print::{END, YELLOW};
+use crate::project::Project;
+use crate::runtime::{FluaRuntime, install_dapi_modules};
+
+// Where the code of a script comes from
+#[derive(Debug, Clone)]
+pub enum ScriptSource {
+    // A Lua file
+    File(String),
+    // Code from `flua -e <code>`
+    Inline(String),
+    // Code piped into `flua -`
+    Stdin,
+}
+
+impl ScriptSource {
+    // Name for the info messages
+    pub fn display_name(&self) -> &str {
+        match self {
+            ScriptSource::File(path) => path,
+            ScriptSource::Inline(_) => "<inline>",
+            ScriptSource::Stdin => "<stdin>",
+        }
+    }
+}
+
+// Function which prepares a Lua state before the script runs
+pub type SetupFn = dyn Fn(&Lua) -> Result<()>;
+
+// Error for a script file which does not exist
+#[derive(Debug)]
+pub struct ScriptNotFound(pub String);
+
+impl fmt::Display for ScriptNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "File '{}' not found!", self.0)
+    }
+}
+
+impl std::error::Error for ScriptNotFound {}
+
+// Function which executes a Lua script and formats an error as a report with
+// the failing source line and the traceback
+pub fn run_script(
+    source: &ScriptSource,
+    permissions: &Permissions,
+    lua_args: Vec<String>,
+) -> std::result::Result<(), FluaError> {
+    run_script_with(source, permissions, lua_args, &|_| Ok(()))
+}
+
+// Like run_script, `setup` can change the Lua state before the script runs
+pub fn run_script_with(
+    source: &ScriptSource,
+    permissions: &Permissions,
+    lua_args: Vec<String>,
+    setup: &SetupFn,
+) -> std::result::Result<(), FluaError> {
+    run_script_then(source, permissions, lua_args, setup, &|_| Ok(()))
+}
+
+// Like run_script_with, `finish` runs in the same Lua state after the script
+// and its dapi_async tasks
+pub fn run_script_then(
+    source: &ScriptSource,
+    permissions: &Permissions,
+    lua_args: Vec<String>,
+    setup: &SetupFn,
+    finish: &SetupFn,
+) -> std::result::Result<(), FluaError> {
+    let (script, chunk_name) = read_source(source).map_err(|e| FluaError::from_lua(&e, None))?;
+
+    execute_chunk(
+        source,
+        &script,
+        &chunk_name,
+        permissions,
+        lua_args,
+        setup,
+        finish,
+    )
+    .map_err(|e| {
+        let main = MainChunk {
+            name: &chunk_name[1..],
+            source: &script,
+        };
+        FluaError::from_lua(&e, Some(&main))
+    })
+}
 
 // Function which executes the Lua scripts
-pub fn execute_script(file: &str, safe_mode: &bool, lua_args: Vec<String>) -> Result<()> {
-    if *safe_mode {
-        println!("Safe mode not yet implemented.");
-        return Ok(());
+pub fn execute_script(
+    source: &ScriptSource,
+    permissions: &Permissions,
+    lua_args: Vec<String>,
+) -> Result<()> {
+    let (script, chunk_name) = read_source(source)?;
+    let none: &SetupFn = &|_| Ok(());
+    execute_chunk(
+        source,
+        &script,
+        &chunk_name,
+        permissions,
+        lua_args,
+        none,
+        none,
+    )
+}
+
+// Reads the code of a script, returns the code and the chunk name
+pub fn read_source(source: &ScriptSource) -> Result<(String, String)> {
+    match source {
+        ScriptSource::File(file) => {
+            if !Path::new(file).exists() {
+                return Err(mlua::Error::external(ScriptNotFound(file.clone())));
+            }
+
+            let script = fs::read_to_string(file)
+                .map_err(|e| mlua::Error::external(format!("Error while reading: {}", e)))?;
+            Ok((strip_shebang(script), format!("@{}", file)))
+        }
+        ScriptSource::Inline(code) => Ok((code.clone(), "=(command line)".to_string())),
+        ScriptSource::Stdin => {
+            let mut script = String::new();
+            std::io::stdin()
+                .read_to_string(&mut script)
+                .map_err(|e| mlua::Error::external(format!("Error while reading stdin: {}", e)))?;
+            Ok((strip_shebang(script), "=stdin".to_string()))
+        }
     }
+}
 
-    if !Path::new(file).exists() {
-        eprintln!("Error: File '{}' not found!", file);
-        return Ok(());
+fn execute_chunk(
+    source: &ScriptSource,
+    script: &str,
+    chunk_name: &str,
+    permissions: &Permissions,
+    lua_args: Vec<String>,
+    setup: &SetupFn,
+    finish: &SetupFn,
+) -> Result<()> {
+    // Scripts in a project get the require paths and default arguments of
+    // the flua.toml or flua.lua manifest
+    let script_dir = match source {
+        ScriptSource::File(file) => fs::canonicalize(file)
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf)),
+        ScriptSource::Inline(_) | ScriptSource::Stdin => std::env::current_dir().ok(),
+    };
+    let project = match &script_dir {
+        Some(dir) => Project::find(dir).map_err(mlua::Error::external)?,
+        None => None,
+    };
+
+    let mut lua_args = lua_args;
+    if let Some(project) = &project {
+        project.check_version().map_err(mlua::Error::external)?;
+
+        if let ScriptSource::File(file) = source
+            && lua_args.is_empty()
+            && project.is_entrypoint(Path::new(file))
+        {
+            lua_args = project.manifest.lua_args.clone();
+        }
     }
 
-    let script = fs::read_to_string(file)
-        .map_err(|e| mlua::Error::external(format!("Error while reading: {}", e)))?;
+    let lua = new_state(permissions, &lua_args)?;
 
-    let lua = Lua::new();
+    // Add the script path as a Lua path Table named SCRIPT_FULL_PATH,
+    // scripts without a file use the current directory
+    match source {
+        ScriptSource::File(file) => set_script_paths(&lua, file)?,
+        ScriptSource::Inline(_) | ScriptSource::Stdin => set_cwd_paths(&lua)?,
+    }
 
-    // Collect Arguments for Lua
-    let lua_arg = lua.create_table()?;
-    for (i, arg) in lua_args.iter().enumerate() {
-        lua_arg.set(i + 1, arg.clone())?;
+    // require() searches next to the script first, then in the project
+    let mut require_dirs: Vec<PathBuf> = script_dir.iter().cloned().collect();
+    if let Some(project) = &project {
+        for dir in project.require_dirs() {
+            if !require_dirs.contains(&dir) {
+                require_dirs.push(dir);
+            }
+        }
+    }
+    set_require_paths(&lua, &require_dirs)?;
+
+    // Scripts of a dlm13 module can require() its dependencies, every script
+    // the modules of the store
+    let store = Store::open();
+    if let Some(root) = script_dir.as_deref().and_then(dlm13::find_root) {
+        match lock::dependencies(&root, &store) {
+            Ok(dependencies) => loader::install(&lua, dependencies)?,
+            Err(e) if is_module_entrypoint(&root, source) => {
+                return Err(mlua::Error::external(e.message));
+            }
+            // Other scripts of the folder, like tests, run without them
+            Err(e) => eprintln!("{}[WARNING] {}{}", YELLOW, e.message, END),
+        }
     }
+    loader::install_store(&lua, store)?;
+
+    setup(&lua)?;
+
+    // Execute the Script, then the tasks of dapi_async which still run
+    let result = lua
+        .load(script)
+        .set_name(chunk_name)
+        .exec()
+        .and_then(|()| async_loop::run_pending(&lua))
+        .and_then(|()| finish(&lua));
+    take_exit_request(&lua, result)
+}
 
-    let dapi = base::register(&lua)?;
-    let dapi_io = api_io::register(&lua)?;
-    let dapi_os = api_os::register(&lua)?;
-    let dapi_http = api_http::http::register(&lua)?;
-    let dapi_json = data_parsing::json::register(&lua)?;
-    let dapi_toml = data_parsing::toml::register(&lua)?;
-    let dapi_dotenv = data_parsing::dotenv::register(&lua)?;
-    let dapi_yaml = data_parsing::yaml::register(&lua)?;
-    let dapi_ini = data_parsing::ini_parser::register(&lua)?;
-    let dapi_base64 = data_parsing::base64_api::register(&lua)?;
-    let dapi_xml = data_parsing::xml::register(&lua)?;
-    let dapi_http_async = api_http::async_server::register(&lua)?;
-    let dapi_net = api_net::net::register(&lua)?;
-    let dapi_time = api_time::register(&lua)?;
-    let dapi_api_async = api_http::async_api_server::register(&lua)?;
+// True if the script is the entrypoint of the dlm13 module in `root`
+fn is_module_entrypoint(root: &Path, source: &ScriptSource) -> bool {
+    let ScriptSource::File(file) = source else {
+        return false;
+    };
+    match (dlm13::ModuleConfig::read(root), fs::canonicalize(file)) {
+        (Ok(config), Ok(script)) => {
+            fs::canonicalize(root.join(config.entrypoint)).is_ok_and(|entry| entry == script)
+        }
+        _ => false,
+    }
+}
 
-    let globals = lua.globals();
+// Fresh Lua state with the permissions, the `arg` table and the dapi modules
+pub fn new_state(permissions: &Permissions, lua_args: &[String]) -> Result<Lua> {
+    FluaRuntime::builder()
+        .permissions(permissions.clone())
+        .args(lua_args.iter().cloned())
+        .build()
+        .map(FluaRuntime::into_lua)
+}
 
-    // Add Arguments as a arg Lua Table
-    globals.set("arg", lua_arg)?;
-
-    // Add the script path as a Lua path Table named SCRIPT_FULL_PATH
-    set_script_paths(&lua, file)?;
-
-    let package: Table = globals.get("package")?;
-    let preload: Table = package.get("preload")?;
-
-    preload.set("dapi", lua.create_function(move |_, ()| Ok(dapi.clone()))?)?;
-    preload.set(
-        "dapi_io",
-        lua.create_function(move |_, ()| Ok(dapi_io.clone()))?,
-    )?;
-    preload.set(
-        "dapi_os",
-        lua.create_function(move |_, ()| Ok(dapi_os.clone()))?,
-    )?;
-    preload.set(
-        "dapi_http",
-        lua.create_function(move |_, ()| Ok(dapi_http.clone()))?,
-    )?;
-    preload.set(
-        "dapi_json",
-        lua.create_function(move |_, ()| Ok(dapi_json.clone()))?,
-    )?;
-    preload.set(
-        "dapi_toml",
-        lua.create_function(move |_, ()| Ok(dapi_toml.clone()))?,
-    )?;
-    preload.set(
-        "dapi_dotenv",
-        lua.create_function(move |_, ()| Ok(dapi_dotenv.clone()))?,
-    )?;
-    preload.set(
-        "dapi_yaml",
-        lua.create_function(move |_, ()| Ok(dapi_yaml.clone()))?,
-    )?;
-    preload.set(
-        "dapi_ini",
-        lua.create_function(move |_, ()| Ok(dapi_ini.clone()))?,
-    )?;
-    preload.set(
-        "dapi_base64",
-        lua.create_function(move |_, ()| Ok(dapi_base64.clone()))?,
-    )?;
-    preload.set(
-        "dapi_xml",
-        lua.create_function(move |_, ()| Ok(dapi_xml.clone()))?,
-    )?;
-    preload.set(
-        "dapi_http_async",
-        lua.create_function(move |_, ()| Ok(dapi_http_async.clone()))?,
-    )?;
-    preload.set(
-        "dapi_net",
-        lua.create_function(move |_, ()| Ok(dapi_net.clone()))?,
-    )?;
-    preload.set(
-        "dapi_time",
-        lua.create_function(move |_, ()| Ok(dapi_time.clone()))?,
-    )?;
-    preload.set(
-        "dapi_api_async",
-        lua.create_function(move |_, ()| Ok(dapi_api_async.clone()))?,
-    )?;
-
-    // Execute the Script
-    lua.load(&script).exec()?;
-    Ok(())
+// Comments out a `#!/usr/bin/env flua` line, so the line numbers stay the same
+pub fn strip_shebang(script: String) -> String {
+    if script.starts_with("#!") {
+        format!("--{}", script)
+    } else {
+        script
+    }
+}
+
+// Register function of a dapi module
+pub type RegisterFn = fn(&Lua) -> Result<Table>;
+
+// All dapi modules which can be loaded with require()
+pub const DAPI_MODULES: &[(&str, RegisterFn)] = &[
+    ("dapi", base::register),
+    ("dapi_io", api_io::register),
+    ("dapi_os", api_os::register),
+    ("dapi_http", api_http::http::register),
+    ("dapi_json", data_parsing::json::register),
+    ("dapi_toml", data_parsing::toml::register),
+    ("dapi_dotenv", data_parsing::dotenv::register),
+    ("dapi_yaml", data_parsing::yaml::register),
+    ("dapi_ini", data_parsing::ini_parser::register),
+    ("dapi_base64", data_parsing::base64_api::register),
+    ("dapi_xml", data_parsing::xml::register),
+    ("dapi_http_async", api_http::async_server::register),
+    ("dapi_net", api_net::net::register),
+    ("dapi_time", api_time::register),
+    ("dapi_api_async", api_http::async_api_server::register),
+    ("dapi_test", api_test::register),
+    ("dapi_async", async_loop::register),
+    ("dapi_thread", api_thread::register),
+];
+
+// Function which adds all dapi modules to package.preload
+pub fn register_modules(lua: &Lua) -> Result<()> {
+    install_dapi_modules(lua, DAPI_MODULES.iter().map(|(name, _)| *name))
 }
 
 // Function which collects the paths of the Lua script
-fn set_script_paths(lua: &Lua, file: &str) -> Result<()> {
+pub fn set_script_paths(lua: &Lua, file: &str) -> Result<()> {
     let globals = lua.globals();
 
     let full_path = std::fs::canonicalize(Path::new(file))
@@ -138,13 +304,38 @@ fn set_script_paths(lua: &Lua, file: &str) -> Result<()> {
     Ok(())
 }
 
-//
-//
-// TODO
-// Refactor a lot of Code here from imports between multiple scripts
-//
-//
-//
+// Function which sets SCRIPT_FULL_PATH and SCRIPT_DIRECTORY to the current
+// directory for scripts from `flua -e` and `flua -`
+fn set_cwd_paths(lua: &Lua) -> Result<()> {
+    let globals = lua.globals();
+    let cwd = std::env::current_dir()
+        .map_err(|e| mlua::Error::external(format!("Path does not work: {}", e)))?;
+    let cwd = cwd.to_string_lossy().to_string();
+
+    globals.set("SCRIPT_FULL_PATH", cwd.clone())?;
+    globals.set("SCRIPT_DIRECTORY", cwd)?;
+
+    Ok(())
+}
+
+// Function which puts `dir/?.lua` and `dir/?/init.lua` of every directory in
+// front of package.path
+pub fn set_require_paths(lua: &Lua, dirs: &[PathBuf]) -> Result<()> {
+    let package: Table = lua.globals().get("package")?;
+    let default_path: String = package.get("path")?;
+
+    let mut paths: Vec<String> = Vec::new();
+    for dir in dirs {
+        let dir = dir.to_string_lossy();
+        paths.push(format!("{}/?.lua", dir));
+        paths.push(format!("{}/?/init.lua", dir));
+    }
+    // The files of these directories can be required in safe mode
+    permissions::trust_package_path(lua, &paths.join(";"));
+    paths.push(default_path);
+
+    package.set("path", paths.join(";"))
+}
 
 #[cfg(test)]
 mod tests {
@@ -242,4 +433,142 @@ mod tests {
         assert_eq!(script_path, expected_path.to_string_lossy());
         assert_eq!(script_dir, expected_dir.to_string_lossy());
     }
+
+    #[test]
+    fn test_execute_inline_code_with_args() {
+        let cwd = std::env::current_dir().unwrap();
+        let code = format!(
+            r#"
+            assert(arg[1] == "first")
+            assert(arg[2] == "-dash")
+            assert(SCRIPT_DIRECTORY == {:?})
+            assert(SCRIPT_FULL_PATH == SCRIPT_DIRECTORY)
+            assert(require("dapi_json").encode({{a = 1}}) == '{{"a":1}}')
+            "#,
+            cwd.to_string_lossy()
+        );
+
+        let result = execute_script(
+            &ScriptSource::Inline(code),
+            &Permissions::allow_all(),
+            vec!["first".to_string(), "-dash".to_string()],
+        );
+        assert!(result.is_ok(), "{:?}", result);
+    }
+
+    #[test]
+    fn test_execute_inline_code_error() {
+        let result = execute_script(
+            &ScriptSource::Inline("error('boom')".to_string()),
+            &Permissions::allow_all(),
+            Vec::new(),
+        );
+        assert!(result.unwrap_err().to_string().contains("boom"));
+    }
+
+    #[test]
+    fn test_require_next_to_script_and_from_project_paths() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("flua.toml"),
+            "entrypoint = \"app/main.lua\"\npaths = [\"lib\"]\nlua_args = [\"default\"]\n",
+        )
+        .unwrap();
+        fs::create_dir_all(dir.path().join("app")).unwrap();
+        fs::create_dir_all(dir.path().join("lib/util")).unwrap();
+        fs::write(dir.path().join("app/helper.lua"), "return 'helper'").unwrap();
+        fs::write(dir.path().join("lib/util/init.lua"), "return 'util'").unwrap();
+        fs::write(dir.path().join("shared.lua"), "return 'shared'").unwrap();
+
+        let main = dir.path().join("app/main.lua");
+        fs::write(
+            &main,
+            r#"
+            assert(require("helper") == "helper")
+            assert(require("util") == "util")
+            assert(require("shared") == "shared")
+            assert(arg[1] == "default")
+            "#,
+        )
+        .unwrap();
+
+        let source = ScriptSource::File(main.to_string_lossy().to_string());
+        let result = execute_script(&source, &Permissions::allow_all(), Vec::new());
+        assert!(result.is_ok(), "{:?}", result);
+    }
+
+    #[test]
+    fn test_project_with_wrong_version() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("flua.toml"), "flua = \"99.0.0\"\n").unwrap();
+        let main = dir.path().join("main.lua");
+        fs::write(&main, "print('should not run')").unwrap();
+
+        let source = ScriptSource::File(main.to_string_lossy().to_string());
+        let err = execute_script(&source, &Permissions::allow_all(), Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("needs flua 99.0.0"), "{}", err);
+    }
+
+    #[test]
+    fn test_lua_manifest_can_not_run_commands_in_safe_mode() {
+        let dir = tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        fs::write(
+            dir.path().join("flua.lua"),
+            format!(
+                "os.execute('touch {}')\nreturn {{}}",
+                marker.to_string_lossy()
+            ),
+        )
+        .unwrap();
+        let main = dir.path().join("main.lua");
+        fs::write(&main, "print('should not run')").unwrap();
+
+        let source = ScriptSource::File(main.to_string_lossy().to_string());
+        let err = execute_script(&source, &Permissions::safe(), Vec::new()).unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid project manifest"),
+            "{}",
+            err
+        );
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_only_the_module_entrypoint_needs_its_dependencies() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join(dlm13::MANIFEST),
+            "name: \"app\"\nversion: \"0.1.0\"\ndescription: \"\"\nauthor: \"\"\nlicense: \"\"\nlink: \"\"\nentrypoint: \"main.lua\"\nluajitversion: { min: \"0.0.1\", max: \"9.9.9\" }\nedition: 2025\ndependencies: { not_installed_module: \"1\" }\n",
+        )
+        .unwrap();
+        let main = dir.path().join("main.lua");
+        fs::write(&main, "").unwrap();
+        let tool = dir.path().join("tool.lua");
+        fs::write(&tool, "").unwrap();
+
+        let run = |file: &Path| {
+            let source = ScriptSource::File(file.to_string_lossy().to_string());
+            execute_script(&source, &Permissions::allow_all(), Vec::new())
+        };
+        let err = run(&main).unwrap_err();
+        assert!(err.to_string().contains("not_installed_module"), "{}", err);
+        assert!(run(&tool).is_ok());
+    }
+
+    #[test]
+    fn test_execute_missing_file() {
+        let source = ScriptSource::File("this_file_should_not_exist.lua".to_string());
+        let err = execute_script(&source, &Permissions::allow_all(), Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("not found"), "{}", err);
+    }
+
+    #[test]
+    fn test_strip_shebang() {
+        assert_eq!(
+            strip_shebang("#!/usr/bin/env flua\nprint(1)".to_string()),
+            "--#!/usr/bin/env flua\nprint(1)"
+        );
+        assert_eq!(strip_shebang("print(1)".to_string()), "print(1)");
+    }
 }
@@ -0,0 +1,316 @@
+// `flua --profile=out.folded script.lua`, a sampling profiler for scripts
+//
+// The hook of hooks.rs runs every SAMPLE_INTERVAL instructions and adds the
+// time since the last sample to the current Lua call stack. Calls of native
+// dapi functions are timed on their own and show up as `[dapi_io.rf]` frames.
+// The stacks are written in the collapsed format of flamegraph.pl and
+// inferno, the weights are microseconds
+
+use mlua::{Function, Lua, MultiValue, Table, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::helper::hooks;
+use crate::lua_script::DAPI_MODULES;
+
+const SAMPLE_INTERVAL: u32 = 1000;
+
+// dapi_async yields and dapi_test is written in Lua, both stay unwrapped
+const UNTIMED_MODULES: [&str; 2] = ["dapi_async", "dapi_test"];
+
+#[derive(Default)]
+struct NativeCalls {
+    calls: u64,
+    time: Duration,
+}
+
+struct Profile {
+    // Frames joined with ';', outermost first
+    stacks: HashMap<String, Duration>,
+    native: HashMap<String, NativeCalls>,
+    last: Instant,
+    // All time which was added to a stack so far
+    sampled: Duration,
+}
+
+#[derive(Clone)]
+pub struct Profiler(Rc<RefCell<Profile>>);
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler(Rc::new(RefCell::new(Profile {
+            stacks: HashMap::new(),
+            native: HashMap::new(),
+            last: Instant::now(),
+            sampled: Duration::ZERO,
+        })))
+    }
+}
+
+impl Profiler {
+    // Starts sampling the Lua state and times its dapi functions
+    pub fn install(&self, lua: &Lua) -> mlua::Result<()> {
+        lua.set_app_data(self.clone());
+        time_dapi_modules(lua)?;
+        self.0.borrow_mut().last = Instant::now();
+        hooks::set_interval(lua, "profiler", Some(SAMPLE_INTERVAL))
+    }
+
+    // The time since the last sample belongs to `stack`
+    fn add(&self, stack: String) {
+        let mut profile = self.0.borrow_mut();
+        let now = Instant::now();
+        let elapsed = now - profile.last;
+        profile.last = now;
+        profile.sampled += elapsed;
+        *profile.stacks.entry(stack).or_default() += elapsed;
+    }
+
+    // Collapsed stacks, one `frame;frame;frame microseconds` line per stack
+    pub fn folded(&self) -> String {
+        let profile = self.0.borrow();
+        let mut stacks: Vec<(&String, &Duration)> = profile.stacks.iter().collect();
+        stacks.sort();
+
+        let mut out = String::new();
+        for (stack, time) in stacks {
+            let micros = time.as_micros();
+            if micros > 0 {
+                let _ = writeln!(out, "{} {}", stack, micros);
+            }
+        }
+        out
+    }
+
+    // Table of the `top` functions with the most own time and of the dapi calls
+    pub fn report(&self, top: usize) -> String {
+        let profile = self.0.borrow();
+        let mut own: HashMap<&str, Duration> = HashMap::new();
+        let mut total: HashMap<&str, Duration> = HashMap::new();
+
+        for (stack, time) in &profile.stacks {
+            let frames: Vec<&str> = stack.split(';').collect();
+            if let Some(leaf) = frames.last() {
+                *own.entry(leaf).or_default() += *time;
+            }
+            let mut seen: Vec<&str> = Vec::new();
+            for frame in frames {
+                if !seen.contains(&frame) {
+                    seen.push(frame);
+                    *total.entry(frame).or_default() += *time;
+                }
+            }
+        }
+
+        let mut functions: Vec<(&str, Duration)> = own.into_iter().collect();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "Profile: {:.3}s sampled",
+            profile.sampled.as_secs_f64()
+        );
+        let _ = writeln!(out, "{:>10} {:>10}  function", "own ms", "total ms");
+        for (function, time) in functions.into_iter().take(top) {
+            let _ = writeln!(
+                out,
+                "{:>10.1} {:>10.1}  {}",
+                millis(time),
+                millis(total[function]),
+                function
+            );
+        }
+
+        if !profile.native.is_empty() {
+            let mut native: Vec<(&String, &NativeCalls)> = profile.native.iter().collect();
+            native.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+
+            let _ = writeln!(out, "\n{:>10} {:>10}  dapi function", "calls", "total ms");
+            for (function, calls) in native {
+                let _ = writeln!(
+                    out,
+                    "{:>10} {:>10.1}  {}",
+                    calls.calls,
+                    millis(calls.time),
+                    function
+                );
+            }
+        }
+        out
+    }
+
+    // Writes the collapsed stacks and prints the report on stderr
+    pub fn finish(&self, file: &Path, top: usize) -> Result<(), String> {
+        fs::write(file, self.folded())
+            .map_err(|e| format!("Could not write the profile '{}': {}", file.display(), e))?;
+        eprintln!();
+        eprint!("{}", self.report(top));
+        eprintln!("Collapsed stacks written to {}", file.display());
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Called by the hook of hooks.rs
+pub(crate) fn sample(lua: &Lua) {
+    let Some(profiler) = lua.app_data_ref::<Profiler>().map(|p| p.clone()) else {
+        return;
+    };
+    profiler.add(current_stack(lua, 0));
+}
+
+// The Lua call stack from `level` upwards, outermost frame first
+fn current_stack(lua: &Lua, level: usize) -> String {
+    let mut frames = Vec::new();
+    let mut level = level;
+    while let Some(frame) = lua.inspect_stack(level, |debug| {
+        let source = debug.source();
+        let file = source.short_src.as_deref().unwrap_or("?").to_string();
+        match source.what {
+            "main" => format!("main ({})", file),
+            "C" => format!("{} [C]", debug.names().name.as_deref().unwrap_or("?")),
+            _ => format!(
+                "{} ({}:{})",
+                debug.names().name.as_deref().unwrap_or("function"),
+                file,
+                source.line_defined.unwrap_or(0)
+            ),
+        }
+    }) {
+        // Frames must not contain the separator of the collapsed format
+        frames.push(frame.replace(';', ","));
+        level += 1;
+    }
+
+    if frames.is_empty() {
+        return "(no Lua code)".to_string();
+    }
+    frames.reverse();
+    frames.join(";")
+}
+
+// Replaces the native functions of the dapi modules with timed versions
+fn time_dapi_modules(lua: &Lua) -> mlua::Result<()> {
+    let package: Table = lua.globals().get("package")?;
+    let preload: Table = package.get("preload")?;
+
+    for (name, _) in DAPI_MODULES {
+        if UNTIMED_MODULES.contains(name) {
+            continue;
+        }
+        let Some(loader) = preload.get::<Option<Function>>(*name)? else {
+            continue;
+        };
+        let module: Table = loader.call(())?;
+
+        let functions: Vec<(String, Function)> = module
+            .pairs::<String, Value>()
+            .filter_map(|pair| match pair {
+                Ok((key, Value::Function(func))) if func.info().what == "C" => {
+                    Some(Ok((key, func)))
+                }
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<mlua::Result<_>>()?;
+
+        for (key, func) in functions {
+            let timed = timed(lua, format!("{}.{}", name, key), func)?;
+            module.set(key, timed)?;
+        }
+    }
+    Ok(())
+}
+
+fn timed(lua: &Lua, name: String, func: Function) -> mlua::Result<Function> {
+    lua.create_function(move |lua, args: MultiValue| {
+        let Some(profiler) = lua.app_data_ref::<Profiler>().map(|p| p.clone()) else {
+            return func.call::<MultiValue>(args);
+        };
+
+        // Level 0 is this function, the Lua code before the call is sampled
+        let stack = current_stack(lua, 1);
+        profiler.add(stack.clone());
+        let sampled = profiler.0.borrow().sampled;
+        let start = Instant::now();
+
+        let result = func.call::<MultiValue>(args);
+
+        let elapsed = start.elapsed();
+        let mut profile = profiler.0.borrow_mut();
+        // Lua callbacks of the function were sampled already
+        let own = elapsed.saturating_sub(profile.sampled - sampled);
+        let calls = profile.native.entry(name.clone()).or_default();
+        calls.calls += 1;
+        calls.time += elapsed;
+
+        profile.last = Instant::now();
+        profile.sampled += own;
+        *profile
+            .stacks
+            .entry(format!("{};[{}]", stack, name))
+            .or_default() += own;
+        drop(profile);
+
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::permissions::Permissions;
+    use crate::lua_script::new_state;
+
+    #[test]
+    fn samples_hot_functions_and_dapi_calls() {
+        let lua = new_state(&Permissions::default(), &[]).unwrap();
+        let profiler = Profiler::default();
+        profiler.install(&lua).unwrap();
+
+        lua.load(
+            r#"
+            local time = require("dapi_time")
+            local function hot()
+                local x = 0
+                for i = 1, 3e6 do x = x + i % 7 end
+                return x
+            end
+            hot()
+            time.wait(30)
+            "#,
+        )
+        .set_name("@hot.lua")
+        .exec()
+        .unwrap();
+
+        let folded = profiler.folded();
+        assert!(
+            folded.contains("main (hot.lua);hot (hot.lua:3) "),
+            "{}",
+            folded
+        );
+        assert!(
+            folded.contains("main (hot.lua);[dapi_time.wait] "),
+            "{}",
+            folded
+        );
+        for line in folded.lines() {
+            let (_, weight) = line.rsplit_once(' ').unwrap();
+            assert!(weight.parse::<u64>().is_ok(), "{}", line);
+        }
+
+        let report = profiler.report(5);
+        assert!(report.contains("hot (hot.lua:3)"), "{}", report);
+        assert!(report.contains("dapi_time.wait"), "{}", report);
+    }
+}
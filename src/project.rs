@@ -0,0 +1,279 @@
+// Multi file projects with a flua.toml or flua.lua manifest
+//
+// The manifest is searched from the directory of the script upwards, the
+// directory which contains it is the root of the project
+
+use mlua::{Lua, LuaOptions, LuaSerdeExt, StdLib, Value};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::VERSION;
+use crate::helper::limits::Limits;
+use crate::helper::update::version_checker;
+
+// Names of the manifest files, flua.toml wins if both exist
+pub const MANIFEST_FILES: &[&str] = &["flua.toml", "flua.lua"];
+
+// Content of the manifest, every value is optional
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ProjectManifest {
+    pub name: Option<String>,
+    // Script which runs with `flua` in the project directory
+    pub entrypoint: Option<String>,
+    // Version of flua the project needs, checked with version_checker
+    pub flua: Option<String>,
+    // Extra directories for require(), relative to the project root
+    pub paths: Vec<String>,
+    // Arguments for the entrypoint when no arguments are given
+    #[serde(alias = "lua-args")]
+    pub lua_args: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Project {
+    pub root: PathBuf,
+    pub manifest_path: PathBuf,
+    pub manifest: ProjectManifest,
+}
+
+impl Project {
+    // Searches a manifest in `start` and all parent directories
+    pub fn find(start: &Path) -> Result<Option<Project>, String> {
+        let start = fs::canonicalize(start).unwrap_or_else(|_| start.to_path_buf());
+
+        for dir in start.ancestors() {
+            for name in MANIFEST_FILES {
+                let path = dir.join(name);
+                if path.is_file() {
+                    return Project::load(&path).map(Some);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Loads a flua.toml or flua.lua manifest
+    pub fn load(path: &Path) -> Result<Project, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read '{}': {}", path.display(), e))?;
+
+        let manifest = if path.extension().is_some_and(|ext| ext == "lua") {
+            parse_lua_manifest(&contents)
+        } else {
+            toml::from_str(&contents).map_err(|e| e.to_string())
+        }
+        .map_err(|e| format!("Invalid project manifest '{}': {}", path.display(), e))?;
+
+        let root = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        Ok(Project {
+            root,
+            manifest_path: path.to_path_buf(),
+            manifest,
+        })
+    }
+
+    // Stops when the project needs another flua version
+    pub fn check_version(&self) -> Result<(), String> {
+        match &self.manifest.flua {
+            Some(version) if !version_checker(version, VERSION) => Err(format!(
+                "The project '{}' needs flua {}, but this is flua {}",
+                self.manifest_path.display(),
+                version,
+                VERSION
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Full path of the entrypoint script
+    pub fn entrypoint(&self) -> Option<PathBuf> {
+        self.manifest
+            .entrypoint
+            .as_ref()
+            .map(|entry| self.root.join(entry))
+    }
+
+    // Directories for require(), the project root comes first
+    pub fn require_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.root.clone()];
+        dirs.extend(self.manifest.paths.iter().map(|p| self.root.join(p)));
+        dirs
+    }
+
+    // True if `script` is the entrypoint of this project
+    pub fn is_entrypoint(&self, script: &Path) -> bool {
+        match (self.entrypoint(), fs::canonicalize(script)) {
+            (Some(entry), Ok(script)) => fs::canonicalize(entry).is_ok_and(|e| e == script),
+            _ => false,
+        }
+    }
+}
+
+// A flua.lua manifest returns a table like `return { entrypoint = "main.lua" }`.
+// The manifest is found in any parent directory, so it runs without os, io,
+// require, load or debug and with a small instruction budget
+fn parse_lua_manifest(contents: &str) -> Result<ProjectManifest, String> {
+    let lua = manifest_state().map_err(|e| e.to_string())?;
+    let value: Value = lua
+        .load(contents)
+        .set_name("=flua.lua")
+        .set_mode(mlua::ChunkMode::Text)
+        .eval()
+        .map_err(|e| e.to_string())?;
+
+    if !value.is_table() {
+        return Err("flua.lua has to return a table".to_string());
+    }
+    lua.from_value(value).map_err(|e| e.to_string())
+}
+
+fn manifest_state() -> mlua::Result<Lua> {
+    // The jit library is only loaded to switch the compiler off for the hook
+    let lua = Lua::new_with(
+        StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::JIT,
+        LuaOptions::default(),
+    )?;
+    Limits {
+        timeout: Some(Duration::from_secs(1)),
+        max_memory: None,
+        max_instructions: Some(1_000_000),
+    }
+    .install(&lua)?;
+
+    let globals = lua.globals();
+    for name in [
+        "jit",
+        "load",
+        "loadstring",
+        "loadfile",
+        "dofile",
+        "collectgarbage",
+    ] {
+        globals.raw_set(name, Value::Nil)?;
+    }
+    let string: mlua::Table = globals.get("string")?;
+    string.raw_set("dump", Value::Nil)?;
+    Ok(lua)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn find_toml_manifest_in_parent_directory() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("flua.toml"),
+            r#"
+name = "demo"
+entrypoint = "src/main.lua"
+paths = ["lib"]
+lua_args = ["--fast"]
+"#,
+        )
+        .unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+
+        let project = Project::find(&src)
+            .unwrap()
+            .expect("project should be found");
+        let root = fs::canonicalize(dir.path()).unwrap();
+
+        assert_eq!(project.root, root);
+        assert_eq!(project.manifest.name.as_deref(), Some("demo"));
+        assert_eq!(project.entrypoint(), Some(root.join("src/main.lua")));
+        assert_eq!(project.require_dirs(), vec![root.clone(), root.join("lib")]);
+        assert_eq!(project.manifest.lua_args, vec!["--fast"]);
+    }
+
+    #[test]
+    fn lua_manifest_returns_a_table() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("flua.lua");
+        fs::write(
+            &path,
+            r#"return { entrypoint = "main.lua", paths = { "vendor" }, ["lua-args"] = { "a" } }"#,
+        )
+        .unwrap();
+
+        let project = Project::load(&path).unwrap();
+        assert_eq!(project.manifest.entrypoint.as_deref(), Some("main.lua"));
+        assert_eq!(project.manifest.paths, vec!["vendor"]);
+        assert_eq!(project.manifest.lua_args, vec!["a"]);
+    }
+
+    #[test]
+    fn lua_manifest_without_table_is_an_error() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("flua.lua");
+        fs::write(&path, "return 5").unwrap();
+        assert!(Project::load(&path).is_err());
+    }
+
+    #[test]
+    fn lua_manifest_has_no_os_io_or_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("flua.lua");
+        for code in [
+            "os.execute('echo hi') return {}",
+            "io.open('file.txt', 'w') return {}",
+            "return require('dapi_os')",
+            "return load('return {}')()",
+            "return debug.getregistry()",
+        ] {
+            fs::write(&path, code).unwrap();
+            assert!(Project::load(&path).is_err(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn lua_manifest_with_endless_loop_stops() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("flua.lua");
+        fs::write(&path, "while true do end").unwrap();
+        let err = Project::load(&path).unwrap_err();
+        assert!(err.contains("Limit exceeded"), "{}", err);
+    }
+
+    #[test]
+    fn no_manifest_found() {
+        let dir = tempdir().unwrap();
+        // tempdir() may be inside a directory with a manifest, only check
+        // that a project which is found is not inside the tempdir
+        if let Some(project) = Project::find(dir.path()).unwrap() {
+            assert!(
+                !project
+                    .root
+                    .starts_with(fs::canonicalize(dir.path()).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn check_required_version() {
+        let mut project = Project {
+            root: PathBuf::from("."),
+            manifest_path: PathBuf::from("flua.toml"),
+            manifest: ProjectManifest::default(),
+        };
+        assert!(project.check_version().is_ok());
+
+        project.manifest.flua = Some(VERSION.to_string());
+        assert!(project.check_version().is_ok());
+
+        project.manifest.flua = Some("99.0.0".to_string());
+        let err = project.check_version().unwrap_err();
+        assert!(err.contains("needs flua 99.0.0"), "{}", err);
+    }
+}
@@ -0,0 +1,242 @@
+// Interactive Lua prompt with all dapi modules preloaded
+
+use mlua::{Lua, MultiValue, Value};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::VERSION;
+use crate::dlm13::loader;
+use crate::dlm13::store::Store;
+use crate::helper::config::flua_config_dir;
+use crate::helper::error_report::{MainChunk, report};
+use crate::helper::exit_code::{ExitRequest, exit_request, take_exit_request};
+use crate::helper::permissions::Permissions;
+use crate::helper::print::{CYAN, END, GREEN, RED, YELLOW};
+use crate::lua_script::{DAPI_MODULES, new_state};
+use crate::utils::json_utils::lua_to_json;
+
+// Result of a chunk which was entered in the REPL
+pub enum Eval {
+    // The chunk is not finished yet, like an open `function` or `do`
+    Incomplete,
+    // The chunk ran and returned these values
+    Values(MultiValue),
+}
+
+// Function to start the REPL, blocks until the user quits
+pub fn start_repl(permissions: &Permissions) -> Result<(), Box<dyn std::error::Error>> {
+    let lua = new_state(permissions, &[])?;
+    loader::install_store(&lua, Store::open())?;
+
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    println!("{}Flua v{} REPL{}", YELLOW, VERSION, END);
+    println!(
+        "Type {}:help{} for help, {}:quit{} to exit",
+        CYAN, END, CYAN, END
+    );
+
+    let mut buffer = String::new();
+    // Code of dapi.exit() or os.exit()
+    let mut exit = None;
+
+    loop {
+        let prompt = if buffer.is_empty() { "> " } else { ">> " };
+
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl+C drops the current input
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            // Ctrl+D quits
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if buffer.is_empty() {
+            match line.trim() {
+                ":quit" | ":exit" | ":q" => break,
+                ":help" | ":h" => {
+                    print_help();
+                    continue;
+                }
+                "" => continue,
+                _ => {}
+            }
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+
+        // An exit() inside a pcall() ends the REPL as well
+        let result =
+            eval(&lua, &buffer).and_then(|values| take_exit_request(&lua, Ok(())).map(|()| values));
+        match result {
+            Ok(Eval::Incomplete) => continue,
+            Ok(Eval::Values(values)) => {
+                if !values.is_empty() {
+                    let formatted: Vec<String> = values.iter().map(format_value).collect();
+                    println!("{}", formatted.join("\t"));
+                }
+            }
+            Err(e) if exit_request(&e).is_some() => {
+                exit = exit_request(&e);
+                let _ = editor.add_history_entry(buffer.trim_end());
+                break;
+            }
+            Err(e) => {
+                let main = MainChunk {
+                    name: "stdin",
+                    source: &buffer,
+                };
+                eprintln!("{}{}{}", RED, report(&e, Some(&main)), END);
+            }
+        }
+
+        let _ = editor.add_history_entry(buffer.trim_end());
+        buffer.clear();
+    }
+
+    if let Some(path) = &history {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = editor.save_history(path);
+    }
+
+    match exit {
+        Some(code) => Err(ExitRequest(code).into()),
+        None => Ok(()),
+    }
+}
+
+// Runs a chunk, expressions like `1 + 2` are returned like `return 1 + 2`
+pub fn eval(lua: &Lua, code: &str) -> mlua::Result<Eval> {
+    if let Ok(function) = lua
+        .load(format!("return {}", code))
+        .set_name("=stdin")
+        .into_function()
+    {
+        return function.call::<MultiValue>(()).map(Eval::Values);
+    }
+
+    match lua.load(code).set_name("=stdin").into_function() {
+        Ok(function) => function.call::<MultiValue>(()).map(Eval::Values),
+        Err(mlua::Error::SyntaxError {
+            incomplete_input: true,
+            ..
+        }) => Ok(Eval::Incomplete),
+        Err(e) => Err(e),
+    }
+}
+
+// Formats a value for printing, tables are printed as pretty JSON
+pub fn format_value(value: &Value) -> String {
+    match value {
+        Value::Table(_) => match lua_to_json(value) {
+            Ok(json) => serde_json::to_string_pretty(&json)
+                .unwrap_or_else(|_| value.to_string().unwrap_or_default()),
+            // Tables with functions or cycles can not be converted
+            Err(_) => value.to_string().unwrap_or_default(),
+        },
+        _ => value.to_string().unwrap_or_else(|_| format!("{:?}", value)),
+    }
+}
+
+fn print_help() {
+    println!("{}Flua REPL Help:{}", GREEN, END);
+    println!("{}  :help, :h{}        show this message", CYAN, END);
+    println!(
+        "{}  :quit, :exit, :q{} exit the REPL (or Ctrl+D)",
+        CYAN, END
+    );
+    println!("\nExpressions are printed, tables as JSON. Unfinished input like");
+    println!("`function f()` continues on the next line, Ctrl+C drops it.");
+    println!("\n{}Modules:{}", GREEN, END);
+    for (name, _) in DAPI_MODULES {
+        println!("  local {} = require(\"{}\")", name, name);
+    }
+}
+
+// The history is saved next to the config file
+fn history_path() -> Option<PathBuf> {
+    dirs_next::config_dir().map(|_| flua_config_dir().join("repl_history.txt"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_script::register_modules;
+
+    fn values(lua: &Lua, code: &str) -> Vec<Value> {
+        match eval(lua, code).unwrap() {
+            Eval::Values(values) => values.into_vec(),
+            Eval::Incomplete => panic!("'{}' should be complete", code),
+        }
+    }
+
+    #[test]
+    fn eval_expression_returns_value() {
+        let lua = Lua::new();
+        let result = values(&lua, "1 + 2");
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].as_integer(), Some(3));
+    }
+
+    #[test]
+    fn eval_statement_keeps_state() {
+        let lua = Lua::new();
+        assert!(values(&lua, "x = 40").is_empty());
+        let result = values(&lua, "x + 2");
+        assert_eq!(result[0].as_integer(), Some(42));
+    }
+
+    #[test]
+    fn eval_detects_incomplete_input() {
+        let lua = Lua::new();
+        assert!(matches!(
+            eval(&lua, "function add(a, b)\n").unwrap(),
+            Eval::Incomplete
+        ));
+        let result = values(&lua, "function add(a, b)\nreturn a + b\nend\n");
+        assert!(result.is_empty());
+        assert_eq!(values(&lua, "add(2, 3)")[0].as_integer(), Some(5));
+    }
+
+    #[test]
+    fn eval_reports_syntax_errors() {
+        let lua = Lua::new();
+        assert!(eval(&lua, "local = 5").is_err());
+    }
+
+    #[test]
+    fn format_table_as_json() {
+        let lua = Lua::new();
+        let value: Value = lua.load("return { name = 'flua' }").eval().unwrap();
+        let formatted = format_value(&value);
+        assert!(formatted.contains("\"name\": \"flua\""), "{}", formatted);
+    }
+
+    #[test]
+    fn format_table_with_cycle() {
+        let lua = Lua::new();
+        let value: Value = lua.load("local t = {}; t.t = t; return t").eval().unwrap();
+        assert!(format_value(&value).starts_with("table: "));
+    }
+
+    #[test]
+    fn modules_are_preloaded() {
+        let lua = Lua::new();
+        register_modules(&lua).unwrap();
+        let result = values(&lua, "require('dapi').version()");
+        assert_eq!(result[0].to_string().unwrap(), VERSION);
+    }
+}
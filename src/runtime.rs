@@ -0,0 +1,346 @@
+// FluaRuntime, the scripting environment of flua for other Rust programs
+//
+// let runtime = FluaRuntime::builder()
+//     .dapi_modules(&["dapi_json", "dapi_io"])
+//     .module("service", |lua| { ... Ok(table) })
+//     .global("VERSION", "1.2.0")
+//     .args(["--fast"])
+//     .build()?;
+// runtime.run_file("hooks/deploy.lua")?;
+
+use mlua::{FromLuaMulti, IntoLua, Lua, Table};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::api::async_loop;
+use crate::helper::error_report::MainChunk;
+use crate::helper::exit_code::{FluaError, create_exit_function, take_exit_request};
+use crate::helper::limits::Limits;
+use crate::helper::permissions::{self, Permissions};
+use crate::lua_script::{
+    DAPI_MODULES, ScriptNotFound, register_modules, set_require_paths, set_script_paths,
+    strip_shebang,
+};
+
+type ModuleFn = Box<dyn Fn(&Lua) -> mlua::Result<Table>>;
+type GlobalFn = Box<dyn FnOnce(&Lua) -> mlua::Result<()>>;
+
+#[derive(Default)]
+pub struct FluaRuntimeBuilder {
+    permissions: Permissions,
+    // None installs all dapi modules
+    dapi_modules: Option<Vec<String>>,
+    modules: Vec<(String, ModuleFn)>,
+    globals: Vec<GlobalFn>,
+    args: Vec<String>,
+    require_paths: Vec<PathBuf>,
+    limits: Limits,
+}
+
+impl FluaRuntimeBuilder {
+    // Permissions of the scripts, everything is allowed by default
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    // Only these dapi modules can be required, e.g. `["dapi_json"]`
+    pub fn dapi_modules(mut self, names: &[&str]) -> Self {
+        self.dapi_modules = Some(names.iter().map(|name| name.to_string()).collect());
+        self
+    }
+
+    // No dapi module can be required
+    pub fn without_dapi_modules(self) -> Self {
+        self.dapi_modules(&[])
+    }
+
+    // Own module for require(), `register` runs on the first require()
+    pub fn module<F>(mut self, name: &str, register: F) -> Self
+    where
+        F: Fn(&Lua) -> mlua::Result<Table> + 'static,
+    {
+        self.modules.push((name.to_string(), Box::new(register)));
+        self
+    }
+
+    // Global variable which is set before a script runs
+    pub fn global<V>(mut self, name: &str, value: V) -> Self
+    where
+        V: IntoLua + 'static,
+    {
+        let name = name.to_string();
+        self.globals
+            .push(Box::new(move |lua| lua.globals().set(name, value)));
+        self
+    }
+
+    // Content of the `arg` table
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // Directories which are searched by require() before package.path
+    pub fn require_path(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.require_paths.push(dir.into());
+        self
+    }
+
+    // Timeout, memory cap and instruction budget for everything the runtime runs
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn build(self) -> mlua::Result<FluaRuntime> {
+        let lua = Lua::new();
+
+        // Restrict the Lua STD and the dapi modules in safe mode
+        permissions::install(&lua, self.permissions)?;
+
+        let globals = lua.globals();
+
+        let arg = lua.create_table()?;
+        for (i, value) in self.args.into_iter().enumerate() {
+            arg.set(i + 1, value)?;
+        }
+        globals.set("arg", arg)?;
+
+        match &self.dapi_modules {
+            None => register_modules(&lua)?,
+            Some(names) => install_dapi_modules(&lua, names.iter().map(String::as_str))?,
+        }
+
+        let package: Table = globals.get("package")?;
+        let preload: Table = package.get("preload")?;
+        for (name, register) in self.modules {
+            preload.set(name, lua.create_function(move |lua, ()| register(lua))?)?;
+        }
+
+        // os.exit() stops the servers and flushes the output like dapi.exit()
+        let os: Table = globals.get("os")?;
+        os.set("exit", create_exit_function(&lua)?)?;
+
+        if !self.require_paths.is_empty() {
+            set_require_paths(&lua, &self.require_paths)?;
+        }
+
+        for set_global in self.globals {
+            set_global(&lua)?;
+        }
+
+        self.limits.install(&lua)?;
+
+        Ok(FluaRuntime { lua })
+    }
+}
+
+// Adds the dapi modules to package.preload
+pub(crate) fn install_dapi_modules<'a>(
+    lua: &Lua,
+    names: impl Iterator<Item = &'a str>,
+) -> mlua::Result<()> {
+    let package: Table = lua.globals().get("package")?;
+    let preload: Table = package.get("preload")?;
+
+    for name in names {
+        let Some((_, register)) = DAPI_MODULES.iter().find(|(module, _)| *module == name) else {
+            return Err(mlua::Error::external(format!(
+                "Unknown dapi module '{}'",
+                name
+            )));
+        };
+        let module = register(lua)?;
+        preload.set(name, lua.create_function(move |_, ()| Ok(module.clone()))?)?;
+    }
+
+    Ok(())
+}
+
+// A Lua state with the dapi modules, scripts share the state
+pub struct FluaRuntime {
+    lua: Lua,
+}
+
+impl FluaRuntime {
+    pub fn builder() -> FluaRuntimeBuilder {
+        FluaRuntimeBuilder::default()
+    }
+
+    // Runtime with all dapi modules and all permissions
+    pub fn new() -> mlua::Result<Self> {
+        Self::builder().build()
+    }
+
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    pub fn into_lua(self) -> Lua {
+        self.lua
+    }
+
+    pub fn set_global(&self, name: &str, value: impl IntoLua) -> mlua::Result<()> {
+        self.lua.globals().set(name, value)
+    }
+
+    // Runs a Lua file, modules next to it can be required
+    pub fn run_file(&self, path: impl AsRef<Path>) -> Result<(), FluaError> {
+        let path = path.as_ref();
+        let file = path.to_string_lossy().to_string();
+
+        if !path.exists() {
+            return Err(FluaError::from_lua(
+                &mlua::Error::external(ScriptNotFound(file)),
+                None,
+            ));
+        }
+        let code = fs::read_to_string(path)
+            .map_err(|e| FluaError::from(format!("Error while reading: {}", e)))?;
+
+        let setup = || -> mlua::Result<()> {
+            set_script_paths(&self.lua, &file)?;
+            if let Some(dir) = fs::canonicalize(path)
+                .ok()
+                .and_then(|p| p.parent().map(Path::to_path_buf))
+            {
+                set_require_paths(&self.lua, &[dir])?;
+            }
+            Ok(())
+        };
+        setup().map_err(|e| FluaError::from_lua(&e, None))?;
+
+        self.run_string(&strip_shebang(code), &file)
+    }
+
+    // Runs Lua code, `name` is the chunk name in error messages. dapi.exit()
+    // and os.exit() end the script with a FluaError where `exited` is set
+    pub fn run_string(&self, code: &str, name: &str) -> Result<(), FluaError> {
+        let result = self
+            .lua
+            .load(code)
+            .set_name(format!("@{}", name))
+            .exec()
+            .and_then(|()| async_loop::run_pending(&self.lua));
+        take_exit_request(&self.lua, result).map_err(|e| {
+            let main = MainChunk { name, source: code };
+            FluaError::from_lua(&e, Some(&main))
+        })
+    }
+
+    // Evaluates an expression or chunk and converts the result
+    pub fn eval<R: FromLuaMulti>(&self, code: &str) -> mlua::Result<R> {
+        let result = self.lua.load(code).eval();
+        take_exit_request(&self.lua, Ok(()))?;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn selected_dapi_modules_only() {
+        let runtime = FluaRuntime::builder()
+            .dapi_modules(&["dapi_json"])
+            .build()
+            .unwrap();
+
+        let ok: bool = runtime.eval("return require('dapi_json') ~= nil").unwrap();
+        assert!(ok);
+        assert!(runtime.eval::<()>("require('dapi_io')").is_err());
+
+        let err = FluaRuntime::builder()
+            .dapi_modules(&["dapi_nope"])
+            .build()
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Unknown dapi module 'dapi_nope'"));
+    }
+
+    #[test]
+    fn custom_module_globals_and_args() {
+        let runtime = FluaRuntime::builder()
+            .without_dapi_modules()
+            .module("service", |lua| {
+                let table = lua.create_table()?;
+                table.set("double", lua.create_function(|_, x: i64| Ok(x * 2))?)?;
+                Ok(table)
+            })
+            .global("PREFIX", "v")
+            .args(["a", "b"])
+            .build()
+            .unwrap();
+
+        let result: String = runtime
+            .eval("return PREFIX .. require('service').double(21) .. arg[1] .. arg[2]")
+            .unwrap();
+        assert_eq!(result, "v42ab");
+    }
+
+    #[test]
+    fn limits_stop_a_runaway_script() {
+        let runtime = FluaRuntime::builder()
+            .limits(Limits {
+                max_instructions: Some(100_000),
+                ..Limits::default()
+            })
+            .build()
+            .unwrap();
+
+        let err = runtime.run_string("while true do end", "loop").unwrap_err();
+        assert_eq!(err.code, crate::helper::exit_code::LIMIT_EXCEEDED);
+        assert!(err.message.contains("100000 instructions"), "{}", err);
+    }
+
+    #[test]
+    fn exit_returns_to_the_caller() {
+        let runtime = FluaRuntime::new().unwrap();
+
+        let err = runtime
+            .run_string("os.exit(3) done = true", "exit")
+            .unwrap_err();
+        assert!(err.exited);
+        assert_eq!(err.code, 3);
+
+        let err = runtime
+            .run_string("pcall(require('dapi').exit, true) done = true", "exit")
+            .unwrap_err();
+        assert!(err.exited);
+        assert_eq!(err.code, 0);
+
+        // The host process is still alive and the runtime can be used again
+        assert!(
+            runtime
+                .eval::<Option<bool>>("return done")
+                .unwrap()
+                .is_none()
+        );
+        runtime.run_string("done = true", "again").unwrap();
+    }
+
+    #[test]
+    fn run_file_requires_next_to_the_script() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("helper.lua"), "return 7").unwrap();
+        fs::write(
+            dir.path().join("main.lua"),
+            "#!/usr/bin/env flua\nresult = require('helper') * 6",
+        )
+        .unwrap();
+
+        let runtime = FluaRuntime::new().unwrap();
+        runtime.run_file(dir.path().join("main.lua")).unwrap();
+        assert_eq!(runtime.eval::<i64>("return result").unwrap(), 42);
+
+        let err = runtime.run_string("error('boom')", "inline").unwrap_err();
+        assert!(err.message.contains("inline:1: boom"), "{}", err);
+    }
+}
@@ -0,0 +1,521 @@
+// `flua test [dir]`, finds and runs the tests of dapi_test
+//
+// Test files are `*_test.lua` and `test_*.lua`. A file runs once, then its
+// tests one after another in the same Lua state. The result can also be
+// written as JUnit XML for CI
+
+use mlua::Lua;
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+use crate::api::async_loop;
+use crate::api::test::TestRun;
+use crate::coverage::Coverage;
+use crate::helper::error_report::report_short;
+use crate::helper::exit_code::take_exit_request;
+use crate::helper::exit_code::{FILE_NOT_FOUND, FluaError, TESTS_FAILED};
+use crate::helper::limits::{self, Limits, with_limits};
+use crate::helper::permissions::Permissions;
+use crate::helper::print::{BOLD, END, GREEN, RED, YELLOW};
+use crate::lua_script::{ScriptSource, run_script_then};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub file: String,
+    pub name: String,
+    pub outcome: Outcome,
+    pub duration: Duration,
+}
+
+pub fn is_test_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    name.ends_with("_test.lua") || (name.starts_with("test_") && name.ends_with(".lua"))
+}
+
+// All test files below the directory, sorted by path. Hidden directories, the
+// cargo target directory and type definitions like types/dapi_test.lua of
+// `flua stubs` are skipped
+pub fn discover(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            entry.depth() == 0 || !(name.starts_with('.') || name == "target")
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && is_test_file(entry.path()))
+        .map(|entry| entry.into_path())
+        .filter(|path| !is_definition_file(path))
+        .collect();
+    files.sort();
+    files
+}
+
+// Definition files for the Lua language server start with `---@meta`
+fn is_definition_file(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|source| source.starts_with("---@meta"))
+        .unwrap_or(false)
+}
+
+// Runs one file: the file itself collects the tests, then each test runs in
+// the same Lua state with its own timeout and instruction budget. The memory
+// cap applies to the whole file
+pub fn run_file(
+    file: &Path,
+    permissions: &Permissions,
+    limits: &Limits,
+    coverage: Option<&Coverage>,
+) -> Vec<TestResult> {
+    let file_name = file.to_string_lossy().to_string();
+    let source = ScriptSource::File(file_name.clone());
+    let limits = *limits;
+    let run = TestRun::default();
+    let results = Rc::new(RefCell::new(Vec::new()));
+
+    let setup = {
+        let (run, coverage) = (run.clone(), coverage.cloned());
+        move |lua: &Lua| {
+            run.install(lua);
+            limits.install(lua)?;
+            match &coverage {
+                Some(coverage) => coverage.install(lua),
+                None => Ok(()),
+            }
+        }
+    };
+    let finish = {
+        let (results, file_name) = (Rc::clone(&results), file_name.clone());
+        move |lua: &Lua| {
+            limits::clear_budgets(lua)?;
+            for test in run.take_tests() {
+                let Some(body) = test.body else {
+                    results.borrow_mut().push(TestResult {
+                        file: file_name.clone(),
+                        name: test.name,
+                        outcome: Outcome::Skipped,
+                        duration: Duration::ZERO,
+                    });
+                    continue;
+                };
+
+                let start = Instant::now();
+                let result = with_limits(lua, &limits, || {
+                    body.call::<()>(())
+                        .and_then(|()| async_loop::run_pending(lua))
+                });
+                let outcome = match take_exit_request(lua, result) {
+                    Ok(()) => Outcome::Passed,
+                    Err(e) => failed(&e),
+                };
+
+                results.borrow_mut().push(TestResult {
+                    file: file_name.clone(),
+                    name: test.name,
+                    outcome,
+                    duration: start.elapsed(),
+                });
+            }
+            Ok(())
+        }
+    };
+
+    let start = Instant::now();
+    if let Err(e) = run_script_then(&source, permissions, Vec::new(), &setup, &finish) {
+        let message = if e.exited {
+            format!("the file called exit({})", e.code)
+        } else {
+            e.message
+        };
+        results.borrow_mut().push(TestResult {
+            file: file_name,
+            name: "(loading the file)".to_string(),
+            outcome: Outcome::Failed(message),
+            duration: start.elapsed(),
+        });
+    }
+    results.take()
+}
+
+// dapi.exit() and os.exit() only end the test which called them, it fails
+// even with code 0
+fn failed(e: &mlua::Error) -> Outcome {
+    match FluaError::from_lua(e, None) {
+        FluaError {
+            exited: true, code, ..
+        } => Outcome::Failed(format!("the test called exit({})", code)),
+        _ => Outcome::Failed(report_short(e, None)),
+    }
+}
+
+// `flua test`, exits with TESTS_FAILED when a test failed
+pub fn run_tests(
+    dir: &Path,
+    permissions: &Permissions,
+    limits: &Limits,
+    junit: Option<&Path>,
+    coverage: Option<&Path>,
+) -> Result<(), FluaError> {
+    if !dir.is_dir() {
+        return Err(FluaError::new(
+            FILE_NOT_FOUND,
+            format!("Test directory '{}' not found!", dir.display()),
+        ));
+    }
+
+    let files = discover(dir);
+    if files.is_empty() {
+        println!(
+            "{}No test files found in '{}'{}",
+            YELLOW,
+            dir.display(),
+            END
+        );
+    }
+
+    let start = Instant::now();
+    let mut results = Vec::new();
+    let lines = coverage.map(|_| Coverage::for_current_dir());
+
+    for file in &files {
+        println!("{}{}{}", BOLD, file.display(), END);
+        for result in run_file(file, permissions, limits, lines.as_ref()) {
+            print_result(&result);
+            results.push(result);
+        }
+    }
+
+    let count =
+        |wanted: fn(&Outcome) -> bool| results.iter().filter(|r| wanted(&r.outcome)).count();
+    let passed = count(|o| *o == Outcome::Passed);
+    let failed = count(|o| matches!(o, Outcome::Failed(_)));
+    let skipped = count(|o| *o == Outcome::Skipped);
+
+    println!();
+    println!(
+        "{}{} passed{}, {}{} failed{}, {} skipped ({} tests in {} files, {:.2}s)",
+        GREEN,
+        passed,
+        END,
+        if failed > 0 { RED } else { "" },
+        failed,
+        END,
+        skipped,
+        results.len(),
+        files.len(),
+        start.elapsed().as_secs_f64()
+    );
+
+    if let Some(path) = junit {
+        fs::write(path, junit_xml(&results)).map_err(|e| {
+            format!(
+                "Could not write the JUnit report '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+    }
+
+    if let (Some(lines), Some(dir)) = (lines, coverage) {
+        lines.finish(dir)?;
+    }
+
+    if failed > 0 {
+        return Err(FluaError::new(
+            TESTS_FAILED,
+            format!("{} of {} tests failed", failed, results.len()),
+        ));
+    }
+    Ok(())
+}
+
+fn print_result(result: &TestResult) {
+    match &result.outcome {
+        Outcome::Passed => println!(
+            "  {}ok{}   {} ({} ms)",
+            GREEN,
+            END,
+            result.name,
+            result.duration.as_millis()
+        ),
+        Outcome::Skipped => println!("  {}skip{} {}", YELLOW, END, result.name),
+        Outcome::Failed(message) => {
+            println!("  {}FAIL{} {}", RED, END, result.name);
+            for line in message.lines() {
+                println!("       {}", line);
+            }
+        }
+    }
+}
+
+// JUnit XML with one testsuite per file
+pub fn junit_xml(results: &[TestResult]) -> String {
+    let failures = |results: &[&TestResult]| {
+        results
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Failed(_)))
+            .count()
+    };
+    let skipped = |results: &[&TestResult]| {
+        results
+            .iter()
+            .filter(|r| r.outcome == Outcome::Skipped)
+            .count()
+    };
+    let time = |results: &[&TestResult]| {
+        results
+            .iter()
+            .map(|r| r.duration.as_secs_f64())
+            .sum::<f64>()
+    };
+
+    let all: Vec<&TestResult> = results.iter().collect();
+    let mut files: Vec<&str> = results.iter().map(|r| r.file.as_str()).collect();
+    files.dedup();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        out,
+        "<testsuites name=\"flua\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        all.len(),
+        failures(&all),
+        skipped(&all),
+        time(&all)
+    );
+
+    for file in files {
+        let suite: Vec<&TestResult> = results.iter().filter(|r| r.file == file).collect();
+        let _ = writeln!(
+            out,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            escape(file),
+            suite.len(),
+            failures(&suite),
+            skipped(&suite),
+            time(&suite)
+        );
+
+        for result in suite {
+            let _ = write!(
+                out,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape(&result.name),
+                escape(file),
+                result.duration.as_secs_f64()
+            );
+            match &result.outcome {
+                Outcome::Passed => out.push_str("/>\n"),
+                Outcome::Skipped => out.push_str(">\n      <skipped/>\n    </testcase>\n"),
+                Outcome::Failed(message) => {
+                    let first = message.lines().next().unwrap_or_default();
+                    let _ = write!(
+                        out,
+                        ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                        escape(first),
+                        escape(message)
+                    );
+                }
+            }
+        }
+        out.push_str("  </testsuite>\n");
+    }
+
+    out.push_str("</testsuites>\n");
+    out
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Control characters are not allowed in XML 1.0
+            c if c.is_control() && c != '\n' && c != '\t' && c != '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn finds_test_files() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::create_dir_all(dir.path().join("types")).unwrap();
+        fs::write(
+            dir.path().join("types/dapi_test.lua"),
+            "---@meta dapi_test\n",
+        )
+        .unwrap();
+        for file in [
+            "a_test.lua",
+            "test_b.lua",
+            "helper.lua",
+            "sub/c_test.lua",
+            ".git/d_test.lua",
+        ] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+
+        let found: Vec<PathBuf> = discover(dir.path())
+            .into_iter()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            found,
+            [
+                PathBuf::from("a_test.lua"),
+                PathBuf::from("sub/c_test.lua"),
+                PathBuf::from("test_b.lua")
+            ]
+        );
+    }
+
+    #[test]
+    fn the_file_runs_once() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("state_test.lua");
+        fs::write(
+            &file,
+            r#"
+            local t = require("dapi_test")
+            counter = (counter or 0) + 1
+            t.it("first", function() t.assert_equal(counter, 1) end)
+            t.it("second", function() counter = counter + 1 end)
+            t.it("broken", function() t.assert_equal(counter, 1) end)
+            t.skip("later", function() end)
+            "#,
+        )
+        .unwrap();
+
+        let results = run_file(&file, &Permissions::default(), &Limits::default(), None);
+        let outcomes: Vec<(&str, &Outcome)> = results
+            .iter()
+            .map(|r| (r.name.as_str(), &r.outcome))
+            .collect();
+
+        assert_eq!(outcomes[0], ("first", &Outcome::Passed));
+        assert_eq!(outcomes[1], ("second", &Outcome::Passed));
+        // The tests share the state of the file
+        assert!(
+            matches!(outcomes[2].1, Outcome::Failed(m) if m.contains("expected 1, got 2")),
+            "{:?}",
+            outcomes[2]
+        );
+        assert_eq!(outcomes[3], ("later", &Outcome::Skipped));
+    }
+
+    #[test]
+    fn exit_fails_only_its_test() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("exit_test.lua");
+        fs::write(
+            &file,
+            r#"
+            local t = require("dapi_test")
+            t.it("exits", function() os.exit(0) end)
+            t.it("caught", function() pcall(require("dapi").exit, 2) end)
+            t.it("after", function() end)
+            "#,
+        )
+        .unwrap();
+
+        let results = run_file(&file, &Permissions::default(), &Limits::default(), None);
+        let outcomes: Vec<&Outcome> = results.iter().map(|r| &r.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                &Outcome::Failed("the test called exit(0)".to_string()),
+                &Outcome::Failed("the test called exit(2)".to_string()),
+                &Outcome::Passed,
+            ]
+        );
+    }
+
+    #[test]
+    fn broken_file_is_one_failure() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("broken_test.lua");
+        fs::write(&file, "local = 1").unwrap();
+
+        let results = run_file(&file, &Permissions::default(), &Limits::default(), None);
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0].outcome, Outcome::Failed(_)));
+    }
+
+    #[test]
+    fn limits_apply_per_test() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("slow_test.lua");
+        fs::write(
+            &file,
+            r#"
+            local t = require("dapi_test")
+            t.it("hangs", function() while true do end end)
+            t.it("quick", function() t.assert_true(true) end)
+            "#,
+        )
+        .unwrap();
+
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(100)),
+            ..Limits::default()
+        };
+        let results = run_file(&file, &Permissions::default(), &limits, None);
+        assert!(
+            matches!(&results[0].outcome, Outcome::Failed(m) if m.contains("Limit exceeded")),
+            "{:?}",
+            results[0]
+        );
+        assert_eq!(results[1].outcome, Outcome::Passed);
+    }
+
+    #[test]
+    fn junit_report() {
+        let results = vec![
+            TestResult {
+                file: "a_test.lua".to_string(),
+                name: "works".to_string(),
+                outcome: Outcome::Passed,
+                duration: Duration::from_millis(5),
+            },
+            TestResult {
+                file: "a_test.lua".to_string(),
+                name: "<fails>".to_string(),
+                outcome: Outcome::Failed("expected \"a\"\ngot \"b\"".to_string()),
+                duration: Duration::ZERO,
+            },
+        ];
+
+        let xml = junit_xml(&results);
+        assert!(xml.contains("<testsuites name=\"flua\" tests=\"2\" failures=\"1\" skipped=\"0\""));
+        assert!(xml.contains("<testcase name=\"works\" classname=\"a_test.lua\" time=\"0.005\"/>"));
+        assert!(xml.contains("<testcase name=\"&lt;fails&gt;\""));
+        assert!(xml.contains("<failure message=\"expected &quot;a&quot;\">"));
+        assert_eq!(xml.matches("<testsuite ").count(), 1);
+    }
+}
@@ -38,6 +38,15 @@ pub fn json_to_lua(lua: &Lua, json: &serde_json::Value) -> Result<mlua::Value> {
 // TODO
 // Probably fix this
 pub fn lua_to_json(value: &Value) -> Result<JsonValue> {
+    lua_to_json_inner(value, &mut Vec::new())
+}
+
+// `parents` contains all tables which are currently converted,
+// to stop tables which contain themselves like `_G._G`
+fn lua_to_json_inner(
+    value: &Value,
+    parents: &mut Vec<*const std::ffi::c_void>,
+) -> Result<JsonValue> {
     match value {
         Value::Nil => Ok(JsonValue::Null),
         Value::Boolean(b) => Ok(JsonValue::Bool(*b)),
@@ -47,60 +56,74 @@ pub fn lua_to_json(value: &Value) -> Result<JsonValue> {
             .ok_or_else(|| mlua::Error::external("Invalid f64 number for JSON")),
         Value::String(s) => Ok(JsonValue::String(s.to_str()?.to_string())),
         Value::Table(table) => {
-            // Lua tables können Array oder Map sein - wir unterscheiden das
-            // Einfachster Ansatz: Wenn alle keys sind 1..n, dann Array, sonst Object
-            let mut is_array = true;
-            let mut max_index = 0usize;
-            let mut keys = vec![];
+            let pointer = table.to_pointer();
+            if parents.contains(&pointer) {
+                return Err(mlua::Error::external(
+                    "Cannot convert a table which contains itself to JSON",
+                ));
+            }
+            parents.push(pointer);
+            let result = table_to_json(table, parents);
+            parents.pop();
+            result
+        }
+        _ => Err(mlua::Error::external(
+            "Unsupported Lua value type for JSON serialization",
+        )),
+    }
+}
+
+fn table_to_json(
+    table: &mlua::Table,
+    parents: &mut Vec<*const std::ffi::c_void>,
+) -> Result<JsonValue> {
+    // Lua tables können Array oder Map sein - wir unterscheiden das
+    // Einfachster Ansatz: Wenn alle keys sind 1..n, dann Array, sonst Object
+    let mut is_array = true;
+    let mut max_index = 0usize;
+    let mut keys = vec![];
 
-            for pair in table.clone().pairs::<Value, Value>() {
-                let (k, _) = pair?;
-                match k {
-                    Value::Integer(i) if i >= 1 => {
-                        if i as usize > max_index {
-                            max_index = i as usize;
-                        }
-                        keys.push(i as usize);
-                    }
-                    _ => {
-                        is_array = false;
-                        break;
-                    }
+    for pair in table.clone().pairs::<Value, Value>() {
+        let (k, _) = pair?;
+        match k {
+            Value::Integer(i) if i >= 1 => {
+                if i as usize > max_index {
+                    max_index = i as usize;
                 }
+                keys.push(i as usize);
             }
+            _ => {
+                is_array = false;
+                break;
+            }
+        }
+    }
 
-            if is_array {
-                // Array
-                let mut vec = Vec::with_capacity(max_index);
-                for i in 1..=max_index {
-                    let v = table.get::<Value>(i)?;
-                    vec.push(lua_to_json(&v)?);
-                }
-                Ok(JsonValue::Array(vec))
-            } else {
-                // Map / Objekt
-                let mut map = serde_json::Map::new();
-                for pair in table.clone().pairs::<Value, Value>() {
-                    let (k, v) = pair?;
-                    // key to string (Lua erlaubt nur string keys im JSON-Objekt)
-                    let key_str = match k {
-                        Value::String(s) => s.to_str()?.to_string(),
-                        Value::Integer(i) => i.to_string(),
-                        Value::Number(n) => n.to_string(),
-                        Value::Boolean(b) => b.to_string(),
-                        _ => {
-                            return Err(mlua::Error::external(
-                                "Unsupported table key type for JSON",
-                            ));
-                        }
-                    };
-                    map.insert(key_str, lua_to_json(&v)?);
+    if is_array {
+        // Array
+        let mut vec = Vec::with_capacity(max_index);
+        for i in 1..=max_index {
+            let v = table.get::<Value>(i)?;
+            vec.push(lua_to_json_inner(&v, parents)?);
+        }
+        Ok(JsonValue::Array(vec))
+    } else {
+        // Map / Objekt
+        let mut map = serde_json::Map::new();
+        for pair in table.clone().pairs::<Value, Value>() {
+            let (k, v) = pair?;
+            // key to string (Lua erlaubt nur string keys im JSON-Objekt)
+            let key_str = match k {
+                Value::String(s) => s.to_str()?.to_string(),
+                Value::Integer(i) => i.to_string(),
+                Value::Number(n) => n.to_string(),
+                Value::Boolean(b) => b.to_string(),
+                _ => {
+                    return Err(mlua::Error::external("Unsupported table key type for JSON"));
                 }
-                Ok(JsonValue::Object(map))
-            }
+            };
+            map.insert(key_str, lua_to_json_inner(&v, parents)?);
         }
-        _ => Err(mlua::Error::external(
-            "Unsupported Lua value type for JSON serialization",
-        )),
+        Ok(JsonValue::Object(map))
     }
 }
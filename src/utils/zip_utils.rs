@@ -45,10 +45,10 @@ pub fn unzip_file(zip_path: &str, dest_dir: &str) -> io::Result<()> {
             .or_else(|_| Ok::<PathBuf, io::Error>(clean_path(&outpath)))?;
 
         if !outpath_clean.starts_with(&dest_path) {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Unsicherer Pfad im ZIP-Archiv: {}", name),
-            ));
+            return Err(io::Error::other(format!(
+                "Unsicherer Pfad im ZIP-Archiv: {}",
+                name
+            )));
         }
 
         if zip_file.is_dir() {
@@ -0,0 +1,349 @@
+// Watch mode, runs a script again when it or one of its files changes
+//
+// Tracked are the script, every file loaded with require() or from a dlm13
+// module and every file which was read with dapi_io. Each run uses a fresh
+// Lua state. Before the next run the servers of the last run are stopped
+// through their server_controls and the hook stops its Lua code, a script
+// which hangs in a native call is abandoned after RESTART_GRACE
+
+use mlua::{Function, Lua, Table, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::api::http::server_controls::stop_all;
+use crate::helper::exit_code::FluaError;
+use crate::helper::hooks;
+use crate::helper::limits::Limits;
+use crate::helper::permissions::Permissions;
+use crate::helper::print::{CYAN, END, GREEN, RED, YELLOW, clear_terminal};
+use crate::lua_script::{ScriptSource, run_script_with};
+
+// How often the files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Changes are collected until no file changed for this time, editors often
+// write a file more than once when saving
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+// Time the last run gets to stop after a change, native calls like
+// dapi_time.waitfr can not be interrupted by the hook
+const RESTART_GRACE: Duration = Duration::from_secs(1);
+
+// How long the next run waits for the ports of the stopped servers
+const PORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Instructions between two checks whether the run was stopped
+const CHECK_INTERVAL: u32 = 1000;
+
+// Error of a run which was stopped because a file changed
+#[derive(Debug)]
+pub struct Restarted;
+
+impl fmt::Display for Restarted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the script was stopped, a file changed")
+    }
+}
+
+impl std::error::Error for Restarted {}
+
+// Files which are used by the running script
+#[derive(Clone, Default)]
+pub struct WatchList {
+    files: Arc<Mutex<BTreeSet<PathBuf>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl WatchList {
+    pub fn add(&self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.files
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path);
+    }
+
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    // The Lua code of the run stops at the next check of the hook
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    // Tracks the files of require() and dapi_io in this Lua state
+    pub fn install(&self, lua: &Lua) -> mlua::Result<()> {
+        lua.set_app_data(self.clone());
+        hooks::set_interval(lua, "watch", Some(CHECK_INTERVAL))?;
+
+        let package: Table = lua.globals().get("package")?;
+        let searchpath: Function = package.get("searchpath")?;
+
+        // Runs before the file loader of Lua and only records the file,
+        // returning nothing lets require() continue with the next loader
+        let tracker = lua.create_function(move |lua, name: String| {
+            let package: Table = lua.globals().get("package")?;
+            let path: String = package.get("path")?;
+
+            if let Some(file) = searchpath.call::<Option<String>>((name, path))?
+                && let Some(list) = lua.app_data_ref::<WatchList>()
+            {
+                list.add(Path::new(&file));
+            }
+            Ok(Value::Nil)
+        })?;
+
+        // LuaJIT calls them loaders, Lua 5.2+ searchers
+        let loaders: Table = match package.get::<Option<Table>>("loaders")? {
+            Some(loaders) => loaders,
+            None => package.get("searchers")?,
+        };
+        // Position 1 is package.preload with the dapi modules
+        loaders.raw_insert(2, tracker)
+    }
+}
+
+// Called by the hook, stops a run after stop()
+pub(crate) fn check_stopped(lua: &Lua) -> mlua::Result<()> {
+    match lua.app_data_ref::<WatchList>() {
+        Some(list) if list.stopped.load(Ordering::Relaxed) => Err(mlua::Error::external(Restarted)),
+        _ => Ok(()),
+    }
+}
+
+// Records a file which was read by a dapi function or a dlm13 module, only in
+// watch mode
+pub fn track_read(lua: &Lua, path: &str) {
+    if let Some(list) = lua.app_data_ref::<WatchList>() {
+        list.add(Path::new(path));
+    }
+}
+
+// Runs the script and runs it again after every change, until flua is stopped
+pub async fn watch_script(
+    file: String,
+    permissions: Permissions,
+    lua_args: Vec<String>,
+    limits: Limits,
+) -> Result<(), FluaError> {
+    if !Path::new(&file).exists() {
+        return Err(FluaError::from_lua(
+            &mlua::Error::external(crate::lua_script::ScriptNotFound(file)),
+            None,
+        ));
+    }
+
+    loop {
+        clear_terminal();
+        println!("{}[WATCH] Running {}{}", CYAN, file, END);
+
+        let list = WatchList::default();
+        list.add(Path::new(&file));
+
+        let mut handle = {
+            let (file, permissions, lua_args, list) = (
+                file.clone(),
+                permissions.clone(),
+                lua_args.clone(),
+                list.clone(),
+            );
+            tokio::task::spawn_blocking(move || {
+                let source = ScriptSource::File(file);
+                let setup = move |lua: &Lua| {
+                    list.install(lua)?;
+                    limits.install(lua)
+                };
+                run_script_with(&source, &permissions, lua_args, &setup)
+            })
+        };
+
+        let mut times = HashMap::new();
+        update_times(&list.files(), &mut times);
+        let mut finished = false;
+
+        // Wait for the first change, the script may still run
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            if !finished && handle.is_finished() {
+                finished = true;
+                report_result(&file, (&mut handle).await);
+                println!(
+                    "{}[WATCH] Waiting for changes in {} files{}",
+                    CYAN,
+                    list.files().len(),
+                    END
+                );
+            }
+
+            if update_times(&list.files(), &mut times) {
+                break;
+            }
+        }
+
+        // Debounce until the files stay the same
+        loop {
+            tokio::time::sleep(DEBOUNCE).await;
+            if !update_times(&list.files(), &mut times) {
+                break;
+            }
+        }
+
+        let ports = stop_all();
+        if !ports.is_empty() {
+            println!(
+                "{}[WATCH] Stopped servers on ports {:?}{}",
+                YELLOW, ports, END
+            );
+        }
+        if !finished {
+            list.stop();
+            match tokio::time::timeout(RESTART_GRACE, &mut handle).await {
+                // The error of the hook after stop()
+                Ok(Ok(Err(_))) => {
+                    println!("{}[WATCH] Stopped the running script{}", YELLOW, END)
+                }
+                Ok(result) => report_result(&file, result),
+                // The thread finishes on its own, the hook stops it as soon
+                // as it runs Lua code again
+                Err(_) => println!(
+                    "{}[WATCH] The script hangs in a native call, starting a new run anyway{}",
+                    YELLOW, END
+                ),
+            }
+        }
+        wait_for_ports(&ports).await;
+    }
+}
+
+// Waits until the ports of the stopped servers can be bound again, so the
+// next run can start its servers on the same ports
+async fn wait_for_ports(ports: &[u16]) {
+    let start = Instant::now();
+    for &port in ports {
+        while TcpListener::bind(("0.0.0.0", port)).is_err() {
+            if start.elapsed() > PORT_TIMEOUT {
+                eprintln!("{}[WATCH] Port {} is still in use{}", RED, port, END);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+fn report_result(file: &str, result: Result<Result<(), FluaError>, tokio::task::JoinError>) {
+    match result {
+        Ok(Ok(())) => println!("{}[WATCH] Finished {}{}", GREEN, file, END),
+        Ok(Err(e)) if e.exited => println!(
+            "{}[WATCH] {} exited with code {}{}",
+            YELLOW, file, e.code, END
+        ),
+        Ok(Err(e)) => eprintln!("{}[FLUA-ERROR] {}{}", RED, e, END),
+        Err(e) => eprintln!("{}[FLUA-ERROR] Join error: {}{}", RED, e, END),
+    }
+}
+
+// Updates the modification times and returns true when a known file changed,
+// files which are new in the list do not count as a change
+fn update_times(files: &[PathBuf], times: &mut HashMap<PathBuf, Option<SystemTime>>) -> bool {
+    let mut changed = false;
+    for file in files {
+        let modified = fs::metadata(file).and_then(|m| m.modified()).ok();
+        if let Some(old) = times.insert(file.clone(), modified) {
+            changed |= old != modified;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_script::register_modules;
+    use tempfile::tempdir;
+
+    #[test]
+    fn require_and_dapi_io_files_are_tracked() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("helper.lua"), "return 1").unwrap();
+        fs::write(dir.path().join("data.txt"), "data").unwrap();
+
+        let lua = Lua::new();
+        register_modules(&lua).unwrap();
+        let list = WatchList::default();
+        list.install(&lua).unwrap();
+
+        let code = format!(
+            r#"
+            package.path = {dir:?} .. "/?.lua;" .. package.path
+            assert(require("helper") == 1)
+            assert(require("dapi_json") ~= nil)
+            assert(require("dapi_io").rf({dir:?} .. "/data.txt") == "data")
+            "#,
+            dir = dir.path().to_string_lossy()
+        );
+        lua.load(&code).exec().unwrap();
+
+        let root = fs::canonicalize(dir.path()).unwrap();
+        assert_eq!(
+            list.files(),
+            vec![root.join("data.txt"), root.join("helper.lua")]
+        );
+    }
+
+    #[test]
+    fn stopped_run_ends_at_the_next_check() {
+        let lua = Lua::new();
+        let list = WatchList::default();
+        list.install(&lua).unwrap();
+        list.stop();
+
+        let err = lua
+            .load("while true do pcall(function() end) end")
+            .exec()
+            .unwrap_err();
+        assert!(err.to_string().contains("a file changed"), "{}", err);
+    }
+
+    #[test]
+    fn missing_module_still_fails() {
+        let lua = Lua::new();
+        WatchList::default().install(&lua).unwrap();
+        let err = lua.load("require('does_not_exist')").exec().unwrap_err();
+        assert!(err.to_string().contains("does_not_exist"));
+    }
+
+    #[test]
+    fn changed_file_is_detected() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("main.lua");
+        fs::write(&file, "print(1)").unwrap();
+
+        let files = vec![file.clone()];
+        let mut times = HashMap::new();
+        assert!(!update_times(&files, &mut times));
+        assert!(!update_times(&files, &mut times));
+
+        let later = SystemTime::now() + Duration::from_secs(5);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        assert!(update_times(&files, &mut times));
+    }
+}
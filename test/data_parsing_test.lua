@@ -0,0 +1,28 @@
+-- Tests for the data parsing modules, run with `flua test test`
+local t = require("dapi_test")
+local dapi_json = require("dapi_json")
+local dapi_base64 = require("dapi_base64")
+
+t.describe("dapi_json", function()
+    t.it("decodes objects", function()
+        local data = dapi_json.decode2('{"name": "flua", "tags": ["lua", "rust"]}')
+        t.assert_equal(data, { name = "flua", tags = { "lua", "rust" } })
+    end)
+
+    t.it("round trips a table", function()
+        local data = { version = 2, ratio = 0.5 }
+        t.assert_equal(dapi_json.decode2(dapi_json.encode(data)), data)
+    end)
+
+    t.it("fails on broken json", function()
+        t.assert_error(function()
+            dapi_json.decode2("{broken")
+        end)
+    end)
+end)
+
+t.describe("dapi_base64", function()
+    t.it("round trips a string", function()
+        t.assert_equal(dapi_base64.decode(dapi_base64.encode("flua")), "flua")
+    end)
+end)